@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Byte ranges of an image that could not be read, kept sorted and merged.
+#[derive(Clone, Debug, Default)]
+pub struct BadSectors {
+    /// Maps the start of each range to its end (exclusive).
+    ranges: BTreeMap<u64, u64>,
+}
+
+/// Bad sectors shared between the reader that finds them and the code that reports them.
+pub type SharedBadSectors = Arc<Mutex<BadSectors>>;
+
+impl BadSectors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the areas of a ddrescue mapfile that are not marked as finished.
+    ///
+    /// ddrescue leaves everything it couldn't read zero-filled in the image, no matter if it gave up on an area
+    /// (`-`) or didn't get to trim, scrape or even try it (`*`, `/`, `?`).
+    pub fn from_ddrescue_mapfile(path: &Path) -> io::Result<Self> {
+        let mapfile = fs::read_to_string(path)?;
+        let mut bad_sectors = Self::new();
+
+        // The first line that isn't a comment is the status line with the current position, which we skip.
+        let lines = mapfile
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .skip(1);
+        for line in lines {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let (position, size, status) = match fields[..] {
+                [position, size, status, ..] => (
+                    parse_mapfile_number(position)?,
+                    parse_mapfile_number(size)?,
+                    status,
+                ),
+                _ => return Err(invalid_mapfile(line)),
+            };
+            match status {
+                "+" => (),
+                "-" | "*" | "/" | "?" => {
+                    bad_sectors.insert(position, position.saturating_add(size))
+                }
+                _ => return Err(invalid_mapfile(line)),
+            }
+        }
+
+        Ok(bad_sectors)
+    }
+
+    /// Adds the range from `start` to `end` (exclusive), merging it with adjacent and overlapping ranges.
+    pub fn insert(&mut self, mut start: u64, mut end: u64) {
+        if start >= end {
+            return;
+        }
+
+        // A range starting before `start` may reach into the new range.
+        if let Some((&previous_start, &previous_end)) = self.ranges.range(..start).next_back() {
+            if previous_end >= start {
+                start = previous_start;
+                end = end.max(previous_end);
+            }
+        }
+
+        let overlapping = self
+            .ranges
+            .range(start..=end)
+            .map(|(&start, &end)| (start, end))
+            .collect::<Vec<_>>();
+        for (overlapping_start, overlapping_end) in overlapping {
+            self.ranges.remove(&overlapping_start);
+            end = end.max(overlapping_end);
+        }
+
+        self.ranges.insert(start, end);
+    }
+
+    /// Returns how many bytes between `start` and `end` (exclusive) are bad.
+    pub fn overlap(&self, start: u64, end: u64) -> u64 {
+        if start >= end {
+            return 0;
+        }
+
+        let first = match self.ranges.range(..=start).next_back() {
+            Some((&range_start, _)) => range_start,
+            None => start,
+        };
+        self.ranges
+            .range(first..end)
+            .map(|(&range_start, &range_end)| {
+                range_end.min(end).saturating_sub(range_start.max(start))
+            })
+            .sum()
+    }
+
+    pub fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.overlap(start, end) > 0
+    }
+
+    /// Number of separate bad ranges.
+    pub fn range_count(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Total number of bad bytes.
+    pub fn total_size(&self) -> u64 {
+        self.ranges.iter().map(|(start, end)| end - start).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+}
+
+/// Numbers in mapfiles are hexadecimal with a `0x` prefix, but ddrescue also accepts decimal ones.
+fn parse_mapfile_number(s: &str) -> io::Result<u64> {
+    let result = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    result.map_err(|_| invalid_mapfile(s))
+}
+
+fn invalid_mapfile(s: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid line in ddrescue mapfile: {}", s),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    fn ranges(bad_sectors: &BadSectors) -> Vec<(u64, u64)> {
+        bad_sectors
+            .ranges
+            .iter()
+            .map(|(&start, &end)| (start, end))
+            .collect()
+    }
+
+    #[test]
+    fn insert_merges_ranges() {
+        let mut bad_sectors = BadSectors::new();
+        bad_sectors.insert(100, 200);
+        bad_sectors.insert(300, 400);
+        // Empty ranges are ignored.
+        bad_sectors.insert(250, 250);
+        bad_sectors.insert(260, 250);
+        assert_eq!(ranges(&bad_sectors), [(100, 200), (300, 400)]);
+
+        // Adjacent ranges are merged on either side.
+        bad_sectors.insert(200, 220);
+        bad_sectors.insert(280, 300);
+        assert_eq!(ranges(&bad_sectors), [(100, 220), (280, 400)]);
+
+        // A contained range changes nothing, an overlapping one extends the range.
+        bad_sectors.insert(120, 150);
+        bad_sectors.insert(390, 450);
+        assert_eq!(ranges(&bad_sectors), [(100, 220), (280, 450)]);
+
+        // A range spanning several ranges merges them all.
+        bad_sectors.insert(500, 600);
+        bad_sectors.insert(150, 550);
+        assert_eq!(ranges(&bad_sectors), [(100, 600)]);
+        assert_eq!(bad_sectors.range_count(), 1);
+        assert_eq!(bad_sectors.total_size(), 500);
+    }
+
+    #[test]
+    fn overlap() {
+        let mut bad_sectors = BadSectors::new();
+        assert_eq!(bad_sectors.overlap(0, 1000), 0);
+        bad_sectors.insert(100, 200);
+        bad_sectors.insert(300, 400);
+
+        assert_eq!(bad_sectors.overlap(0, 1000), 200);
+        // Ranges starting before the start count only from there on.
+        assert_eq!(bad_sectors.overlap(150, 350), 100);
+        assert_eq!(bad_sectors.overlap(120, 130), 10);
+        assert_eq!(bad_sectors.overlap(200, 300), 0);
+        assert_eq!(bad_sectors.overlap(399, 1000), 1);
+        assert_eq!(bad_sectors.overlap(150, 150), 0);
+        assert_eq!(bad_sectors.overlap(350, 150), 0);
+        assert!(bad_sectors.overlaps(190, 310));
+        assert!(!bad_sectors.overlaps(400, 500));
+    }
+
+    #[test]
+    fn ddrescue_mapfile() {
+        let dir = test_dir("bad-sectors-mapfile");
+        let path = dir.join("image.map");
+        fs::write(
+            &path,
+            "# Mapfile. Created by GNU ddrescue version 1.25\n\
+             # current_pos  current_status  current_pass\n\
+             0x00120000     +               1\n\
+             #      pos        size  status\n\
+             0x00000000  0x00100000  +\n\
+             0x00100000  0x00000200  -\n\
+             0x00100200  0x00000400  *\n\
+             \n\
+             0x00100600  0x00000200  /\n\
+             0x00100800  0x00000200  ?\n\
+             0x00100A00  0x00000200  +\n\
+             1055744     4096        -\n",
+        )
+        .unwrap();
+
+        let bad_sectors = BadSectors::from_ddrescue_mapfile(&path).unwrap();
+        assert_eq!(
+            ranges(&bad_sectors),
+            [(0x10_0000, 0x10_0a00), (0x10_1c00, 0x10_2c00)]
+        );
+
+        for line in ["0x100 0x200", "0x100 zz +", "0x100 0x200 x", "-1 0x200 -"] {
+            fs::write(&path, format!("0x0 ?\n{}\n", line)).unwrap();
+            let error = BadSectors::from_ddrescue_mapfile(&path).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}", line);
+        }
+    }
+}
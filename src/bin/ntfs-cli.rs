@@ -0,0 +1,890 @@
+use std::collections::HashSet;
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail};
+use bytesize::ByteSize;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use ntfs_explorer::block_cache::CacheConfig;
+use ntfs_explorer::deleted::recover_files;
+use ntfs_explorer::disk::{parse_fill_pattern, parse_sector_size};
+use ntfs_explorer::export::{export, ExportOptions};
+use ntfs_explorer::hashes::hash_files;
+use ntfs_explorer::partition::Partition;
+use ntfs_explorer::timeline::{
+    parse_range_end, parse_range_start, TimeSource, TimelineFormat, TimelineOptions,
+};
+use ntfs_explorer::usn::UsnFilter;
+use ntfs_explorer::volume::{format_time, DirEntry, FileInfo, FileTimes, ROOT_DIRECTORY};
+use ntfs_explorer::{Disk, DiskOptions, Volume};
+use serde_json::{json, Value};
+
+/// Inspect NTFS volumes in disk images and raw devices from the command line.
+#[derive(Parser)]
+#[clap(version, about)]
+struct Args {
+    /// An image file or raw device (like `/dev/sdb1` or `\\.\C:`) to open.
+    image: PathBuf,
+    /// Index of the partition to open, as listed by `info`.
+    /// Defaults to the first NTFS partition, or the entire disk if there is no partition table.
+    #[clap(long, short)]
+    partition: Option<usize>,
+    /// Print JSON instead of human-readable output.
+    #[clap(long, global = true)]
+    json: bool,
+    /// Size of the block cache in MiB.
+    #[clap(long, default_value_t = 64)]
+    cache_size: usize,
+    /// Maximum number of KiB to read ahead when reading sequentially, 0 disables read-ahead.
+    #[clap(long, default_value_t = 1024)]
+    read_ahead: usize,
+    /// Fill unreadable sectors instead of failing, for images of failing disks.
+    #[clap(long)]
+    tolerant: bool,
+    /// Hex pattern to fill unreadable sectors with in tolerant mode, like `00` or `deadbeef`.
+    #[clap(long, value_parser = parse_fill_pattern_arg, default_value = "00")]
+    bad_sector_fill: FillPattern,
+    /// ddrescue mapfile of the image, everything not marked as finished is treated as bad sectors.
+    /// Implies `--tolerant`.
+    #[clap(long)]
+    mapfile: Option<PathBuf>,
+    /// Logical sector size of the disk in bytes.
+    /// Detected from the device, the GPT header or the NTFS boot sector by default.
+    #[clap(long, value_parser = parse_sector_size)]
+    sector_size: Option<u64>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Debug)]
+struct FillPattern(Vec<u8>);
+
+fn parse_fill_pattern_arg(s: &str) -> Result<FillPattern, String> {
+    parse_fill_pattern(s).map(FillPattern)
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Show details about the image and its partitions.
+    Info,
+    /// List a directory.
+    Ls {
+        #[clap(default_value = r"\")]
+        path: String,
+        /// List files once for each of their names, short names included.
+        #[clap(long)]
+        all_names: bool,
+    },
+    /// Show the metadata of a file.
+    Stat { path: String },
+    /// Write a file to stdout, or one of its alternate data streams with `path:stream`.
+    Cat { path: String },
+    /// List a directory and everything below it.
+    Tree {
+        #[clap(default_value = r"\")]
+        path: String,
+    },
+    /// Show the metadata of a file by its file record number.
+    Mft { record_number: u64 },
+    /// List the system files, like `$MFT`, `$LogFile` and `$Extend`.
+    System,
+    /// Copy a file, or a folder with everything below it, into a folder on the host and list what was written.
+    Export {
+        path: String,
+        target: PathBuf,
+        /// Also write alternate data streams, as `<name>_<stream>` next to their file.
+        #[clap(long)]
+        streams: bool,
+        /// Don't set the modification and access times of what was written to the ones on the volume.
+        #[clap(long)]
+        no_times: bool,
+    },
+    /// Scan `$MFT` for deleted files and list them with their original paths and how well they can be recovered.
+    Deleted,
+    /// Recover deleted files by file record number, or all that can be found, into a folder on the host.
+    /// Each file gets a report of which of its clusters were reallocated and zero filled.
+    Recover {
+        target: PathBuf,
+        record_numbers: Vec<u64>,
+    },
+    /// Hash a file, or every file in a folder and below it, with MD5, SHA-1 and SHA-256 and print them as CSV.
+    Hash {
+        #[clap(default_value = r"\")]
+        path: String,
+        /// Print only MD5 hashes in the format of `md5sum`, with paths relative to the parent of `path`.
+        #[clap(long)]
+        md5sum: bool,
+    },
+    /// Print the `$STANDARD_INFORMATION` and `$FILE_NAME` timestamps of every file record as a timeline.
+    Timeline {
+        /// `bodyfile` for `mactime` and other tools, `csv` with a line for each timestamp attribute, or `mactime` for a
+        /// sorted timeline with a line for each point in time.
+        #[clap(long, value_parser = TimelineFormat::parse, default_value = "bodyfile")]
+        format: TimelineFormat,
+        /// Leave out timestamps before this date, like `2021-06-30` or `2021-06-30 14:00:00` in UTC.
+        #[clap(long, value_parser = parse_range_start)]
+        from: Option<DateTime<Utc>>,
+        /// Leave out timestamps after this date, a date alone includes the whole day.
+        #[clap(long, value_parser = parse_range_end)]
+        to: Option<DateTime<Utc>>,
+        /// Include file records that are no longer in use.
+        #[clap(long)]
+        deleted: bool,
+    },
+    /// Check the timestamps of every file for signs of having been changed, like `$STANDARD_INFORMATION` creation
+    /// times before `$FILE_NAME` ones, and print the suspicious files as CSV.
+    Timestomping,
+    /// Print the records of the change journal, `$Extend\$UsnJrnl:$J`, as CSV with the paths of their files.
+    Usn {
+        /// Print only the records of a file record number, like `#1234`, or those whose path or reasons contain this
+        /// text, ignoring case.
+        #[clap(long)]
+        filter: Option<String>,
+    },
+    /// Mount the volume as a read-only filesystem until it is unmounted with `fusermount -u`.
+    /// Alternate data streams are exposed as extended attributes named `user.<stream>`.
+    #[cfg(feature = "fuse")]
+    Mount { mountpoint: PathBuf },
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let options = DiskOptions {
+        bad_sector_fill: if args.tolerant || args.mapfile.is_some() {
+            Some(args.bad_sector_fill.0.clone())
+        } else {
+            None
+        },
+        mapfile: args.mapfile.clone(),
+        sector_size: args.sector_size,
+    };
+    // Progress messages would only get in the way of the output.
+    let mut disk = Disk::open(&args.image, &options, &mut |_| ())?;
+    let partitions = disk.partitions()?;
+
+    if let Command::Info = args.command {
+        return info(&disk, &partitions, args.json);
+    }
+
+    let partition = choose_partition(&partitions, args.partition)?;
+    let cache_config = CacheConfig::new(args.cache_size << 20, args.read_ahead << 10);
+    let mut volume = disk.open_volume(partition, cache_config)?;
+
+    match args.command {
+        Command::Info => unreachable!("handled before opening a volume"),
+        Command::Ls { path, all_names } => {
+            let directory = lookup(&mut volume, &path)?;
+            let entries = if all_names {
+                volume.list_all_names(directory)?
+            } else {
+                volume.list_directory(directory)?
+            };
+            ls(&entries, args.json)
+        }
+        Command::Stat { path } => {
+            let record_number = lookup(&mut volume, &path)?;
+            stat(&mut volume, record_number, args.json)
+        }
+        Command::Cat { path } => {
+            let (path, stream) = split_stream(&path);
+            let record_number = lookup(&mut volume, path)?;
+            let stdout = std::io::stdout();
+            let mut stdout = stdout.lock();
+            volume.copy_stream(record_number, stream, &mut stdout)?;
+            stdout.flush()?;
+            Ok(())
+        }
+        Command::Tree { path } => {
+            let directory = lookup(&mut volume, &path)?;
+            tree(&mut volume, directory, args.json)
+        }
+        Command::Mft { record_number } => stat(&mut volume, record_number, args.json),
+        Command::System => ls(&volume.system_files()?, args.json),
+        Command::Export {
+            path,
+            target,
+            streams,
+            no_times,
+        } => {
+            let options = ExportOptions {
+                named_streams: streams,
+                restore_times: !no_times,
+            };
+            export_path(&mut volume, &path, &target, options, args.json)
+        }
+        Command::Deleted => deleted(&mut volume, args.json),
+        Command::Recover {
+            target,
+            record_numbers,
+        } => recover(&mut volume, &target, &record_numbers, args.json),
+        Command::Hash { path, md5sum } => hash_path(&mut volume, &path, md5sum, args.json),
+        Command::Timeline {
+            format,
+            from,
+            to,
+            deleted,
+        } => {
+            let options = TimelineOptions {
+                start: from,
+                end: to,
+                include_deleted: deleted,
+            };
+            timeline(&mut volume, &options, format, args.json)
+        }
+        Command::Timestomping => timestomping(&mut volume, args.json),
+        Command::Usn { filter } => usn(&mut volume, filter.as_deref().unwrap_or(""), args.json),
+        #[cfg(feature = "fuse")]
+        Command::Mount { mountpoint } => ntfs_explorer::fuse::mount(volume, &mountpoint),
+    }
+}
+
+/// Picks the partition to open, `None` meaning the entire disk.
+fn choose_partition(
+    partitions: &[Partition],
+    index: Option<usize>,
+) -> anyhow::Result<Option<&Partition>> {
+    if let Some(index) = index {
+        return match partitions.get(index) {
+            Some(partition) => Ok(Some(partition)),
+            None => bail!("there is no partition {}, see `info` for a list", index),
+        };
+    }
+    if partitions.is_empty() {
+        return Ok(None);
+    }
+    match partitions.iter().find(|p| p.is_ntfs) {
+        Some(partition) => Ok(Some(partition)),
+        None => bail!("found no NTFS partition, choose one with --partition"),
+    }
+}
+
+fn lookup<R>(volume: &mut Volume<R>, path: &str) -> anyhow::Result<u64>
+where
+    R: Read + Seek,
+{
+    volume
+        .lookup(path)?
+        .ok_or_else(|| anyhow!("{}: no such file or directory", path))
+}
+
+/// Splits `path:stream` into the path and the name of the data stream, which is empty for the unnamed one.
+fn split_stream(path: &str) -> (&str, &str) {
+    let file_name_start = path.rfind(['\\', '/']).map_or(0, |i| i + 1);
+    match path[file_name_start..].find(':') {
+        Some(i) => (
+            &path[..file_name_start + i],
+            &path[file_name_start + i + 1..],
+        ),
+        None => (path, ""),
+    }
+}
+
+fn info(disk: &Disk, partitions: &[Partition], json: bool) -> anyhow::Result<()> {
+    if json {
+        let properties = disk
+            .properties
+            .iter()
+            .map(|(headline, values)| {
+                let values = values
+                    .iter()
+                    .map(|(name, value)| json!({ "name": name, "value": value }))
+                    .collect::<Vec<_>>();
+                json!({ "headline": headline, "values": values })
+            })
+            .collect::<Vec<_>>();
+        let partitions = partitions
+            .iter()
+            .enumerate()
+            .map(|(index, partition)| {
+                json!({
+                    "index": index,
+                    "description": partition.description(),
+                    "offset": partition.offset,
+                    "size": partition.size,
+                    "ntfs": partition.is_ntfs,
+                })
+            })
+            .collect::<Vec<_>>();
+        return print_json(&json!({
+            "properties": properties,
+            "sector_size": disk.sector_size,
+            "partitions": partitions,
+        }));
+    }
+
+    for (headline, values) in &disk.properties {
+        println!("{}", headline);
+        for (name, value) in values {
+            println!("  {}: {}", name, value);
+        }
+    }
+    println!();
+    if partitions.is_empty() {
+        println!("No partition table");
+    }
+    for (index, partition) in partitions.iter().enumerate() {
+        println!(
+            "{:>3}  {:#14x}  {:>10}  {}{}",
+            index,
+            partition.offset,
+            ByteSize(partition.size).to_string(),
+            partition.description(),
+            if partition.is_ntfs { "" } else { " (not NTFS)" }
+        );
+    }
+    Ok(())
+}
+
+fn ls(entries: &[DirEntry], json: bool) -> anyhow::Result<()> {
+    if json {
+        return print_json(&Value::from(
+            entries.iter().map(dir_entry_json).collect::<Vec<_>>(),
+        ));
+    }
+
+    for entry in entries {
+        let size = if entry.is_directory {
+            "<DIR>".to_owned()
+        } else {
+            ByteSize(entry.size).to_string()
+        };
+        println!("{:>10}  {:>8}  {}", size, entry.record_number, entry.name);
+    }
+    Ok(())
+}
+
+fn stat<R>(volume: &mut Volume<R>, record_number: u64, json: bool) -> anyhow::Result<()>
+where
+    R: Read + Seek,
+{
+    let info = volume.file_info(record_number)?;
+    // Deleted or orphaned files may have no path, that's worth showing anyway.
+    let path = volume.path(record_number).ok();
+    let size = volume.stream_size(record_number, "").ok();
+    let attributes = volume.attributes(record_number)?;
+    let streams = volume.streams(record_number)?;
+    // A damaged Zone.Identifier is no reason to hide the rest.
+    let zone_identifier = volume.zone_identifier(record_number).ok().flatten();
+
+    if json {
+        let mut value = file_info_json(&info);
+        value["path"] = Value::from(path);
+        value["size"] = Value::from(size);
+        value["ntfs_attributes"] = attributes
+            .iter()
+            .map(|attribute| {
+                json!({
+                    "type": attribute.ty,
+                    "name": attribute.name,
+                    "resident": attribute.resident,
+                    "size": attribute.size,
+                })
+            })
+            .collect();
+        value["streams"] = streams
+            .iter()
+            .map(|(name, size)| json!({ "name": name, "size": size }))
+            .collect();
+        value["zone_identifier"] = zone_identifier.as_ref().map_or(Value::Null, |zone| {
+            json!({
+                "zone_id": zone.zone_id,
+                "referrer_url": zone.referrer_url,
+                "host_url": zone.host_url,
+            })
+        });
+        return print_json(&value);
+    }
+
+    println!("Path: {}", path.as_deref().unwrap_or("?"));
+    println!(
+        "File record: {} at {:#x}",
+        info.record_number, info.record_position
+    );
+    println!(
+        "Type: {}",
+        if info.is_directory {
+            "directory"
+        } else {
+            "file"
+        }
+    );
+    if let Some(size) = size {
+        println!("Size: {} ({} bytes)", ByteSize(size), size);
+    }
+    if let Some(attributes) = info.attributes {
+        println!("Attributes: {:?}", attributes);
+    }
+    if let Some(times) = &info.times {
+        println!("Creation: {}", format_time(times.creation));
+        println!("Modification: {}", format_time(times.modification));
+        println!(
+            "MFT Record Modification: {}",
+            format_time(times.mft_record_modification)
+        );
+        println!("Last Access: {}", format_time(times.access));
+    }
+    for file_name in &info.file_names {
+        println!(
+            "Name: {} ({:?}, in directory {})",
+            file_name.name, file_name.namespace, file_name.parent_record_number
+        );
+    }
+    if let Some(object_id) = &info.object_id {
+        println!("Object ID: {}", object_id);
+    }
+    for attribute in &attributes {
+        println!(
+            "Attribute: {}{}{} ({}, {} bytes)",
+            attribute.ty,
+            if attribute.name.is_empty() { "" } else { ":" },
+            attribute.name,
+            if attribute.resident {
+                "resident"
+            } else {
+                "non-resident"
+            },
+            attribute.size
+        );
+    }
+    for (name, size) in &streams {
+        println!("Stream: {} ({})", name, ByteSize(*size));
+    }
+    if let Some(zone) = &zone_identifier {
+        if let Some(zone_id) = zone.zone_id {
+            println!(
+                "Zone: {} ({})",
+                zone_id,
+                zone.zone_name().unwrap_or("unknown")
+            );
+        }
+        if let Some(referrer_url) = &zone.referrer_url {
+            println!("Referrer URL: {}", referrer_url);
+        }
+        if let Some(host_url) = &zone.host_url {
+            println!("Host URL: {}", host_url);
+        }
+    }
+    Ok(())
+}
+
+/// Looks up `path` and returns its parent directory along with its entry there, which is what exporting and hashing
+/// start from.
+fn parent_entries<R>(volume: &mut Volume<R>, path: &str) -> anyhow::Result<(u64, Vec<DirEntry>)>
+where
+    R: Read + Seek,
+{
+    let record_number = lookup(volume, path)?;
+    // The root directory has no entry in a parent, so its contents are used instead.
+    if record_number == ROOT_DIRECTORY {
+        return Ok((ROOT_DIRECTORY, volume.list_directory(ROOT_DIRECTORY)?));
+    }
+    let components = volume.path_components(record_number)?;
+    let directory = components
+        .iter()
+        .rev()
+        .nth(1)
+        .map_or(ROOT_DIRECTORY, |&(directory, _)| directory);
+    let entries = volume
+        .list_directory(directory)?
+        .into_iter()
+        .filter(|entry| entry.record_number == record_number)
+        .collect::<Vec<_>>();
+    Ok((directory, entries))
+}
+
+fn export_path<R>(
+    volume: &mut Volume<R>,
+    path: &str,
+    target: &Path,
+    options: ExportOptions,
+    json: bool,
+) -> anyhow::Result<()>
+where
+    R: Read + Seek,
+{
+    let (directory, entries) = parent_entries(volume, path)?;
+    let source_directory = volume.path(directory)?;
+    let log = export(
+        volume,
+        directory,
+        &source_directory,
+        &entries,
+        target,
+        options,
+        |_, _| true,
+    )?;
+
+    if json {
+        print_json(&Value::from(
+            log.items
+                .iter()
+                .map(|item| {
+                    json!({
+                        "source": item.source,
+                        "target": item.target.to_string_lossy(),
+                        "size": item.size,
+                        "error": item.error,
+                    })
+                })
+                .collect::<Vec<_>>(),
+        ))?;
+    } else {
+        let stdout = std::io::stdout();
+        log.write(&mut stdout.lock())?;
+    }
+    if log.errors() > 0 {
+        bail!("{} items could not be exported", log.errors());
+    }
+    Ok(())
+}
+
+fn deleted<R>(volume: &mut Volume<R>, json: bool) -> anyhow::Result<()>
+where
+    R: Read + Seek,
+{
+    let deleted_files = volume.scan_deleted_files(|_, _| true)?;
+    if json {
+        return print_json(&Value::from(
+            deleted_files
+                .iter()
+                .map(|file| {
+                    let mut value = dir_entry_json(&file.entry);
+                    value["path"] = file.path.clone().into();
+                    value["parent_record_number"] = file.parent_record_number.into();
+                    value["recoverability"] = file.recoverability.description().into();
+                    value
+                })
+                .collect::<Vec<_>>(),
+        ));
+    }
+
+    for file in &deleted_files {
+        let size = if file.entry.is_directory {
+            "<DIR>".to_owned()
+        } else {
+            ByteSize(file.entry.size).to_string()
+        };
+        println!(
+            "{:>10}  {:>8}  {}  ({})",
+            size,
+            file.entry.record_number,
+            file.path,
+            file.recoverability.description()
+        );
+    }
+    Ok(())
+}
+
+fn recover<R>(
+    volume: &mut Volume<R>,
+    target: &Path,
+    record_numbers: &[u64],
+    json: bool,
+) -> anyhow::Result<()>
+where
+    R: Read + Seek,
+{
+    let mut deleted_files = volume.scan_deleted_files(|_, _| true)?;
+    if !record_numbers.is_empty() {
+        if let Some(record_number) = record_numbers.iter().find(|&&record_number| {
+            !deleted_files
+                .iter()
+                .any(|file| file.entry.record_number == record_number)
+        }) {
+            bail!("file record {} is not a deleted file", record_number);
+        }
+        deleted_files.retain(|file| record_numbers.contains(&file.entry.record_number));
+    }
+    let log = recover_files(volume, &deleted_files, target, |_, _| true)?;
+
+    if json {
+        print_json(&Value::from(
+            log.files
+                .iter()
+                .map(|file| {
+                    let mut value = json!({
+                        "path": file.path,
+                        "target": file.target.to_string_lossy(),
+                    });
+                    match &file.report {
+                        Ok(report) => {
+                            value["size"] = report.size.into();
+                            value["recovered"] = report.recovered.into();
+                            value["reallocated_clusters"] = report
+                                .runs
+                                .iter()
+                                .map(|run| run.reallocated)
+                                .sum::<u64>()
+                                .into();
+                        }
+                        Err(err) => value["error"] = err.clone().into(),
+                    }
+                    value
+                })
+                .collect::<Vec<_>>(),
+        ))?;
+    } else {
+        for file in &log.files {
+            match &file.report {
+                Ok(report) => println!(
+                    "{:>10} of {:>10}  {} -> {}",
+                    ByteSize(report.recovered).to_string(),
+                    ByteSize(report.size).to_string(),
+                    file.path,
+                    file.target.display()
+                ),
+                Err(err) => println!("{:>24}  {}: {}", "failed", file.path, err),
+            }
+        }
+    }
+    if log.errors() > 0 {
+        bail!("{} files could not be recovered", log.errors());
+    }
+    Ok(())
+}
+
+fn hash_path<R>(volume: &mut Volume<R>, path: &str, md5sum: bool, json: bool) -> anyhow::Result<()>
+where
+    R: Read + Seek,
+{
+    let (directory, entries) = parent_entries(volume, path)?;
+    let source_directory = volume.path(directory)?;
+    let list = hash_files(volume, directory, &source_directory, &entries, |_, _| true);
+
+    if json {
+        print_json(&Value::from(
+            list.files
+                .iter()
+                .map(|file| {
+                    let mut value = json!({
+                        "record_number": file.record_number,
+                        "path": file.path,
+                        "size": file.size,
+                    });
+                    match &file.hashes {
+                        Ok(hashes) => {
+                            value["md5"] = hashes.md5.clone().into();
+                            value["sha1"] = hashes.sha1.clone().into();
+                            value["sha256"] = hashes.sha256.clone().into();
+                        }
+                        Err(err) => value["error"] = err.clone().into(),
+                    }
+                    value
+                })
+                .collect::<Vec<_>>(),
+        ))?;
+    } else {
+        let stdout = std::io::stdout();
+        if md5sum {
+            list.write_md5sum(&mut stdout.lock())?;
+        } else {
+            list.write_csv(&mut stdout.lock())?;
+        }
+    }
+    if list.errors() > 0 {
+        bail!("{} files could not be hashed", list.errors());
+    }
+    Ok(())
+}
+
+fn timeline<R>(
+    volume: &mut Volume<R>,
+    options: &TimelineOptions,
+    format: TimelineFormat,
+    json: bool,
+) -> anyhow::Result<()>
+where
+    R: Read + Seek,
+{
+    let timeline = volume.timeline(options, |_, _| true)?;
+
+    if json {
+        return print_json(&Value::from(
+            timeline
+                .records
+                .iter()
+                .map(|record| {
+                    json!({
+                        "record_number": record.record_number,
+                        "sequence_number": record.sequence_number,
+                        "path": record.path,
+                        "attribute": record.source.attribute_name(),
+                        "namespace": match record.source {
+                            TimeSource::StandardInformation => None,
+                            TimeSource::FileName(namespace) => Some(format!("{:?}", namespace)),
+                        },
+                        "is_directory": record.is_directory,
+                        "deleted": record.deleted,
+                        "size": record.size,
+                        "times": file_times_json(&record.times),
+                    })
+                })
+                .collect::<Vec<_>>(),
+        ));
+    }
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    timeline.write(format, &mut stdout)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn timestomping<R>(volume: &mut Volume<R>, json: bool) -> anyhow::Result<()>
+where
+    R: Read + Seek,
+{
+    let report = volume.scan_timestomping(|_, _| true)?;
+
+    if json {
+        return print_json(&json!({
+            "volume_created": report.lifetime.created.to_rfc3339(),
+            "files": report.files.iter().map(|file| json!({
+                "record_number": file.record_number,
+                "path": file.path,
+                "times": file_times_json(&file.times),
+                "file_name_times": file_times_json(&file.file_name_times),
+                "anomalies": file.anomalies.iter().map(|anomaly| anomaly.description()).collect::<Vec<_>>(),
+            })).collect::<Vec<_>>(),
+        }));
+    }
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    report.write_csv(&mut stdout)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn usn<R>(volume: &mut Volume<R>, filter: &str, json: bool) -> anyhow::Result<()>
+where
+    R: Read + Seek,
+{
+    let journal = volume.usn_journal(|_, _| true)?;
+    let filter = UsnFilter::parse(filter);
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    if json {
+        journal.write_json(&filter, &mut stdout)?;
+    } else {
+        journal.write_csv(&filter, &mut stdout)?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+fn tree<R>(volume: &mut Volume<R>, directory: u64, json: bool) -> anyhow::Result<()>
+where
+    R: Read + Seek,
+{
+    let mut visited = HashSet::new();
+    visited.insert(directory);
+    if json {
+        let children = tree_json(volume, directory, &mut visited)?;
+        return print_json(&Value::from(children));
+    }
+
+    print_tree(volume, directory, 0, &mut visited)
+}
+
+fn print_tree<R>(
+    volume: &mut Volume<R>,
+    directory: u64,
+    depth: usize,
+    visited: &mut HashSet<u64>,
+) -> anyhow::Result<()>
+where
+    R: Read + Seek,
+{
+    for entry in volume.list_directory(directory)? {
+        // The root directory contains itself as ".", and a damaged volume may contain worse loops.
+        if !visited.insert(entry.record_number) {
+            continue;
+        }
+
+        if entry.is_directory {
+            println!("{:indent$}{}\\", "", entry.name, indent = depth * 2);
+            print_tree(volume, entry.record_number, depth + 1, visited)?;
+        } else {
+            println!("{:indent$}{}", "", entry.name, indent = depth * 2);
+        }
+    }
+    Ok(())
+}
+
+fn tree_json<R>(
+    volume: &mut Volume<R>,
+    directory: u64,
+    visited: &mut HashSet<u64>,
+) -> anyhow::Result<Vec<Value>>
+where
+    R: Read + Seek,
+{
+    let mut children = vec![];
+    for entry in volume.list_directory(directory)? {
+        if !visited.insert(entry.record_number) {
+            continue;
+        }
+
+        let mut value = dir_entry_json(&entry);
+        if entry.is_directory {
+            value["children"] = Value::from(tree_json(volume, entry.record_number, visited)?);
+        }
+        children.push(value);
+    }
+    Ok(children)
+}
+
+fn dir_entry_json(entry: &DirEntry) -> Value {
+    json!({
+        "record_number": entry.record_number,
+        "sequence_number": entry.sequence_number,
+        "name": entry.name,
+        "namespace": format!("{:?}", entry.namespace),
+        "is_directory": entry.is_directory,
+        "size": entry.size,
+        "allocated_size": entry.allocated_size,
+        "attributes": format!("{:?}", entry.attributes),
+        "times": file_times_json(&entry.times),
+        "file_name_times": file_times_json(&entry.file_name_times),
+        "bad_sectors": entry.bad_sectors.map(|overlap| json!({
+            "mft_record": overlap.mft_record,
+            "data": overlap.data,
+        })),
+    })
+}
+
+fn file_info_json(info: &FileInfo) -> Value {
+    json!({
+        "record_number": info.record_number,
+        "record_position": info.record_position,
+        "is_directory": info.is_directory,
+        "attributes": info.attributes.map(|attributes| format!("{:?}", attributes)),
+        "times": info.times.as_ref().map(file_times_json),
+        "file_names": info.file_names.iter().map(|file_name| json!({
+            "namespace": format!("{:?}", file_name.namespace),
+            "name": file_name.name,
+            "parent_record_number": file_name.parent_record_number,
+        })).collect::<Vec<_>>(),
+        "object_id": info.object_id,
+    })
+}
+
+fn file_times_json(times: &FileTimes) -> Value {
+    json!({
+        "creation": times.creation.to_rfc3339(),
+        "modification": times.modification.to_rfc3339(),
+        "mft_record_modification": times.mft_record_modification.to_rfc3339(),
+        "access": times.access.to_rfc3339(),
+    })
+}
+
+fn print_json(value: &Value) -> anyhow::Result<()> {
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    serde_json::to_writer_pretty(&mut stdout, value)?;
+    writeln!(stdout)?;
+    Ok(())
+}
@@ -0,0 +1,413 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Settings of a `BlockCache`.
+#[derive(Clone, Copy, Debug)]
+pub struct CacheConfig {
+    /// Size of a cached block in bytes.
+    /// This has to be a multiple of the sector size, because the inner reader may only accept sector-sized reads.
+    pub block_size: usize,
+    /// Maximum number of cached blocks.
+    pub capacity: usize,
+    /// Maximum number of blocks to read at once when reading sequentially, 1 disables read-ahead.
+    pub max_read_ahead: usize,
+}
+
+impl CacheConfig {
+    const DEFAULT_BLOCK_SIZE: usize = 64 * 1024;
+
+    /// Creates a configuration for a cache of `cache_size` bytes that reads up to `read_ahead` bytes at once.
+    pub fn new(cache_size: usize, read_ahead: usize) -> Self {
+        let block_size = Self::DEFAULT_BLOCK_SIZE;
+        let max_read_ahead = (read_ahead / block_size).max(1);
+        Self {
+            block_size,
+            // A read-ahead has to fit into the cache.
+            capacity: (cache_size / block_size).max(max_read_ahead),
+            max_read_ahead,
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self::new(64 << 20, 1 << 20)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CacheStatistics {
+    /// Number of reads served from the cache.
+    pub hits: u64,
+    /// Number of reads that had to go to the inner reader.
+    pub misses: u64,
+    /// Number of blocks that have been read ahead of a miss.
+    pub read_ahead_blocks: u64,
+    /// Number of bytes read from the inner reader.
+    pub bytes_read: u64,
+}
+
+impl CacheStatistics {
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+struct CachedBlock {
+    data: Vec<u8>,
+    last_used: u64,
+}
+
+/// `BlockCache` keeps recently read blocks of the inner reader in memory and evicts the least recently used ones.
+///
+/// When blocks are missed in sequential order, the cache reads ahead of the requested block, doubling the
+/// number of blocks on every sequential miss up to the configured maximum.
+/// This makes up for `SectorReader` rereading whole sectors for misaligned reads and for the `BufReader` on top
+/// being invalidated by every seek.
+///
+/// The cache assumes that the inner reader doesn't change, so it must only be used for reading.
+pub struct BlockCache<R>
+where
+    R: Read + Seek,
+{
+    inner: R,
+    config: CacheConfig,
+    blocks: HashMap<u64, CachedBlock>,
+    /// Block numbers ordered by their last use, to find the least recently used block.
+    lru: BTreeMap<u64, u64>,
+    /// Incremented on every use of a block.
+    clock: u64,
+    /// The block after the last block read from the inner reader, to detect sequential reads.
+    next_sequential_block: Option<u64>,
+    /// Number of blocks to read on the next miss.
+    read_ahead: usize,
+    statistics: CacheStatistics,
+    /// The current stream position as requested by the caller through `read` or `seek`.
+    stream_position: u64,
+}
+
+impl<R> BlockCache<R>
+where
+    R: Read + Seek,
+{
+    pub fn new(inner: R, config: CacheConfig) -> Self {
+        Self {
+            inner,
+            config,
+            blocks: HashMap::new(),
+            lru: BTreeMap::new(),
+            clock: 0,
+            next_sequential_block: None,
+            read_ahead: 1,
+            statistics: CacheStatistics::default(),
+            stream_position: 0,
+        }
+    }
+
+    pub fn statistics(&self) -> CacheStatistics {
+        self.statistics
+    }
+
+    /// Marks `block` as most recently used.
+    fn touch(&mut self, block: u64) {
+        if let Some(cached) = self.blocks.get_mut(&block) {
+            self.lru.remove(&cached.last_used);
+            self.clock += 1;
+            cached.last_used = self.clock;
+            self.lru.insert(self.clock, block);
+        }
+    }
+
+    fn insert(&mut self, block: u64, data: Vec<u8>) {
+        while self.blocks.len() >= self.config.capacity {
+            let (&last_used, &evicted) = match self.lru.iter().next() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            self.lru.remove(&last_used);
+            self.blocks.remove(&evicted);
+        }
+
+        self.clock += 1;
+        self.lru.insert(self.clock, block);
+        self.blocks.insert(
+            block,
+            CachedBlock {
+                data,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    /// Reads `block` from the inner reader, along with the following blocks if reading sequentially.
+    fn load(&mut self, block: u64) -> io::Result<()> {
+        self.read_ahead = if self.next_sequential_block == Some(block) {
+            (self.read_ahead * 2).min(self.config.max_read_ahead)
+        } else {
+            1
+        };
+
+        // Stop reading ahead at the first block that is already cached.
+        let mut count = 1;
+        while count < self.read_ahead && !self.blocks.contains_key(&(block + count as u64)) {
+            count += 1;
+        }
+
+        let block_size = self.config.block_size;
+        let mut buf = vec![0u8; count * block_size];
+        self.inner
+            .seek(SeekFrom::Start(block * block_size as u64))?;
+        let len = read_up_to(&mut self.inner, &mut buf)?;
+        self.statistics.bytes_read += len as u64;
+
+        // Insert the requested block last, so that it is the most recently used one.
+        let mut loaded = 0;
+        for i in (1..count).chain([0]) {
+            let start = i * block_size;
+            if i > 0 && start >= len {
+                continue;
+            }
+            let end = len.min(start + block_size);
+            self.insert(block + i as u64, buf[start..end].to_vec());
+            loaded += 1;
+        }
+
+        self.statistics.read_ahead_blocks += loaded - 1;
+        self.next_sequential_block = Some(block + count as u64);
+        Ok(())
+    }
+}
+
+/// Reads until `buf` is full or the reader ends and returns the number of bytes read.
+fn read_up_to<R>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize>
+where
+    R: Read,
+{
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+impl<R> Read for BlockCache<R>
+where
+    R: Read + Seek,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let block_size = self.config.block_size as u64;
+        let block = self.stream_position / block_size;
+        let offset = (self.stream_position % block_size) as usize;
+        if self.blocks.contains_key(&block) {
+            self.statistics.hits += 1;
+            self.touch(block);
+        } else {
+            self.statistics.misses += 1;
+            self.load(block)?;
+        }
+
+        // Only the last block of the inner reader can be shorter than the block size.
+        let data = &self.blocks[&block].data;
+        if offset >= data.len() {
+            return Ok(0);
+        }
+        let len = buf.len().min(data.len() - offset);
+        buf[..len].copy_from_slice(&data[offset..offset + len]);
+
+        self.stream_position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R> Seek for BlockCache<R>
+where
+    R: Read + Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => Some(self.inner.seek(SeekFrom::End(n))?),
+            SeekFrom::Current(n) => {
+                if n >= 0 {
+                    self.stream_position.checked_add(n as u64)
+                } else {
+                    self.stream_position.checked_sub(n.wrapping_neg() as u64)
+                }
+            }
+        };
+
+        match new_pos {
+            Some(n) => {
+                // The inner reader is only moved when a block has to be read.
+                self.stream_position = n;
+                Ok(self.stream_position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const BLOCK_SIZE: usize = 16;
+
+    /// An in-memory disk that records the position and length of every read.
+    struct CountingReader {
+        data: Cursor<Vec<u8>>,
+        reads: Vec<(u64, usize)>,
+    }
+
+    impl Read for CountingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads.push((self.data.position(), buf.len()));
+            self.data.read(buf)
+        }
+    }
+
+    impl Seek for CountingReader {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.data.seek(pos)
+        }
+    }
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn cache(len: usize, capacity: usize, max_read_ahead: usize) -> BlockCache<CountingReader> {
+        let inner = CountingReader {
+            data: Cursor::new(data(len)),
+            reads: Vec::new(),
+        };
+        let config = CacheConfig {
+            block_size: BLOCK_SIZE,
+            capacity,
+            max_read_ahead,
+        };
+        BlockCache::new(inner, config)
+    }
+
+    fn read_block(cache: &mut BlockCache<CountingReader>, block: u64) {
+        let mut buf = [0u8; BLOCK_SIZE];
+        cache
+            .seek(SeekFrom::Start(block * BLOCK_SIZE as u64))
+            .unwrap();
+        cache.read_exact(&mut buf).unwrap();
+    }
+
+    fn cached_blocks(cache: &BlockCache<CountingReader>) -> Vec<u64> {
+        let mut blocks = cache.blocks.keys().copied().collect::<Vec<u64>>();
+        blocks.sort_unstable();
+        blocks
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_block() {
+        let mut cache = cache(16 * BLOCK_SIZE, 3, 1);
+        for block in [0, 1, 2] {
+            read_block(&mut cache, block);
+        }
+        // Using block 0 again makes block 1 the least recently used one.
+        read_block(&mut cache, 0);
+        read_block(&mut cache, 3);
+        assert_eq!(cached_blocks(&cache), [0, 2, 3]);
+
+        read_block(&mut cache, 1);
+        assert_eq!(cached_blocks(&cache), [0, 1, 3]);
+    }
+
+    #[test]
+    fn read_ahead_doubles_and_resets() {
+        let mut cache = cache(64 * BLOCK_SIZE, 16, 4);
+        // Sequential misses read 1, 2, 4 and then at most 4 blocks at once.
+        for block in 0..15 {
+            read_block(&mut cache, block);
+        }
+        let lens = cache.inner.reads.iter().map(|&(_, len)| len / BLOCK_SIZE);
+        assert_eq!(lens.collect::<Vec<usize>>(), [1, 2, 4, 4, 4]);
+        assert_eq!(cache.statistics().read_ahead_blocks, 1 + 3 + 3 + 3);
+
+        // A jump starts over with a single block.
+        cache.inner.reads.clear();
+        read_block(&mut cache, 40);
+        read_block(&mut cache, 41);
+        assert_eq!(
+            cache.inner.reads,
+            [
+                (40 * BLOCK_SIZE as u64, BLOCK_SIZE),
+                (41 * BLOCK_SIZE as u64, 2 * BLOCK_SIZE)
+            ]
+        );
+
+        // Reading ahead stops at the first block that is already cached.
+        cache.inner.reads.clear();
+        for block in [33, 28, 29, 31] {
+            read_block(&mut cache, block);
+        }
+        let lens = cache.inner.reads.iter().map(|&(_, len)| len / BLOCK_SIZE);
+        assert_eq!(lens.collect::<Vec<usize>>(), [1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn statistics() {
+        let mut cache = cache(8 * BLOCK_SIZE, 8, 1);
+        assert_eq!(cache.statistics().hit_rate(), 0.0);
+        read_block(&mut cache, 0);
+        read_block(&mut cache, 0);
+        read_block(&mut cache, 0);
+        read_block(&mut cache, 5);
+
+        let statistics = cache.statistics();
+        assert_eq!(statistics.hits, 2);
+        assert_eq!(statistics.misses, 2);
+        assert_eq!(statistics.read_ahead_blocks, 0);
+        assert_eq!(statistics.bytes_read, 2 * BLOCK_SIZE as u64);
+        assert_eq!(statistics.hit_rate(), 0.5);
+    }
+
+    #[test]
+    fn reads_across_cached_and_uncached_blocks() {
+        let len = 5 * BLOCK_SIZE + 8;
+        let mut cache = cache(len, 8, 1);
+        read_block(&mut cache, 1);
+        read_block(&mut cache, 3);
+
+        let mut all = Vec::new();
+        cache.seek(SeekFrom::Start(4)).unwrap();
+        cache.read_to_end(&mut all).unwrap();
+        assert_eq!(all, data(len)[4..]);
+        // Blocks 1 and 3 were hits, as was block 5 once more for the end of the stream.
+        // Only blocks 0, 2, 4 and the short block 5 had to be read, so every byte was read once.
+        assert_eq!(cache.statistics().hits, 3);
+        assert_eq!(cache.statistics().misses, 2 + 4);
+        assert_eq!(cache.statistics().bytes_read, len as u64);
+
+        // Reading at the end, and beyond it, returns nothing.
+        let mut buf = [0u8; 4];
+        cache.seek(SeekFrom::End(0)).unwrap();
+        assert_eq!(cache.read(&mut buf).unwrap(), 0);
+        cache.seek(SeekFrom::Start(len as u64 + 100)).unwrap();
+        assert_eq!(cache.read(&mut buf).unwrap(), 0);
+    }
+}
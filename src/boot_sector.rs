@@ -0,0 +1,361 @@
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::partition::{Partition, PartitionScheme};
+
+/// Number of sectors read at once while scanning.
+const SCAN_CHUNK_SECTORS: u64 = 2048;
+
+/// The fields of an NTFS boot sector (BIOS Parameter Block) we need to locate a volume.
+#[derive(Debug, Clone)]
+pub struct BootSector {
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u32,
+    /// Number of sectors of the volume, not counting the backup boot sector in the last sector of the partition.
+    pub total_sectors: u64,
+    pub mft_lcn: u64,
+    pub mft_mirr_lcn: u64,
+    pub serial_number: u64,
+}
+
+impl BootSector {
+    /// Parses and validates an NTFS boot sector.
+    ///
+    /// Returns `None` if `sector` is not a plausible NTFS boot sector.
+    pub fn parse(sector: &[u8]) -> Option<Self> {
+        if sector.len() < 512 || !is_ntfs_boot_sector(sector) || sector[510..512] != [0x55, 0xaa] {
+            return None;
+        }
+
+        let bytes_per_sector = u16::from_le_bytes([sector[0x0b], sector[0x0c]]);
+        if !(256..=4096).contains(&bytes_per_sector) || !bytes_per_sector.is_power_of_two() {
+            return None;
+        }
+
+        // Values above 0x80 encode cluster sizes of more than 128 sectors as a negative power of two.
+        let sectors_per_cluster = match sector[0x0d] {
+            n @ 1..=0x80 => n as u32,
+            n @ 0xf4..=0xff => 1 << (256 - n as u32),
+            _ => return None,
+        };
+        if !sectors_per_cluster.is_power_of_two() {
+            return None;
+        }
+
+        let total_sectors = u64::from_le_bytes(sector[0x28..0x30].try_into().unwrap());
+        let mft_lcn = u64::from_le_bytes(sector[0x30..0x38].try_into().unwrap());
+        let mft_mirr_lcn = u64::from_le_bytes(sector[0x38..0x40].try_into().unwrap());
+        let total_clusters = total_sectors / sectors_per_cluster as u64;
+        if total_sectors == 0 || mft_lcn >= total_clusters || mft_mirr_lcn >= total_clusters {
+            return None;
+        }
+        if sector[0x40] == 0 {
+            return None;
+        }
+
+        Some(Self {
+            bytes_per_sector,
+            sectors_per_cluster,
+            total_sectors,
+            mft_lcn,
+            mft_mirr_lcn,
+            serial_number: u64::from_le_bytes(sector[0x48..0x50].try_into().unwrap()),
+        })
+    }
+
+    /// Size of the volume in bytes, not counting the backup boot sector.
+    pub fn volume_size(&self) -> u64 {
+        self.total_sectors * self.bytes_per_sector as u64
+    }
+}
+
+/// Checks for the "NTFS    " OEM ID at the start of a boot sector.
+pub fn is_ntfs_boot_sector(sector: &[u8]) -> bool {
+    sector.len() >= 11 && &sector[3..11] == b"NTFS    "
+}
+
+struct Candidate {
+    offset: u64,
+    boot_sector: BootSector,
+    /// Position and contents of the backup boot sector, if we found one for this volume.
+    backup: Option<(u64, Vec<u8>)>,
+    has_primary: bool,
+}
+
+impl Candidate {
+    fn is_confirmed(&self) -> bool {
+        self.has_primary && self.backup.is_some()
+    }
+
+    /// Positions of the sectors this candidate is derived from.
+    fn evidence_positions(&self) -> Vec<u64> {
+        let mut positions = Vec::new();
+        if self.has_primary {
+            positions.push(self.offset);
+        }
+        if let Some((position, _)) = &self.backup {
+            positions.push(*position);
+        }
+        positions
+    }
+}
+
+/// Walks the entire disk sector by sector and looks for NTFS boot sectors and backup boot sectors.
+///
+/// This finds volumes on disks whose partition table is missing or corrupted.
+/// A backup boot sector sits in the last sector of a volume, so its volume starts `total_sectors` before it.
+/// Volumes only found through their backup are returned with that backup as the replacement boot sector.
+///
+/// `progress` is called with the number of scanned bytes and candidates before every chunk.
+/// The scan stops early if it returns `false`.
+pub fn scan_for_ntfs_volumes<R, F>(
+    disk: &mut R,
+    sector_size: u64,
+    mut progress: F,
+) -> io::Result<Vec<Partition>>
+where
+    R: Read + Seek,
+    F: FnMut(u64, usize) -> bool,
+{
+    let mut candidates: Vec<Candidate> = Vec::new();
+    let mut buf = vec![0u8; (SCAN_CHUNK_SECTORS * sector_size) as usize];
+    let mut position = 0;
+
+    disk.seek(SeekFrom::Start(0))?;
+    while progress(
+        position,
+        candidates.iter().filter(|c| c.has_primary).count(),
+    ) {
+        let bytes_read = read_whole_sectors(disk, &mut buf, position, sector_size)?;
+        if bytes_read == 0 {
+            break;
+        }
+
+        for (i, sector) in buf[..bytes_read]
+            .chunks_exact(sector_size as usize)
+            .enumerate()
+        {
+            let boot_sector = match BootSector::parse(sector) {
+                Some(boot_sector) => boot_sector,
+                None => continue,
+            };
+            let sector_position = position + i as u64 * sector_size;
+
+            // Every boot sector may either be the first or the last sector of a volume.
+            // We record both interpretations and sort out the wrong one after the scan.
+            add_candidate(&mut candidates, sector_position, &boot_sector, None);
+            if let Some(offset) = sector_position.checked_sub(boot_sector.volume_size()) {
+                add_candidate(
+                    &mut candidates,
+                    offset,
+                    &boot_sector,
+                    Some((sector_position, sector.to_vec())),
+                );
+            }
+        }
+
+        position += bytes_read as u64;
+    }
+
+    // A volume with both boot sectors is certain, so any other interpretation of its sectors is wrong.
+    let confirmed_positions = candidates
+        .iter()
+        .filter(|candidate| candidate.is_confirmed())
+        .flat_map(|candidate| candidate.evidence_positions())
+        .collect::<Vec<u64>>();
+    let mut volumes = Vec::new();
+    for candidate in candidates {
+        if !candidate.is_confirmed() {
+            if candidate
+                .evidence_positions()
+                .iter()
+                .any(|position| confirmed_positions.contains(position))
+            {
+                continue;
+            }
+
+            // For the remaining single boot sectors, the $MFT has to be where the boot sector says.
+            if !has_mft_at(disk, &candidate)? {
+                continue;
+            }
+        }
+
+        volumes.push(candidate);
+    }
+
+    Ok(volumes
+        .into_iter()
+        .enumerate()
+        .map(|(i, candidate)| {
+            let found = match (candidate.has_primary, candidate.backup.is_some()) {
+                (true, true) => "boot sector and backup",
+                (true, false) => "boot sector only",
+                _ => "backup boot sector only",
+            };
+            let serial = candidate.boot_sector.serial_number;
+            Partition {
+                scheme: PartitionScheme::Scan,
+                number: i as u32 + 1,
+                type_description: format!(
+                    "NTFS {:04X}-{:04X} ({})",
+                    (serial >> 16) as u16,
+                    serial as u16,
+                    found
+                ),
+                name: String::new(),
+                offset: candidate.offset,
+                size: candidate.boot_sector.volume_size()
+                    + candidate.boot_sector.bytes_per_sector as u64,
+                is_ntfs: true,
+                replacement_boot_sector: if candidate.has_primary {
+                    None
+                } else {
+                    candidate.backup.map(|(_, sector)| sector)
+                },
+            }
+        })
+        .collect())
+}
+
+fn add_candidate(
+    candidates: &mut Vec<Candidate>,
+    offset: u64,
+    boot_sector: &BootSector,
+    backup: Option<(u64, Vec<u8>)>,
+) {
+    let existing = candidates.iter_mut().find(|candidate| {
+        candidate.offset == offset
+            && candidate.boot_sector.serial_number == boot_sector.serial_number
+    });
+
+    match (existing, backup) {
+        (Some(candidate), None) => candidate.has_primary = true,
+        (Some(candidate), Some(backup)) => candidate.backup = Some(backup),
+        (None, backup) => candidates.push(Candidate {
+            offset,
+            boot_sector: boot_sector.clone(),
+            has_primary: backup.is_none(),
+            backup,
+        }),
+    }
+}
+
+/// Checks for a "FILE" record signature at the position of the $MFT or its mirror.
+fn has_mft_at<R>(disk: &mut R, candidate: &Candidate) -> io::Result<bool>
+where
+    R: Read + Seek,
+{
+    let boot_sector = &candidate.boot_sector;
+    let cluster_size = boot_sector.sectors_per_cluster as u64 * boot_sector.bytes_per_sector as u64;
+    let mut sector = vec![0u8; boot_sector.bytes_per_sector as usize];
+
+    for lcn in [boot_sector.mft_lcn, boot_sector.mft_mirr_lcn] {
+        let position = candidate.offset + lcn * cluster_size;
+        let aligned_position = position / sector.len() as u64 * sector.len() as u64;
+        disk.seek(SeekFrom::Start(aligned_position))?;
+        match disk.read_exact(&mut sector) {
+            Ok(()) if &sector[0..4] == b"FILE" => return Ok(true),
+            Ok(()) => (),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => (),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(false)
+}
+
+/// Fills `buf` with as many whole sectors as are available at `position`.
+///
+/// Raw devices only allow sector-sized reads, so the final partial chunk is read sector by sector.
+fn read_whole_sectors<R>(
+    disk: &mut R,
+    buf: &mut [u8],
+    position: u64,
+    sector_size: u64,
+) -> io::Result<usize>
+where
+    R: Read + Seek,
+{
+    match disk.read_exact(buf) {
+        Ok(()) => return Ok(buf.len()),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => (),
+        Err(err) => return Err(err),
+    }
+
+    disk.seek(SeekFrom::Start(position))?;
+    let mut bytes_read = 0;
+    for sector in buf.chunks_exact_mut(sector_size as usize) {
+        match disk.read_exact(sector) {
+            Ok(()) => bytes_read += sector.len(),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(bytes_read)
+}
+
+/// `BootSectorOverlay` replaces the first bytes of its inner reader with a given boot sector.
+///
+/// This lets us open volumes whose primary boot sector is damaged through their backup boot sector.
+/// Without a replacement, all reads are passed through unchanged.
+pub struct BootSectorOverlay<R>
+where
+    R: Read + Seek,
+{
+    /// The inner reader stream.
+    inner: R,
+    /// The boot sector to return instead of the first bytes of `inner`.
+    replacement: Option<Vec<u8>>,
+    /// The current stream position.
+    stream_position: u64,
+}
+
+impl<R> BootSectorOverlay<R>
+where
+    R: Read + Seek,
+{
+    pub fn new(mut inner: R, replacement: Option<Vec<u8>>) -> io::Result<Self> {
+        let stream_position = inner.stream_position()?;
+
+        Ok(Self {
+            inner,
+            replacement,
+            stream_position,
+        })
+    }
+}
+
+impl<R> Read for BootSectorOverlay<R>
+where
+    R: Read + Seek,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(replacement) = &self.replacement {
+            if self.stream_position < replacement.len() as u64 {
+                let start = self.stream_position as usize;
+                let len = buf.len().min(replacement.len() - start);
+                buf[..len].copy_from_slice(&replacement[start..start + len]);
+
+                // Keep the inner reader in sync for the next read beyond the replacement.
+                self.stream_position += len as u64;
+                self.inner.seek(SeekFrom::Start(self.stream_position))?;
+                return Ok(len);
+            }
+        }
+
+        let bytes_read = self.inner.read(buf)?;
+        self.stream_position += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl<R> Seek for BootSectorOverlay<R>
+where
+    R: Read + Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.stream_position = self.inner.seek(pos)?;
+        Ok(self.stream_position)
+    }
+}
@@ -0,0 +1,475 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use bytesize::ByteSize;
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use ntfs::structured_values::NtfsFileAttributeFlags;
+use ntfs_explorer::hashes::FileHashes;
+use ntfs_explorer::timestomping::{anomalies, Anomaly};
+use ntfs_explorer::volume::DirEntry;
+use serde_json::{json, Value};
+
+use crate::{format_time, Findings};
+
+/// A column of the file list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Column {
+    Name,
+    RecordNumber,
+    SequenceNumber,
+    Size,
+    AllocatedSize,
+    Creation,
+    Modification,
+    MftRecordModification,
+    Access,
+    FileNameCreation,
+    FileNameModification,
+    FileNameMftRecordModification,
+    FileNameAccess,
+    Attributes,
+    Namespace,
+    Md5,
+    Sha1,
+    Sha256,
+    Timestomping,
+}
+
+/// All columns in their order in the file list.
+pub const COLUMNS: [Column; 19] = [
+    Column::Name,
+    Column::RecordNumber,
+    Column::SequenceNumber,
+    Column::Size,
+    Column::AllocatedSize,
+    Column::Creation,
+    Column::Modification,
+    Column::MftRecordModification,
+    Column::Access,
+    Column::FileNameCreation,
+    Column::FileNameModification,
+    Column::FileNameMftRecordModification,
+    Column::FileNameAccess,
+    Column::Attributes,
+    Column::Namespace,
+    Column::Md5,
+    Column::Sha1,
+    Column::Sha256,
+    Column::Timestomping,
+];
+
+/// Columns can't be made narrower than this, so that they can still be grabbed.
+const MIN_WIDTH: f32 = 20.0;
+
+impl Column {
+    /// Name of the column in the saved layout.
+    fn id(self) -> &'static str {
+        match self {
+            Column::Name => "name",
+            Column::RecordNumber => "record_number",
+            Column::SequenceNumber => "sequence_number",
+            Column::Size => "size",
+            Column::AllocatedSize => "allocated_size",
+            Column::Creation => "creation",
+            Column::Modification => "modification",
+            Column::MftRecordModification => "mft_record_modification",
+            Column::Access => "access",
+            Column::FileNameCreation => "file_name_creation",
+            Column::FileNameModification => "file_name_modification",
+            Column::FileNameMftRecordModification => "file_name_mft_record_modification",
+            Column::FileNameAccess => "file_name_access",
+            Column::Attributes => "attributes",
+            Column::Namespace => "namespace",
+            Column::Md5 => "md5",
+            Column::Sha1 => "sha1",
+            Column::Sha256 => "sha256",
+            Column::Timestomping => "timestomping",
+        }
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Column::Name => "Name",
+            Column::RecordNumber => "Record",
+            Column::SequenceNumber => "Seq.",
+            Column::Size => "Size",
+            Column::AllocatedSize => "Allocated",
+            Column::Creation => "Created",
+            Column::Modification => "Modified",
+            Column::MftRecordModification => "MFT modified",
+            Column::Access => "Accessed",
+            Column::FileNameCreation => "FN created",
+            Column::FileNameModification => "FN modified",
+            Column::FileNameMftRecordModification => "FN MFT modified",
+            Column::FileNameAccess => "FN accessed",
+            Column::Attributes => "Flags",
+            Column::Namespace => "Namespace",
+            Column::Md5 => "MD5",
+            Column::Sha1 => "SHA-1",
+            Column::Sha256 => "SHA-256",
+            Column::Timestomping => "Timestomping",
+        }
+    }
+
+    fn default_width(self) -> f32 {
+        match self {
+            Column::Name => 200.0,
+            Column::RecordNumber => 70.0,
+            Column::SequenceNumber => 40.0,
+            Column::Size | Column::AllocatedSize => 70.0,
+            Column::Attributes => 60.0,
+            Column::Namespace => 90.0,
+            Column::Md5 | Column::Sha1 | Column::Sha256 => 240.0,
+            Column::Timestomping => 160.0,
+            _ => 130.0,
+        }
+    }
+
+    fn visible_by_default(self) -> bool {
+        matches!(
+            self,
+            Column::Name | Column::Size | Column::Modification | Column::Attributes
+        )
+    }
+
+    /// Whether the column holds numbers, which are aligned to the right.
+    pub fn is_numeric(self) -> bool {
+        matches!(
+            self,
+            Column::RecordNumber | Column::SequenceNumber | Column::Size | Column::AllocatedSize
+        )
+    }
+
+    /// Whether the column holds warnings, which are highlighted.
+    pub fn is_warning(self) -> bool {
+        self == Column::Timestomping
+    }
+
+    fn is_hash(self) -> bool {
+        matches!(self, Column::Md5 | Column::Sha1 | Column::Sha256)
+    }
+
+    /// Formats the value of the column for a file, hash columns are empty until the file has been hashed and the
+    /// timestomping column until the creation time of the volume is known.
+    pub fn cell(self, entry: &DirEntry, findings: &Findings) -> String {
+        match self {
+            Column::Name => entry.name.clone(),
+            Column::RecordNumber => entry.record_number.to_string(),
+            Column::SequenceNumber => entry.sequence_number.to_string(),
+            Column::Size => ByteSize(entry.size).to_string(),
+            Column::AllocatedSize => ByteSize(entry.allocated_size).to_string(),
+            Column::Attributes => format_flags(entry.attributes),
+            Column::Namespace => format!("{:?}", entry.namespace),
+            Column::Md5 | Column::Sha1 | Column::Sha256 => self
+                .hash(entry, &findings.hashes)
+                .unwrap_or_default()
+                .to_owned(),
+            Column::Timestomping => timestomping(entry, findings)
+                .iter()
+                .map(|anomaly| anomaly.label())
+                .join(", "),
+            _ => format_time(self.time(entry).unwrap()),
+        }
+    }
+
+    fn hash<'h>(self, entry: &DirEntry, hashes: &'h HashMap<u64, FileHashes>) -> Option<&'h str> {
+        let hashes = hashes.get(&entry.record_number)?;
+        match self {
+            Column::Md5 => Some(&hashes.md5),
+            Column::Sha1 => Some(&hashes.sha1),
+            Column::Sha256 => Some(&hashes.sha256),
+            _ => None,
+        }
+    }
+
+    fn time(self, entry: &DirEntry) -> Option<DateTime<Utc>> {
+        match self {
+            Column::Creation => Some(entry.times.creation),
+            Column::Modification => Some(entry.times.modification),
+            Column::MftRecordModification => Some(entry.times.mft_record_modification),
+            Column::Access => Some(entry.times.access),
+            Column::FileNameCreation => Some(entry.file_name_times.creation),
+            Column::FileNameModification => Some(entry.file_name_times.modification),
+            Column::FileNameMftRecordModification => {
+                Some(entry.file_name_times.mft_record_modification)
+            }
+            Column::FileNameAccess => Some(entry.file_name_times.access),
+            _ => None,
+        }
+    }
+
+    /// Compares two files by the value of the column.
+    fn compare(self, a: &DirEntry, b: &DirEntry, findings: &Findings) -> Ordering {
+        let hashes = &findings.hashes;
+        match self {
+            Column::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            Column::RecordNumber => a.record_number.cmp(&b.record_number),
+            Column::SequenceNumber => a.sequence_number.cmp(&b.sequence_number),
+            Column::Size => a.size.cmp(&b.size),
+            Column::AllocatedSize => a.allocated_size.cmp(&b.allocated_size),
+            Column::Attributes => a.attributes.bits().cmp(&b.attributes.bits()),
+            Column::Namespace => (a.namespace as u8).cmp(&(b.namespace as u8)),
+            Column::Md5 | Column::Sha1 | Column::Sha256 => {
+                self.hash(a, hashes).cmp(&self.hash(b, hashes))
+            }
+            Column::Timestomping => timestomping(a, findings)
+                .len()
+                .cmp(&timestomping(b, findings).len()),
+            _ => self.time(a).cmp(&self.time(b)),
+        }
+    }
+}
+
+/// How the file list is sorted.
+#[derive(Clone, Copy, Debug)]
+pub struct SortOrder {
+    pub column: Column,
+    pub descending: bool,
+}
+
+impl SortOrder {
+    /// Sorts files by the column, keeping directories in front like Windows Explorer does.
+    pub fn sort(self, entries: &mut [DirEntry], findings: &Findings) {
+        entries.sort_by(|a, b| {
+            let ordering = self.column.compare(a, b, findings);
+            let ordering = if self.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+            b.is_directory.cmp(&a.is_directory).then(ordering)
+        });
+    }
+}
+
+/// Width and visibility of a column.
+#[derive(Clone, Copy, Debug)]
+pub struct ColumnState {
+    pub column: Column,
+    pub width: f32,
+    pub visible: bool,
+}
+
+/// The columns of the file list as the user arranged them, which is kept between sessions.
+#[derive(Clone, Debug)]
+pub struct ColumnLayout {
+    pub columns: Vec<ColumnState>,
+    pub sort: SortOrder,
+}
+
+impl Default for ColumnLayout {
+    fn default() -> Self {
+        Self {
+            columns: COLUMNS
+                .iter()
+                .map(|&column| ColumnState {
+                    column,
+                    width: column.default_width(),
+                    visible: column.visible_by_default(),
+                })
+                .collect(),
+            sort: SortOrder {
+                column: Column::Name,
+                descending: false,
+            },
+        }
+    }
+}
+
+impl ColumnLayout {
+    /// Loads the saved layout, or the default layout if there is none.
+    ///
+    /// Columns missing from the saved layout, like ones added in a newer version, keep their defaults.
+    pub fn load() -> Self {
+        let mut layout = Self::default();
+        let saved = match config_path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|s| serde_json::from_str::<Value>(&s).ok())
+        {
+            Some(saved) => saved,
+            None => return layout,
+        };
+
+        for state in &mut layout.columns {
+            let saved = &saved["columns"][state.column.id()];
+            if let Some(width) = saved["width"].as_f64() {
+                state.width = (width as f32).max(MIN_WIDTH);
+            }
+            if let Some(visible) = saved["visible"].as_bool() {
+                state.visible = visible;
+            }
+        }
+        if let Some(column) = COLUMNS
+            .iter()
+            .find(|column| saved["sort"]["column"].as_str() == Some(column.id()))
+        {
+            layout.sort.column = *column;
+        }
+        if let Some(descending) = saved["sort"]["descending"].as_bool() {
+            layout.sort.descending = descending;
+        }
+
+        layout
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let path = match config_path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let columns = self
+            .columns
+            .iter()
+            .map(|state| {
+                (
+                    state.column.id().to_owned(),
+                    json!({ "width": state.width, "visible": state.visible }),
+                )
+            })
+            .collect::<serde_json::Map<_, _>>();
+        let layout = json!({
+            "columns": columns,
+            "sort": {
+                "column": self.sort.column.id(),
+                "descending": self.sort.descending,
+            },
+        });
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(&layout)?)?;
+        Ok(())
+    }
+
+    pub fn resize(&mut self, index: usize, delta: f32) {
+        if let Some(state) = self.columns.get_mut(index) {
+            state.width = (state.width + delta).max(MIN_WIDTH);
+        }
+    }
+
+    pub fn toggle(&mut self, index: usize) {
+        // The name is needed to enter directories.
+        if let Some(state) = self.columns.get_mut(index) {
+            if state.column != Column::Name {
+                state.visible = !state.visible;
+            }
+        }
+    }
+
+    /// Shows the SHA-256 column, unless a hash column is already visible, so that hashes show up once computed.
+    pub fn show_hashes(&mut self) {
+        if self
+            .columns
+            .iter()
+            .any(|state| state.visible && state.column.is_hash())
+        {
+            return;
+        }
+        if let Some(state) = self
+            .columns
+            .iter_mut()
+            .find(|state| state.column == Column::Sha256)
+        {
+            state.visible = true;
+        }
+    }
+
+    /// Shows the timestomping column, so that the files of a timestomping report stand out.
+    pub fn show_timestomping(&mut self) {
+        if let Some(state) = self
+            .columns
+            .iter_mut()
+            .find(|state| state.column == Column::Timestomping)
+        {
+            state.visible = true;
+        }
+    }
+
+    /// Sorts by the column, or reverses the order if the files are already sorted by it.
+    pub fn sort_by(&mut self, index: usize) {
+        if let Some(state) = self.columns.get(index) {
+            if self.sort.column == state.column {
+                self.sort.descending = !self.sort.descending;
+            } else {
+                self.sort = SortOrder {
+                    column: state.column,
+                    descending: false,
+                };
+            }
+        }
+    }
+
+    /// Returns the index of the column the files are sorted by.
+    pub fn sort_index(&self) -> usize {
+        self.columns
+            .iter()
+            .position(|state| state.column == self.sort.column)
+            .unwrap_or_default()
+    }
+
+    /// Returns the horizontal position of each column, hidden ones taking no space.
+    pub fn offsets(&self) -> Vec<f32> {
+        let mut offset = 0.0;
+        self.columns
+            .iter()
+            .map(|state| {
+                let column_offset = offset;
+                if state.visible {
+                    offset += state.width;
+                }
+                column_offset
+            })
+            .collect()
+    }
+}
+
+/// Where the column layout is saved, following the conventions of each platform.
+fn config_path() -> Option<PathBuf> {
+    let config_dir = if cfg!(windows) {
+        PathBuf::from(env::var_os("APPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(env::var_os("HOME")?).join("Library/Application Support")
+    } else {
+        match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        }
+    };
+    Some(config_dir.join("ntfs-explorer").join("columns.json"))
+}
+
+/// Checks the timestamps of a file, if the creation time of the volume is known.
+fn timestomping(entry: &DirEntry, findings: &Findings) -> Vec<Anomaly> {
+    match &findings.lifetime {
+        Some(lifetime) => anomalies(&entry.times, entry.first_name_creation, lifetime),
+        None => vec![],
+    }
+}
+
+/// Formats file attributes as letters, like `attrib` and the Windows Explorer do.
+fn format_flags(flags: NtfsFileAttributeFlags) -> String {
+    const LETTERS: [(NtfsFileAttributeFlags, char); 11] = [
+        (NtfsFileAttributeFlags::READ_ONLY, 'R'),
+        (NtfsFileAttributeFlags::HIDDEN, 'H'),
+        (NtfsFileAttributeFlags::SYSTEM, 'S'),
+        (NtfsFileAttributeFlags::ARCHIVE, 'A'),
+        (NtfsFileAttributeFlags::TEMPORARY, 'T'),
+        (NtfsFileAttributeFlags::SPARSE_FILE, 'P'),
+        (NtfsFileAttributeFlags::REPARSE_POINT, 'L'),
+        (NtfsFileAttributeFlags::COMPRESSED, 'C'),
+        (NtfsFileAttributeFlags::OFFLINE, 'O'),
+        (NtfsFileAttributeFlags::NOT_CONTENT_INDEXED, 'I'),
+        (NtfsFileAttributeFlags::ENCRYPTED, 'E'),
+    ];
+    LETTERS
+        .iter()
+        .filter(|(flag, _)| flags.contains(*flag))
+        .map(|(_, letter)| letter)
+        .collect()
+}
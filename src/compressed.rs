@@ -0,0 +1,307 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Take};
+
+/// A part of a compressed image that can be decompressed independently of the others.
+pub struct Block {
+    pub compressed_offset: u64,
+    pub compressed_size: u64,
+    /// Position of the decompressed data in the image.
+    pub offset: u64,
+    /// Size of the decompressed data, `None` if it is only known after decompressing the block.
+    pub size: Option<u64>,
+}
+
+/// A compression format whose blocks can be decompressed by a stream decoder.
+pub trait BlockFormat: Send {
+    /// Starts decompressing block `index`, whose compressed data is provided by `data`.
+    fn decoder(&self, index: usize, data: Take<File>) -> io::Result<Box<dyn Read + Send>>;
+}
+
+/// `BlockReader` provides the decompressed contents of a compressed image as a seekable stream.
+///
+/// Each block is decompressed sequentially. Seeking forward within a block skips data, seeking backward restarts
+/// decompressing the block from its start, so random access is only fast for images with small blocks.
+pub struct BlockReader {
+    file: File,
+    format: Box<dyn BlockFormat>,
+    blocks: Vec<Block>,
+    /// Index of the block being decompressed, its decoder and the position of the decoder within the block.
+    current: Option<(usize, Box<dyn Read + Send>, u64)>,
+    /// The current stream position in the decompressed image.
+    stream_position: u64,
+}
+
+impl BlockReader {
+    /// Creates a reader for `blocks`, which have to be sorted and start at 0.
+    pub fn new(file: File, format: Box<dyn BlockFormat>, blocks: Vec<Block>) -> Self {
+        Self {
+            file,
+            format,
+            blocks,
+            current: None,
+            stream_position: 0,
+        }
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Returns the decompressed size of the image, if it is known without decompressing it.
+    pub fn known_size(&self) -> Option<u64> {
+        let last = self.blocks.last()?;
+        Some(last.offset + last.size?)
+    }
+
+    /// Returns the decompressed size of the image, decompressing the last block if its size is not known.
+    pub fn size(&mut self) -> io::Result<u64> {
+        if let Some(size) = self.known_size() {
+            return Ok(size);
+        }
+
+        let index = self.blocks.len() - 1;
+        let position = self.current_position_in(index);
+        let decoder = self
+            .decoder_at(index, position)?
+            .expect("decoder is already at the requested position");
+        let remaining = io::copy(decoder, &mut io::sink())?;
+        self.current = None;
+
+        let last = &mut self.blocks[index];
+        last.size = Some(position + remaining);
+        Ok(last.offset + position + remaining)
+    }
+
+    /// Returns the position of the current decoder within block `index`, or 0 if it decompresses another block.
+    fn current_position_in(&self, index: usize) -> u64 {
+        match &self.current {
+            Some((current_index, _, position)) if *current_index == index => *position,
+            _ => 0,
+        }
+    }
+
+    /// Returns a decoder for block `index` that has been advanced to `position` within the block.
+    ///
+    /// Returns `None` if the block ends before `position`.
+    fn decoder_at(
+        &mut self,
+        index: usize,
+        position: u64,
+    ) -> io::Result<Option<&mut Box<dyn Read + Send>>> {
+        let can_continue = matches!(&self.current, Some((current_index, _, current_position))
+            if *current_index == index && *current_position <= position);
+        if !can_continue {
+            let block = &self.blocks[index];
+            let mut data = self.file.try_clone()?;
+            data.seek(SeekFrom::Start(block.compressed_offset))?;
+            let decoder = self
+                .format
+                .decoder(index, data.take(block.compressed_size))?;
+            self.current = Some((index, decoder, 0));
+        }
+
+        let (_, decoder, current_position) = self.current.as_mut().unwrap();
+        let skip = position - *current_position;
+        let skipped = io::copy(&mut decoder.by_ref().take(skip), &mut io::sink())?;
+        *current_position += skipped;
+        if skipped < skip {
+            return Ok(None);
+        }
+
+        Ok(Some(decoder))
+    }
+}
+
+impl Read for BlockReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let index = self
+            .blocks
+            .partition_point(|block| block.offset <= self.stream_position)
+            - 1;
+        let block = &self.blocks[index];
+        let position = self.stream_position - block.offset;
+        let max_len = match block.size {
+            Some(size) if position >= size => return Ok(0),
+            Some(size) => buf
+                .len()
+                .min((size - position).try_into().unwrap_or(usize::MAX)),
+            None => buf.len(),
+        };
+        let expected_size = block.size;
+
+        let bytes_read = match self.decoder_at(index, position)? {
+            Some(decoder) => decoder.read(&mut buf[..max_len])?,
+            None => 0,
+        };
+        if bytes_read == 0 {
+            if expected_size.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "compressed block ends early",
+                ));
+            }
+            // We have reached the end of a block whose size was unknown.
+            return Ok(0);
+        }
+
+        self.current.as_mut().unwrap().2 += bytes_read as u64;
+        self.stream_position += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl Seek for BlockReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => {
+                let size = self.size()?;
+                if n >= 0 {
+                    size.checked_add(n as u64)
+                } else {
+                    size.checked_sub(n.wrapping_neg() as u64)
+                }
+            }
+            SeekFrom::Current(n) => {
+                if n >= 0 {
+                    self.stream_position.checked_add(n as u64)
+                } else {
+                    self.stream_position.checked_sub(n.wrapping_neg() as u64)
+                }
+            }
+        };
+
+        match new_pos {
+            Some(n) => {
+                self.stream_position = n;
+                Ok(self.stream_position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Blocks stored without compression, counting how often a block is decompressed from its start.
+    struct Stored(Arc<AtomicUsize>);
+
+    impl BlockFormat for Stored {
+        fn decoder(&self, _index: usize, data: Take<File>) -> io::Result<Box<dyn Read + Send>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(Box::new(data))
+        }
+    }
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// Opens `data` as blocks of the given sizes, the last one with an unknown size if `last_size_known` is false.
+    fn open(
+        name: &str,
+        data: &[u8],
+        sizes: &[u64],
+        last_size_known: bool,
+    ) -> (BlockReader, Arc<AtomicUsize>) {
+        let path = test_dir(name).join("image");
+        std::fs::write(&path, data).unwrap();
+
+        let mut blocks = Vec::new();
+        let mut offset = 0;
+        for (i, &size) in sizes.iter().enumerate() {
+            let is_last = i == sizes.len() - 1;
+            blocks.push(Block {
+                compressed_offset: offset,
+                compressed_size: size,
+                offset,
+                size: if is_last && !last_size_known {
+                    None
+                } else {
+                    Some(size)
+                },
+            });
+            offset += size;
+        }
+
+        let decoders = Arc::new(AtomicUsize::new(0));
+        let reader = BlockReader::new(
+            File::open(&path).unwrap(),
+            Box::new(Stored(decoders.clone())),
+            blocks,
+        );
+        (reader, decoders)
+    }
+
+    fn read_at(reader: &mut BlockReader, position: u64, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        reader.seek(SeekFrom::Start(position)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn seek_within_blocks() {
+        let data = data(3000);
+        let (mut reader, decoders) = open("compressed-seek", &data, &[1000, 2000], true);
+        assert_eq!(reader.known_size(), Some(3000));
+
+        // Seeking forward within a block continues with the same decoder.
+        assert_eq!(read_at(&mut reader, 1100, 100), data[1100..1200]);
+        assert_eq!(read_at(&mut reader, 1500, 100), data[1500..1600]);
+        assert_eq!(decoders.load(Ordering::SeqCst), 1);
+
+        // Seeking backward restarts the block.
+        assert_eq!(read_at(&mut reader, 1050, 100), data[1050..1150]);
+        assert_eq!(decoders.load(Ordering::SeqCst), 2);
+
+        // Reads stop at the end of a block and continue in the next one.
+        assert_eq!(read_at(&mut reader, 900, 200), data[900..1100]);
+        assert_eq!(decoders.load(Ordering::SeqCst), 4);
+
+        let mut end = Vec::new();
+        reader.seek(SeekFrom::Start(2900)).unwrap();
+        reader.read_to_end(&mut end).unwrap();
+        assert_eq!(end, data[2900..]);
+    }
+
+    #[test]
+    fn unknown_size_of_the_last_block() {
+        let data = data(3000);
+        let (mut reader, _) = open("compressed-size", &data, &[1000, 2000], false);
+        assert_eq!(reader.known_size(), None);
+
+        // Reading into the last block doesn't stop the size from being found out.
+        assert_eq!(read_at(&mut reader, 1500, 100), data[1500..1600]);
+        assert_eq!(reader.size().unwrap(), 3000);
+        assert_eq!(reader.known_size(), Some(3000));
+        assert_eq!(reader.seek(SeekFrom::End(-10)).unwrap(), 2990);
+        assert_eq!(read_at(&mut reader, 2990, 10), data[2990..]);
+    }
+
+    #[test]
+    fn block_ends_early() {
+        let data = data(1000);
+        // The index claims more data than the block holds.
+        let (mut reader, _) = open("compressed-early-end", &data, &[1000], true);
+        reader.blocks[0].size = Some(2000);
+
+        let mut buf = vec![0u8; 100];
+        reader.seek(SeekFrom::Start(1500)).unwrap();
+        let error = reader.read(&mut buf).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,796 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use itertools::Itertools;
+use ntfs::attribute_value::NtfsAttributeValue;
+use ntfs::{NtfsAttribute, NtfsAttributeType, NtfsFile, NtfsFileFlags, NtfsReadSeek};
+
+use crate::export::{host_file_name, unique_path};
+use crate::volume::{
+    best_file_name, data_runs, dir_entry, DirEntry, Volume, FIRST_USER_FILE_RECORD, MAX_PATH_DEPTH,
+    ROOT_DIRECTORY,
+};
+
+/// File record number of `$MFT`, whose `$BITMAP` attribute tells which file records are in use.
+const MFT_RECORD: u64 = 0;
+/// File record number of `$Bitmap`, which tells which clusters are in use.
+const BITMAP_RECORD: u64 = 6;
+/// How many file records are read between progress reports.
+const PROGRESS_INTERVAL: u64 = 1024;
+/// How much of a deleted file is read from the volume at once.
+const CHUNK_SIZE: u64 = 1 << 20;
+
+/// A file whose file record is no longer in use, but still holds its metadata.
+#[derive(Clone, Debug)]
+pub struct DeletedFile {
+    /// The file as it was, under its preferred name.
+    pub entry: DirEntry,
+    pub parent_record_number: u64,
+    /// The path the file had, rebuilt from the parent references of the file names.
+    /// It starts with `?` if the path could only be followed up to a directory whose file record has been reused.
+    pub path: String,
+    pub recoverability: Recoverability,
+}
+
+/// How much of the content of a deleted file can still be read, judging by whether its clusters have been allocated
+/// to other files since.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Recoverability {
+    /// The content is stored in the file record itself, or the file is empty.
+    Resident,
+    /// None of the clusters of the content are in use.
+    Intact,
+    /// Some of the clusters of the content are in use by other files.
+    Partial { reallocated: u64, clusters: u64 },
+    /// All clusters of the content are in use by other files.
+    Overwritten,
+    /// The data runs of the content couldn't be read, or are spread over several file records.
+    Unknown,
+}
+
+impl Recoverability {
+    /// Judges the data runs of a file by how many of their clusters are in use by other files. Sparse runs don't
+    /// count, they have no clusters.
+    fn of_runs(runs: &[RecoveredRun]) -> Self {
+        let clusters = runs
+            .iter()
+            .filter(|run| run.position.is_some())
+            .map(|run| run.clusters)
+            .sum::<u64>();
+        let reallocated = runs.iter().map(|run| run.reallocated).sum::<u64>();
+        match (clusters, reallocated) {
+            (0, _) => Recoverability::Resident,
+            (_, 0) => Recoverability::Intact,
+            (clusters, reallocated) if reallocated == clusters => Recoverability::Overwritten,
+            (clusters, reallocated) => Recoverability::Partial {
+                reallocated,
+                clusters,
+            },
+        }
+    }
+
+    pub fn description(self) -> String {
+        match self {
+            Recoverability::Resident => "good, stored in the file record".to_owned(),
+            Recoverability::Intact => "good".to_owned(),
+            Recoverability::Partial {
+                reallocated,
+                clusters,
+            } => format!(
+                "partial, {} of {} clusters reallocated",
+                reallocated, clusters
+            ),
+            Recoverability::Overwritten => "overwritten".to_owned(),
+            Recoverability::Unknown => "unknown".to_owned(),
+        }
+    }
+}
+
+/// A data run of a deleted file, and how much of it has been allocated to other files since.
+#[derive(Clone, Debug)]
+pub struct RecoveredRun {
+    /// Position of the run in the file.
+    pub offset: u64,
+    /// Byte position of the run in the volume, `None` for sparse runs.
+    pub position: Option<u64>,
+    pub clusters: u64,
+    /// Clusters of the run that are in use by other files, which are zero filled.
+    pub reallocated: u64,
+}
+
+/// What could be recovered of a deleted file.
+#[derive(Clone, Debug, Default)]
+pub struct RecoveryReport {
+    pub size: u64,
+    /// Whether the content was stored in the file record, which makes it complete.
+    pub resident: bool,
+    pub runs: Vec<RecoveredRun>,
+    /// Bytes written from the file record, sparse ranges or clusters that are still free. The rest was zero filled.
+    pub recovered: u64,
+    /// Whether recovery was stopped before the end of the file.
+    pub cancelled: bool,
+}
+
+impl RecoveryReport {
+    pub fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(writer, "Size: {} bytes", self.size)?;
+        if self.resident {
+            writeln!(writer, "Stored in the file record, recovered completely")?;
+            return Ok(());
+        }
+        writeln!(
+            writer,
+            "Recovered: {} bytes, {} bytes zero filled",
+            self.recovered,
+            self.size.saturating_sub(self.recovered)
+        )?;
+        if self.cancelled {
+            writeln!(writer, "Recovery was stopped before the end of the file")?;
+        }
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "{:>14}  {:>14}  {:>10}  {:>11}",
+            "file offset", "volume offset", "clusters", "reallocated"
+        )?;
+        for run in &self.runs {
+            let position = match run.position {
+                Some(position) => position.to_string(),
+                None => "sparse".to_owned(),
+            };
+            writeln!(
+                writer,
+                "{:>14}  {:>14}  {:>10}  {:>11}",
+                run.offset, position, run.clusters, run.reallocated
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A deleted file recovered into a folder on the host, or that failed to.
+#[derive(Clone, Debug)]
+pub struct RecoveredFile {
+    /// The original path of the file.
+    pub path: String,
+    pub target: PathBuf,
+    pub report: Result<RecoveryReport, String>,
+}
+
+/// What a recovery job did.
+#[derive(Clone, Debug, Default)]
+pub struct RecoveryLog {
+    pub files: Vec<RecoveredFile>,
+    /// Whether recovery was stopped before all files were recovered.
+    pub cancelled: bool,
+}
+
+impl RecoveryLog {
+    pub fn errors(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|file| file.report.is_err())
+            .count()
+    }
+
+    /// Returns the number of files that were recovered completely.
+    pub fn complete(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|file| {
+                matches!(&file.report, Ok(report) if !report.cancelled && report.recovered >= report.size)
+            })
+            .count()
+    }
+}
+
+/// A bitmap of `$MFT` or `$Bitmap`, with a bit for each file record or cluster that is set if it is in use.
+pub(crate) struct Bitmap(Vec<u8>);
+
+impl Bitmap {
+    /// Anything beyond the end of the bitmap counts as in use, as nothing can be said about it.
+    pub(crate) fn is_set(&self, index: u64) -> bool {
+        self.0
+            .get((index / 8) as usize)
+            .map_or(true, |byte| byte & (1 << (index % 8)) != 0)
+    }
+
+    pub(crate) fn count_set(&self, start: u64, count: u64) -> u64 {
+        (start..start + count)
+            .filter(|&index| self.is_set(index))
+            .count() as u64
+    }
+}
+
+/// A deleted file record along with the parent reference of its name, before the paths are known.
+struct Candidate {
+    entry: DirEntry,
+    parent_record_number: u64,
+    parent_sequence_number: u16,
+    recoverability: Recoverability,
+}
+
+impl<R> Volume<R>
+where
+    R: Read + Seek,
+{
+    /// Reads every file record of `$MFT` and returns the deleted files among them, those that are no longer in use
+    /// according to the record header or the `$MFT` bitmap but still have a name.
+    ///
+    /// `progress` is called with the number of file records read and their total every now and then, and stops the
+    /// scan by returning `false`.
+    pub fn scan_deleted_files<F>(&mut self, mut progress: F) -> anyhow::Result<Vec<DeletedFile>>
+    where
+        F: FnMut(u64, u64) -> bool,
+    {
+        let (record_count, mft_bitmap) = self.read_mft_bitmap()?;
+        self.load_cluster_bitmap()?;
+        let fs = &mut self.fs;
+        let cluster_bitmap = self.cluster_bitmap.as_ref().unwrap();
+        let cluster_size = self.ntfs.cluster_size() as u64;
+
+        // Paths can only be rebuilt once all deleted directories are known.
+        let mut candidates = vec![];
+        for record_number in FIRST_USER_FILE_RECORD..record_count {
+            if record_number % PROGRESS_INTERVAL == 0 && !progress(record_number, record_count) {
+                break;
+            }
+            // Records that were never used have no valid header.
+            let file = match self.ntfs.file(fs, record_number) {
+                Ok(file) => file,
+                Err(_) => continue,
+            };
+            if file.flags().contains(NtfsFileFlags::IN_USE) && mft_bitmap.is_set(record_number) {
+                continue;
+            }
+            // Extension records of files with many attributes have no name of their own.
+            let file_name = match best_file_name(fs, &file, None) {
+                Ok(file_name) => file_name,
+                Err(_) => continue,
+            };
+            let entry = match dir_entry(
+                fs,
+                &self.ntfs,
+                &file,
+                &file_name,
+                self.offset,
+                &self.bad_sectors,
+            ) {
+                Ok(entry) => entry,
+                Err(_) => continue,
+            };
+            let parent = file_name.parent_directory_reference();
+            candidates.push(Candidate {
+                entry,
+                parent_record_number: parent.file_record_number(),
+                parent_sequence_number: parent.sequence_number(),
+                recoverability: recoverability(fs, &file, cluster_bitmap, cluster_size),
+            });
+        }
+
+        let by_record = candidates
+            .iter()
+            .enumerate()
+            .map(|(i, candidate)| (candidate.entry.record_number, i))
+            .collect::<HashMap<_, _>>();
+        let mut deleted_files = vec![];
+        for candidate in &candidates {
+            let path = self.original_path(candidate, &candidates, &by_record);
+            deleted_files.push(DeletedFile {
+                entry: candidate.entry.clone(),
+                parent_record_number: candidate.parent_record_number,
+                path,
+                recoverability: candidate.recoverability,
+            });
+        }
+        Ok(deleted_files)
+    }
+
+    /// Recovers the unnamed data stream of a deleted file into `writer`, from its file record or its data runs.
+    ///
+    /// Clusters that have been allocated to other files since the file was deleted hold their data now, so they are
+    /// written as zeros rather than mixing someone else's content into the file.
+    /// `progress` is called with the number of bytes written so far, and stops recovering by returning `false`.
+    pub fn recover_deleted_file<F>(
+        &mut self,
+        record_number: u64,
+        writer: &mut dyn Write,
+        mut progress: F,
+    ) -> anyhow::Result<RecoveryReport>
+    where
+        F: FnMut(u64) -> bool,
+    {
+        self.load_cluster_bitmap()?;
+        let cluster_bitmap = self.cluster_bitmap.as_ref().unwrap();
+        let cluster_size = self.ntfs.cluster_size() as u64;
+        let fs = &mut self.fs;
+        let file = self.ntfs.file(fs, record_number)?;
+        let mut report = RecoveryReport {
+            resident: true,
+            ..RecoveryReport::default()
+        };
+        let data = match file.data(fs, "") {
+            Some(data) => data?,
+            None => return Ok(report),
+        };
+        let data = data.to_attribute();
+        report.size = data.value_length();
+
+        if data.is_resident() {
+            let content = read_value(fs, &data)?;
+            writer.write_all(&content)?;
+            report.recovered = content.len() as u64;
+            return Ok(report);
+        }
+        report.resident = false;
+        let value = match data.value(fs)? {
+            NtfsAttributeValue::NonResident(value) => value,
+            _ => bail!(
+                "the data runs of file record {:#x} are spread over several file records",
+                record_number
+            ),
+        };
+        let data_runs = value
+            .data_runs()
+            .map(|data_run| {
+                data_run.map(|data_run| (data_run.data_position(), data_run.allocated_size()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        report.runs = recovered_runs(&data_runs, cluster_bitmap, cluster_size);
+
+        // Whole clusters at a time, so that each can be checked against the bitmap.
+        let chunk_size = (CHUNK_SIZE / cluster_size).max(1) * cluster_size;
+        let mut buf = vec![0u8; chunk_size as usize];
+        let mut written = 0;
+        'runs: for run in &report.runs {
+            let run_size = run.clusters * cluster_size;
+            let mut run_offset = 0;
+            while run_offset < run_size && written < report.size {
+                if !progress(written) {
+                    report.cancelled = true;
+                    break 'runs;
+                }
+                let len = chunk_size
+                    .min(run_size - run_offset)
+                    .min(report.size - written);
+                let chunk = &mut buf[..len as usize];
+                match run.position {
+                    Some(position) => {
+                        fs.seek(SeekFrom::Start(position + run_offset))?;
+                        fs.read_exact(chunk)?;
+                        report.recovered += zero_fill_reallocated(
+                            chunk,
+                            position + run_offset,
+                            cluster_bitmap,
+                            cluster_size,
+                        );
+                    }
+                    // Sparse ranges are zeros in the original file as well.
+                    None => {
+                        chunk.fill(0);
+                        report.recovered += len;
+                    }
+                }
+                writer.write_all(chunk)?;
+                written += len;
+                run_offset += len;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Returns the number of file records in `$MFT` and the bitmap of those in use.
+    pub(crate) fn read_mft_bitmap(&mut self) -> anyhow::Result<(u64, Bitmap)> {
+        let fs = &mut self.fs;
+        let mft = self.ntfs.file(fs, MFT_RECORD)?;
+        let mft_size = match mft.data(fs, "") {
+            Some(data) => data?.to_attribute().value_length(),
+            None => bail!("$MFT has no data"),
+        };
+        let record_count = mft_size / self.ntfs.file_record_size() as u64;
+        Ok((record_count, mft_bitmap(fs, &mft)?))
+    }
+
+    /// Reads `$Bitmap` unless that has been done before.
+    fn load_cluster_bitmap(&mut self) -> anyhow::Result<()> {
+        if self.cluster_bitmap.is_none() {
+            let fs = &mut self.fs;
+            let bitmap = self.ntfs.file(fs, BITMAP_RECORD)?;
+            self.cluster_bitmap = Some(cluster_bitmap(fs, &bitmap)?);
+        }
+        Ok(())
+    }
+
+    /// Follows the parent references of a deleted file through deleted directories, until it reaches the root
+    /// directory or one that still exists.
+    fn original_path(
+        &mut self,
+        candidate: &Candidate,
+        candidates: &[Candidate],
+        by_record: &HashMap<u64, usize>,
+    ) -> String {
+        let mut components = vec![candidate.entry.name.as_str()];
+        let mut parent = (
+            candidate.parent_record_number,
+            candidate.parent_sequence_number,
+        );
+        while components.len() < MAX_PATH_DEPTH {
+            let (record_number, sequence_number) = parent;
+            if record_number == ROOT_DIRECTORY {
+                return format!(r"\{}", components.iter().rev().join(r"\"));
+            }
+
+            if let Some(&i) = by_record.get(&record_number) {
+                // Deleting a file increments the sequence number of its record.
+                let directory = &candidates[i];
+                let current = directory.entry.sequence_number;
+                if current != sequence_number && current != sequence_number.wrapping_add(1) {
+                    break;
+                }
+                components.push(&directory.entry.name);
+                parent = (
+                    directory.parent_record_number,
+                    directory.parent_sequence_number,
+                );
+                continue;
+            }
+
+            // Otherwise the directory may still be there, unless its record has been reused for another file.
+            let fs = &mut self.fs;
+            let exists = match self.ntfs.file(fs, record_number) {
+                Ok(file) => {
+                    file.flags().contains(NtfsFileFlags::IN_USE)
+                        && file.sequence_number() == sequence_number
+                }
+                Err(_) => false,
+            };
+            if let (true, Ok(path)) = (exists, self.path(record_number)) {
+                return format!(
+                    r"{}\{}",
+                    path.trim_end_matches('\\'),
+                    components.iter().rev().join(r"\")
+                );
+            }
+            break;
+        }
+        format!(r"?\{}", components.iter().rev().join(r"\"))
+    }
+}
+
+/// Reads the `$BITMAP` attribute of `$MFT`.
+fn mft_bitmap<T>(fs: &mut T, mft: &NtfsFile) -> anyhow::Result<Bitmap>
+where
+    T: Read + Seek,
+{
+    let mut attributes = mft.attributes();
+    while let Some(attribute) = attributes.next(fs) {
+        let attribute = attribute?.to_attribute();
+        if matches!(attribute.ty(), Ok(NtfsAttributeType::Bitmap)) {
+            return Ok(Bitmap(read_value(fs, &attribute)?));
+        }
+    }
+    bail!("$MFT has no $BITMAP attribute")
+}
+
+/// Recovers deleted files into `target_directory`, each along with a report named `<name>.recovery.txt`.
+///
+/// Deleted directories are skipped, the files they contained are deleted files of their own.
+/// `progress` is called with the original path of the file being recovered and the number of bytes written so far,
+/// and stops recovering by returning `false`.
+pub fn recover_files<R, F>(
+    volume: &mut Volume<R>,
+    files: &[DeletedFile],
+    target_directory: &Path,
+    mut progress: F,
+) -> anyhow::Result<RecoveryLog>
+where
+    R: Read + Seek,
+    F: FnMut(&str, u64) -> bool,
+{
+    std::fs::create_dir_all(target_directory)?;
+    let mut log = RecoveryLog::default();
+    let mut bytes = 0;
+    for file in files.iter().filter(|file| !file.entry.is_directory) {
+        let target = unique_path(&target_directory.join(host_file_name(&file.entry.name)));
+        let result = recover_file(volume, file, &target, |written| {
+            progress(&file.path, bytes + written)
+        });
+        let cancelled = matches!(&result, Ok(report) if report.cancelled);
+        if let Ok(report) = &result {
+            bytes += report.recovered;
+        }
+        log.files.push(RecoveredFile {
+            path: file.path.clone(),
+            target,
+            report: result.map_err(|err| format!("{:#}", err)),
+        });
+        if cancelled {
+            log.cancelled = true;
+            break;
+        }
+    }
+    Ok(log)
+}
+
+fn recover_file<R, F>(
+    volume: &mut Volume<R>,
+    file: &DeletedFile,
+    target: &Path,
+    progress: F,
+) -> anyhow::Result<RecoveryReport>
+where
+    R: Read + Seek,
+    F: FnMut(u64) -> bool,
+{
+    let mut writer = BufWriter::new(File::create(target)?);
+    let report = volume.recover_deleted_file(file.entry.record_number, &mut writer, progress)?;
+    writer.flush()?;
+
+    let mut report_name = target.file_name().unwrap_or_default().to_owned();
+    report_name.push(".recovery.txt");
+    let mut writer = BufWriter::new(File::create(unique_path(
+        &target.with_file_name(report_name),
+    ))?);
+    writeln!(writer, "Original path: {}", file.path)?;
+    writeln!(
+        writer,
+        "File record: {} (sequence number {})",
+        file.entry.record_number, file.entry.sequence_number
+    )?;
+    report.write(&mut writer)?;
+    writer.flush()?;
+    Ok(report)
+}
+
+/// Reads the content of `$Bitmap`.
+fn cluster_bitmap<T>(fs: &mut T, bitmap: &NtfsFile) -> anyhow::Result<Bitmap>
+where
+    T: Read + Seek,
+{
+    let data = match bitmap.data(fs, "") {
+        Some(data) => data?,
+        None => bail!("$Bitmap has no data"),
+    };
+    let data = data.to_attribute();
+    Ok(Bitmap(read_value(fs, &data)?))
+}
+
+fn read_value<T>(fs: &mut T, attribute: &NtfsAttribute) -> anyhow::Result<Vec<u8>>
+where
+    T: Read + Seek,
+{
+    let mut value = attribute.value(fs)?;
+    let mut buf = vec![0u8; attribute.value_length() as usize];
+    let mut len = 0;
+    while len < buf.len() {
+        let bytes_read = value.read(fs, &mut buf[len..])?;
+        if bytes_read == 0 {
+            break;
+        }
+        len += bytes_read;
+    }
+    buf.truncate(len);
+    Ok(buf)
+}
+
+/// Estimates how much of the unnamed data stream of a deleted file can be recovered.
+fn recoverability<T>(
+    fs: &mut T,
+    file: &NtfsFile,
+    cluster_bitmap: &Bitmap,
+    cluster_size: u64,
+) -> Recoverability
+where
+    T: Read + Seek,
+{
+    let data_runs = match data_runs(fs, file) {
+        Ok(Some(data_runs)) => data_runs,
+        Ok(None) | Err(_) => return Recoverability::Unknown,
+    };
+    let data_runs = data_runs
+        .into_iter()
+        .map(|(position, size)| (Some(position), size))
+        .collect_vec();
+    Recoverability::of_runs(&recovered_runs(&data_runs, cluster_bitmap, cluster_size))
+}
+
+/// Lays out the data runs of a file, given as their position in the volume (`None` for sparse runs) and allocated
+/// size, and counts the clusters of each that are in use according to `cluster_bitmap`.
+fn recovered_runs(
+    data_runs: &[(Option<u64>, u64)],
+    cluster_bitmap: &Bitmap,
+    cluster_size: u64,
+) -> Vec<RecoveredRun> {
+    let mut runs = vec![];
+    let mut offset = 0;
+    for &(position, size) in data_runs {
+        let clusters = size / cluster_size;
+        runs.push(RecoveredRun {
+            offset,
+            position,
+            clusters,
+            reallocated: position.map_or(0, |position| {
+                cluster_bitmap.count_set(position / cluster_size, clusters)
+            }),
+        });
+        offset += size;
+    }
+    runs
+}
+
+/// Zero fills the clusters of `chunk`, read from `position` in the volume, that are in use according to
+/// `cluster_bitmap`. Returns the number of bytes left as they were.
+fn zero_fill_reallocated(
+    chunk: &mut [u8],
+    position: u64,
+    cluster_bitmap: &Bitmap,
+    cluster_size: u64,
+) -> u64 {
+    let first_cluster = position / cluster_size;
+    let mut recovered = 0;
+    for (i, cluster) in chunk.chunks_mut(cluster_size as usize).enumerate() {
+        if cluster_bitmap.is_set(first_cluster + i as u64) {
+            cluster.fill(0);
+        } else {
+            recovered += cluster.len() as u64;
+        }
+    }
+    recovered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLUSTER_SIZE: u64 = 4096;
+
+    /// Clusters 2, 3 and 8 to 15 are in use, 16 to 23 are free and anything beyond is unknown.
+    fn cluster_bitmap() -> Bitmap {
+        Bitmap(vec![0b0000_1100, 0xff, 0])
+    }
+
+    #[test]
+    fn bitmap() {
+        let bitmap = cluster_bitmap();
+        assert!(!bitmap.is_set(0));
+        assert!(bitmap.is_set(2));
+        assert!(bitmap.is_set(15));
+        assert!(!bitmap.is_set(23));
+        assert!(bitmap.is_set(24));
+        assert!(bitmap.is_set(u64::MAX));
+        assert_eq!(bitmap.count_set(0, 8), 2);
+        assert_eq!(bitmap.count_set(20, 8), 4);
+    }
+
+    #[test]
+    fn recovered_runs_and_recoverability() {
+        let data_runs = [
+            (Some(0), 4 * CLUSTER_SIZE),
+            (None, 2 * CLUSTER_SIZE),
+            (Some(16 * CLUSTER_SIZE), 3 * CLUSTER_SIZE),
+        ];
+        let runs = recovered_runs(&data_runs, &cluster_bitmap(), CLUSTER_SIZE);
+        let layout = runs
+            .iter()
+            .map(|run| (run.offset, run.position, run.clusters, run.reallocated))
+            .collect_vec();
+        assert_eq!(
+            layout,
+            [
+                (0, Some(0), 4, 2),
+                (4 * CLUSTER_SIZE, None, 2, 0),
+                (6 * CLUSTER_SIZE, Some(16 * CLUSTER_SIZE), 3, 0)
+            ]
+        );
+        assert_eq!(
+            Recoverability::of_runs(&runs),
+            Recoverability::Partial {
+                reallocated: 2,
+                clusters: 7
+            }
+        );
+
+        let recoverability = |data_runs: &[(Option<u64>, u64)]| {
+            Recoverability::of_runs(&recovered_runs(data_runs, &cluster_bitmap(), CLUSTER_SIZE))
+        };
+        assert_eq!(
+            recoverability(&[(Some(16 * CLUSTER_SIZE), 8 * CLUSTER_SIZE)]),
+            Recoverability::Intact
+        );
+        assert_eq!(
+            recoverability(&[(Some(8 * CLUSTER_SIZE), 8 * CLUSTER_SIZE)]),
+            Recoverability::Overwritten
+        );
+        assert_eq!(
+            recoverability(&[(Some(20 * CLUSTER_SIZE), 8 * CLUSTER_SIZE)]),
+            Recoverability::Partial {
+                reallocated: 4,
+                clusters: 8
+            }
+        );
+        assert_eq!(
+            recoverability(&[(None, CLUSTER_SIZE)]),
+            Recoverability::Resident
+        );
+        assert_eq!(recoverability(&[]), Recoverability::Resident);
+    }
+
+    #[test]
+    fn zero_fill() {
+        // Clusters 0 to 3, the last one only partly.
+        let mut chunk = vec![0xaa; 3 * CLUSTER_SIZE as usize + 100];
+        let recovered = zero_fill_reallocated(&mut chunk, 0, &cluster_bitmap(), CLUSTER_SIZE);
+        assert_eq!(recovered, 2 * CLUSTER_SIZE);
+        let cluster_size = CLUSTER_SIZE as usize;
+        assert!(chunk[..2 * cluster_size].iter().all(|&b| b == 0xaa));
+        assert!(chunk[2 * cluster_size..].iter().all(|&b| b == 0));
+
+        // Clusters 22 to 25, the last two beyond the end of the bitmap.
+        let mut chunk = vec![0xaa; 3 * cluster_size + 100];
+        let recovered = zero_fill_reallocated(
+            &mut chunk,
+            22 * CLUSTER_SIZE,
+            &cluster_bitmap(),
+            CLUSTER_SIZE,
+        );
+        assert_eq!(recovered, 2 * CLUSTER_SIZE);
+        assert!(chunk[..2 * cluster_size].iter().all(|&b| b == 0xaa));
+        assert!(chunk[2 * cluster_size..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn report() {
+        let report = RecoveryReport {
+            size: 10000,
+            resident: false,
+            runs: vec![
+                RecoveredRun {
+                    offset: 0,
+                    position: Some(8192),
+                    clusters: 2,
+                    reallocated: 1,
+                },
+                RecoveredRun {
+                    offset: 8192,
+                    position: None,
+                    clusters: 1,
+                    reallocated: 0,
+                },
+            ],
+            recovered: 5904,
+            cancelled: true,
+        };
+        let mut output = vec![];
+        report.write(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            [
+                "Size: 10000 bytes",
+                "Recovered: 5904 bytes, 4096 bytes zero filled",
+                "Recovery was stopped before the end of the file",
+                "",
+                "   file offset   volume offset    clusters  reallocated",
+                "             0            8192           2            1",
+                "          8192          sparse           1            0",
+                "",
+            ]
+            .join("\n")
+        );
+
+        let report = RecoveryReport {
+            size: 100,
+            resident: true,
+            recovered: 100,
+            ..RecoveryReport::default()
+        };
+        let mut output = vec![];
+        report.write(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Size: 100 bytes\nStored in the file record, recovered completely\n"
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use bytesize::ByteSize;
+
+use crate::bad_sectors::{BadSectors, SharedBadSectors};
+use crate::block_cache::CacheConfig;
+use crate::boot_sector::scan_for_ntfs_volumes;
+use crate::image::{open_image, Image, ReadSeek};
+use crate::partition::{
+    detect_sector_size, read_partitions, Partition, SectorSizeSource, DEFAULT_SECTOR_SIZE,
+};
+use crate::sector_reader::{BadSectorTolerance, SectorReader};
+use crate::volume::Volume;
+
+/// How to read a disk.
+#[derive(Clone, Debug, Default)]
+pub struct DiskOptions {
+    /// Fill pattern for unreadable sectors, `None` to fail on them.
+    pub bad_sector_fill: Option<Vec<u8>>,
+    /// ddrescue mapfile of the image, its unfinished areas are known bad sectors.
+    pub mapfile: Option<PathBuf>,
+    /// Sector size to use instead of detecting it.
+    pub sector_size: Option<u64>,
+}
+
+/// Parses a sector size given by the user, a power of two between 512 and 4096.
+pub fn parse_sector_size(s: &str) -> Result<u64, String> {
+    let sector_size = s.parse::<u64>().map_err(|e| e.to_string())?;
+    if (512..=4096).contains(&sector_size) && sector_size.is_power_of_two() {
+        Ok(sector_size)
+    } else {
+        Err("expected a power of two between 512 and 4096".to_owned())
+    }
+}
+
+/// Parses a fill pattern for bad sectors given as hex digits, like `00` or `deadbeef`.
+pub fn parse_fill_pattern(s: &str) -> Result<Vec<u8>, String> {
+    if s.is_empty() || s.len() % 2 != 0 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err("expected an even number of hex digits".to_owned());
+    }
+    Ok((0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect())
+}
+
+/// A disk in an image or raw device, along with what we know about it.
+pub struct Disk {
+    reader: Box<dyn ReadSeek>,
+    /// Details about the image, as pairs of section headline and name/value pairs.
+    pub properties: Vec<(String, Vec<(String, String)>)>,
+    pub sector_size: u64,
+    pub sector_size_source: SectorSizeSource,
+    /// Unreadable ranges of the disk, only filled when reading with a `bad_sector_fill`.
+    pub bad_sectors: SharedBadSectors,
+}
+
+impl Disk {
+    /// Opens an image or raw device, see `open_image`.
+    pub fn open(
+        path: &Path,
+        options: &DiskOptions,
+        progress: &mut dyn FnMut(String),
+    ) -> io::Result<Self> {
+        let Image {
+            reader: mut image,
+            mut properties,
+            sector_size: device_sector_size,
+        } = open_image(path, progress)?;
+
+        // Bad sectors are tracked for the whole disk, so that the ranges match up with a ddrescue mapfile.
+        let bad_sectors = match &options.mapfile {
+            Some(mapfile) => {
+                let bad_sectors = BadSectors::from_ddrescue_mapfile(mapfile)?;
+                properties.push((
+                    "Bad sectors".to_owned(),
+                    vec![
+                        ("Mapfile".to_owned(), mapfile.display().to_string()),
+                        (
+                            "Unread areas".to_owned(),
+                            bad_sectors.range_count().to_string(),
+                        ),
+                        (
+                            "Unread size".to_owned(),
+                            ByteSize(bad_sectors.total_size()).to_string(),
+                        ),
+                    ],
+                ));
+                bad_sectors
+            }
+            None => BadSectors::new(),
+        };
+        let bad_sectors = Arc::new(Mutex::new(bad_sectors));
+
+        let (sector_size, sector_size_source) = match options.sector_size {
+            Some(sector_size) => (sector_size, SectorSizeSource::Override),
+            None => match device_sector_size {
+                Some(sector_size) => (sector_size, SectorSizeSource::Device),
+                None => {
+                    detect_sector_size(&mut detection_reader(&mut image, options, &bad_sectors)?)?
+                        .unwrap_or((DEFAULT_SECTOR_SIZE, SectorSizeSource::Default))
+                }
+            },
+        };
+        properties[0].1.push((
+            "Sector size".to_owned(),
+            format!(
+                "{} bytes ({})",
+                sector_size,
+                sector_size_source.description()
+            ),
+        ));
+
+        if let Some(fill_pattern) = &options.bad_sector_fill {
+            let mut reader = SectorReader::new(image, sector_size as usize)?;
+            reader.tolerate_bad_sectors(BadSectorTolerance {
+                fill_pattern: fill_pattern.clone(),
+                bad_sectors: bad_sectors.clone(),
+            });
+            image = Box::new(reader);
+        }
+
+        Ok(Self {
+            reader: image,
+            properties,
+            sector_size,
+            sector_size_source,
+            bad_sectors,
+        })
+    }
+
+    /// Reads the partition table, see `read_partitions`.
+    pub fn partitions(&mut self) -> io::Result<Vec<Partition>> {
+        let sector_size = self.sector_size;
+        read_partitions(&mut self.sector_reader()?, sector_size)
+    }
+
+    /// Scans the disk for NTFS boot sectors, see `scan_for_ntfs_volumes`.
+    pub fn scan_for_ntfs_volumes<F>(&mut self, progress: F) -> io::Result<Vec<Partition>>
+    where
+        F: FnMut(u64, usize) -> bool,
+    {
+        let sector_size = self.sector_size;
+        scan_for_ntfs_volumes(&mut self.sector_reader()?, sector_size, progress)
+    }
+
+    /// Opens the NTFS volume in `partition`, or the entire disk as a single volume if no `partition` is given.
+    pub fn open_volume(
+        &mut self,
+        partition: Option<&Partition>,
+        cache_config: CacheConfig,
+    ) -> anyhow::Result<Volume<&mut dyn ReadSeek>> {
+        Volume::open(
+            self.reader.as_mut() as &mut dyn ReadSeek,
+            partition,
+            self.sector_size,
+            self.sector_size_source,
+            self.bad_sectors.clone(),
+            cache_config,
+        )
+    }
+
+    /// Like `open_volume`, but the volume takes over the disk.
+    pub fn into_volume(
+        self,
+        partition: Option<&Partition>,
+        cache_config: CacheConfig,
+    ) -> anyhow::Result<Volume<Box<dyn ReadSeek>>> {
+        Volume::open(
+            self.reader,
+            partition,
+            self.sector_size,
+            self.sector_size_source,
+            self.bad_sectors,
+            cache_config,
+        )
+    }
+
+    fn sector_reader(&mut self) -> io::Result<SectorReader<&mut Box<dyn ReadSeek>>> {
+        SectorReader::new(&mut self.reader, self.sector_size as usize)
+    }
+}
+
+/// Returns a reader for detecting the sector size, before the disk is wrapped in a `SectorReader` of that size.
+///
+/// Reading with a `bad_sector_fill` must not fail on a bad sector at the start of the disk, so the reader then
+/// tolerates bad sectors in units of 512 bytes, the smallest common sector size.
+/// It works on a copy of the known bad sectors, because its ranges would be too fine for 4096 byte sectors.
+fn detection_reader<'a>(
+    image: &'a mut Box<dyn ReadSeek>,
+    options: &DiskOptions,
+    bad_sectors: &SharedBadSectors,
+) -> io::Result<SectorReader<&'a mut Box<dyn ReadSeek>>> {
+    let fill_pattern = match &options.bad_sector_fill {
+        Some(fill_pattern) => fill_pattern,
+        None => return SectorReader::new(image, 4096),
+    };
+
+    let mut reader = SectorReader::new(image, DEFAULT_SECTOR_SIZE as usize)?;
+    reader.tolerate_bad_sectors(BadSectorTolerance {
+        fill_pattern: fill_pattern.clone(),
+        bad_sectors: Arc::new(Mutex::new(bad_sectors.lock().unwrap().clone())),
+    });
+    Ok(reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read, Seek, SeekFrom};
+
+    /// A disk with 512 byte sectors whose first sector cannot be read.
+    struct FailingDisk(Cursor<Vec<u8>>);
+
+    impl Read for FailingDisk {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.position() < 512 {
+                return Err(io::Error::new(io::ErrorKind::Other, "bad sector"));
+            }
+            self.0.read(buf)
+        }
+    }
+
+    impl Seek for FailingDisk {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.0.seek(pos)
+        }
+    }
+
+    fn gpt_disk() -> Box<dyn ReadSeek> {
+        let mut disk = vec![0u8; 64 * 1024];
+        disk[512..520].copy_from_slice(b"EFI PART");
+        Box::new(FailingDisk(Cursor::new(disk)))
+    }
+
+    #[test]
+    fn sector_sizes() {
+        assert_eq!(parse_sector_size("512"), Ok(512));
+        assert_eq!(parse_sector_size("4096"), Ok(4096));
+        for invalid in ["256", "513", "8192", "0", "-512", "4k"] {
+            assert!(parse_sector_size(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn detection_tolerates_bad_sectors() {
+        let bad_sectors = Arc::new(Mutex::new(BadSectors::new()));
+        let options = DiskOptions {
+            bad_sector_fill: Some(vec![0]),
+            ..Default::default()
+        };
+
+        let mut image = gpt_disk();
+        let mut reader = detection_reader(&mut image, &options, &bad_sectors).unwrap();
+        assert_eq!(
+            detect_sector_size(&mut reader).unwrap(),
+            Some((512, SectorSizeSource::Gpt))
+        );
+        // The bad sectors found while probing are not kept, they may be finer than the real sectors.
+        assert!(bad_sectors.lock().unwrap().is_empty());
+
+        let mut image = gpt_disk();
+        let mut reader =
+            detection_reader(&mut image, &DiskOptions::default(), &bad_sectors).unwrap();
+        assert!(detect_sector_size(&mut reader).is_err());
+    }
+}
@@ -0,0 +1,705 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use flate2::read::ZlibDecoder;
+
+/// Signature of an EWF-E01 segment file (EnCase 1 to 6).
+pub const EWF_SIGNATURE: [u8; 8] = *b"EVF\x09\x0d\x0a\xff\x00";
+/// Signature of an EWF2-Ex01 segment file (EnCase 7 and later).
+///
+/// Ex01 images use a different section layout and are recognized only to reject them with a clear error.
+pub const EWF2_SIGNATURE: [u8; 8] = *b"EVF2\x0d\x0a\x81\x00";
+
+/// Size of the file header at the start of every segment file.
+const FILE_HEADER_SIZE: u64 = 13;
+/// Size of the descriptor in front of every section.
+const SECTION_DESCRIPTOR_SIZE: u64 = 76;
+/// Size of the header of a table section, in front of the chunk offsets.
+const TABLE_HEADER_SIZE: u64 = 24;
+/// Upper limit for the number of segment files, which is the number of extensions from E01 to ZZZ.
+const MAX_SEGMENTS: u32 = 14971;
+/// Upper limit for the number of entries of a single table section, to not allocate absurd amounts of memory.
+const MAX_TABLE_ENTRIES: u32 = 1 << 24;
+/// Size of the Adler-32 checksum that follows the data of an uncompressed chunk.
+const CHECKSUM_SIZE: u64 = 4;
+/// Upper limit for the size of a chunk. EnCase writes 32 KiB chunks and allows at most 32768 sectors per chunk.
+const MAX_CHUNK_SIZE: u64 = 16 << 20;
+
+struct Chunk {
+    /// Index of the segment file containing the chunk.
+    segment: usize,
+    /// Position of the chunk data in the segment file.
+    offset: u64,
+    /// Size of the chunk data in the segment file.
+    size: u64,
+    /// Whether the chunk is zlib-compressed. Uncompressed chunks are followed by an Adler-32 checksum.
+    compressed: bool,
+}
+
+/// Acquisition details stored in an EWF image.
+#[derive(Debug, Clone, Default)]
+pub struct EwfMetadata {
+    /// Case metadata from the header section, as pairs of human-readable name and value.
+    pub header: Vec<(String, String)>,
+    /// MD5 hash of the acquired media, as computed during acquisition.
+    pub md5: Option<[u8; 16]>,
+    /// SHA-1 hash of the acquired media, as computed during acquisition.
+    pub sha1: Option<[u8; 20]>,
+    pub bytes_per_sector: u32,
+    pub sector_count: u64,
+    pub segment_count: usize,
+}
+
+/// `EwfReader` provides the acquired media of an Expert Witness Format (EWF-E01) image as a seekable stream.
+///
+/// Consecutive segment files (`.E01`, `.E02`, ..., `.E99`, `.EAA`, ...) are opened automatically.
+/// The media is stored in chunks of usually 32 KiB, which may be zlib-compressed.
+/// The most recently read chunk is kept decompressed, because reads of the same chunk usually come in bursts.
+///
+/// EWF2 (Ex01) images are not supported.
+pub struct EwfReader {
+    segments: Vec<File>,
+    /// Length of each segment file, which bounds everything read from it.
+    segment_lengths: Vec<u64>,
+    chunks: Vec<Chunk>,
+    chunk_size: u64,
+    media_size: u64,
+    /// The current stream position in the acquired media.
+    stream_position: u64,
+    /// Index and data of the most recently read chunk.
+    cached_chunk: Option<(usize, Vec<u8>)>,
+    metadata: EwfMetadata,
+}
+
+impl EwfReader {
+    /// Opens an EWF image, given the path to its first segment file.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut reader = Self {
+            segments: Vec::new(),
+            segment_lengths: Vec::new(),
+            chunks: Vec::new(),
+            chunk_size: 0,
+            media_size: 0,
+            stream_position: 0,
+            cached_chunk: None,
+            metadata: EwfMetadata::default(),
+        };
+
+        for number in 1..=MAX_SEGMENTS {
+            let segment_path = segment_path(path, number);
+            if number > 1 && !segment_path.exists() {
+                break;
+            }
+
+            let file = File::open(&segment_path)?;
+            reader.segment_lengths.push(file.metadata()?.len());
+            reader.segments.push(file);
+            let done = reader.read_segment(reader.segments.len() - 1)?;
+            if done {
+                break;
+            }
+        }
+
+        if reader.chunk_size == 0 {
+            return Err(invalid_data("EWF image has no volume section"));
+        }
+        reader.metadata.segment_count = reader.segments.len();
+
+        Ok(reader)
+    }
+
+    pub fn metadata(&self) -> &EwfMetadata {
+        &self.metadata
+    }
+
+    /// Size of the acquired media in bytes.
+    pub fn media_size(&self) -> u64 {
+        self.media_size
+    }
+
+    /// Reads all sections of a segment file.
+    ///
+    /// Returns whether this segment is the last one.
+    fn read_segment(&mut self, segment: usize) -> io::Result<bool> {
+        let file = &mut self.segments[segment];
+        let mut file_header = [0u8; FILE_HEADER_SIZE as usize];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut file_header)?;
+        if file_header[0..8] == EWF2_SIGNATURE {
+            return Err(invalid_data("EWF2 (Ex01) images are not supported yet"));
+        }
+        if file_header[0..8] != EWF_SIGNATURE {
+            return Err(invalid_data("not an EWF segment file"));
+        }
+
+        // The data of the most recent sectors section, which tells us where the last chunk of a table ends.
+        let mut sectors_end = None;
+        let mut section_offset = FILE_HEADER_SIZE;
+        loop {
+            let mut descriptor = [0u8; SECTION_DESCRIPTOR_SIZE as usize];
+            self.segments[segment].seek(SeekFrom::Start(section_offset))?;
+            self.segments[segment].read_exact(&mut descriptor)?;
+
+            let section_type = descriptor[0..16]
+                .iter()
+                .take_while(|&&b| b != 0)
+                .map(|&b| b as char)
+                .collect::<String>();
+            let next_offset = u64::from_le_bytes(descriptor[16..24].try_into().unwrap());
+            let section_size = u64::from_le_bytes(descriptor[24..32].try_into().unwrap());
+            let data_offset = section_offset + SECTION_DESCRIPTOR_SIZE;
+            let data_size = section_size.saturating_sub(SECTION_DESCRIPTOR_SIZE);
+
+            match section_type.as_str() {
+                "header" | "header2" if self.metadata.header.is_empty() => {
+                    let data = self.read_section_data(segment, data_offset, data_size)?;
+                    self.metadata.header = parse_header_section(&data)?;
+                }
+                "volume" | "disk" if self.chunk_size == 0 => {
+                    let data = self.read_section_data(segment, data_offset, 24)?;
+                    let sectors_per_chunk = u32::from_le_bytes(data[8..12].try_into().unwrap());
+                    let bytes_per_sector = u32::from_le_bytes(data[12..16].try_into().unwrap());
+                    let sector_count = u64::from_le_bytes(data[16..24].try_into().unwrap());
+
+                    if !(512..=4096).contains(&bytes_per_sector)
+                        || !bytes_per_sector.is_power_of_two()
+                    {
+                        return Err(invalid_data(
+                            "EWF volume section has an invalid sector size",
+                        ));
+                    }
+                    self.chunk_size = sectors_per_chunk as u64 * bytes_per_sector as u64;
+                    if self.chunk_size == 0 || self.chunk_size > MAX_CHUNK_SIZE {
+                        return Err(invalid_data("EWF volume section has an invalid chunk size"));
+                    }
+                    self.media_size = sector_count
+                        .checked_mul(bytes_per_sector as u64)
+                        .ok_or_else(|| {
+                            invalid_data("EWF volume section has an invalid sector count")
+                        })?;
+                    self.metadata.bytes_per_sector = bytes_per_sector;
+                    self.metadata.sector_count = sector_count;
+                }
+                "sectors" => sectors_end = Some(section_offset + section_size),
+                "table" => {
+                    // The chunks of a table directly precede it, unless the segment told us otherwise.
+                    let chunks_end = sectors_end.unwrap_or(section_offset);
+                    self.read_table(segment, data_offset, chunks_end)?;
+                }
+                "hash" => {
+                    let data = self.read_section_data(segment, data_offset, 16)?;
+                    self.metadata.md5 = Some(data[0..16].try_into().unwrap());
+                }
+                "digest" => {
+                    let data = self.read_section_data(segment, data_offset, 36)?;
+                    self.metadata.md5 = Some(data[0..16].try_into().unwrap());
+                    self.metadata.sha1 = Some(data[16..36].try_into().unwrap());
+                }
+                "done" => return Ok(true),
+                "next" => return Ok(false),
+                _ => (),
+            }
+
+            if next_offset <= section_offset {
+                // A corrupted descriptor would otherwise send us into an endless loop.
+                return Ok(false);
+            }
+            section_offset = next_offset;
+        }
+    }
+
+    /// Reads `size` bytes at `offset` of a segment file, which must lie within the file.
+    fn read_section_data(&mut self, segment: usize, offset: u64, size: u64) -> io::Result<Vec<u8>> {
+        match offset.checked_add(size) {
+            Some(end) if end <= self.segment_lengths[segment] => (),
+            _ => {
+                return Err(invalid_data(
+                    "EWF section extends beyond the end of its segment file",
+                ))
+            }
+        }
+
+        let mut data = vec![0u8; size as usize];
+        let file = &mut self.segments[segment];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    fn read_table(&mut self, segment: usize, data_offset: u64, chunks_end: u64) -> io::Result<()> {
+        let header = self.read_section_data(segment, data_offset, TABLE_HEADER_SIZE)?;
+        let entry_count = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let base_offset = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        if entry_count > MAX_TABLE_ENTRIES {
+            return Err(invalid_data("EWF table section has too many entries"));
+        }
+
+        let entries = self.read_section_data(
+            segment,
+            data_offset + TABLE_HEADER_SIZE,
+            entry_count as u64 * 4,
+        )?;
+        let offsets = entries
+            .chunks_exact(4)
+            .map(|entry| u32::from_le_bytes(entry.try_into().unwrap()))
+            .map(|entry| {
+                (
+                    base_offset + (entry & 0x7fff_ffff) as u64,
+                    entry & 0x8000_0000 != 0,
+                )
+            })
+            .collect::<Vec<(u64, bool)>>();
+
+        for (i, &(offset, compressed)) in offsets.iter().enumerate() {
+            let end = match offsets.get(i + 1) {
+                Some(&(next_offset, _)) => next_offset,
+                None => chunks_end,
+            };
+            let size = end.saturating_sub(offset);
+            // Chunks are read as a whole, so a corrupted table must not make one span most of the segment.
+            let max_size = if compressed {
+                MAX_CHUNK_SIZE
+            } else {
+                self.chunk_size + CHECKSUM_SIZE
+            };
+            if size > max_size {
+                return Err(invalid_data(
+                    "EWF table section has an invalid chunk offset",
+                ));
+            }
+            self.chunks.push(Chunk {
+                segment,
+                offset,
+                size,
+                compressed,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn load_chunk(&mut self, index: usize) -> io::Result<&[u8]> {
+        let is_cached =
+            matches!(&self.cached_chunk, Some((cached_index, _)) if *cached_index == index);
+        if !is_cached {
+            let chunk = self
+                .chunks
+                .get(index)
+                .ok_or_else(|| invalid_data("EWF image is missing a chunk"))?;
+            let (segment, offset, size, compressed) =
+                (chunk.segment, chunk.offset, chunk.size, chunk.compressed);
+            let raw = self.read_section_data(segment, offset, size)?;
+
+            let data = if compressed {
+                let mut data = Vec::with_capacity(self.chunk_size as usize);
+                ZlibDecoder::new(&raw[..])
+                    .take(self.chunk_size)
+                    .read_to_end(&mut data)?;
+                data
+            } else {
+                // Strip the checksum that follows uncompressed chunk data.
+                let mut data = raw;
+                data.truncate(self.chunk_size as usize);
+                data
+            };
+            self.cached_chunk = Some((index, data));
+        }
+
+        Ok(&self.cached_chunk.as_ref().unwrap().1)
+    }
+}
+
+impl Read for EwfReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.stream_position >= self.media_size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let chunk_size = self.chunk_size;
+        let index = (self.stream_position / chunk_size) as usize;
+        let start = (self.stream_position % chunk_size) as usize;
+        let remaining_media = self.media_size - self.stream_position;
+
+        let data = self.load_chunk(index)?;
+        if start >= data.len() {
+            return Err(invalid_data("EWF chunk is shorter than expected"));
+        }
+        let len = buf
+            .len()
+            .min(data.len() - start)
+            .min(remaining_media.try_into().unwrap_or(usize::MAX));
+        buf[..len].copy_from_slice(&data[start..start + len]);
+
+        self.stream_position += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for EwfReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => {
+                if n >= 0 {
+                    self.media_size.checked_add(n as u64)
+                } else {
+                    self.media_size.checked_sub(n.wrapping_neg() as u64)
+                }
+            }
+            SeekFrom::Current(n) => {
+                if n >= 0 {
+                    self.stream_position.checked_add(n as u64)
+                } else {
+                    self.stream_position.checked_sub(n.wrapping_neg() as u64)
+                }
+            }
+        };
+
+        match new_pos {
+            Some(n) => {
+                self.stream_position = n;
+                Ok(self.stream_position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+/// Returns the path of segment `number` (starting at 1), given the path of the first segment.
+///
+/// Segments are numbered E01 to E99, followed by EAA to EZZ, FAA and so on up to ZZZ.
+/// The case of the first segment's extension is kept.
+fn segment_path(first_segment: &Path, number: u32) -> PathBuf {
+    if number == 1 {
+        return first_segment.to_owned();
+    }
+
+    let extension = first_segment
+        .extension()
+        .map(|e| e.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let lowercase = extension.starts_with(|c: char| c.is_ascii_lowercase());
+    let first_letter = extension.chars().next().unwrap_or('E').to_ascii_uppercase();
+
+    let new_extension = if number <= 99 {
+        format!("{}{:02}", first_letter, number)
+    } else {
+        let n = number - 100;
+        let letter = |i: u32| (b'A' + i as u8) as char;
+        format!(
+            "{}{}{}",
+            (first_letter as u8 + (n / 676) as u8) as char,
+            letter((n / 26) % 26),
+            letter(n % 26)
+        )
+    };
+    let new_extension = if lowercase {
+        new_extension.to_ascii_lowercase()
+    } else {
+        new_extension
+    };
+
+    first_segment.with_extension(new_extension)
+}
+
+/// Parses the zlib-compressed, tab-separated case metadata of a header or header2 section.
+fn parse_header_section(compressed: &[u8]) -> io::Result<Vec<(String, String)>> {
+    let mut data = Vec::new();
+    ZlibDecoder::new(compressed).read_to_end(&mut data)?;
+
+    // header2 sections are UTF-16 with a byte order mark, header sections are plain ASCII.
+    let text = if data.starts_with(&[0xff, 0xfe]) {
+        let utf16 = data[2..]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect::<Vec<u16>>();
+        String::from_utf16_lossy(&utf16)
+    } else {
+        String::from_utf8_lossy(&data).into_owned()
+    };
+
+    let lines = text
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .collect::<Vec<&str>>();
+    let main = match lines.iter().position(|&line| line == "main") {
+        Some(main) if main + 2 < lines.len() => main,
+        _ => return Ok(Vec::new()),
+    };
+
+    Ok(lines[main + 1]
+        .split('\t')
+        .zip(lines[main + 2].split('\t'))
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(key, value)| (header_key_name(key), value.to_owned()))
+        .collect())
+}
+
+fn header_key_name(key: &str) -> String {
+    let name = match key {
+        "a" => "Description",
+        "c" => "Case number",
+        "n" => "Evidence number",
+        "e" => "Examiner",
+        "t" => "Notes",
+        "md" => "Model",
+        "sn" => "Serial number",
+        "l" => "Device label",
+        "av" => "Acquisition software",
+        "ov" => "Acquisition OS",
+        "m" => "Acquisition date",
+        "u" => "System date",
+        "p" => "Password hash",
+        "r" => "Compression",
+        "pid" => "Process ID",
+        _ => return key.to_owned(),
+    };
+    name.to_owned()
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    const SECTORS_PER_CHUNK: u32 = 2;
+    const BYTES_PER_SECTOR: u32 = 512;
+    const CHUNK_SIZE: usize = (SECTORS_PER_CHUNK * BYTES_PER_SECTOR) as usize;
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Builds a segment file section by section.
+    struct Segment(Vec<u8>);
+
+    impl Segment {
+        fn new(number: u16) -> Self {
+            let mut data = EWF_SIGNATURE.to_vec();
+            data.push(1);
+            data.extend_from_slice(&number.to_le_bytes());
+            data.extend_from_slice(&0u16.to_le_bytes());
+            Self(data)
+        }
+
+        /// Appends a section and returns the offset of its data.
+        fn section(&mut self, section_type: &str, data: &[u8]) -> u64 {
+            let offset = self.0.len() as u64;
+            let size = SECTION_DESCRIPTOR_SIZE + data.len() as u64;
+            let mut descriptor = [0u8; SECTION_DESCRIPTOR_SIZE as usize];
+            descriptor[..section_type.len()].copy_from_slice(section_type.as_bytes());
+            let next_offset = if section_type == "done" || section_type == "next" {
+                offset
+            } else {
+                offset + size
+            };
+            descriptor[16..24].copy_from_slice(&next_offset.to_le_bytes());
+            descriptor[24..32].copy_from_slice(&size.to_le_bytes());
+            self.0.extend_from_slice(&descriptor);
+            self.0.extend_from_slice(data);
+            offset + SECTION_DESCRIPTOR_SIZE
+        }
+
+        fn volume(&mut self, sector_count: u64) {
+            self.volume_with_sector_size(BYTES_PER_SECTOR, sector_count);
+        }
+
+        fn volume_with_sector_size(&mut self, bytes_per_sector: u32, sector_count: u64) {
+            let mut data = vec![0u8; 94];
+            data[8..12].copy_from_slice(&SECTORS_PER_CHUNK.to_le_bytes());
+            data[12..16].copy_from_slice(&bytes_per_sector.to_le_bytes());
+            data[16..24].copy_from_slice(&sector_count.to_le_bytes());
+            self.section("volume", &data);
+        }
+
+        /// Appends a sectors section with the given chunks, followed by its table section.
+        fn chunks(&mut self, chunks: &[&[u8]], compress: &[bool]) {
+            let mut sectors = Vec::new();
+            let mut entries = Vec::new();
+            let data_offset = self.0.len() as u64 + SECTION_DESCRIPTOR_SIZE;
+            for (chunk, &compressed) in chunks.iter().zip(compress) {
+                let mut entry = (data_offset + sectors.len() as u64) as u32;
+                if compressed {
+                    entry |= 0x8000_0000;
+                    sectors.extend_from_slice(&zlib(chunk));
+                } else {
+                    sectors.extend_from_slice(chunk);
+                    sectors.extend_from_slice(&[0xaa; 4]);
+                }
+                entries.extend_from_slice(&entry.to_le_bytes());
+            }
+            self.section("sectors", &sectors);
+
+            let mut table = vec![0u8; TABLE_HEADER_SIZE as usize];
+            table[0..4].copy_from_slice(&(chunks.len() as u32).to_le_bytes());
+            table.extend_from_slice(&entries);
+            table.extend_from_slice(&[0; 4]);
+            self.section("table", &table);
+        }
+
+        fn write(&self, path: &Path) {
+            File::create(path).unwrap().write_all(&self.0).unwrap();
+        }
+    }
+
+    fn chunk(fill: u8) -> Vec<u8> {
+        (0..CHUNK_SIZE).map(|i| fill ^ (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn chunk_table() {
+        let dir = test_dir("ewf-chunk-table");
+        let chunks = [chunk(1), chunk(2), chunk(3)];
+        let mut segment = Segment::new(1);
+        segment.section(
+            "header",
+            &zlib(b"1\r\nmain\r\nc\tn\te\r\n42\t7\tJane\r\n\r\n"),
+        );
+        // Five sectors, so the last chunk is only half used.
+        segment.volume(5);
+        segment.chunks(&[&chunks[0], &chunks[1], &chunks[2]], &[true, false, true]);
+        segment.section("hash", &[0x11; 16]);
+        segment.section("done", &[]);
+        segment.write(&dir.join("image.E01"));
+
+        let mut reader = EwfReader::open(&dir.join("image.E01")).unwrap();
+        let metadata = reader.metadata().clone();
+        assert_eq!(metadata.segment_count, 1);
+        assert_eq!(metadata.sector_count, 5);
+        assert_eq!(metadata.md5, Some([0x11; 16]));
+        assert_eq!(
+            metadata.header,
+            vec![
+                ("Case number".to_owned(), "42".to_owned()),
+                ("Evidence number".to_owned(), "7".to_owned()),
+                ("Examiner".to_owned(), "Jane".to_owned()),
+            ]
+        );
+
+        let mut media = Vec::new();
+        reader.read_to_end(&mut media).unwrap();
+        let expected = [&chunks[0][..], &chunks[1][..], &chunks[2][..512]].concat();
+        assert_eq!(media, expected);
+
+        // Seek back to a read that spans the compressed and the uncompressed chunk.
+        let mut buf = [0u8; 100];
+        reader
+            .seek(SeekFrom::Start(CHUNK_SIZE as u64 - 50))
+            .unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[..], expected[CHUNK_SIZE - 50..CHUNK_SIZE + 50]);
+        assert_eq!(reader.seek(SeekFrom::End(0)).unwrap(), 5 * 512);
+    }
+
+    #[test]
+    fn multiple_segments() {
+        let dir = test_dir("ewf-multiple-segments");
+        let chunks = [chunk(4), chunk(5)];
+        let mut first = Segment::new(1);
+        first.volume(4);
+        first.chunks(&[&chunks[0]], &[true]);
+        first.section("next", &[]);
+        first.write(&dir.join("image.E01"));
+        let mut second = Segment::new(2);
+        second.chunks(&[&chunks[1]], &[false]);
+        second.section("done", &[]);
+        second.write(&dir.join("image.E02"));
+
+        let mut reader = EwfReader::open(&dir.join("image.E01")).unwrap();
+        assert_eq!(reader.metadata().segment_count, 2);
+        let mut media = Vec::new();
+        reader.read_to_end(&mut media).unwrap();
+        assert_eq!(media, chunks.concat());
+    }
+
+    #[test]
+    fn section_beyond_the_end() {
+        let dir = test_dir("ewf-section-beyond-the-end");
+        let mut segment = Segment::new(1);
+        segment.volume(4);
+        let data_offset = segment.section("header", &[0; 16]);
+        // Claim a header of 1 TiB.
+        let size_offset = (data_offset - SECTION_DESCRIPTOR_SIZE) as usize + 24;
+        segment.0[size_offset..size_offset + 8].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        segment.write(&dir.join("image.E01"));
+
+        let error = EwfReader::open(&dir.join("image.E01")).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn invalid_volume() {
+        let dir = test_dir("ewf-invalid-volume");
+        for (bytes_per_sector, sector_count) in [(256, 4), (513, 4), (8192, 4), (512, u64::MAX)] {
+            let mut segment = Segment::new(1);
+            segment.volume_with_sector_size(bytes_per_sector, sector_count);
+            segment.section("done", &[]);
+            segment.write(&dir.join("image.E01"));
+
+            let error = EwfReader::open(&dir.join("image.E01")).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn oversized_chunk() {
+        let dir = test_dir("ewf-oversized-chunk");
+        for compressed in [false, true] {
+            let mut segment = Segment::new(1);
+            segment.volume(4);
+            // Claim a sectors section of 32 MiB, which makes the only chunk of the table just as large.
+            let size_offset = segment.0.len() + 24;
+            segment.chunks(&[&chunk(6)], &[compressed]);
+            segment.0[size_offset..size_offset + 8].copy_from_slice(&(32u64 << 20).to_le_bytes());
+            segment.section("done", &[]);
+            segment.write(&dir.join("image.E01"));
+
+            let error = EwfReader::open(&dir.join("image.E01")).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn ewf2_is_rejected() {
+        let dir = test_dir("ewf-ex01");
+        let mut data = EWF2_SIGNATURE.to_vec();
+        data.resize(512, 0);
+        File::create(dir.join("image.Ex01"))
+            .unwrap()
+            .write_all(&data)
+            .unwrap();
+
+        let error = EwfReader::open(&dir.join("image.Ex01")).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn segment_paths() {
+        let first = Path::new("/cases/disk.E01");
+        assert_eq!(segment_path(first, 1), first);
+        assert_eq!(segment_path(first, 2), Path::new("/cases/disk.E02"));
+        assert_eq!(segment_path(first, 99), Path::new("/cases/disk.E99"));
+        assert_eq!(segment_path(first, 100), Path::new("/cases/disk.EAA"));
+        assert_eq!(segment_path(first, 101), Path::new("/cases/disk.EAB"));
+        assert_eq!(segment_path(first, 775), Path::new("/cases/disk.EZZ"));
+        assert_eq!(segment_path(first, 776), Path::new("/cases/disk.FAA"));
+        assert_eq!(
+            segment_path(Path::new("disk.e01"), 100),
+            Path::new("disk.eaa")
+        );
+    }
+}
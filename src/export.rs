@@ -0,0 +1,382 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use filetime::FileTime;
+
+use crate::volume::{DirEntry, FileTimes, Volume};
+use crate::walk::{walk, Visitor};
+
+const LOG_FILE_NAME: &str = "ntfs-explorer-export.log";
+/// Characters that Windows doesn't allow in file names. Exports often end up there, so they are replaced everywhere.
+const ILLEGAL_CHARACTERS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+/// Device names that Windows doesn't allow as file names, with or without an extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// What to export besides the content of files.
+#[derive(Clone, Copy, Debug)]
+pub struct ExportOptions {
+    /// Also write the alternate data streams of files, as `<name>_<stream>` next to them.
+    pub named_streams: bool,
+    /// Set the modification and access times of exported files and folders to the ones on the volume.
+    pub restore_times: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            named_streams: false,
+            restore_times: true,
+        }
+    }
+}
+
+/// A file, folder or stream that was exported, or failed to.
+#[derive(Clone, Debug)]
+pub struct ExportedItem {
+    /// Path on the volume, with `:stream` for alternate data streams.
+    pub source: String,
+    pub target: PathBuf,
+    /// Bytes written, 0 for folders.
+    pub size: u64,
+    pub error: Option<String>,
+}
+
+/// What an export did, to be kept along with the exported files.
+#[derive(Clone, Debug, Default)]
+pub struct ExportLog {
+    pub items: Vec<ExportedItem>,
+    /// Whether the export was stopped before everything was written.
+    pub cancelled: bool,
+}
+
+impl ExportLog {
+    pub fn bytes(&self) -> u64 {
+        self.items.iter().map(|item| item.size).sum()
+    }
+
+    pub fn errors(&self) -> usize {
+        self.items
+            .iter()
+            .filter(|item| item.error.is_some())
+            .count()
+    }
+
+    /// Writes the log as tab-separated lines of status, size, source and target.
+    pub fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(writer, "status\tsize\tsource\ttarget")?;
+        for item in &self.items {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}",
+                item.error.as_deref().unwrap_or("ok"),
+                item.size,
+                item.source,
+                item.target.display()
+            )?;
+        }
+        if self.cancelled {
+            writeln!(writer, "cancelled")?;
+        }
+        Ok(())
+    }
+
+    /// Writes the log into the folder the files were exported to, under a name that isn't taken, and returns its path.
+    pub fn save(&self, target_directory: &Path) -> io::Result<PathBuf> {
+        let path = unique_path(&target_directory.join(LOG_FILE_NAME));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        self.write(&mut writer)?;
+        writer.flush()?;
+        Ok(path)
+    }
+}
+
+/// Exports files and folders listed in `directory`, which has the path `source_directory` on the volume, into
+/// `target_directory`. Folders are exported with everything below them.
+///
+/// Names that are illegal on Windows are changed, and names that are already taken get a number appended, so
+/// nothing in `target_directory` is ever overwritten.
+/// `progress` is called with the file being exported and the number of bytes written so far, and stops the export
+/// by returning `false`.
+/// Failures are recorded in the log rather than ending the export, only the target directory has to be writable.
+pub fn export<R, F>(
+    volume: &mut Volume<R>,
+    directory: u64,
+    source_directory: &str,
+    entries: &[DirEntry],
+    target_directory: &Path,
+    options: ExportOptions,
+    progress: F,
+) -> anyhow::Result<ExportLog>
+where
+    R: Read + Seek,
+    F: FnMut(&str, u64) -> bool,
+{
+    fs::create_dir_all(target_directory)?;
+    let mut exporter = Exporter {
+        options,
+        progress,
+        log: ExportLog::default(),
+        bytes: 0,
+    };
+    if !walk(
+        volume,
+        directory,
+        source_directory,
+        entries,
+        &target_directory.to_owned(),
+        &mut exporter,
+    ) {
+        exporter.log.cancelled = true;
+    }
+    Ok(exporter.log)
+}
+
+struct Exporter<F> {
+    options: ExportOptions,
+    progress: F,
+    log: ExportLog,
+    /// Bytes written so far, for progress reports.
+    bytes: u64,
+}
+
+impl<R, F> Visitor<R> for Exporter<F>
+where
+    R: Read + Seek,
+    F: FnMut(&str, u64) -> bool,
+{
+    /// The folder to export into.
+    type Context = PathBuf;
+
+    fn file(
+        &mut self,
+        volume: &mut Volume<R>,
+        entry: &DirEntry,
+        source: &str,
+        target_directory: &PathBuf,
+    ) -> bool {
+        let target = unique_path(&target_directory.join(host_file_name(&entry.name)));
+        let result = self.export_stream(volume, entry.record_number, "", source, &target);
+        let failed = result.is_err();
+        if !self.record(source, &target, result) {
+            return false;
+        }
+        if self.options.restore_times && !failed {
+            // Failing that is no reason to fail the export.
+            let _ = restore_times(&target, &entry.times);
+        }
+
+        if self.options.named_streams {
+            let streams = match volume.streams(entry.record_number) {
+                Ok(streams) => streams,
+                Err(err) => {
+                    return self.record(source, &target, Err(err));
+                }
+            };
+            for (stream, _) in streams {
+                let stream_source = format!("{}:{}", source, stream);
+                let stream_name = format!("{}_{}", entry.name, stream);
+                let stream_target =
+                    unique_path(&target_directory.join(host_file_name(&stream_name)));
+                let result = self.export_stream(
+                    volume,
+                    entry.record_number,
+                    &stream,
+                    &stream_source,
+                    &stream_target,
+                );
+                if !self.record(&stream_source, &stream_target, result) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn enter_directory(
+        &mut self,
+        entry: &DirEntry,
+        source: &str,
+        target_directory: &PathBuf,
+    ) -> Option<PathBuf> {
+        let target = unique_path(&target_directory.join(host_file_name(&entry.name)));
+        match fs::create_dir(&target) {
+            Ok(()) => {
+                self.record(source, &target, Ok(Some(0)));
+                Some(target)
+            }
+            Err(err) => {
+                self.record(source, &target, Err(err.into()));
+                None
+            }
+        }
+    }
+
+    fn leave_directory(&mut self, entry: &DirEntry, target: &PathBuf) {
+        // Only now, as writing the children changes the modification time.
+        if self.options.restore_times {
+            let _ = restore_times(target, &entry.times);
+        }
+    }
+
+    fn directory_failed(
+        &mut self,
+        entry: &DirEntry,
+        source: &str,
+        target_directory: &PathBuf,
+        err: anyhow::Error,
+    ) {
+        let target = unique_path(&target_directory.join(host_file_name(&entry.name)));
+        self.record(source, &target, Err(err));
+    }
+}
+
+impl<F> Exporter<F>
+where
+    F: FnMut(&str, u64) -> bool,
+{
+    /// Copies a stream to `target` and returns its size, or `None` if the export was cancelled.
+    fn export_stream<R>(
+        &mut self,
+        volume: &mut Volume<R>,
+        record_number: u64,
+        stream: &str,
+        source: &str,
+        target: &Path,
+    ) -> anyhow::Result<Option<u64>>
+    where
+        R: Read + Seek,
+    {
+        let mut writer = BufWriter::new(File::create(target)?);
+        let bytes = self.bytes;
+        let progress = &mut self.progress;
+        let copied = volume.copy_stream_with_progress(
+            record_number,
+            stream,
+            &mut writer,
+            &mut |copied| progress(source, bytes + copied),
+        )?;
+        writer.flush()?;
+        self.bytes += copied.unwrap_or_default();
+        Ok(copied)
+    }
+
+    /// Adds the outcome of exporting `source` to the log and returns `false` if the export was cancelled.
+    fn record(&mut self, source: &str, target: &Path, result: anyhow::Result<Option<u64>>) -> bool {
+        let (size, error, completed) = match result {
+            Ok(Some(size)) => (size, None, true),
+            Ok(None) => (0, Some("cancelled".to_owned()), false),
+            Err(err) => (0, Some(format!("{:#}", err)), true),
+        };
+        self.log.items.push(ExportedItem {
+            source: source.to_owned(),
+            target: target.to_owned(),
+            size,
+            error,
+        });
+        completed
+    }
+}
+
+/// Turns an NTFS file name into one that can be created on the host, and on Windows in particular.
+pub fn host_file_name(name: &str) -> String {
+    let mut host_name = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || ILLEGAL_CHARACTERS.contains(&c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect::<String>();
+
+    // Windows drops trailing dots and spaces, so that the name would refer to another file.
+    let trimmed_len = host_name.trim_end_matches(['.', ' ']).len();
+    if trimmed_len < host_name.len() || host_name.is_empty() {
+        host_name.truncate(trimmed_len);
+        host_name.push('_');
+    }
+    let stem = host_name.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+    {
+        host_name.insert(0, '_');
+    }
+    host_name
+}
+
+/// Returns `path`, or if that exists, the first of `name (1).ext`, `name (2).ext`, … that doesn't.
+pub(crate) fn unique_path(path: &Path) -> PathBuf {
+    unique_path_by(path, |candidate| fs::symlink_metadata(candidate).is_ok())
+}
+
+/// Like [`unique_path`], with `taken` telling which paths exist.
+pub(crate) fn unique_path_by<F>(path: &Path, taken: F) -> PathBuf
+where
+    F: Fn(&Path) -> bool,
+{
+    if !taken(path) {
+        return path.to_owned();
+    }
+    let stem = path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|i| path.with_file_name(format!("{} ({}){}", stem, i, extension)))
+        .find(|candidate| !taken(candidate))
+        .unwrap()
+}
+
+fn restore_times(path: &Path, times: &FileTimes) -> io::Result<()> {
+    filetime::set_file_times(
+        path,
+        FileTime::from_system_time(SystemTime::from(times.access)),
+        FileTime::from_system_time(SystemTime::from(times.modification)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    #[test]
+    fn host_file_names() {
+        assert_eq!(host_file_name("report.docx"), "report.docx");
+        assert_eq!(host_file_name("a:b?c*.txt"), "a_b_c_.txt");
+        assert_eq!(host_file_name("back\\slash\nline"), "back_slash_line");
+        assert_eq!(host_file_name("name. "), "name_");
+        assert_eq!(host_file_name("..."), "_");
+        assert_eq!(host_file_name(""), "_");
+        assert_eq!(host_file_name("nul"), "_nul");
+        assert_eq!(host_file_name("Com1.log"), "_Com1.log");
+        assert_eq!(host_file_name("console"), "console");
+    }
+
+    #[test]
+    fn unique_paths() {
+        let dir = test_dir("export-unique-paths");
+        let path = dir.join("file.txt");
+        assert_eq!(unique_path(&path), path);
+
+        File::create(&path).unwrap();
+        assert_eq!(unique_path(&path), dir.join("file (1).txt"));
+        File::create(dir.join("file (1).txt")).unwrap();
+        assert_eq!(unique_path(&path), dir.join("file (2).txt"));
+
+        fs::create_dir(dir.join("folder")).unwrap();
+        assert_eq!(unique_path(&dir.join("folder")), dir.join("folder (1)"));
+    }
+}
@@ -0,0 +1,329 @@
+use std::ffi::OsStr;
+use std::io::{Read, Seek};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
+    ReplyXattr, Request, FUSE_ROOT_ID,
+};
+use ntfs::structured_values::NtfsFileNamespace;
+
+use crate::volume::{DirEntry, Volume, ROOT_DIRECTORY};
+
+/// How long the kernel may cache attributes and lookups. Nothing ever changes on a read-only mount.
+const TTL: Duration = Duration::from_secs(3600);
+/// Prefix of the extended attributes that expose alternate data streams, the same that ntfs-3g uses.
+const STREAM_XATTR_PREFIX: &str = "user.";
+/// Linux doesn't take extended attributes larger than this, bigger streams can only be read with `ntfs-cli cat`.
+const XATTR_SIZE_MAX: u64 = 64 * 1024;
+
+/// Mounts `volume` read-only at `mountpoint` and serves it until it is unmounted.
+pub fn mount<R>(volume: Volume<R>, mountpoint: &Path) -> anyhow::Result<()>
+where
+    R: Read + Seek,
+{
+    let options = [
+        MountOption::RO,
+        MountOption::FSName("ntfs-explorer".to_owned()),
+        MountOption::Subtype("ntfs".to_owned()),
+    ];
+    fuser::mount2(NtfsFilesystem::new(volume), mountpoint, &options)?;
+    Ok(())
+}
+
+/// A read-only FUSE filesystem of an NTFS volume.
+///
+/// Inode numbers are file record numbers plus one, so hard links of a file share one.
+/// FUSE insists on inode 1 for the root directory though, so `$MFT` gets the one of the root directory instead.
+/// Alternate data streams show up as extended attributes named `user.<stream>`.
+struct NtfsFilesystem<R>
+where
+    R: Read + Seek,
+{
+    volume: Volume<R>,
+    /// The last directory listed, as `readdir` is called repeatedly for large directories.
+    listing: Option<(u64, Vec<DirEntry>)>,
+    uid: u32,
+    gid: u32,
+}
+
+impl<R> NtfsFilesystem<R>
+where
+    R: Read + Seek,
+{
+    fn new(volume: Volume<R>) -> Self {
+        // Everything belongs to whoever mounted the volume, there is no sensible mapping of NTFS security descriptors.
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        Self {
+            volume,
+            listing: None,
+            uid,
+            gid,
+        }
+    }
+
+    fn attr(&mut self, record_number: u64) -> anyhow::Result<FileAttr> {
+        let info = self.volume.file_info(record_number)?;
+        let (kind, perm, size) = if info.is_directory {
+            (FileType::Directory, 0o555, 0)
+        } else {
+            let size = self
+                .volume
+                .stream_size(record_number, "")
+                .unwrap_or_default();
+            (FileType::RegularFile, 0o444, size)
+        };
+        let (crtime, mtime, ctime, atime) = match &info.times {
+            Some(times) => (
+                SystemTime::from(times.creation),
+                SystemTime::from(times.modification),
+                SystemTime::from(times.mft_record_modification),
+                SystemTime::from(times.access),
+            ),
+            None => (
+                SystemTime::UNIX_EPOCH,
+                SystemTime::UNIX_EPOCH,
+                SystemTime::UNIX_EPOCH,
+                SystemTime::UNIX_EPOCH,
+            ),
+        };
+        // Short names are no separate links, they belong to a long name.
+        let nlink = info
+            .file_names
+            .iter()
+            .filter(|file_name| file_name.namespace != NtfsFileNamespace::Dos)
+            .count()
+            .max(1);
+
+        Ok(FileAttr {
+            ino: record_number_to_inode(record_number),
+            size,
+            blocks: (size + 511) / 512,
+            atime,
+            mtime,
+            ctime,
+            crtime,
+            kind,
+            perm,
+            nlink: nlink as u32,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        })
+    }
+
+    fn listing(&mut self, record_number: u64) -> anyhow::Result<&[DirEntry]> {
+        if !matches!(&self.listing, Some((listed, _)) if *listed == record_number) {
+            let entries = self.volume.list_directory(record_number)?;
+            self.listing = Some((record_number, entries));
+        }
+        Ok(&self.listing.as_ref().unwrap().1)
+    }
+
+    /// Returns the directory holding the preferred name of a file.
+    fn parent(&mut self, record_number: u64) -> anyhow::Result<u64> {
+        if record_number == ROOT_DIRECTORY {
+            return Ok(ROOT_DIRECTORY);
+        }
+        self.volume.parent_directory(record_number)
+    }
+
+    fn stream_names(&mut self, record_number: u64) -> anyhow::Result<Vec<u8>> {
+        let mut names = vec![];
+        for (name, _) in self.volume.streams(record_number)? {
+            names.extend_from_slice(STREAM_XATTR_PREFIX.as_bytes());
+            names.extend_from_slice(name.as_bytes());
+            names.push(0);
+        }
+        Ok(names)
+    }
+}
+
+impl<R> Filesystem for NtfsFilesystem<R>
+where
+    R: Read + Seek,
+{
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => return reply.error(libc::ENOENT),
+        };
+        let record_number = match self.volume.find(inode_to_record_number(parent), name) {
+            Ok(Some(record_number)) => record_number,
+            Ok(None) => return reply.error(libc::ENOENT),
+            Err(_) => return reply.error(libc::EIO),
+        };
+        match self.attr(record_number) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.attr(inode_to_record_number(ino)) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(_) => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        if offset < 0 {
+            return reply.error(libc::EINVAL);
+        }
+        let record_number = inode_to_record_number(ino);
+        match self
+            .volume
+            .read_stream_range(record_number, "", offset as u64, size as usize)
+        {
+            Ok(data) => reply.data(&data),
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let record_number = inode_to_record_number(ino);
+        let parent = match self.parent(record_number) {
+            Ok(parent) => parent,
+            Err(_) => return reply.error(libc::EIO),
+        };
+        let entries = match self.listing(record_number) {
+            Ok(entries) => entries,
+            Err(_) => return reply.error(libc::EIO),
+        };
+
+        let dot_entries = [
+            (record_number, FileType::Directory, "."),
+            (parent, FileType::Directory, ".."),
+        ];
+        let entries = entries
+            .iter()
+            // The root directory lists itself as ".".
+            .filter(|entry| entry.record_number != record_number)
+            .map(|entry| {
+                let kind = if entry.is_directory {
+                    FileType::Directory
+                } else {
+                    FileType::RegularFile
+                };
+                (entry.record_number, kind, entry.name.as_str())
+            });
+        for (i, (record_number, kind, name)) in dot_entries
+            .into_iter()
+            .chain(entries)
+            .enumerate()
+            .skip(offset as usize)
+        {
+            // The offset is that of the next entry, for the following call.
+            if reply.add(
+                record_number_to_inode(record_number),
+                i as i64 + 1,
+                kind,
+                name,
+            ) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn listxattr(&mut self, _req: &Request<'_>, ino: u64, size: u32, reply: ReplyXattr) {
+        match self.stream_names(inode_to_record_number(ino)) {
+            Ok(names) => reply_xattr(reply, size, &names),
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+
+    fn getxattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        size: u32,
+        reply: ReplyXattr,
+    ) {
+        let stream = match name
+            .to_str()
+            .and_then(|name| name.strip_prefix(STREAM_XATTR_PREFIX))
+        {
+            Some(stream) if !stream.is_empty() => stream,
+            _ => return reply.error(libc::ENODATA),
+        };
+        let record_number = inode_to_record_number(ino);
+        match self.volume.stream_size(record_number, stream) {
+            Ok(stream_size) if stream_size > XATTR_SIZE_MAX => return reply.error(libc::E2BIG),
+            Ok(_) => (),
+            Err(_) => return reply.error(libc::ENODATA),
+        }
+        let mut data = vec![];
+        match self.volume.copy_stream(record_number, stream, &mut data) {
+            Ok(_) => reply_xattr(reply, size, &data),
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+}
+
+/// Answers an xattr request: a `size` of 0 asks for the size only, a smaller one is an error.
+fn reply_xattr(reply: ReplyXattr, size: u32, data: &[u8]) {
+    if size == 0 {
+        reply.size(data.len() as u32);
+    } else if data.len() > size as usize {
+        reply.error(libc::ERANGE);
+    } else {
+        reply.data(data);
+    }
+}
+
+fn record_number_to_inode(record_number: u64) -> u64 {
+    match record_number {
+        ROOT_DIRECTORY => FUSE_ROOT_ID,
+        0 => ROOT_DIRECTORY + 1,
+        _ => record_number + 1,
+    }
+}
+
+fn inode_to_record_number(inode: u64) -> u64 {
+    match inode {
+        FUSE_ROOT_ID => ROOT_DIRECTORY,
+        inode if inode == ROOT_DIRECTORY + 1 => 0,
+        _ => inode - 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inode_numbers() {
+        // The root directory and `$MFT` swap their inode numbers, everything else is shifted by one.
+        assert_eq!(record_number_to_inode(ROOT_DIRECTORY), FUSE_ROOT_ID);
+        assert_eq!(record_number_to_inode(0), ROOT_DIRECTORY + 1);
+        assert_eq!(record_number_to_inode(1), 2);
+        assert_eq!(record_number_to_inode(6), 7);
+
+        for record_number in [0, 1, 5, 6, 1 << 40] {
+            let inode = record_number_to_inode(record_number);
+            assert_eq!(inode_to_record_number(inode), record_number);
+        }
+    }
+}
@@ -0,0 +1,812 @@
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::time::UNIX_EPOCH;
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use libc::{c_int, c_uint, c_void};
+use libz_sys as zlib;
+
+pub const GZIP_MAGIC: &[u8; 2] = b"\x1f\x8b";
+
+/// Distance between checkpoints in the decompressed data.
+///
+/// Every read has to decompress up to this much data, but every checkpoint costs up to 32 KiB in the index.
+const CHECKPOINT_SPAN: u64 = 4 << 20;
+/// Size of the deflate history that a checkpoint needs to resume decompression.
+const WINDOW_SIZE: usize = 32 * 1024;
+const INPUT_BUFFER_SIZE: usize = 64 * 1024;
+/// Window bits for inflating a gzip member including its header.
+const GZIP_WINDOW_BITS: c_int = 16 + 15;
+/// Window bits for inflating raw deflate data.
+const RAW_WINDOW_BITS: c_int = -15;
+/// Size of the CRC32 and length at the end of each gzip member.
+const GZIP_TRAILER_SIZE: usize = 8;
+const INDEX_MAGIC: &[u8; 8] = b"NTFSXGZI";
+const INDEX_VERSION: u32 = 1;
+
+/// A position from which decompression can be resumed.
+struct Checkpoint {
+    /// Position in the decompressed data.
+    uncompressed: u64,
+    /// Offset of the first compressed byte that has not been completely consumed.
+    compressed: u64,
+    /// Number of bits of the byte before `compressed` that belong to the next deflate block.
+    bits: u8,
+    /// The last 32 KiB of decompressed data before the checkpoint, deflate-compressed to keep the index small.
+    /// `None` for checkpoints at the start of a gzip member, which need no history.
+    window: Option<Vec<u8>>,
+}
+
+/// `GzipReader` provides the decompressed contents of a gzip compressed image as a seekable stream.
+///
+/// gzip has no random access, so on first open the whole file is decompressed once to build an index of
+/// checkpoints from which decompression can be resumed (the technique of zlib's `zran.c`).
+/// The index is cached in the user's cache directory and reused as long as the image doesn't change.
+pub struct GzipReader {
+    file: File,
+    checkpoints: Vec<Checkpoint>,
+    size: u64,
+    inflater: Inflater,
+    /// Position of `inflater` in the decompressed data, `None` if it hasn't been started at a checkpoint yet.
+    inflater_position: Option<u64>,
+    /// Buffer for data that is decompressed only to be skipped.
+    scratch: Vec<u8>,
+    /// Whether the index has been loaded from the cache.
+    index_cached: bool,
+    /// The current stream position in the decompressed image.
+    stream_position: u64,
+}
+
+impl GzipReader {
+    /// Opens a gzip compressed image, building its index if there is no cached index yet.
+    ///
+    /// `progress` is called with the number of compressed bytes processed while building the index.
+    pub fn open(path: &Path, progress: &mut dyn FnMut(u64)) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let cache_path = index_cache_path(path);
+
+        let cached_index = cache_path
+            .as_ref()
+            .and_then(|cache_path| load_index(cache_path, &metadata).ok());
+        let index_cached = cached_index.is_some();
+        let (checkpoints, size) = match cached_index {
+            Some(index) => index,
+            None => {
+                let (checkpoints, size) = build_index(&mut file, progress)?;
+                // The cache is only an optimization, so failing to write it is not an error.
+                if let Some(cache_path) = cache_path {
+                    let _ = save_index(&cache_path, &metadata, &checkpoints, size);
+                }
+                (checkpoints, size)
+            }
+        };
+
+        Self::with_index(file, checkpoints, size, index_cached)
+    }
+
+    fn with_index(
+        file: File,
+        checkpoints: Vec<Checkpoint>,
+        size: u64,
+        index_cached: bool,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            file,
+            checkpoints,
+            size,
+            inflater: Inflater::new()?,
+            inflater_position: None,
+            scratch: vec![0; INPUT_BUFFER_SIZE],
+            index_cached,
+            stream_position: 0,
+        })
+    }
+
+    /// Size of the decompressed image.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn checkpoint_count(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    pub fn index_cached(&self) -> bool {
+        self.index_cached
+    }
+
+    /// Moves the inflater to `position`, resuming from a checkpoint unless it can get there by skipping ahead.
+    fn move_inflater_to(&mut self, position: u64) -> io::Result<()> {
+        let mut inflater_position = match self.inflater_position {
+            Some(current) if current <= position && position - current < CHECKPOINT_SPAN => current,
+            _ => {
+                let index = self
+                    .checkpoints
+                    .partition_point(|checkpoint| checkpoint.uncompressed <= position)
+                    - 1;
+                let checkpoint = &self.checkpoints[index];
+                self.inflater_position = None;
+                self.inflater.start(&mut self.file, checkpoint)?;
+                checkpoint.uncompressed
+            }
+        };
+
+        while inflater_position < position {
+            let len = self
+                .scratch
+                .len()
+                .min((position - inflater_position) as usize);
+            let produced = self.inflater.inflate(
+                &mut self.file,
+                &mut self.scratch[..len],
+                zlib::Z_NO_FLUSH,
+            )?;
+            if produced == 0 {
+                return Err(truncated());
+            }
+            inflater_position += produced as u64;
+        }
+
+        self.inflater_position = Some(inflater_position);
+        Ok(())
+    }
+}
+
+impl Read for GzipReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.stream_position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.move_inflater_to(self.stream_position)?;
+        let max_len = buf.len().min(
+            (self.size - self.stream_position)
+                .try_into()
+                .unwrap_or(usize::MAX),
+        );
+        let produced =
+            self.inflater
+                .inflate(&mut self.file, &mut buf[..max_len], zlib::Z_NO_FLUSH)?;
+        if produced == 0 {
+            return Err(truncated());
+        }
+
+        self.stream_position += produced as u64;
+        self.inflater_position = Some(self.stream_position);
+        Ok(produced)
+    }
+}
+
+impl Seek for GzipReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => {
+                if n >= 0 {
+                    self.size.checked_add(n as u64)
+                } else {
+                    self.size.checked_sub(n.wrapping_neg() as u64)
+                }
+            }
+            SeekFrom::Current(n) => {
+                if n >= 0 {
+                    self.stream_position.checked_add(n as u64)
+                } else {
+                    self.stream_position.checked_sub(n.wrapping_neg() as u64)
+                }
+            }
+        };
+
+        match new_pos {
+            Some(n) => {
+                self.stream_position = n;
+                Ok(self.stream_position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+/// Decompresses the whole file and records a checkpoint at a deflate block boundary about every `CHECKPOINT_SPAN`
+/// bytes, as well as at the start of every gzip member.
+fn build_index(
+    file: &mut File,
+    progress: &mut dyn FnMut(u64),
+) -> io::Result<(Vec<Checkpoint>, u64)> {
+    let mut inflater = Inflater::new()?;
+    let start = Checkpoint {
+        uncompressed: 0,
+        compressed: 0,
+        bits: 0,
+        window: None,
+    };
+    inflater.start(file, &start)?;
+    let mut checkpoints = vec![start];
+
+    // The decompressed data is written into a ring buffer, so that it always holds the history for a checkpoint.
+    let mut window = vec![0u8; WINDOW_SIZE];
+    let mut window_position = 0;
+    let mut size = 0;
+    let mut last_checkpoint = 0;
+    let mut last_progress = 0;
+    while !inflater.finished {
+        let produced = inflater.inflate(file, &mut window[window_position..], zlib::Z_BLOCK)?;
+        window_position = (window_position + produced) % WINDOW_SIZE;
+        size += produced as u64;
+
+        if inflater.member_started {
+            inflater.member_started = false;
+            checkpoints.push(Checkpoint {
+                uncompressed: size,
+                compressed: inflater.consumed_offset(),
+                bits: 0,
+                window: None,
+            });
+            last_checkpoint = size;
+            continue;
+        }
+
+        // Bit 7 of `data_type` is set at the end of a deflate block, bit 6 if it was the last block of the member.
+        let data_type = inflater.stream.data_type;
+        if data_type & 128 != 0 && data_type & 64 == 0 && size - last_checkpoint >= CHECKPOINT_SPAN
+        {
+            let mut history = window[window_position..].to_vec();
+            history.extend_from_slice(&window[..window_position]);
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(&history)?;
+
+            checkpoints.push(Checkpoint {
+                uncompressed: size,
+                compressed: inflater.consumed_offset(),
+                bits: (data_type & 7) as u8,
+                window: Some(encoder.finish()?),
+            });
+            last_checkpoint = size;
+        }
+
+        let consumed = inflater.consumed_offset();
+        if consumed - last_progress >= 64 << 20 {
+            progress(consumed);
+            last_progress = consumed;
+        }
+    }
+
+    Ok((checkpoints, size))
+}
+
+/// Returns where the index of the image at `path` is cached.
+///
+/// The name of the cache file contains a hash of the image's absolute path, so that images with the same name
+/// in different directories get different indexes.
+fn index_cache_path(path: &Path) -> Option<PathBuf> {
+    let cache_dir = if cfg!(windows) {
+        PathBuf::from(env::var_os("LOCALAPPDATA")?)
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| Some(PathBuf::from(env::var_os("HOME")?).join(".cache")))?
+    };
+
+    let mut hasher = DefaultHasher::new();
+    fs::canonicalize(path).ok()?.hash(&mut hasher);
+    let file_name = path.file_name()?.to_string_lossy();
+    Some(cache_dir.join("ntfs-explorer").join(format!(
+        "{}-{:016x}.gzindex",
+        file_name,
+        hasher.finish()
+    )))
+}
+
+/// Identifies the version of the image an index was built for.
+fn image_stamp(metadata: &fs::Metadata) -> (u64, u64) {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |modified| modified.as_nanos() as u64);
+    (metadata.len(), modified)
+}
+
+fn save_index(
+    cache_path: &Path,
+    metadata: &fs::Metadata,
+    checkpoints: &[Checkpoint],
+    size: u64,
+) -> io::Result<()> {
+    if let Some(dir) = cache_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    // Write to a temporary file first, so that an interrupted write doesn't leave a broken index behind.
+    let temp_path = cache_path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    let (len, modified) = image_stamp(metadata);
+    writer.write_all(INDEX_MAGIC)?;
+    writer.write_all(&INDEX_VERSION.to_le_bytes())?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(&modified.to_le_bytes())?;
+    writer.write_all(&size.to_le_bytes())?;
+    writer.write_all(&(checkpoints.len() as u64).to_le_bytes())?;
+    for checkpoint in checkpoints {
+        writer.write_all(&checkpoint.uncompressed.to_le_bytes())?;
+        writer.write_all(&checkpoint.compressed.to_le_bytes())?;
+        writer.write_all(&[checkpoint.bits])?;
+        match &checkpoint.window {
+            Some(window) => {
+                writer.write_all(&(window.len() as u32).to_le_bytes())?;
+                writer.write_all(window)?;
+            }
+            None => writer.write_all(&u32::MAX.to_le_bytes())?,
+        }
+    }
+    writer.into_inner()?.sync_all()?;
+
+    fs::rename(&temp_path, cache_path)
+}
+
+fn load_index(cache_path: &Path, metadata: &fs::Metadata) -> io::Result<(Vec<Checkpoint>, u64)> {
+    let mut reader = BufReader::new(File::open(cache_path)?);
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    let version = read_u32(&mut reader)?;
+    let stamp = (read_u64(&mut reader)?, read_u64(&mut reader)?);
+    if &magic != INDEX_MAGIC || version != INDEX_VERSION || stamp != image_stamp(metadata) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "cached index is outdated",
+        ));
+    }
+
+    let size = read_u64(&mut reader)?;
+    let count = read_u64(&mut reader)?;
+    let mut checkpoints = Vec::new();
+    for _ in 0..count {
+        let uncompressed = read_u64(&mut reader)?;
+        let compressed = read_u64(&mut reader)?;
+        let mut bits = [0u8; 1];
+        reader.read_exact(&mut bits)?;
+        let window = match read_u32(&mut reader)? {
+            u32::MAX => None,
+            len if len as usize > 2 * WINDOW_SIZE => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid window in cached index",
+                ))
+            }
+            len => {
+                let mut window = vec![0u8; len as usize];
+                reader.read_exact(&mut window)?;
+                Some(window)
+            }
+        };
+        checkpoints.push(Checkpoint {
+            uncompressed,
+            compressed,
+            bits: bits[0],
+            window,
+        });
+    }
+
+    if checkpoints
+        .first()
+        .map(|checkpoint| checkpoint.uncompressed)
+        != Some(0)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "cached index has no start checkpoint",
+        ));
+    }
+    Ok((checkpoints, size))
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "gzip file is truncated or has changed since it was indexed",
+    )
+}
+
+/// zlib's inflate state together with the compressed input it is working on.
+///
+/// We use zlib directly instead of flate2, because resuming at a checkpoint needs `inflatePrime`.
+struct Inflater {
+    /// zlib keeps a pointer to the stream, so it has to stay at the same address.
+    stream: Box<zlib::z_stream>,
+    /// Whether we are inflating raw deflate data, as opposed to a whole gzip member including header and trailer.
+    raw: bool,
+    input: Vec<u8>,
+    /// Range of `input` that has not been consumed yet.
+    input_start: usize,
+    input_end: usize,
+    /// Offset in the file of the next byte to be read into `input`.
+    file_offset: u64,
+    /// Set when a gzip member has been followed by another one.
+    member_started: bool,
+    /// Set once the last gzip member has ended.
+    finished: bool,
+}
+
+impl Inflater {
+    fn new() -> io::Result<Self> {
+        let mut stream = Box::new(zlib::z_stream {
+            next_in: ptr::null_mut(),
+            avail_in: 0,
+            total_in: 0,
+            next_out: ptr::null_mut(),
+            avail_out: 0,
+            total_out: 0,
+            msg: ptr::null_mut(),
+            state: ptr::null_mut(),
+            zalloc,
+            zfree,
+            opaque: ptr::null_mut(),
+            data_type: 0,
+            adler: 0,
+            reserved: 0,
+        });
+        let ret = unsafe {
+            zlib::inflateInit2_(
+                &mut *stream,
+                GZIP_WINDOW_BITS,
+                zlib::zlibVersion(),
+                mem::size_of::<zlib::z_stream>() as c_int,
+            )
+        };
+        if ret != zlib::Z_OK {
+            return Err(zlib_error(ret));
+        }
+
+        Ok(Self {
+            stream,
+            raw: false,
+            input: vec![0; INPUT_BUFFER_SIZE],
+            input_start: 0,
+            input_end: 0,
+            file_offset: 0,
+            member_started: false,
+            finished: false,
+        })
+    }
+
+    /// Prepares to decompress from `checkpoint`.
+    fn start(&mut self, file: &mut File, checkpoint: &Checkpoint) -> io::Result<()> {
+        self.input_start = 0;
+        self.input_end = 0;
+        self.member_started = false;
+        self.finished = false;
+
+        let window = match &checkpoint.window {
+            Some(window) => window,
+            None => {
+                self.file_offset = checkpoint.compressed;
+                return self.reset(false);
+            }
+        };
+
+        self.reset(true)?;
+        if checkpoint.bits > 0 {
+            // The next block starts in the middle of the previous byte.
+            self.file_offset = checkpoint.compressed - 1;
+            if !self.ensure_input(file, 1)? {
+                return Err(truncated());
+            }
+            let byte = self.input[self.input_start] as c_int;
+            self.input_start += 1;
+            let bits = checkpoint.bits as c_int;
+            let ret = unsafe { zlib::inflatePrime(&mut *self.stream, bits, byte >> (8 - bits)) };
+            if ret != zlib::Z_OK {
+                return Err(zlib_error(ret));
+            }
+        } else {
+            self.file_offset = checkpoint.compressed;
+        }
+
+        let mut history = Vec::with_capacity(WINDOW_SIZE);
+        DeflateDecoder::new(&window[..]).read_to_end(&mut history)?;
+        let ret = unsafe {
+            zlib::inflateSetDictionary(&mut *self.stream, history.as_ptr(), history.len() as c_uint)
+        };
+        if ret != zlib::Z_OK {
+            return Err(zlib_error(ret));
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self, raw: bool) -> io::Result<()> {
+        let window_bits = if raw {
+            RAW_WINDOW_BITS
+        } else {
+            GZIP_WINDOW_BITS
+        };
+        let ret = unsafe { zlib::inflateReset2(&mut *self.stream, window_bits) };
+        if ret != zlib::Z_OK {
+            return Err(zlib_error(ret));
+        }
+        self.raw = raw;
+        Ok(())
+    }
+
+    /// Offset in the file of the first byte that has not been consumed.
+    fn consumed_offset(&self) -> u64 {
+        self.file_offset - (self.input_end - self.input_start) as u64
+    }
+
+    /// Makes sure that at least `len` bytes of input are available, returns false if the file ends before.
+    fn ensure_input(&mut self, file: &mut File, len: usize) -> io::Result<bool> {
+        if self.input_end - self.input_start >= len {
+            return Ok(true);
+        }
+
+        self.input.copy_within(self.input_start..self.input_end, 0);
+        self.input_end -= self.input_start;
+        self.input_start = 0;
+        file.seek(SeekFrom::Start(self.file_offset))?;
+        while self.input_end < len {
+            match file.read(&mut self.input[self.input_end..])? {
+                0 => return Ok(false),
+                n => {
+                    self.input_end += n;
+                    self.file_offset += n as u64;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Decompresses into `output` and returns the number of bytes produced.
+    ///
+    /// With `Z_NO_FLUSH`, this only returns 0 once all gzip members have ended. With `Z_BLOCK`, it also returns
+    /// at the end of every deflate block.
+    fn inflate(&mut self, file: &mut File, output: &mut [u8], flush: c_int) -> io::Result<usize> {
+        loop {
+            if self.finished {
+                return Ok(0);
+            }
+            if !self.ensure_input(file, 1)? {
+                return Err(truncated());
+            }
+
+            let available = self.input_end - self.input_start;
+            self.stream.next_in = self.input[self.input_start..].as_mut_ptr();
+            self.stream.avail_in = available as c_uint;
+            self.stream.next_out = output.as_mut_ptr();
+            self.stream.avail_out = output.len() as c_uint;
+            let ret = unsafe { zlib::inflate(&mut *self.stream, flush) };
+            self.input_start += available - self.stream.avail_in as usize;
+            let produced = output.len() - self.stream.avail_out as usize;
+
+            match ret {
+                zlib::Z_OK | zlib::Z_BUF_ERROR => (),
+                zlib::Z_STREAM_END => self.end_member(file)?,
+                _ => return Err(zlib_error(ret)),
+            }
+
+            if produced > 0 || flush == zlib::Z_BLOCK || self.finished {
+                return Ok(produced);
+            }
+        }
+    }
+
+    /// Continues with the next gzip member, if there is one.
+    fn end_member(&mut self, file: &mut File) -> io::Result<()> {
+        // Raw inflating stops before the trailer, while zlib consumes it when it handles the gzip format.
+        if self.raw {
+            if !self.ensure_input(file, GZIP_TRAILER_SIZE)? {
+                return Err(truncated());
+            }
+            self.input_start += GZIP_TRAILER_SIZE;
+        }
+
+        // Anything else than another member after the end of a member is ignored, just like gzip does.
+        let has_next_member = self.ensure_input(file, GZIP_MAGIC.len())?
+            && &self.input[self.input_start..self.input_start + GZIP_MAGIC.len()] == GZIP_MAGIC;
+        if has_next_member {
+            self.reset(false)?;
+            self.member_started = true;
+        } else {
+            self.finished = true;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Inflater {
+    fn drop(&mut self) {
+        unsafe {
+            zlib::inflateEnd(&mut *self.stream);
+        }
+    }
+}
+
+// zlib's inflate state is only accessed through `&mut Inflater`.
+unsafe impl Send for Inflater {}
+
+extern "C" fn zalloc(_opaque: *mut c_void, items: c_uint, size: c_uint) -> *mut c_void {
+    unsafe { libc::calloc(items as usize, size as usize) }
+}
+
+extern "C" fn zfree(_opaque: *mut c_void, address: *mut c_void) {
+    unsafe { libc::free(address) }
+}
+
+fn zlib_error(ret: c_int) -> io::Error {
+    let message = match ret {
+        zlib::Z_NEED_DICT | zlib::Z_DATA_ERROR => "invalid or corrupted gzip data",
+        zlib::Z_MEM_ERROR => "out of memory while decompressing",
+        _ => "zlib error",
+    };
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+    use flate2::write::GzEncoder;
+
+    /// Returns compressible data that doesn't repeat within the deflate window.
+    fn data(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                b"ntfs explorer "[(state >> 16) as usize % 14]
+            })
+            .collect()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Writes `compressed` to a file and opens it with a freshly built index.
+    fn open(name: &str, compressed: &[u8]) -> GzipReader {
+        let path = test_dir(name).join("image.gz");
+        fs::write(&path, compressed).unwrap();
+        let mut file = File::open(&path).unwrap();
+        let (checkpoints, size) = build_index(&mut file, &mut |_| ()).unwrap();
+        GzipReader::with_index(file, checkpoints, size, false).unwrap()
+    }
+
+    fn read_at(reader: &mut GzipReader, position: u64, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        reader.seek(SeekFrom::Start(position)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn resume_at_checkpoints() {
+        let data = data(3 * CHECKPOINT_SPAN as usize, 1);
+        let mut reader = open("gzip-checkpoints", &gzip(&data));
+        assert_eq!(reader.size(), data.len() as u64);
+        assert!(reader.checkpoint_count() >= 3);
+        // Deflate blocks rarely end on a byte boundary, so some checkpoints need `inflatePrime`.
+        assert!(reader
+            .checkpoints
+            .iter()
+            .any(|checkpoint| checkpoint.bits != 0));
+
+        // Going backwards makes the reader resume at each checkpoint in turn.
+        let positions = reader
+            .checkpoints
+            .iter()
+            .map(|checkpoint| checkpoint.uncompressed)
+            .collect::<Vec<u64>>();
+        for &position in positions.iter().rev() {
+            let start = position.saturating_sub(100);
+            let end = (position + 100).min(data.len() as u64);
+            let expected = &data[start as usize..end as usize];
+            assert_eq!(read_at(&mut reader, start, expected.len()), expected);
+        }
+
+        let mut all = Vec::new();
+        reader.seek(SeekFrom::Start(0)).unwrap();
+        reader.read_to_end(&mut all).unwrap();
+        assert!(all == data);
+    }
+
+    #[test]
+    fn multiple_members() {
+        let (first, second) = (data(100_000, 2), data(50_000, 3));
+        let mut compressed = gzip(&first);
+        compressed.extend_from_slice(&gzip(&second));
+        // Trailing zeros, as left by some tape drives, are ignored like gzip does.
+        compressed.extend_from_slice(&[0; 512]);
+        let mut reader = open("gzip-members", &compressed);
+
+        // Each member starts with a checkpoint that needs no history.
+        assert_eq!(reader.checkpoint_count(), 2);
+        assert_eq!(reader.checkpoints[1].uncompressed, first.len() as u64);
+        assert!(reader.checkpoints[1].window.is_none());
+
+        let expected = [&first[..], &second[..]].concat();
+        assert_eq!(reader.size(), expected.len() as u64);
+        assert_eq!(
+            read_at(&mut reader, first.len() as u64 + 10, 1000),
+            expected[first.len() + 10..first.len() + 1010]
+        );
+        assert_eq!(
+            read_at(&mut reader, first.len() as u64 - 500, 1000),
+            expected[first.len() - 500..first.len() + 500]
+        );
+    }
+
+    #[test]
+    fn truncated_file() {
+        let compressed = gzip(&data(100_000, 4));
+        let path = test_dir("gzip-truncated").join("image.gz");
+        fs::write(&path, &compressed[..compressed.len() / 2]).unwrap();
+
+        let error = build_index(&mut File::open(&path).unwrap(), &mut |_| ())
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn saved_index() {
+        let data = data(2 * CHECKPOINT_SPAN as usize, 5);
+        let dir = test_dir("gzip-index");
+        let path = dir.join("image.gz");
+        fs::write(&path, gzip(&data)).unwrap();
+        let mut file = File::open(&path).unwrap();
+        let metadata = file.metadata().unwrap();
+        let (checkpoints, size) = build_index(&mut file, &mut |_| ()).unwrap();
+
+        let cache_path = dir.join("cache").join("image.gz.gzindex");
+        save_index(&cache_path, &metadata, &checkpoints, size).unwrap();
+        let (loaded, loaded_size) = load_index(&cache_path, &metadata).unwrap();
+        assert_eq!(loaded_size, size);
+        assert_eq!(loaded.len(), checkpoints.len());
+        for (loaded, checkpoint) in loaded.iter().zip(&checkpoints) {
+            assert_eq!(loaded.uncompressed, checkpoint.uncompressed);
+            assert_eq!(loaded.compressed, checkpoint.compressed);
+            assert_eq!(loaded.bits, checkpoint.bits);
+            assert_eq!(loaded.window, checkpoint.window);
+        }
+
+        // The loaded index resumes just like the built one.
+        let mut reader = GzipReader::with_index(file, loaded, loaded_size, true).unwrap();
+        let position = checkpoints.last().unwrap().uncompressed as usize + 1000;
+        assert_eq!(
+            read_at(&mut reader, position as u64, 1000),
+            data[position..position + 1000]
+        );
+
+        // An index of an image that has changed since is not used.
+        fs::write(&path, gzip(&data[..1000])).unwrap();
+        let changed = fs::metadata(&path).unwrap();
+        assert!(load_index(&cache_path, &changed).is_err());
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::HashSet;
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use itertools::Itertools;
+use md5::{Digest, Md5};
+use sha1::Sha1;
+use sha2::Sha256;
+
+use crate::export::{host_file_name, unique_path_by};
+use crate::volume::{DirEntry, Volume};
+use crate::walk::{walk, Visitor};
+
+/// Hashes of the content of a file, as lowercase hex.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileHashes {
+    pub md5: String,
+    pub sha1: String,
+    pub sha256: String,
+}
+
+/// A file that was hashed, or failed to.
+#[derive(Clone, Debug)]
+pub struct HashedFile {
+    pub record_number: u64,
+    /// Path on the volume.
+    pub path: String,
+    /// Path relative to the root of the list that the file gets when exported, with `/` as separator.
+    pub export_path: String,
+    /// Bytes hashed.
+    pub size: u64,
+    pub hashes: Result<FileHashes, String>,
+}
+
+/// The result of hashing files, to be saved as a hash list.
+#[derive(Clone, Debug, Default)]
+pub struct HashList {
+    /// Path of the directory the hashed files were listed in, paths in `md5sum` lists are relative to it.
+    pub root: String,
+    pub files: Vec<HashedFile>,
+    /// Whether hashing was stopped before all files were hashed.
+    pub cancelled: bool,
+}
+
+impl HashList {
+    pub fn bytes(&self) -> u64 {
+        self.files.iter().map(|file| file.size).sum()
+    }
+
+    pub fn errors(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|file| file.hashes.is_err())
+            .count()
+    }
+
+    /// Writes the list as CSV with a header line, including files that couldn't be hashed.
+    pub fn write_csv(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(writer, "record,path,size,md5,sha1,sha256,error")?;
+        for file in &self.files {
+            let (md5, sha1, sha256, error) = match &file.hashes {
+                Ok(hashes) => (&*hashes.md5, &*hashes.sha1, &*hashes.sha256, ""),
+                Err(err) => ("", "", "", &**err),
+            };
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{}",
+                file.record_number,
+                csv_field(&file.path),
+                file.size,
+                md5,
+                sha1,
+                sha256,
+                csv_field(error)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes the MD5 hashes in the format of `md5sum`, so that `md5sum -c` can check files exported from the root
+    /// directory of the list. Files that couldn't be hashed are left out.
+    ///
+    /// Files are listed under their export paths, so names that are illegal on Windows are changed the same way, and
+    /// names that an export of the same files into an empty folder gives a number to get the same number.
+    /// Those names have no backslashes or line breaks that md5sum would escape. Exports with alternate data streams,
+    /// into folders that aren't empty or onto file systems that ignore case can number files differently.
+    pub fn write_md5sum(&self, writer: &mut dyn Write) -> io::Result<()> {
+        for file in &self.files {
+            if let Ok(hashes) = &file.hashes {
+                writeln!(writer, "{}  {}", hashes.md5, file.export_path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Hashes the content of the files listed in `directory`, which has the path `source_directory` on the volume.
+/// Folders are hashed with everything below them.
+///
+/// `progress` is called with the file being hashed and the number of bytes hashed so far, and stops hashing by
+/// returning `false`.
+/// Files that can't be read are recorded in the list rather than ending the job.
+pub fn hash_files<R, F>(
+    volume: &mut Volume<R>,
+    directory: u64,
+    source_directory: &str,
+    entries: &[DirEntry],
+    progress: F,
+) -> HashList
+where
+    R: Read + Seek,
+    F: FnMut(&str, u64) -> bool,
+{
+    let mut hasher = Hasher {
+        progress,
+        list: HashList {
+            root: source_directory.to_owned(),
+            ..HashList::default()
+        },
+        bytes: 0,
+        export_paths: HashSet::new(),
+    };
+    if !walk(
+        volume,
+        directory,
+        source_directory,
+        entries,
+        &PathBuf::new(),
+        &mut hasher,
+    ) {
+        hasher.list.cancelled = true;
+    }
+    hasher.list
+}
+
+struct Hasher<F> {
+    progress: F,
+    list: HashList,
+    /// Bytes hashed so far, for progress reports.
+    bytes: u64,
+    /// Paths that files and folders get when exported, to number the names that are taken like an export does.
+    export_paths: HashSet<PathBuf>,
+}
+
+impl<R, F> Visitor<R> for Hasher<F>
+where
+    R: Read + Seek,
+    F: FnMut(&str, u64) -> bool,
+{
+    /// The export path of the folder.
+    type Context = PathBuf;
+
+    fn file(
+        &mut self,
+        volume: &mut Volume<R>,
+        entry: &DirEntry,
+        path: &str,
+        parent: &PathBuf,
+    ) -> bool {
+        let mut digests = Digests::default();
+        let bytes = self.bytes;
+        let progress = &mut self.progress;
+        let result = volume.copy_stream_with_progress(
+            entry.record_number,
+            "",
+            &mut digests,
+            &mut |hashed| progress(path, bytes + hashed),
+        );
+        let (size, hashes) = match result {
+            Ok(Some(size)) => (size, Ok(digests.finalize())),
+            Ok(None) => return false,
+            Err(err) => (0, Err(format!("{:#}", err))),
+        };
+        self.bytes += size;
+        // The export creates the file before reading it, so the name is taken even if that fails.
+        let export_path = self.take_export_path(entry, parent);
+        self.list.files.push(HashedFile {
+            record_number: entry.record_number,
+            path: path.to_owned(),
+            export_path: export_path_string(&export_path),
+            size,
+            hashes,
+        });
+        true
+    }
+
+    fn enter_directory(&mut self, entry: &DirEntry, _: &str, parent: &PathBuf) -> Option<PathBuf> {
+        Some(self.take_export_path(entry, parent))
+    }
+
+    fn directory_failed(
+        &mut self,
+        entry: &DirEntry,
+        path: &str,
+        parent: &PathBuf,
+        err: anyhow::Error,
+    ) {
+        let export_path = unique_path_by(&parent.join(host_file_name(&entry.name)), |path| {
+            self.export_paths.contains(path)
+        });
+        self.list.files.push(HashedFile {
+            record_number: entry.record_number,
+            path: path.to_owned(),
+            export_path: export_path_string(&export_path),
+            size: 0,
+            hashes: Err(format!("{:#}", err)),
+        });
+    }
+}
+
+impl<F> Hasher<F> {
+    /// Returns the path the export gives to `entry` in the folder with the export path `parent`.
+    fn take_export_path(&mut self, entry: &DirEntry, parent: &Path) -> PathBuf {
+        let export_path = unique_path_by(&parent.join(host_file_name(&entry.name)), |path| {
+            self.export_paths.contains(path)
+        });
+        self.export_paths.insert(export_path.clone());
+        export_path
+    }
+}
+
+fn export_path_string(export_path: &Path) -> String {
+    export_path
+        .iter()
+        .map(|component| component.to_string_lossy())
+        .join("/")
+}
+
+/// Feeds everything written to it into all hashes at once.
+#[derive(Default)]
+struct Digests {
+    md5: Md5,
+    sha1: Sha1,
+    sha256: Sha256,
+}
+
+impl Digests {
+    fn finalize(self) -> FileHashes {
+        FileHashes {
+            md5: format!("{:x}", self.md5.finalize()),
+            sha1: format!("{:x}", self.sha1.finalize()),
+            sha256: format!("{:x}", self.sha256.finalize()),
+        }
+    }
+}
+
+impl Write for Digests {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.md5.update(buf);
+        self.sha1.update(buf);
+        self.sha256.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Quotes a CSV field if it contains anything that would break the line into more fields.
+pub(crate) fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashed_file(path: &str, export_path: &str, md5: &str) -> HashedFile {
+        HashedFile {
+            record_number: 64,
+            path: path.to_owned(),
+            export_path: export_path.to_owned(),
+            size: 3,
+            hashes: Ok(FileHashes {
+                md5: md5.to_owned(),
+                sha1: "sha1".to_owned(),
+                sha256: "sha256".to_owned(),
+            }),
+        }
+    }
+
+    fn hash_list() -> HashList {
+        let mut failed = hashed_file(r"\Users\missing", "missing", "");
+        failed.size = 0;
+        failed.hashes = Err("unreadable, bad sectors".to_owned());
+        HashList {
+            root: r"\Users".to_owned(),
+            files: vec![
+                hashed_file(r"\Users\a:b", "a_b", "0cc175b9c0f1b6a831c399e269772661"),
+                hashed_file(
+                    r"\Users\x\a?b",
+                    "x/a_b (1)",
+                    "92eb5ffee6ae2fec3ad71c777531578f",
+                ),
+                failed,
+            ],
+            cancelled: false,
+        }
+    }
+
+    #[test]
+    fn md5sum_lists_export_paths() {
+        let mut md5sum = vec![];
+        hash_list().write_md5sum(&mut md5sum).unwrap();
+        assert_eq!(
+            String::from_utf8(md5sum).unwrap(),
+            "0cc175b9c0f1b6a831c399e269772661  a_b\n\
+             92eb5ffee6ae2fec3ad71c777531578f  x/a_b (1)\n"
+        );
+    }
+
+    #[test]
+    fn csv_includes_errors() {
+        let mut csv = vec![];
+        hash_list().write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[1],
+            r"64,\Users\a:b,3,0cc175b9c0f1b6a831c399e269772661,sha1,sha256,"
+        );
+        assert_eq!(
+            lines[3],
+            r#"64,\Users\missing,0,,,,"unreadable, bad sectors""#
+        );
+    }
+
+    #[test]
+    fn csv_fields() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
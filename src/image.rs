@@ -0,0 +1,365 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use bytesize::ByteSize;
+
+use crate::compressed::BlockReader;
+use crate::ewf::{EwfReader, EWF2_SIGNATURE, EWF_SIGNATURE};
+use crate::gzip::{GzipReader, GZIP_MAGIC};
+use crate::offset_reader::OffsetReader;
+use crate::qcow2::{Qcow2, QCOW2_MAGIC};
+use crate::split_image::{segment_paths, SplitImage};
+use crate::vhd::{Vhd, VHD_FOOTER_COOKIE};
+use crate::vhdx::{Vhdx, VHDX_SIGNATURE};
+use crate::virtual_disk::{VirtualDisk, VirtualDiskReader};
+use crate::vmdk::{Vmdk, VMDK_DESCRIPTOR_START, VMDK_SPARSE_MAGIC};
+use crate::xz::{open_xz, XZ_MAGIC};
+use crate::zstd_seekable::{open_zstd, ZSTD_MAGIC};
+
+/// Maximum length of a chain of differencing disks, to not loop forever on disks that are their own parent.
+const MAX_PARENT_DEPTH: usize = 32;
+
+/// Any seekable stream that can be handed to the worker thread.
+pub trait ReadSeek: Read + Seek + Send {}
+
+impl<T> ReadSeek for T where T: Read + Seek + Send {}
+
+/// An opened disk image, with its container format already taken care of.
+pub struct Image {
+    /// The raw disk or volume contained in the image.
+    pub reader: Box<dyn ReadSeek>,
+    /// Details about the image, as pairs of section headline and name/value pairs.
+    pub properties: Vec<(String, Vec<(String, String)>)>,
+    /// Logical sector size of the disk, if the device or container format records it.
+    pub sector_size: Option<u64>,
+}
+
+/// Opens a raw image, raw device, evidence container or virtual disk and returns a stream of the disk inside.
+///
+/// The container format is detected from the file signature, split images from their file names.
+/// Parents of differencing disks are opened as well.
+///
+/// Opening a gzip compressed image for the first time takes long, `progress` is called with status messages then.
+pub fn open_image(path: &Path, progress: &mut dyn FnMut(String)) -> io::Result<Image> {
+    open_disk(path, 0, progress)
+}
+
+fn open_disk(path: &Path, depth: usize, progress: &mut dyn FnMut(String)) -> io::Result<Image> {
+    if let Some(segments) = segment_paths(path) {
+        let reader = SplitImage::open(&segments)?;
+        let properties = vec![(
+            "Image".to_owned(),
+            vec![
+                ("Format".to_owned(), "Raw (split)".to_owned()),
+                ("Segments".to_owned(), reader.segment_count().to_string()),
+                (
+                    "First segment".to_owned(),
+                    segments[0].display().to_string(),
+                ),
+            ],
+        )];
+        return Ok(Image {
+            reader: Box::new(reader),
+            properties,
+            sector_size: None,
+        });
+    }
+
+    let mut file = File::open(path)?;
+    let mut signature = [0u8; 32];
+    let signature_len = read_signature(&mut file, &mut signature)?;
+    let signature = &signature[..signature_len];
+
+    if signature.starts_with(&EWF_SIGNATURE) || signature.starts_with(&EWF2_SIGNATURE) {
+        let reader = EwfReader::open(path)?;
+        let properties = ewf_properties(&reader);
+        let bytes_per_sector = reader.metadata().bytes_per_sector as u64;
+        return Ok(Image {
+            reader: Box::new(reader),
+            properties,
+            sector_size: if bytes_per_sector != 0 {
+                Some(bytes_per_sector)
+            } else {
+                None
+            },
+        });
+    }
+
+    // Fixed VHDs are raw images with a footer, so they can only be told apart by their extension.
+    let has_vhd_extension = path
+        .extension()
+        .map_or(false, |extension| extension.eq_ignore_ascii_case("vhd"));
+    let disk: Option<Box<dyn VirtualDisk>> =
+        if signature.starts_with(VHD_FOOTER_COOKIE) || has_vhd_extension {
+            Some(Box::new(Vhd::open(path)?))
+        } else if signature.starts_with(VHDX_SIGNATURE) {
+            Some(Box::new(Vhdx::open(path)?))
+        } else if signature.starts_with(VMDK_SPARSE_MAGIC)
+            || signature.starts_with(VMDK_DESCRIPTOR_START)
+        {
+            Some(Box::new(Vmdk::open(path)?))
+        } else if signature.starts_with(QCOW2_MAGIC) {
+            Some(Box::new(Qcow2::open(path)?))
+        } else {
+            None
+        };
+    if let Some(disk) = disk {
+        return open_virtual_disk(path, disk, depth, progress);
+    }
+
+    if signature.starts_with(GZIP_MAGIC) {
+        let compressed_size = file.metadata()?.len().max(1);
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let reader = GzipReader::open(path, &mut |consumed| {
+            progress(format!(
+                "Indexing {} ({}%), this is only necessary once",
+                file_name,
+                consumed * 100 / compressed_size
+            ))
+        })?;
+        let index = format!(
+            "{} checkpoints{}",
+            reader.checkpoint_count(),
+            if reader.index_cached() {
+                ", cached"
+            } else {
+                ""
+            }
+        );
+        let properties = vec![(
+            "Image".to_owned(),
+            vec![
+                ("Format".to_owned(), "Raw (gzip compressed)".to_owned()),
+                ("Size".to_owned(), format!("{}", ByteSize(reader.size()))),
+                ("Index".to_owned(), index),
+            ],
+        )];
+        return Ok(Image {
+            reader: Box::new(reader),
+            properties,
+            sector_size: None,
+        });
+    }
+
+    if signature.starts_with(XZ_MAGIC) {
+        let reader = open_xz(path)?;
+        return Ok(compressed_image(reader, "Raw (xz compressed)", "Blocks"));
+    }
+
+    if signature.starts_with(ZSTD_MAGIC) {
+        let (reader, seekable) = open_zstd(path)?;
+        let format = if seekable {
+            "Raw (zstd seekable format)"
+        } else {
+            "Raw (zstd compressed, not seekable)"
+        };
+        return Ok(compressed_image(reader, format, "Frames"));
+    }
+
+    let length = raw_length(&file);
+    let mut image = vec![("Format".to_owned(), "Raw".to_owned())];
+    if let Some(length) = length {
+        image.push(("Size".to_owned(), format!("{}", ByteSize(length))));
+    }
+    Ok(Image {
+        sector_size: raw_sector_size(&file),
+        reader: Box::new(OffsetReader::new(file, 0, length)?),
+        properties: vec![("Image".to_owned(), image)],
+    })
+}
+
+/// Determines the size of a raw image or device.
+///
+/// Devices are asked first, because they report a size of 0 in their metadata and seeking to their end is not
+/// reliable on every platform. Their size is queried from the kernel on Linux. Elsewhere, the size of a device
+/// remains unknown and is later taken from the NTFS boot sector by `SectorReader`.
+/// Only regular files are trusted to report their size in their metadata.
+fn raw_length(file: &File) -> Option<u64> {
+    let metadata = file.metadata().ok()?;
+
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        use std::os::unix::io::AsRawFd;
+
+        // _IOR(0x12, 114, size_t) from <linux/fs.h>, which the libc crate doesn't define.
+        const BLKGETSIZE64: libc::c_ulong =
+            0x8000_1272 | ((std::mem::size_of::<usize>() as libc::c_ulong) << 16);
+
+        if metadata.file_type().is_block_device() {
+            let mut size: u64 = 0;
+            let result = unsafe { libc::ioctl(file.as_raw_fd(), BLKGETSIZE64 as _, &mut size) };
+            return if result == 0 { Some(size) } else { None };
+        }
+    }
+
+    if metadata.is_file() {
+        Some(metadata.len())
+    } else {
+        None
+    }
+}
+
+/// Queries the logical sector size of a block device from the kernel on Linux.
+fn raw_sector_size(file: &File) -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        use std::os::unix::io::AsRawFd;
+
+        // _IO(0x12, 104) from <linux/fs.h>.
+        const BLKSSZGET: libc::c_ulong = 0x1268;
+
+        if file.metadata().ok()?.file_type().is_block_device() {
+            let mut sector_size: libc::c_int = 0;
+            let result = unsafe { libc::ioctl(file.as_raw_fd(), BLKSSZGET as _, &mut sector_size) };
+            if result == 0 && sector_size > 0 {
+                return Some(sector_size as u64);
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    let _ = file;
+
+    None
+}
+
+/// Opens the parent of a virtual disk, if it has one, and combines both into a single stream.
+fn open_virtual_disk(
+    path: &Path,
+    disk: Box<dyn VirtualDisk>,
+    depth: usize,
+    progress: &mut dyn FnMut(String),
+) -> io::Result<Image> {
+    let mut image = vec![
+        ("Format".to_owned(), disk.format_name()),
+        (
+            "Virtual size".to_owned(),
+            format!("{}", ByteSize(disk.size())),
+        ),
+    ];
+
+    let parent_paths = disk.parent_paths();
+    let mut parent_properties = Vec::new();
+    let parent = if parent_paths.is_empty() {
+        None
+    } else {
+        if depth >= MAX_PARENT_DEPTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "chain of parent disks is too long",
+            ));
+        }
+
+        let parent_path = parent_paths
+            .iter()
+            .find(|candidate| candidate.is_file() && candidate.as_path() != path)
+            .ok_or_else(|| {
+                let tried = parent_paths
+                    .iter()
+                    .map(|candidate| candidate.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "parent disk of {} not found (tried {})",
+                        path.display(),
+                        tried
+                    ),
+                )
+            })?;
+        let parent = open_disk(parent_path, depth + 1, progress)?;
+
+        image.push(("Parent".to_owned(), parent_path.display().to_string()));
+        parent_properties = parent
+            .properties
+            .into_iter()
+            .map(|(headline, values)| (format!("Parent {}", headline.to_lowercase()), values))
+            .collect();
+        Some(parent.reader)
+    };
+
+    let mut properties = vec![("Image".to_owned(), image)];
+    properties.extend(parent_properties);
+    Ok(Image {
+        sector_size: disk.logical_sector_size(),
+        reader: Box::new(VirtualDiskReader::new(disk, parent)),
+        properties,
+    })
+}
+
+fn compressed_image(reader: BlockReader, format: &str, blocks_name: &str) -> Image {
+    let mut image = vec![
+        ("Format".to_owned(), format.to_owned()),
+        (blocks_name.to_owned(), reader.block_count().to_string()),
+    ];
+    if let Some(size) = reader.known_size() {
+        image.push(("Size".to_owned(), format!("{}", ByteSize(size))));
+    }
+    if reader.block_count() == 1 {
+        image.push((
+            "Random access".to_owned(),
+            "Slow, the only block is decompressed from its start for every backward seek"
+                .to_owned(),
+        ));
+    }
+
+    Image {
+        reader: Box::new(reader),
+        properties: vec![("Image".to_owned(), image)],
+        sector_size: None,
+    }
+}
+
+/// Reads the first bytes of a file to detect its format.
+///
+/// Raw devices only allow sector-sized reads, so this reads an entire sector.
+fn read_signature(file: &mut File, signature: &mut [u8; 32]) -> io::Result<usize> {
+    let mut sector = [0u8; 512];
+    let mut len = 0;
+    while len < sector.len() {
+        match file.read(&mut sector[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+
+    let len = len.min(signature.len());
+    signature[..len].copy_from_slice(&sector[..len]);
+    Ok(len)
+}
+
+fn ewf_properties(reader: &EwfReader) -> Vec<(String, Vec<(String, String)>)> {
+    let metadata = reader.metadata();
+    let media_size = reader.media_size();
+
+    let mut image = vec![
+        ("Format".to_owned(), "Expert Witness (E01)".to_owned()),
+        ("Segments".to_owned(), metadata.segment_count.to_string()),
+        ("Media size".to_owned(), format!("{}", ByteSize(media_size))),
+        (
+            "Bytes per sector".to_owned(),
+            metadata.bytes_per_sector.to_string(),
+        ),
+    ];
+    if let Some(md5) = metadata.md5 {
+        image.push(("Stored MD5".to_owned(), hex(&md5)));
+    }
+    if let Some(sha1) = metadata.sha1 {
+        image.push(("Stored SHA1".to_owned(), hex(&sha1)));
+    }
+
+    let mut properties = vec![("Image".to_owned(), image)];
+    if !metadata.header.is_empty() {
+        properties.push(("Case".to_owned(), metadata.header.clone()));
+    }
+    properties
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
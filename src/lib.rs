@@ -0,0 +1,43 @@
+//! Reading NTFS volumes from disk images and raw devices.
+//!
+//! `Disk` opens an image in any of the supported container formats and finds the volumes on it,
+//! `Volume` lists directories and reads files of one of them.
+//!
+//! The GUI of NTFS Explorer is built on top of this library. Build with `default-features = false` to use the
+//! library without the GUI dependencies.
+
+pub mod bad_sectors;
+pub mod block_cache;
+pub mod boot_sector;
+mod compressed;
+pub mod deleted;
+pub mod disk;
+mod ewf;
+pub mod export;
+#[cfg(feature = "fuse")]
+pub mod fuse;
+mod gzip;
+pub mod hashes;
+pub mod image;
+pub mod offset_reader;
+pub mod partition;
+mod qcow2;
+pub mod sector_reader;
+mod split_image;
+#[cfg(test)]
+mod test_util;
+pub mod timeline;
+pub mod timestomping;
+pub mod usn;
+mod vhd;
+mod vhdx;
+mod virtual_disk;
+mod vmdk;
+pub mod volume;
+mod walk;
+mod xz;
+pub mod zone_identifier;
+mod zstd_seekable;
+
+pub use disk::{Disk, DiskOptions};
+pub use volume::{DirEntry, FileInfo, Volume};
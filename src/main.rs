@@ -1,40 +1,282 @@
 use std::{
+    cell::RefCell,
+    collections::HashMap,
     fs::File,
-    io::{BufReader, Read, Seek},
-    sync::mpsc,
+    io::{BufWriter, Read, Seek, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use bytesize::ByteSize;
-use chrono::{DateTime, Utc};
+use clap::Parser;
 use itertools::{enumerate, Itertools};
-use ntfs::{
-    indexes::NtfsFileNameIndex,
-    structured_values::{NtfsFileName, NtfsFileNamespace, NtfsObjectId, NtfsStandardInformation},
-    Ntfs, NtfsAttributeType, NtfsFile, NtfsTime,
+use ntfs_explorer::block_cache::CacheConfig;
+use ntfs_explorer::deleted::{recover_files, DeletedFile, RecoveryLog};
+use ntfs_explorer::disk::parse_sector_size;
+use ntfs_explorer::export::{export, ExportLog, ExportOptions};
+use ntfs_explorer::hashes::{hash_files, FileHashes, HashList};
+use ntfs_explorer::partition::Partition;
+use ntfs_explorer::timeline::{
+    parse_range_end, parse_range_start, TimelineFormat, TimelineOptions,
 };
-use sector_reader::SectorReader;
+use ntfs_explorer::timestomping::{anomalies, format_precise_time, VolumeLifetime};
+use ntfs_explorer::usn::{UsnFilter, UsnJournal, UsnRecord};
+use ntfs_explorer::volume::{
+    format_time, BadSectorOverlap, DirEntry, FileInfo, FileTimes, ROOT_DIRECTORY,
+};
+use ntfs_explorer::zone_identifier::ZoneIdentifier;
+use ntfs_explorer::{Disk, DiskOptions, Volume};
+use slint::Model;
+
+use columns::{ColumnLayout, SortOrder, COLUMNS};
 
-mod sector_reader;
+mod columns;
 
 slint::include_modules!();
 
+/// Browse NTFS volumes in disk images and raw devices.
+#[derive(Parser)]
+#[clap(version, about)]
+struct Args {
+    /// An image file or raw device (like `/dev/sdb1` or `\\.\C:`) to open.
+    image: Option<PathBuf>,
+    /// Size of the block cache in MiB.
+    #[clap(long, default_value_t = 64)]
+    cache_size: usize,
+    /// Maximum number of KiB to read ahead when reading sequentially, 0 disables read-ahead.
+    #[clap(long, default_value_t = 1024)]
+    read_ahead: usize,
+    /// Fill unreadable sectors instead of failing, for images of failing disks.
+    #[clap(long)]
+    tolerant: bool,
+    /// Hex pattern to fill unreadable sectors with in tolerant mode, like `00` or `deadbeef`.
+    #[clap(long, value_parser = parse_fill_pattern, default_value = "00")]
+    bad_sector_fill: FillPattern,
+    /// ddrescue mapfile of the image, everything not marked as finished is treated as bad sectors.
+    /// Implies `--tolerant`.
+    #[clap(long, requires = "image")]
+    mapfile: Option<PathBuf>,
+    /// Logical sector size of the disk in bytes.
+    /// Detected from the device, the GPT header or the NTFS boot sector by default.
+    #[clap(long, value_parser = parse_sector_size)]
+    sector_size: Option<u64>,
+}
+
+#[derive(Clone, Debug)]
+struct FillPattern(Vec<u8>);
+
+fn parse_fill_pattern(s: &str) -> Result<FillPattern, String> {
+    ntfs_explorer::disk::parse_fill_pattern(s).map(FillPattern)
+}
+
+/// How images are read, as configured on the command line.
+struct ReadOptions {
+    cache_config: CacheConfig,
+    /// Fill pattern for unreadable sectors, `None` to fail on them.
+    bad_sector_fill: Option<Vec<u8>>,
+    /// A ddrescue mapfile along with the image it belongs to.
+    mapfile: Option<(PathBuf, PathBuf)>,
+    /// Sector size chosen on the command line or in the toolbar, 0 to detect it.
+    /// This is shared with the UI thread, which updates it before opening an image.
+    sector_size: Arc<AtomicU64>,
+}
+
 enum Command {
+    OpenImage(PathBuf),
+    OpenVolume(usize),
+    ShowVolumes(),
+    ScanVolumes(),
     EnterSubdir(String),
     MoveToParent(),
+    /// Go to a path typed into the address bar.
+    NavigateTo(String),
+    /// Go to the ancestor at the given depth, 0 being the root directory.
+    NavigateUp(usize),
+    /// Go to a file or directory by its file record number.
+    GoToRecord(u64),
+    Back(),
+    Forward(),
+    /// Show the current directory again, after the listing options changed.
+    Refresh(),
+    /// Show the properties and alternate data streams of the file with the given index in the file list.
+    ShowDetails(usize),
+    /// Show the start of an alternate data stream of the file with the given index in the file list.
+    ViewStream(usize, String),
+    /// Write an alternate data stream of the file with the given index in the file list to a file.
+    ExportStream(usize, String, PathBuf),
+    /// Export the files with the given indexes in the file list, or all of them if there are none, into a folder.
+    Export(Vec<usize>, PathBuf, ExportOptions),
+    /// Hash the files with the given indexes in the file list, or all of them if there are none, folders with
+    /// everything below them.
+    Hash(Vec<usize>),
+    /// Show the deleted files found in `$MFT`, scanning it first if that hasn't been done yet.
+    ShowDeleted(),
+    /// Recover the deleted files with the given indexes in the file list, or all of them if there are none, into a
+    /// folder.
+    Recover(Vec<usize>, PathBuf),
+    /// Check the timestamps of every file for signs of timestomping and save the suspicious files as CSV.
+    SaveTimestompingReport(PathBuf),
+    /// Read the timestamps of every file record and save them as a timeline.
+    SaveTimeline(TimelineOptions, TimelineFormat, PathBuf),
+    /// Show the change journal records that match a filter, reading the journal first if that hasn't been done yet.
+    ShowUsn(String),
+    /// Save the change journal records that match a filter, as JSON if the file name ends in `.json` and as CSV
+    /// otherwise.
+    ExportUsn(String, PathBuf),
+    /// Save the hashes of the last hash job, as `md5sum` list if the file name ends in `.md5` and as CSV otherwise.
+    SaveHashList(PathBuf),
+}
+
+/// Stands in for a file record number in the path of the virtual folder of deleted files.
+const DELETED_FILES: u64 = u64::MAX;
+/// Upper limit for the change journal records in the list, the journal can hold millions of them.
+const MAX_USN_ITEMS: usize = 10_000;
+
+/// Results of jobs run on the opened volume, shown along with the files they concern.
+#[derive(Default)]
+pub struct Findings {
+    /// Hashes of every file hashed so far, by file record number.
+    hashes: HashMap<u64, FileHashes>,
+    /// Deleted files by file record number, `None` until `$MFT` has been scanned for them.
+    deleted: Option<HashMap<u64, DeletedFile>>,
+    /// When the volume was formatted, to check the timestamps of files against. `None` if `$Volume` can't be read.
+    lifetime: Option<VolumeLifetime>,
+    /// The change journal, `None` until it has been read.
+    usn: Option<UsnJournal>,
+}
+
+/// How directories are listed, as chosen in the UI.
+#[derive(Clone, Copy, Debug)]
+struct ListingOptions {
+    sort: SortOrder,
+    /// List files once for each of their names, short names included, instead of under their preferred name.
+    all_names: bool,
+    /// List the system files in place of the root directory.
+    system_files: bool,
 }
 
 fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let sector_size = Arc::new(AtomicU64::new(args.sector_size.unwrap_or_default()));
+    let options = ReadOptions {
+        cache_config: CacheConfig::new(args.cache_size << 20, args.read_ahead << 10),
+        bad_sector_fill: if args.tolerant || args.mapfile.is_some() {
+            Some(args.bad_sector_fill.0)
+        } else {
+            None
+        },
+        mapfile: args
+            .mapfile
+            .zip(args.image.clone())
+            .map(|(mapfile, image)| (image, mapfile)),
+        sector_size: sector_size.clone(),
+    };
     let ui = MainWindow::new();
+    ui.set_sector_size_choice(match args.sector_size {
+        Some(sector_size) => sector_size.to_string().into(),
+        None => "Auto".into(),
+    });
+
+    // The column layout belongs to the UI thread, the worker only needs to know how to sort.
+    let layout = Rc::new(RefCell::new(ColumnLayout::load()));
+    let listing = Arc::new(Mutex::new(ListingOptions {
+        sort: layout.borrow().sort,
+        all_names: false,
+        system_files: false,
+    }));
+    let columns_model = Rc::new(slint::VecModel::from(file_columns(&layout.borrow())));
+    ui.set_columns(columns_model.clone().into());
+    ui.set_sort_column(layout.borrow().sort_index() as i32);
+    ui.set_sort_descending(layout.borrow().sort.descending);
 
     let (tx, rx) = mpsc::channel();
+    let cancel = Arc::new(AtomicBool::new(false));
     let ui_handle = ui.as_weak();
+    let cancel1 = cancel.clone();
+    let listing1 = listing.clone();
     std::thread::spawn(move || -> () {
-        worker_thread(ui_handle, rx).unwrap();
+        worker_thread(ui_handle, rx, cancel1, listing1, options).unwrap();
+    });
+
+    if let Some(path) = args.image {
+        tx.send(Command::OpenImage(path)).unwrap();
+    }
+
+    let tx1 = tx.clone();
+    let sector_size1 = sector_size.clone();
+    let ui_handle = ui.as_weak();
+    ui.on_open_image_dialog(move || {
+        let path = rfd::FileDialog::new()
+            .set_title("Open image")
+            .add_filter("Disk images", &["img", "dd", "raw", "bin"])
+            .add_filter("Expert Witness images", &["E01", "e01"])
+            .add_filter("Virtual disks", &["vhd", "vhdx", "vmdk", "qcow2"])
+            .add_filter(
+                "Split and compressed images",
+                &["001", "000", "aa", "gz", "xz", "zst"],
+            )
+            .add_filter("All files", &["*"])
+            .pick_file();
+        if let Some(path) = path {
+            // "Auto" doesn't parse and becomes 0.
+            let choice = ui_handle.unwrap().get_sector_size_choice();
+            sector_size1.store(choice.parse().unwrap_or_default(), Ordering::Relaxed);
+            tx1.send(Command::OpenImage(path)).unwrap();
+        }
+    });
+
+    let tx1 = tx.clone();
+    ui.on_open_volume(move |index| {
+        tx1.send(Command::OpenVolume(index as usize)).unwrap();
+    });
+
+    let tx1 = tx.clone();
+    ui.on_show_volumes(move || {
+        tx1.send(Command::ShowVolumes()).unwrap();
+    });
+
+    let tx1 = tx.clone();
+    ui.on_scan_volumes(move || {
+        tx1.send(Command::ScanVolumes()).unwrap();
+    });
+
+    // The worker is busy while scanning or exporting, so cancellation has to bypass the command queue.
+    let cancel1 = cancel.clone();
+    ui.on_cancel_scan(move || {
+        cancel1.store(true, Ordering::Relaxed);
+    });
+
+    let cancel1 = cancel.clone();
+    ui.on_cancel_export(move || {
+        cancel1.store(true, Ordering::Relaxed);
+    });
+
+    let cancel1 = cancel.clone();
+    ui.on_cancel_hash(move || {
+        cancel1.store(true, Ordering::Relaxed);
+    });
+
+    let cancel1 = cancel.clone();
+    ui.on_cancel_recovery(move || {
+        cancel1.store(true, Ordering::Relaxed);
+    });
+
+    let cancel1 = cancel.clone();
+    ui.on_cancel_timeline(move || {
+        cancel1.store(true, Ordering::Relaxed);
+    });
+
+    ui.on_cancel_usn(move || {
+        cancel.store(true, Ordering::Relaxed);
     });
 
     let tx1 = tx.clone();
     ui.on_enter_directory(move |dir_name| {
-        println!("enter dir {}", dir_name);
         tx1.send(Command::EnterSubdir(dir_name.to_string()))
             .unwrap();
     });
@@ -44,6 +286,284 @@ fn main() -> anyhow::Result<()> {
         tx1.send(Command::MoveToParent()).unwrap();
     });
 
+    let tx1 = tx.clone();
+    let ui_handle = ui.as_weak();
+    ui.on_navigate_to(move |path| {
+        let command = match parse_record_number(&path) {
+            Some(record_number) => Command::GoToRecord(record_number),
+            None => match host_image_path(&path) {
+                Some(image) => {
+                    let choice = ui_handle.unwrap().get_sector_size_choice();
+                    sector_size.store(choice.parse().unwrap_or_default(), Ordering::Relaxed);
+                    Command::OpenImage(image)
+                }
+                None => Command::NavigateTo(path.to_string()),
+            },
+        };
+        tx1.send(command).unwrap();
+    });
+
+    let tx1 = tx.clone();
+    ui.on_go_back(move || {
+        tx1.send(Command::Back()).unwrap();
+    });
+
+    let tx1 = tx.clone();
+    ui.on_go_forward(move || {
+        tx1.send(Command::Forward()).unwrap();
+    });
+
+    let tx1 = tx.clone();
+    ui.on_navigate_up(move |depth| {
+        tx1.send(Command::NavigateUp(depth as usize)).unwrap();
+    });
+
+    let tx1 = tx.clone();
+    ui.on_show_details(move |file| {
+        tx1.send(Command::ShowDetails(file as usize)).unwrap();
+    });
+
+    let tx1 = tx.clone();
+    ui.on_view_stream(move |file, stream| {
+        tx1.send(Command::ViewStream(file as usize, stream.to_string()))
+            .unwrap();
+    });
+
+    let tx1 = tx.clone();
+    ui.on_export_stream(move |file, file_name, stream| {
+        let path = rfd::FileDialog::new()
+            .set_title("Export stream")
+            .set_file_name(&format!("{}_{}", file_name, stream))
+            .save_file();
+        if let Some(path) = path {
+            tx1.send(Command::ExportStream(
+                file as usize,
+                stream.to_string(),
+                path,
+            ))
+            .unwrap();
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_toggle_selected(move |index| {
+        let model = ui_handle.unwrap().get_file_model();
+        if let Some(mut file) = model.row_data(index as usize) {
+            file.selected = !file.selected;
+            model.set_row_data(index as usize, file);
+        }
+    });
+
+    let tx1 = tx.clone();
+    let ui_handle = ui.as_weak();
+    ui.on_export_files(move || {
+        let ui = ui_handle.unwrap();
+        let selected = ui
+            .get_file_model()
+            .iter()
+            .filter(|file| file.selected)
+            .map(|file| file.id as usize)
+            .collect_vec();
+        let options = ExportOptions {
+            named_streams: ui.get_export_named_streams(),
+            restore_times: ui.get_export_restore_times(),
+        };
+        let target = rfd::FileDialog::new()
+            .set_title("Export into folder")
+            .pick_folder();
+        if let Some(target) = target {
+            tx1.send(Command::Export(selected, target, options))
+                .unwrap();
+        }
+    });
+
+    let tx1 = tx.clone();
+    let layout1 = layout.clone();
+    let columns_model1 = columns_model.clone();
+    let ui_handle = ui.as_weak();
+    ui.on_hash_files(move |everything| {
+        let selected = if everything {
+            vec![]
+        } else {
+            ui_handle
+                .unwrap()
+                .get_file_model()
+                .iter()
+                .filter(|file| file.selected)
+                .map(|file| file.id as usize)
+                .collect_vec()
+        };
+        let mut layout = layout1.borrow_mut();
+        layout.show_hashes();
+        update_file_columns(&columns_model1, &layout);
+        tx1.send(Command::Hash(selected)).unwrap();
+    });
+
+    let tx1 = tx.clone();
+    ui.on_show_deleted(move || {
+        tx1.send(Command::ShowDeleted()).unwrap();
+    });
+
+    let tx1 = tx.clone();
+    let ui_handle = ui.as_weak();
+    ui.on_recover_files(move || {
+        let selected = ui_handle
+            .unwrap()
+            .get_file_model()
+            .iter()
+            .filter(|file| file.selected)
+            .map(|file| file.id as usize)
+            .collect_vec();
+        let target = rfd::FileDialog::new()
+            .set_title("Recover into folder")
+            .pick_folder();
+        if let Some(target) = target {
+            tx1.send(Command::Recover(selected, target)).unwrap();
+        }
+    });
+
+    let tx1 = tx.clone();
+    let layout1 = layout.clone();
+    let columns_model1 = columns_model.clone();
+    ui.on_save_timestomping_report(move || {
+        let path = rfd::FileDialog::new()
+            .set_title("Save timestomping report")
+            .add_filter("CSV", &["csv"])
+            .set_file_name("timestomping.csv")
+            .save_file();
+        if let Some(path) = path {
+            let mut layout = layout1.borrow_mut();
+            layout.show_timestomping();
+            update_file_columns(&columns_model1, &layout);
+            tx1.send(Command::SaveTimestompingReport(path)).unwrap();
+        }
+    });
+
+    let tx1 = tx.clone();
+    let ui_handle = ui.as_weak();
+    ui.on_save_timeline(move || {
+        let ui = ui_handle.unwrap();
+        let parse_bound = |text: slint::SharedString, parse: fn(&str) -> Result<_, String>| {
+            if text.trim().is_empty() {
+                Ok(None)
+            } else {
+                parse(&text).map(Some)
+            }
+        };
+        let range = parse_bound(ui.get_timeline_from(), parse_range_start)
+            .and_then(|start| Ok((start, parse_bound(ui.get_timeline_to(), parse_range_end)?)));
+        let (start, end) = match range {
+            Ok(range) => range,
+            Err(err) => {
+                ui.set_timeline_status(err.into());
+                return;
+            }
+        };
+        let options = TimelineOptions {
+            start,
+            end,
+            include_deleted: ui.get_timeline_deleted(),
+        };
+        let (format, filter, extension, file_name) = match ui.get_timeline_format().as_str() {
+            "CSV" => (TimelineFormat::Csv, "CSV", "csv", "timeline.csv"),
+            "mactime" => (TimelineFormat::Mactime, "CSV", "csv", "mactime.csv"),
+            _ => (
+                TimelineFormat::Bodyfile,
+                "Body file",
+                "body",
+                "timeline.body",
+            ),
+        };
+        let path = rfd::FileDialog::new()
+            .set_title("Save timeline")
+            .add_filter(filter, &[extension])
+            .set_file_name(file_name)
+            .save_file();
+        if let Some(path) = path {
+            tx1.send(Command::SaveTimeline(options, format, path))
+                .unwrap();
+        }
+    });
+
+    let tx1 = tx.clone();
+    ui.on_show_usn(move |filter| {
+        tx1.send(Command::ShowUsn(filter.to_string())).unwrap();
+    });
+
+    let tx1 = tx.clone();
+    let ui_handle = ui.as_weak();
+    ui.on_export_usn(move || {
+        let path = rfd::FileDialog::new()
+            .set_title("Export change journal")
+            .add_filter("CSV", &["csv"])
+            .add_filter("JSON", &["json"])
+            .set_file_name("usn.csv")
+            .save_file();
+        if let Some(path) = path {
+            let filter = ui_handle.unwrap().get_usn_filter().to_string();
+            tx1.send(Command::ExportUsn(filter, path)).unwrap();
+        }
+    });
+
+    let tx1 = tx.clone();
+    ui.on_save_hash_list(move || {
+        let path = rfd::FileDialog::new()
+            .set_title("Save hash list")
+            .add_filter("CSV", &["csv"])
+            .add_filter("md5sum", &["md5"])
+            .set_file_name("hashes.csv")
+            .save_file();
+        if let Some(path) = path {
+            tx1.send(Command::SaveHashList(path)).unwrap();
+        }
+    });
+
+    let tx1 = tx.clone();
+    let listing1 = listing.clone();
+    let ui_handle = ui.as_weak();
+    ui.on_listing_changed(move || {
+        let ui = ui_handle.unwrap();
+        let mut listing = listing1.lock().unwrap();
+        let show_system_files = ui.get_system_files() && !listing.system_files;
+        listing.all_names = ui.get_all_names();
+        listing.system_files = ui.get_system_files();
+        // The system files are listed in place of the root directory.
+        let command = if show_system_files {
+            Command::NavigateUp(0)
+        } else {
+            Command::Refresh()
+        };
+        tx1.send(command).unwrap();
+    });
+
+    let tx1 = tx.clone();
+    let layout1 = layout.clone();
+    let ui_handle = ui.as_weak();
+    ui.on_sort_by(move |index| {
+        let mut layout = layout1.borrow_mut();
+        layout.sort_by(index as usize);
+        listing.lock().unwrap().sort = layout.sort;
+        let ui = ui_handle.unwrap();
+        ui.set_sort_column(layout.sort_index() as i32);
+        ui.set_sort_descending(layout.sort.descending);
+        tx1.send(Command::Refresh()).unwrap();
+    });
+
+    let layout1 = layout.clone();
+    let columns_model1 = columns_model.clone();
+    ui.on_resize_column(move |index, delta| {
+        let mut layout = layout1.borrow_mut();
+        layout.resize(index as usize, delta);
+        update_file_columns(&columns_model1, &layout);
+    });
+
+    let layout1 = layout.clone();
+    ui.on_toggle_column(move |index| {
+        let mut layout = layout1.borrow_mut();
+        layout.toggle(index as usize);
+        update_file_columns(&columns_model, &layout);
+    });
+
     // ui.on_show_details(move |x| {
     //     dbg!(x);
     // });
@@ -58,53 +578,1309 @@ fn main() -> anyhow::Result<()> {
 
     ui.run();
 
+    if let Err(err) = layout.borrow().save() {
+        eprintln!("Could not save the column layout: {:#}", err);
+    }
+
     Ok(())
 }
 
+fn file_columns(layout: &ColumnLayout) -> Vec<FileColumn> {
+    layout
+        .columns
+        .iter()
+        .zip(layout.offsets())
+        .map(|(state, offset)| FileColumn {
+            name: state.column.title().into(),
+            width: state.width,
+            offset,
+            visible: state.visible,
+            numeric: state.column.is_numeric(),
+            warning: state.column.is_warning(),
+        })
+        .collect()
+}
+
+/// Updates the columns in place, as replacing the model would interrupt resizing a column with the mouse.
+fn update_file_columns(model: &slint::VecModel<FileColumn>, layout: &ColumnLayout) {
+    for (i, column) in enumerate(file_columns(layout)) {
+        model.set_row_data(i, column);
+    }
+}
+
+/// How much of a stream is shown in the stream viewer.
+const STREAM_PREVIEW_SIZE: usize = 16 * 1024;
+
+/// Parses `#1234` or `#0x4d2` from the address bar as a file record number.
+fn parse_record_number(s: &str) -> Option<u64> {
+    let s = s.trim().strip_prefix('#')?;
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Recognizes an image on the host pasted into the address bar, as an absolute path or a `file://` URI.
+///
+/// This stands in for dropping files onto the window, which Slint doesn't report. Paths on the volume never count,
+/// as they have no drive letter on Windows and start with a backslash elsewhere.
+fn host_image_path(text: &str) -> Option<PathBuf> {
+    let text = text.trim();
+    let path = match text.strip_prefix("file://") {
+        Some(uri) => PathBuf::from(percent_decode(
+            uri.strip_prefix("localhost").unwrap_or(uri),
+        )?),
+        None => PathBuf::from(text),
+    };
+    // `file:///C:/image.E01` on Windows.
+    #[cfg(windows)]
+    let path = match path.strip_prefix("/") {
+        Ok(stripped) if stripped.is_absolute() => stripped.to_owned(),
+        _ => path,
+    };
+    (path.is_absolute() && path.is_file()).then(|| path)
+}
+
+/// Decodes the `%xx` escapes of a URI path.
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = vec![];
+    let mut rest = s.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
 fn worker_thread(
     ui_handle: slint::Weak<MainWindow>,
     rx: mpsc::Receiver<Command>,
+    cancel: Arc<AtomicBool>,
+    listing: Arc<Mutex<ListingOptions>>,
+    options: ReadOptions,
 ) -> anyhow::Result<()> {
-    //let f = File::open(r"\\.\C:")?;
-    let f = File::open(r"C:\Users\JannisFroese\Downloads\dd\links-quota-defaultBlocks-8GB.img")?;
-    let sr = SectorReader::new(f, 512)?;
-    let mut fs = BufReader::new(sr);
-    // let mut fs =
-    //     ZstdReader::new(r"C:\Users\JannisFroese\Downloads\dd\links-quota-defaultBlocks-8GB.img")?;
-    let mut ntfs = Ntfs::new(&mut fs)?;
-    ntfs.read_upcase_table(&mut fs)?;
-    let mut current_directory = vec![ntfs.root_directory(&mut fs)?];
-    show_dir(&current_directory, &mut fs, &ntfs, &ui_handle)?;
+    let mut next_image = None;
+    loop {
+        let path = match next_image.take() {
+            Some(path) => path,
+            None => match rx.recv() {
+                Ok(Command::OpenImage(path)) => path,
+                // Nothing to navigate in before an image has been opened.
+                Ok(_) => continue,
+                Err(_) => return Ok(()),
+            },
+        };
+
+        set_status(&ui_handle, format!("Opening {}", path.display()));
+        match browse_image(&path, &ui_handle, &rx, &cancel, &listing, &options) {
+            Ok(Some(path)) => next_image = Some(path),
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                clear_dir(&ui_handle);
+                set_volumes(&ui_handle, &[]);
+                show_volume_chooser(&ui_handle, false);
+                set_image_open(&ui_handle, false);
+                set_status(
+                    &ui_handle,
+                    format!("Could not open {}: {:#}", path.display(), err),
+                );
+            }
+        }
+    }
+}
+
+/// How browsing a volume ended.
+enum VolumeExit {
+    /// The user wants to open another image.
+    OpenImage(PathBuf),
+    /// The user wants to go back to the partition list of the current image.
+    ShowVolumes,
+    /// The UI has gone away.
+    Closed,
+}
+
+/// Opens the image at `path` and handles navigation commands for it.
+///
+/// All state belonging to the image (the reader, `Ntfs` and the directory stack) lives in this function,
+/// so it is torn down as soon as we return.
+/// Returns the path of the next image to open, or `None` if the UI has gone away.
+fn browse_image(
+    path: &Path,
+    ui_handle: &slint::Weak<MainWindow>,
+    rx: &mpsc::Receiver<Command>,
+    cancel: &AtomicBool,
+    listing: &Mutex<ListingOptions>,
+    options: &ReadOptions,
+) -> anyhow::Result<Option<PathBuf>> {
+    let disk_options = DiskOptions {
+        bad_sector_fill: options.bad_sector_fill.clone(),
+        mapfile: match &options.mapfile {
+            Some((image_path, mapfile)) if image_path == path => Some(mapfile.clone()),
+            _ => None,
+        },
+        sector_size: match options.sector_size.load(Ordering::Relaxed) {
+            0 => None,
+            sector_size => Some(sector_size),
+        },
+    };
+    let mut disk = Disk::open(path, &disk_options, &mut |message| {
+        set_status(ui_handle, message)
+    })?;
+    let properties = disk.properties.clone();
+
+    let mut volumes = disk.partitions()?;
+    set_volumes(ui_handle, &volumes);
+    set_image_open(ui_handle, true);
+    set_image_properties(ui_handle, &properties);
+
+    if volumes.is_empty() {
+        // This is either an image of a single volume or a disk with a wiped partition table.
+        match browse_volume(
+            &mut disk,
+            None,
+            path,
+            ui_handle,
+            rx,
+            cancel,
+            listing,
+            options.cache_config,
+        ) {
+            Ok(VolumeExit::OpenImage(path)) => return Ok(Some(path)),
+            Ok(VolumeExit::ShowVolumes) => set_status(
+                ui_handle,
+                format!(
+                    "{}: no partition table, scan for volumes to find more",
+                    path.display()
+                ),
+            ),
+            Ok(VolumeExit::Closed) => return Ok(None),
+            Err(err) => {
+                clear_dir(ui_handle);
+                set_image_properties(ui_handle, &properties);
+                set_status(
+                    ui_handle,
+                    format!(
+                        "{}: no partition table and no NTFS volume at offset 0 ({:#}), scan for volumes to find one",
+                        path.display(),
+                        err
+                    ),
+                );
+            }
+        }
+    } else {
+        set_status(
+            ui_handle,
+            format!("{}: choose a volume to open", path.display()),
+        );
+    }
+
+    show_volume_chooser(ui_handle, true);
     loop {
-        match rx.recv().unwrap() {
+        let command = match rx.recv() {
+            Ok(command) => command,
+            Err(_) => return Ok(None),
+        };
+        match command {
+            Command::OpenImage(path) => return Ok(Some(path)),
+            Command::OpenVolume(index) => {
+                let volume = match volumes.get(index) {
+                    Some(volume) => volume,
+                    None => continue,
+                };
+
+                show_volume_chooser(ui_handle, false);
+                let result = browse_volume(
+                    &mut disk,
+                    Some(volume),
+                    path,
+                    ui_handle,
+                    rx,
+                    cancel,
+                    listing,
+                    options.cache_config,
+                );
+                clear_dir(ui_handle);
+                show_volume_chooser(ui_handle, true);
+                set_image_properties(ui_handle, &properties);
+                match result {
+                    Ok(VolumeExit::OpenImage(path)) => return Ok(Some(path)),
+                    Ok(VolumeExit::ShowVolumes) => set_status(
+                        ui_handle,
+                        format!("{}: choose a volume to open", path.display()),
+                    ),
+                    Ok(VolumeExit::Closed) => return Ok(None),
+                    Err(err) => set_status(
+                        ui_handle,
+                        format!("Could not open {}: {:#}", volume.description(), err),
+                    ),
+                }
+            }
+            Command::ScanVolumes() => {
+                cancel.store(false, Ordering::Relaxed);
+                set_scanning(ui_handle, true);
+                let result = disk.scan_for_ntfs_volumes(|position, found| {
+                    // Don't flood the event loop, once every 256 MiB is plenty.
+                    if position % (256 << 20) == 0 {
+                        set_status(
+                            ui_handle,
+                            format!(
+                                "Scanning for NTFS boot sectors: {} scanned, {} candidates",
+                                ByteSize(position),
+                                found
+                            ),
+                        );
+                    }
+                    !cancel.load(Ordering::Relaxed)
+                });
+                set_scanning(ui_handle, false);
+
+                let found = match result {
+                    Ok(found) => found,
+                    Err(err) => {
+                        set_status(ui_handle, format!("Scan failed: {:#}", err));
+                        continue;
+                    }
+                };
+                let found_count = found.len();
+                for volume in found {
+                    if !volumes.iter().any(|v| v.offset == volume.offset) {
+                        volumes.push(volume);
+                    }
+                }
+                set_volumes(ui_handle, &volumes);
+                let cancelled = if cancel.load(Ordering::Relaxed) {
+                    " (cancelled)"
+                } else {
+                    ""
+                };
+                set_status(
+                    ui_handle,
+                    format!(
+                        "{}: scan found {} NTFS volumes{}",
+                        path.display(),
+                        found_count,
+                        cancelled
+                    ),
+                );
+            }
+            _ => continue,
+        }
+    }
+}
+
+/// Opens an NTFS volume of `disk` and handles navigation commands for it.
+///
+/// If no `partition` is given, the entire disk is opened as a single volume.
+fn browse_volume(
+    disk: &mut Disk,
+    partition: Option<&Partition>,
+    path: &Path,
+    ui_handle: &slint::Weak<MainWindow>,
+    rx: &mpsc::Receiver<Command>,
+    cancel: &AtomicBool,
+    listing: &Mutex<ListingOptions>,
+    cache_config: CacheConfig,
+) -> anyhow::Result<VolumeExit> {
+    let mut volume = disk.open_volume(partition, cache_config)?;
+    // The directories from the root down to the current one, along with their names.
+    let mut current_directory = vec![(ROOT_DIRECTORY, String::new())];
+    let mut history = History::default();
+    let mut findings = Findings {
+        lifetime: volume.lifetime().ok(),
+        ..Findings::default()
+    };
+    // The files of the last hash job, to be saved as a hash list.
+    let mut hash_list = HashList::default();
+    set_hash_list(ui_handle, false);
+    set_usn(ui_handle, false, String::new(), Some(vec![]));
+    // The files in the order of the file list, whose indexes the UI refers to.
+    let mut entries = show_dir(
+        &mut volume,
+        ROOT_DIRECTORY,
+        None,
+        *listing.lock().unwrap(),
+        &findings,
+        ui_handle,
+    )?;
+    set_current_path(ui_handle, &current_directory, &history);
+    set_volume_status(ui_handle, path, &volume);
+    loop {
+        let command = match rx.recv() {
+            Ok(command) => command,
+            Err(_) => return Ok(VolumeExit::Closed),
+        };
+        // The file to select in the new directory, if we went to a file.
+        let mut selected_file = None;
+        match command {
+            Command::OpenImage(path) => return Ok(VolumeExit::OpenImage(path)),
+            Command::ShowVolumes() => return Ok(VolumeExit::ShowVolumes),
+            Command::OpenVolume(_) | Command::ScanVolumes() => continue,
             Command::EnterSubdir(dir_name) => {
-                let index = current_directory
-                    .last()
-                    .unwrap()
-                    .directory_index(&mut fs)
-                    .unwrap();
-                let mut finder = index.finder();
-                let maybe_entry =
-                    NtfsFileNameIndex::find(&mut finder, &ntfs, &mut fs, dir_name.as_str());
-
-                if maybe_entry.is_none() {
+                let directory = current_directory.last().unwrap().0;
+                // Deleted directories are listed with the other deleted files.
+                if directory == DELETED_FILES {
                     continue;
                 }
-                let entry = maybe_entry.unwrap();
-                let file = entry.unwrap().to_file(&ntfs, &mut fs).unwrap();
-                current_directory.push(file);
-
-                show_dir(&current_directory, &mut fs, &ntfs, &ui_handle)?;
+                let subdir = match volume.find(directory, &dir_name)? {
+                    Some(subdir) => subdir,
+                    None => continue,
+                };
+                let mut target = current_directory.clone();
+                target.push((subdir, dir_name));
+                history.visit(&mut current_directory, target);
             }
             Command::MoveToParent() => {
-                if current_directory.len() > 1 {
-                    current_directory.pop();
+                if current_directory.len() == 1 {
+                    continue;
+                }
+                let target = current_directory[..current_directory.len() - 1].to_vec();
+                history.visit(&mut current_directory, target);
+            }
+            Command::NavigateTo(target) => {
+                match resolve_path(&mut volume, &current_directory, &target) {
+                    Ok(Some(directories)) => history.visit(&mut current_directory, directories),
+                    Ok(None) => {
+                        // Put the current path back into the address bar.
+                        set_current_path(ui_handle, &current_directory, &history);
+                        set_status(ui_handle, format!("{}: no such directory", target));
+                        continue;
+                    }
+                    Err(err) => {
+                        set_current_path(ui_handle, &current_directory, &history);
+                        set_status(ui_handle, format!("{}: {:#}", target, err));
+                        continue;
+                    }
+                }
+            }
+            Command::NavigateUp(depth) => {
+                if depth >= current_directory.len() {
+                    continue;
+                }
+                // Going to the current directory only lists it again.
+                if depth + 1 < current_directory.len() {
+                    let target = current_directory[..=depth].to_vec();
+                    history.visit(&mut current_directory, target);
+                }
+            }
+            Command::GoToRecord(record_number) => match locate_record(&mut volume, record_number) {
+                Ok((directories, file)) => {
+                    history.visit(&mut current_directory, directories);
+                    selected_file = file;
+                }
+                Err(err) => {
+                    set_current_path(ui_handle, &current_directory, &history);
+                    set_status(
+                        ui_handle,
+                        format!("File record {}: {:#}", record_number, err),
+                    );
+                    continue;
+                }
+            },
+            Command::Back() => {
+                if !history.back(&mut current_directory) {
+                    continue;
+                }
+            }
+            Command::Forward() => {
+                if !history.forward(&mut current_directory) {
+                    continue;
+                }
+            }
+            Command::Refresh() => (),
+            Command::ShowDetails(index) => {
+                if let Some(entry) = entries.get(index) {
+                    show_details(&mut volume, index, entry, &findings, ui_handle);
+                }
+                continue;
+            }
+            Command::ViewStream(index, stream) => {
+                let entry = match entries.get(index) {
+                    Some(entry) => entry,
+                    None => continue,
+                };
+                match volume.read_stream_range(entry.record_number, &stream, 0, STREAM_PREVIEW_SIZE)
+                {
+                    Ok(data) => {
+                        show_stream_view(ui_handle, format!("{}:{}", entry.name, stream), &data)
+                    }
+                    Err(err) => {
+                        set_status(ui_handle, format!("{}:{}: {:#}", entry.name, stream, err))
+                    }
+                }
+                continue;
+            }
+            Command::ExportStream(index, stream, target) => {
+                let entry = match entries.get(index) {
+                    Some(entry) => entry,
+                    None => continue,
+                };
+                let status = match export_stream(&mut volume, entry.record_number, &stream, &target)
+                {
+                    Ok(size) => format!(
+                        "Exported {}:{} to {} ({})",
+                        entry.name,
+                        stream,
+                        target.display(),
+                        ByteSize(size)
+                    ),
+                    Err(err) => format!(
+                        "Could not export {}:{} to {}: {:#}",
+                        entry.name,
+                        stream,
+                        target.display(),
+                        err
+                    ),
+                };
+                set_status(ui_handle, status);
+                continue;
+            }
+            Command::Export(indexes, target, export_options) => {
+                let selection = if indexes.is_empty() {
+                    entries.clone()
+                } else {
+                    indexes
+                        .iter()
+                        .filter_map(|&index| entries.get(index).cloned())
+                        .collect_vec()
+                };
+                cancel.store(false, Ordering::Relaxed);
+                set_exporting(ui_handle, true, "Exporting…".into());
+                let mut last_update = Instant::now();
+                let result = export(
+                    &mut volume,
+                    current_directory.last().unwrap().0,
+                    &directory_path(&current_directory),
+                    &selection,
+                    &target,
+                    export_options,
+                    |source, bytes| {
+                        // Don't flood the event loop, a few updates a second are plenty.
+                        if last_update.elapsed() >= Duration::from_millis(200) {
+                            set_exporting(
+                                ui_handle,
+                                true,
+                                format!("Exporting {} ({} written)", source, ByteSize(bytes)),
+                            );
+                            last_update = Instant::now();
+                        }
+                        !cancel.load(Ordering::Relaxed)
+                    },
+                );
+                let status = match result {
+                    Ok(log) => export_summary(&log, &target),
+                    Err(err) => format!("Could not export to {}: {:#}", target.display(), err),
+                };
+                set_exporting(ui_handle, false, status);
+                continue;
+            }
+            Command::Hash(indexes) => {
+                let selection = if indexes.is_empty() {
+                    entries.clone()
+                } else {
+                    indexes
+                        .iter()
+                        .filter_map(|&index| entries.get(index).cloned())
+                        .collect_vec()
+                };
+                cancel.store(false, Ordering::Relaxed);
+                set_hashing(ui_handle, true, "Hashing…".into());
+                let mut last_update = Instant::now();
+                hash_list = hash_files(
+                    &mut volume,
+                    current_directory.last().unwrap().0,
+                    &directory_path(&current_directory),
+                    &selection,
+                    |path, bytes| {
+                        if last_update.elapsed() >= Duration::from_millis(200) {
+                            set_hashing(
+                                ui_handle,
+                                true,
+                                format!("Hashing {} ({} hashed)", path, ByteSize(bytes)),
+                            );
+                            last_update = Instant::now();
+                        }
+                        !cancel.load(Ordering::Relaxed)
+                    },
+                );
+                for file in &hash_list.files {
+                    if let Ok(file_hashes) = &file.hashes {
+                        findings
+                            .hashes
+                            .insert(file.record_number, file_hashes.clone());
+                    }
+                }
+
+                let mut status = format!(
+                    "{} {} files ({})",
+                    if hash_list.cancelled {
+                        "Stopped after hashing"
+                    } else {
+                        "Hashed"
+                    },
+                    hash_list.files.len() - hash_list.errors(),
+                    ByteSize(hash_list.bytes())
+                );
+                if hash_list.errors() > 0 {
+                    status += &format!(", {} could not be read", hash_list.errors());
+                }
+                set_hashing(ui_handle, false, status);
+                set_hash_list(ui_handle, !hash_list.files.is_empty());
+                // List the directory again to show the hashes.
+            }
+            Command::ShowDeleted() => {
+                if findings.deleted.is_none() {
+                    cancel.store(false, Ordering::Relaxed);
+                    set_scanning(ui_handle, true);
+                    let result = volume.scan_deleted_files(|scanned, total| {
+                        set_status(
+                            ui_handle,
+                            format!(
+                                "Scanning $MFT for deleted files: {} of {} file records",
+                                scanned, total
+                            ),
+                        );
+                        !cancel.load(Ordering::Relaxed)
+                    });
+                    set_scanning(ui_handle, false);
+                    match result {
+                        Ok(_) if cancel.load(Ordering::Relaxed) => {
+                            set_status(ui_handle, "Scan for deleted files stopped".into());
+                            continue;
+                        }
+                        Ok(deleted_files) => {
+                            findings.deleted = Some(
+                                deleted_files
+                                    .into_iter()
+                                    .map(|file| (file.entry.record_number, file))
+                                    .collect(),
+                            );
+                        }
+                        Err(err) => {
+                            set_status(ui_handle, format!("Could not scan $MFT: {:#}", err));
+                            continue;
+                        }
+                    }
+                }
+                if current_directory.last().unwrap().0 != DELETED_FILES {
+                    history.visit(&mut current_directory, deleted_files_path());
+                }
+            }
+            Command::Recover(indexes, target) => {
+                let deleted = match &findings.deleted {
+                    Some(deleted) if current_directory.last().unwrap().0 == DELETED_FILES => {
+                        deleted
+                    }
+                    _ => continue,
+                };
+                let selection = if indexes.is_empty() {
+                    entries.iter().collect_vec()
+                } else {
+                    indexes
+                        .iter()
+                        .filter_map(|&index| entries.get(index))
+                        .collect_vec()
+                };
+                let files = selection
+                    .iter()
+                    .filter_map(|entry| deleted.get(&entry.record_number).cloned())
+                    .collect_vec();
+                cancel.store(false, Ordering::Relaxed);
+                set_recovering(ui_handle, true);
+                let mut last_update = Instant::now();
+                let result = recover_files(&mut volume, &files, &target, |path, bytes| {
+                    if last_update.elapsed() >= Duration::from_millis(200) {
+                        set_status(
+                            ui_handle,
+                            format!("Recovering {} ({} written)", path, ByteSize(bytes)),
+                        );
+                        last_update = Instant::now();
+                    }
+                    !cancel.load(Ordering::Relaxed)
+                });
+                set_recovering(ui_handle, false);
+                let status = match result {
+                    Ok(log) => recovery_summary(&log, &target),
+                    Err(err) => format!("Could not recover to {}: {:#}", target.display(), err),
+                };
+                set_status(ui_handle, status);
+                continue;
+            }
+            Command::SaveTimestompingReport(target) => {
+                cancel.store(false, Ordering::Relaxed);
+                set_scanning(ui_handle, true);
+                let result = volume.scan_timestomping(|scanned, total| {
+                    set_status(
+                        ui_handle,
+                        format!("Checking timestamps: {} of {} file records", scanned, total),
+                    );
+                    !cancel.load(Ordering::Relaxed)
+                });
+                set_scanning(ui_handle, false);
+                let status = match result {
+                    Ok(report) if report.cancelled => {
+                        "Timestomping check stopped, nothing was saved".to_owned()
+                    }
+                    Ok(report) => {
+                        let result =
+                            File::create(&target)
+                                .map(BufWriter::new)
+                                .and_then(|mut writer| {
+                                    report.write_csv(&mut writer)?;
+                                    writer.flush()
+                                });
+                        match result {
+                            Ok(()) => format!(
+                                "Found {} files with suspicious timestamps, saved the report to {}",
+                                report.files.len(),
+                                target.display()
+                            ),
+                            Err(err) => format!("Could not save {}: {:#}", target.display(), err),
+                        }
+                    }
+                    Err(err) => format!("Could not check the timestamps: {:#}", err),
+                };
+                set_status(ui_handle, status);
+                continue;
+            }
+            Command::SaveTimeline(options, format, target) => {
+                cancel.store(false, Ordering::Relaxed);
+                set_timeline(ui_handle, true, "Reading $MFT…".into());
+                let mut last_update = Instant::now();
+                let result = volume.timeline(&options, |read, total| {
+                    if last_update.elapsed() >= Duration::from_millis(200) {
+                        set_timeline(
+                            ui_handle,
+                            true,
+                            format!("Reading file record {} of {}", read, total),
+                        );
+                        last_update = Instant::now();
+                    }
+                    !cancel.load(Ordering::Relaxed)
+                });
+                let status = match result {
+                    Ok(timeline) if timeline.cancelled => "Stopped, nothing was saved".to_owned(),
+                    Ok(timeline) => {
+                        let result =
+                            File::create(&target)
+                                .map(BufWriter::new)
+                                .and_then(|mut writer| {
+                                    timeline.write(format, &mut writer)?;
+                                    writer.flush()
+                                });
+                        match result {
+                            Ok(()) => format!(
+                                "Saved {} timestamp records to {}",
+                                timeline.records.len(),
+                                target.display()
+                            ),
+                            Err(err) => format!("Could not save {}: {:#}", target.display(), err),
+                        }
+                    }
+                    Err(err) => format!("Could not read the timestamps: {:#}", err),
+                };
+                set_timeline(ui_handle, false, status);
+                continue;
+            }
+            Command::ShowUsn(filter) => {
+                // Read the journal only once, the properties of the files show their records from then on.
+                let loaded = findings.usn.is_none();
+                if loaded {
+                    cancel.store(false, Ordering::Relaxed);
+                    set_usn(ui_handle, true, "Reading the change journal…".into(), None);
+                    let mut last_update = Instant::now();
+                    let result = volume.usn_journal(|position, size| {
+                        if last_update.elapsed() >= Duration::from_millis(200) {
+                            set_usn(
+                                ui_handle,
+                                true,
+                                format!(
+                                    "Reading the change journal: {} of {}",
+                                    ByteSize(position),
+                                    ByteSize(size)
+                                ),
+                                None,
+                            );
+                            last_update = Instant::now();
+                        }
+                        !cancel.load(Ordering::Relaxed)
+                    });
+                    match result {
+                        Ok(journal) if journal.cancelled => {
+                            set_usn(
+                                ui_handle,
+                                false,
+                                "Stopped reading the change journal".into(),
+                                None,
+                            );
+                            continue;
+                        }
+                        Ok(journal) => findings.usn = Some(journal),
+                        Err(err) => {
+                            set_usn(
+                                ui_handle,
+                                false,
+                                format!("Could not read the change journal: {:#}", err),
+                                None,
+                            );
+                            continue;
+                        }
+                    }
+                }
+                let journal = findings.usn.as_ref().unwrap();
+                let filter = UsnFilter::parse(&filter);
+                let records = journal.filter(&filter).collect_vec();
+                let status = if records.len() > MAX_USN_ITEMS {
+                    format!(
+                        "Showing the first {} of {} matching records, {} in total",
+                        MAX_USN_ITEMS,
+                        records.len(),
+                        journal.records.len()
+                    )
+                } else {
+                    format!(
+                        "{} matching records, {} in total",
+                        records.len(),
+                        journal.records.len()
+                    )
+                };
+                let items = records
+                    .into_iter()
+                    .take(MAX_USN_ITEMS)
+                    .map(StringUsnItem::from)
+                    .collect_vec();
+                set_usn(ui_handle, false, status, Some(items));
+                // List the directory again to show the records in the properties of its files.
+                if !loaded {
+                    continue;
+                }
+            }
+            Command::ExportUsn(filter, target) => {
+                let journal = match &findings.usn {
+                    Some(journal) => journal,
+                    None => continue,
+                };
+                let filter = UsnFilter::parse(&filter);
+                let result = File::create(&target)
+                    .map(BufWriter::new)
+                    .and_then(|mut writer| {
+                        if target
+                            .extension()
+                            .map_or(false, |extension| extension == "json")
+                        {
+                            journal.write_json(&filter, &mut writer)?;
+                        } else {
+                            journal.write_csv(&filter, &mut writer)?;
+                        }
+                        writer.flush()
+                    });
+                let status = match result {
+                    Ok(()) => format!(
+                        "Saved {} records to {}",
+                        journal.filter(&filter).count(),
+                        target.display()
+                    ),
+                    Err(err) => format!("Could not save {}: {:#}", target.display(), err),
+                };
+                set_usn(ui_handle, false, status, None);
+                continue;
+            }
+            Command::SaveHashList(target) => {
+                let result = File::create(&target)
+                    .map(BufWriter::new)
+                    .and_then(|mut writer| {
+                        if target
+                            .extension()
+                            .map_or(false, |extension| extension == "md5")
+                        {
+                            hash_list.write_md5sum(&mut writer)?;
+                        } else {
+                            hash_list.write_csv(&mut writer)?;
+                        }
+                        writer.flush()
+                    });
+                let status = match result {
+                    Ok(()) => format!("Saved the hash list to {}", target.display()),
+                    Err(err) => format!("Could not save {}: {:#}", target.display(), err),
+                };
+                set_hashing(ui_handle, false, status);
+                continue;
+            }
+        }
+
+        entries = show_dir(
+            &mut volume,
+            current_directory.last().unwrap().0,
+            selected_file,
+            *listing.lock().unwrap(),
+            &findings,
+            ui_handle,
+        )?;
+        set_current_path(ui_handle, &current_directory, &history);
+        set_volume_status(ui_handle, path, &volume);
+    }
+}
+
+/// Saves the log of an export next to the exported files and sums it up for the export dialog.
+fn export_summary(log: &ExportLog, target: &Path) -> String {
+    let mut summary = format!(
+        "{} {} items ({}) to {}",
+        if log.cancelled {
+            "Stopped after exporting"
+        } else {
+            "Exported"
+        },
+        log.items.len() - log.errors(),
+        ByteSize(log.bytes()),
+        target.display()
+    );
+    if log.errors() > 0 {
+        summary += &format!(", {} failed", log.errors());
+    }
+    match log.save(target) {
+        Ok(path) => summary += &format!(". The log is in {}", path.display()),
+        Err(err) => summary += &format!(". Could not save the log: {:#}", err),
+    }
+    summary
+}
+
+fn recovery_summary(log: &RecoveryLog, target: &Path) -> String {
+    let mut summary = format!(
+        "{} {} files into {}, {} of them completely",
+        if log.cancelled {
+            "Stopped after recovering"
+        } else {
+            "Recovered"
+        },
+        log.files.len() - log.errors(),
+        target.display(),
+        log.complete()
+    );
+    if log.errors() > 0 {
+        summary += &format!(", {} failed", log.errors());
+    }
+    summary + ". See the .recovery.txt files for reallocated clusters"
+}
+
+fn export_stream<R>(
+    volume: &mut Volume<R>,
+    record_number: u64,
+    stream: &str,
+    target: &Path,
+) -> anyhow::Result<u64>
+where
+    R: Read + Seek,
+{
+    let mut writer = BufWriter::new(File::create(target)?);
+    let size = volume.copy_stream(record_number, stream, &mut writer)?;
+    writer.flush()?;
+    Ok(size)
+}
+
+/// The directories visited before and after the current one.
+#[derive(Default)]
+struct History {
+    back: Vec<Vec<(u64, String)>>,
+    forward: Vec<Vec<(u64, String)>>,
+}
+
+impl History {
+    /// Goes to `target`, which makes the forward history obsolete.
+    fn visit(&mut self, current: &mut Vec<(u64, String)>, target: Vec<(u64, String)>) {
+        self.back.push(std::mem::replace(current, target));
+        self.forward.clear();
+    }
+
+    /// Goes back, if possible.
+    fn back(&mut self, current: &mut Vec<(u64, String)>) -> bool {
+        match self.back.pop() {
+            Some(target) => {
+                self.forward.push(std::mem::replace(current, target));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Goes forward, if possible.
+    fn forward(&mut self, current: &mut Vec<(u64, String)>) -> bool {
+        match self.forward.pop() {
+            Some(target) => {
+                self.back.push(std::mem::replace(current, target));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Finds the directory to show for a file record: the directory itself, or the directory containing a file.
+///
+/// Returns the directories from the root down to it, along with the file to select, if any.
+fn locate_record<R>(
+    volume: &mut Volume<R>,
+    record_number: u64,
+) -> anyhow::Result<(Vec<(u64, String)>, Option<u64>)>
+where
+    R: Read + Seek,
+{
+    let is_directory = volume.file_info(record_number)?.is_directory;
+    let mut directories = vec![(ROOT_DIRECTORY, String::new())];
+    directories.extend(volume.path_components(record_number)?);
+    if is_directory {
+        Ok((directories, None))
+    } else {
+        directories.pop();
+        Ok((directories, Some(record_number)))
+    }
+}
+
+/// Resolves a path typed into the address bar to the directories from the root down to it.
+///
+/// Paths starting with a separator begin at the root directory, others at `current_directory`.
+/// Returns `None` if the path doesn't lead to a directory.
+fn resolve_path<R>(
+    volume: &mut Volume<R>,
+    current_directory: &[(u64, String)],
+    path: &str,
+) -> anyhow::Result<Option<Vec<(u64, String)>>>
+where
+    R: Read + Seek,
+{
+    let path = path.trim();
+    let mut directories = if path.starts_with(['\\', '/']) {
+        vec![current_directory[0].clone()]
+    } else {
+        current_directory.to_vec()
+    };
 
-                    show_dir(&current_directory, &mut fs, &ntfs, &ui_handle)?;
+    for component in path.split(['\\', '/']).filter(|c| !c.is_empty()) {
+        match component {
+            "." => continue,
+            ".." => {
+                if directories.len() > 1 {
+                    directories.pop();
                 }
+                continue;
             }
+            _ => (),
+        }
+
+        let parent = directories.last().unwrap().0;
+        let record_number = match volume.find(parent, component)? {
+            Some(record_number) => record_number,
+            None => return Ok(None),
+        };
+        if !volume.file_info(record_number)?.is_directory {
+            return Ok(None);
         }
+        // Show the name as it is stored, not how it was typed.
+        let name = volume.file_name(record_number, parent)?;
+        directories.push((record_number, name));
     }
+
+    Ok(Some(directories))
+}
+
+/// Shows the path of the current directory in the address bar and as breadcrumbs, and whether we can go back and
+/// forward from it.
+fn set_current_path(
+    ui: &slint::Weak<MainWindow>,
+    current_directory: &[(u64, String)],
+    history: &History,
+) {
+    let path = directory_path(current_directory);
+    let breadcrumbs = enumerate(current_directory)
+        .map(|(depth, (_, name))| Breadcrumb {
+            name: if depth == 0 { r"\".into() } else { name.into() },
+            depth: depth as i32,
+        })
+        .collect_vec();
+    let can_go_back = !history.back.is_empty();
+    let can_go_forward = !history.forward.is_empty();
+    let deleted_view =
+        current_directory.last().map(|&(directory, _)| directory) == Some(DELETED_FILES);
+    ui.upgrade_in_event_loop(move |ui| {
+        ui.set_current_path(path.into());
+        ui.set_deleted_view(deleted_view);
+        ui.set_can_go_back(can_go_back);
+        ui.set_can_go_forward(can_go_forward);
+        ui.set_breadcrumbs(std::rc::Rc::new(slint::VecModel::from(breadcrumbs)).into());
+    });
+}
+
+/// The path of the virtual folder of deleted files, which is shown below the root directory.
+fn deleted_files_path() -> Vec<(u64, String)> {
+    vec![
+        (ROOT_DIRECTORY, String::new()),
+        (DELETED_FILES, "Deleted files".into()),
+    ]
+}
+
+fn directory_path(current_directory: &[(u64, String)]) -> String {
+    format!(
+        r"\{}",
+        current_directory[1..]
+            .iter()
+            .map(|(_, name)| name.as_str())
+            .join(r"\")
+    )
+}
+
+/// Shows the opened volume and how well the block cache is doing in the status bar.
+fn set_volume_status<R>(ui: &slint::Weak<MainWindow>, path: &Path, volume: &Volume<R>)
+where
+    R: Read + Seek,
+{
+    let location = if volume.offset() == 0 {
+        path.display().to_string()
+    } else {
+        format!("{} @ {:#x}", path.display(), volume.offset())
+    };
+    let statistics = volume.cache_statistics();
+    let mut status = format!(
+        "{} | cache: {:.0}% hits, {} read",
+        location,
+        statistics.hit_rate() * 100.0,
+        ByteSize(statistics.bytes_read)
+    );
+    let bad_sectors = volume.bad_sectors().lock().unwrap();
+    if !bad_sectors.is_empty() {
+        status += &format!(
+            " | {} bad in {} ranges",
+            ByteSize(bad_sectors.total_size()),
+            bad_sectors.range_count()
+        );
+    }
+    set_status(ui, status);
+}
+
+fn set_volumes(ui: &slint::Weak<MainWindow>, partitions: &[Partition]) {
+    let partitions = partitions.to_vec();
+    ui.upgrade_in_event_loop(move |ui| {
+        let volume_model = enumerate(&partitions)
+            .map(|(i, partition)| VolumeItem {
+                id: i as i32,
+                description: partition.description().into(),
+                offset: format!("{:#x}", partition.offset).into(),
+                size: format!("{}", ByteSize(partition.size)).into(),
+                openable: partition.is_ntfs,
+            })
+            .collect_vec();
+        ui.set_volume_model(std::rc::Rc::new(slint::VecModel::from(volume_model)).into());
+    });
+}
+
+/// Shows details about the image in the properties panel, until a file is hovered.
+fn set_image_properties(
+    ui: &slint::Weak<MainWindow>,
+    properties: &[(String, Vec<(String, String)>)],
+) {
+    let sections = properties
+        .iter()
+        .map(|(headline, values)| StringFilePropertySection {
+            headline: headline.clone(),
+            values: values
+                .iter()
+                .map(|(name, value)| StringFileProperty {
+                    name: name.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
+        })
+        .collect_vec();
+    ui.upgrade_in_event_loop(move |ui| {
+        let sections = sections
+            .into_iter()
+            .map(|v| FilePropertySection {
+                headline: v.headline.into(),
+                values: std::rc::Rc::new(slint::VecModel::from(
+                    v.values
+                        .into_iter()
+                        .map(|u| FileProperty {
+                            name: u.name.into(),
+                            value: u.value.into(),
+                        })
+                        .collect_vec(),
+                ))
+                .into(),
+            })
+            .collect_vec();
+        ui.set_file_property_sections(std::rc::Rc::new(slint::VecModel::from(sections)).into());
+    });
+}
+
+fn set_image_open(ui: &slint::Weak<MainWindow>, open: bool) {
+    ui.upgrade_in_event_loop(move |ui| {
+        ui.set_image_open(open);
+    });
+}
+
+fn set_scanning(ui: &slint::Weak<MainWindow>, scanning: bool) {
+    ui.upgrade_in_event_loop(move |ui| {
+        ui.set_scanning(scanning);
+    });
+}
+
+fn set_exporting(ui: &slint::Weak<MainWindow>, exporting: bool, status: String) {
+    ui.upgrade_in_event_loop(move |ui| {
+        ui.set_exporting(exporting);
+        ui.set_export_status(status.into());
+    });
+}
+
+fn set_timeline(ui: &slint::Weak<MainWindow>, running: bool, status: String) {
+    ui.upgrade_in_event_loop(move |ui| {
+        ui.set_timeline_running(running);
+        ui.set_timeline_status(status.into());
+    });
+}
+
+/// Updates the change journal view, along with its list if `items` is given.
+fn set_usn(
+    ui: &slint::Weak<MainWindow>,
+    running: bool,
+    status: String,
+    items: Option<Vec<StringUsnItem>>,
+) {
+    ui.upgrade_in_event_loop(move |ui| {
+        ui.set_usn_running(running);
+        ui.set_usn_status(status.into());
+        if let Some(items) = items {
+            let items = items
+                .into_iter()
+                .map(|item| UsnItem {
+                    record: item.record.into(),
+                    usn: item.usn.into(),
+                    time: item.time.into(),
+                    reasons: item.reasons.into(),
+                    path: item.path.into(),
+                })
+                .collect_vec();
+            ui.set_usn_model(Rc::new(slint::VecModel::from(items)).into());
+        }
+    });
+}
+
+fn set_hashing(ui: &slint::Weak<MainWindow>, hashing: bool, status: String) {
+    ui.upgrade_in_event_loop(move |ui| {
+        ui.set_hashing(hashing);
+        ui.set_hash_status(status.into());
+    });
+}
+
+fn set_recovering(ui: &slint::Weak<MainWindow>, recovering: bool) {
+    ui.upgrade_in_event_loop(move |ui| {
+        ui.set_recovering(recovering);
+    });
+}
+
+fn set_hash_list(ui: &slint::Weak<MainWindow>, has_hash_list: bool) {
+    ui.upgrade_in_event_loop(move |ui| {
+        ui.set_has_hash_list(has_hash_list);
+    });
+}
+
+fn show_stream_view(ui: &slint::Weak<MainWindow>, title: String, data: &[u8]) {
+    let text = stream_preview(data);
+    ui.upgrade_in_event_loop(move |ui| {
+        ui.set_stream_view_title(title.into());
+        ui.set_stream_view_text(text.into());
+        ui.set_show_stream_view(true);
+    });
+}
+
+/// Shows a stream as text if it looks like text, like most `Zone.Identifier` streams do, or as a hex dump otherwise.
+fn stream_preview(data: &[u8]) -> String {
+    let is_text = |text: &str| {
+        !text
+            .chars()
+            .any(|c| c.is_control() && !matches!(c, '\t' | '\r' | '\n'))
+    };
+    if let [0xff, 0xfe, rest @ ..] = data {
+        let units = rest
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .collect_vec();
+        let text = String::from_utf16_lossy(&units);
+        if is_text(&text) {
+            return text;
+        }
+    }
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => Some(text),
+        // The preview may end in the middle of a character.
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&data[..err.valid_up_to()]).ok()
+        }
+        Err(_) => None,
+    };
+    if let Some(text) = text.filter(|text| !text.is_empty() && is_text(text)) {
+        return text.to_owned();
+    }
+
+    data.chunks(16)
+        .enumerate()
+        .map(|(i, line)| {
+            let hex = line.iter().map(|b| format!("{:02x}", b)).join(" ");
+            let ascii = line
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect::<String>();
+            format!("{:08x}  {:<47}  {}", i * 16, hex, ascii)
+        })
+        .join("\n")
+}
+
+fn show_volume_chooser(ui: &slint::Weak<MainWindow>, show: bool) {
+    ui.upgrade_in_event_loop(move |ui| {
+        ui.set_show_volume_chooser(show);
+    });
+}
+
+fn set_status(ui: &slint::Weak<MainWindow>, status: String) {
+    ui.upgrade_in_event_loop(move |ui| {
+        ui.set_status_text(status.into());
+    });
+}
+
+fn clear_dir(ui: &slint::Weak<MainWindow>) {
+    ui.upgrade_in_event_loop(|ui| {
+        ui.set_file_model(std::rc::Rc::new(slint::VecModel::<FileItem>::default()).into());
+        ui.set_file_properties(
+            std::rc::Rc::new(slint::VecModel::<slint::ModelRc<FilePropertySection>>::default())
+                .into(),
+        );
+        ui.set_file_property_sections(
+            std::rc::Rc::new(slint::VecModel::<FilePropertySection>::default()).into(),
+        );
+        ui.set_current_path("".into());
+        ui.set_deleted_view(false);
+        ui.set_can_go_back(false);
+        ui.set_can_go_forward(false);
+        ui.set_breadcrumbs(std::rc::Rc::new(slint::VecModel::<Breadcrumb>::default()).into());
+        ui.set_stream_items(std::rc::Rc::new(slint::VecModel::<StreamItem>::default()).into());
+        ui.set_show_stream_view(false);
+    });
+}
+
+#[derive(Debug, Clone)]
+struct StringFileItem {
+    filename: String,
+    cells: Vec<String>,
+    is_directory: bool,
+    bad_sectors: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -119,177 +1895,108 @@ struct StringFilePropertySection {
     values: Vec<StringFileProperty>,
 }
 
-fn show_dir<T>(
-    current_directory: &[ntfs::NtfsFile],
-    fs: &mut T,
-    ntfs: &Ntfs,
+#[derive(Debug, Clone)]
+struct StringUsnItem {
+    record: String,
+    usn: String,
+    time: String,
+    reasons: String,
+    path: String,
+}
+
+impl From<&UsnRecord> for StringUsnItem {
+    fn from(record: &UsnRecord) -> Self {
+        Self {
+            // In the form the address bar takes.
+            record: format!("#{}", record.record_number),
+            usn: record.usn.to_string(),
+            time: record.timestamp.map(format_time).unwrap_or_default(),
+            reasons: record.reasons().join(", "),
+            path: record.path.clone(),
+        }
+    }
+}
+
+/// Lists `directory` in the file list, selecting and scrolling to the file with the record number `selected_file`.
+fn show_dir<R>(
+    volume: &mut Volume<R>,
+    directory: u64,
+    selected_file: Option<u64>,
+    listing: ListingOptions,
+    findings: &Findings,
     ui: &slint::Weak<MainWindow>,
-) -> Result<(), anyhow::Error>
+) -> Result<Vec<DirEntry>, anyhow::Error>
 where
-    T: Read + Seek,
+    R: Read + Seek,
 {
-    let dir = current_directory.last().unwrap();
-    let index = dir.directory_index(fs)?;
-    let mut iter = index.entries();
-    let mut file_model = vec![];
+    let mut entries = if directory == DELETED_FILES {
+        findings
+            .deleted
+            .iter()
+            .flat_map(|deleted| deleted.values())
+            .map(|file| file.entry.clone())
+            .collect()
+    } else if listing.system_files && directory == ROOT_DIRECTORY {
+        volume.system_files()?
+    } else if listing.all_names {
+        volume.list_all_names(directory)?
+    } else {
+        volume.list_directory(directory)?
+    };
+    listing.sort.sort(&mut entries, findings);
+    let selected_index =
+        selected_file.and_then(|record| entries.iter().position(|e| e.record_number == record));
+    // Models can only be created on the UI thread, so the rows are sent as plain strings.
+    let mut rows = vec![];
+    // Only what is already known from the listing, `show_details` reads the rest once a file is hovered.
     let mut properties_model = vec![];
 
-    let parent_record_number = dir.file_record_number();
-    let mut files = vec![];
-    while let Some(entry) = iter.next(fs) {
-        let entry = entry?;
-        let file = entry.to_file(ntfs, fs)?;
-        let record_number = file.file_record_number();
-        files.push((file, record_number));
-    }
-    let files = files
-        .into_iter()
-        .unique_by(|x| x.1)
-        .map(|x| (best_file_name(fs, &x.0, parent_record_number), x.0))
-        .map(|(a, b)| match a {
-            Ok(a) => Ok((a, b)),
-            Err(err) => Err(err),
-        })
-        .filter_map(|x| x.ok())
-        .collect_vec();
+    for entry in &entries {
+        let file_size = format!("{}", ByteSize(entry.size));
+        let bad_section = entry.bad_sectors.map(bad_sector_section);
 
-    for (i, (filename, file)) in enumerate(&files) {
-        // let filename = filename?;
-        let attributes = format!("{:?}", file.info()?.file_attributes());
-        let file_size = format!(
-            "{}",
-            ByteSize(
-                file.data(fs, "")
-                    .transpose()?
-                    .map(|d| d.to_attribute().value_length())
-                    .unwrap_or_default(),
-            ),
-        );
-        // println!("file {}", file.file_record_number());
-
-        // let file_name = entry
-        //     .key()
-        //     .expect("key must exist for a found Index Entry")?;
-        // let is_directory = file_name.is_directory();
-        // println!(
-        //     "{}, keyspace: {:?}",
-        //     file_name.name().to_string_lossy(),
-        //     file_name.namespace()
-        // );
-        // if file_name.namespace() == NtfsFileNamespace::Dos {
-        //     continue;
-        // }
-        let is_directory = filename.is_directory();
-        let filename_str = filename.name().to_string_lossy();
-
-        file_model.push(FileItem {
-            id: i as i32,
-            attributes: attributes.into(),
-            filename: filename_str.clone().into(),
-            selected: false,
-            size: file_size.clone().into(),
-            is_directory,
+        rows.push(StringFileItem {
+            filename: entry.name.clone(),
+            cells: COLUMNS
+                .iter()
+                .map(|column| column.cell(entry, findings))
+                .collect(),
+            is_directory: entry.is_directory,
+            bad_sectors: bad_section.is_some(),
         });
-        properties_model.push(vec![StringFilePropertySection {
+        let mut sections = vec![StringFilePropertySection {
             headline: "General".into(),
             values: vec![
                 StringFileProperty {
                     name: "Filename".into(),
-                    value: filename_str,
+                    value: entry.name.clone(),
                 },
                 StringFileProperty {
                     name: "Size".into(),
                     value: file_size,
                 },
             ],
-        }]);
-
-        // let prefix = if file_name.is_directory() {
-        //     "<DIR>"
-        // } else {
-        //     ""
-        // };
-        // println!("{:5}  {}", prefix, file_name.name());
+        }];
+        sections.extend(bad_section);
+        properties_model.push(sections);
     }
-    let properties2 = properties_model.clone();
-    ui.upgrade_in_event_loop(|ui| {
-        let file_model = std::rc::Rc::new(slint::VecModel::from(file_model));
-        let properties_model = std::rc::Rc::new(slint::VecModel::from(
-            properties2
-                .into_iter()
-                .map(|f| {
-                    std::rc::Rc::new(slint::VecModel::from(
-                        f.into_iter()
-                            .map(|v| FilePropertySection {
-                                headline: v.headline.into(),
-                                values: std::rc::Rc::new(slint::VecModel::from(
-                                    v.values
-                                        .into_iter()
-                                        .map(|u| FileProperty {
-                                            name: u.name.into(),
-                                            value: u.value.into(),
-                                        })
-                                        .collect_vec(),
-                                ))
-                                .into(),
-                            })
-                            .collect_vec(),
+    let selected_sections = selected_index.map(|i| properties_model[i].clone());
+    ui.upgrade_in_event_loop(move |ui| {
+        let file_model = std::rc::Rc::new(slint::VecModel::from(
+            enumerate(rows)
+                .map(|(i, row)| FileItem {
+                    id: i as i32,
+                    filename: row.filename.into(),
+                    cells: std::rc::Rc::new(slint::VecModel::from(
+                        row.cells.into_iter().map(Into::into).collect_vec(),
                     ))
-                    .into()
+                    .into(),
+                    selected: Some(i) == selected_index,
+                    is_directory: row.is_directory,
+                    bad_sectors: row.bad_sectors,
                 })
                 .collect_vec(),
         ));
-        // let properties = vec![FilePropertySection {
-        //     headline: "General".into(),
-        //     values: std::rc::Rc::new(slint::VecModel::from(vec![
-        //         FileProperty {
-        //             name: "Size".into(),
-        //             value: "412 kB".into(),
-        //         },
-        //         FileProperty {
-        //             name: "Filename".into(),
-        //             value: "example.txt".into(),
-        //         },
-        //     ]))
-        //     .into(),
-        // }];
-
-        ui.set_file_model(file_model.into());
-        ui.set_file_properties(properties_model.into());
-        //ui.set_file_property_sections(std::rc::Rc::new(slint::VecModel::from(properties)).into());
-        ui.set_scroll_y(0.0);
-    });
-
-    for (i, (_filename, file)) in enumerate(files) {
-        let properties = get_file_attributes(fs, ntfs, &file, parent_record_number)?;
-        let mut string_properties = vec![];
-        for standard_information in properties.standard_informations {
-            string_properties.push(StringFilePropertySection {
-                headline: "General".into(),
-                values: vec![
-                    StringFileProperty {
-                        name: "Creation".into(),
-                        value: format_time(standard_information.creation_time()),
-                    },
-                    StringFileProperty {
-                        name: "Last Access".into(),
-                        value: format_time(standard_information.access_time()),
-                    },
-                    StringFileProperty {
-                        name: "Modification".into(),
-                        value: format_time(standard_information.modification_time()),
-                    },
-                    StringFileProperty {
-                        name: "MFT Record Modification".into(),
-                        value: format_time(standard_information.mft_record_modification_time()),
-                    },
-                ],
-            })
-        }
-        properties_model[i] = string_properties;
-    }
-
-    ui.upgrade_in_event_loop(|ui| {
         let properties_model = std::rc::Rc::new(slint::VecModel::from(
             properties_model
                 .into_iter()
@@ -315,148 +2022,360 @@ where
                 })
                 .collect_vec(),
         ));
+
+        ui.set_file_model(file_model.into());
         ui.set_file_properties(properties_model.into());
+        ui.set_stream_items(std::rc::Rc::new(slint::VecModel::<StreamItem>::default()).into());
+        ui.set_show_stream_view(false);
+        match (selected_index, selected_sections) {
+            (Some(index), Some(sections)) => {
+                ui.set_highlighted_file(index as i32);
+                ui.set_file_property_sections(property_sections_model(sections));
+                // Keep a few files above the selected one in view.
+                ui.set_scroll_y(-(index.saturating_sub(3) as f32) * 18.0);
+            }
+            _ => ui.set_scroll_y(0.0),
+        }
     });
 
-    Ok(())
-}
+    if let Some(index) = selected_index {
+        show_details(volume, index, &entries[index], findings, ui);
+    }
 
-#[derive(Debug)]
-struct FileAttributes {
-    filenames: Vec<(NtfsFileNamespace, String, NtfsFileName)>,
-    hard_links: Vec<(NtfsFileNamespace, String, NtfsFileName)>,
-    standard_informations: Vec<NtfsStandardInformation>,
-    object_ids: Vec<NtfsObjectId>,
+    Ok(entries)
 }
 
-fn get_file_attributes<T>(
-    fs: &mut T,
-    ntfs: &Ntfs,
-    file: &NtfsFile,
-    parent_dir: u64,
-) -> anyhow::Result<FileAttributes>
-where
-    T: Read + Seek,
+/// Shows the properties and alternate data streams of the file with the given index in the file list.
+///
+/// The file list only comes with what the directory index tells about each file. Everything else is read from the
+/// file record when the file is hovered or selected, so that a large directory doesn't have to parse every record
+/// and a damaged record only spoils its own properties.
+fn show_details<R>(
+    volume: &mut Volume<R>,
+    index: usize,
+    entry: &DirEntry,
+    findings: &Findings,
+    ui: &slint::Weak<MainWindow>,
+) where
+    R: Read + Seek,
 {
-    let mut standard_informations = vec![];
-    let mut filenames = vec![];
-    let mut hard_links = vec![];
-    let mut object_ids = vec![];
-    let own_record_number = file.file_record_number();
-    let mut attributes = file.attributes();
-    while let Some(attr) = attributes.next(fs) {
-        if let Ok(attr) = attr {
-            let attr = attr.to_attribute();
-            // dbg!(attr.position());
-            dbg!(best_file_name(fs, file, parent_dir)?
-                .name()
-                .to_string_lossy());
-            dbg!(attr.ty()?);
-            if attr.ty().is_err() {
-                eprint!("unknown attribute type: {:?}", attr.name());
-            }
-            match attr.ty().unwrap() {
-                NtfsAttributeType::StandardInformation => {
-                    let data: NtfsStandardInformation = attr.structured_value(fs).unwrap();
-                    standard_informations.push(data);
-                }
-                NtfsAttributeType::AttributeList => continue,
-                NtfsAttributeType::FileName => {
-                    let data: NtfsFileName = attr.structured_value(fs).unwrap();
-                    if data.parent_directory_reference().file_record_number() == parent_dir {
-                        filenames.push((data.namespace(), data.name().to_string_lossy(), data));
+    let mut general = vec![
+        StringFileProperty {
+            name: "Filename".into(),
+            value: entry.name.clone(),
+        },
+        StringFileProperty {
+            name: "Size".into(),
+            value: format!("{}", ByteSize(entry.size)),
+        },
+    ];
+    match volume.file_info(entry.record_number) {
+        Ok(FileInfo {
+            times: Some(times), ..
+        }) => general.extend([
+            StringFileProperty {
+                name: "Creation".into(),
+                value: format_time(times.creation),
+            },
+            StringFileProperty {
+                name: "Last Access".into(),
+                value: format_time(times.access),
+            },
+            StringFileProperty {
+                name: "Modification".into(),
+                value: format_time(times.modification),
+            },
+            StringFileProperty {
+                name: "MFT Record Modification".into(),
+                value: format_time(times.mft_record_modification),
+            },
+        ]),
+        Ok(_) => (),
+        Err(err) => general.push(StringFileProperty {
+            name: "Error".into(),
+            value: format!("{:#}", err),
+        }),
+    }
+
+    let mut sections = vec![StringFilePropertySection {
+        headline: "General".into(),
+        values: general,
+    }];
+    sections.push(file_name_times_section(&entry.file_name_times));
+    sections.extend(
+        findings
+            .lifetime
+            .as_ref()
+            .and_then(|lifetime| timestomping_section(entry, lifetime)),
+    );
+    // This is mostly of interest for system files, a damaged attribute shouldn't spoil the listing.
+    if let Ok(attributes) = volume.attributes(entry.record_number) {
+        sections.push(StringFilePropertySection {
+            headline: "Attributes".into(),
+            values: attributes
+                .into_iter()
+                .map(|attribute| StringFileProperty {
+                    name: if attribute.name.is_empty() {
+                        attribute.ty.to_owned()
                     } else {
-                        let ns = data.namespace();
-                        let mut path = vec![data.name().to_string_lossy()];
-                        let mut parent = data.parent_directory_reference();
-                        let mut current_file_record_number = own_record_number;
-                        while parent.file_record_number() != current_file_record_number {
-                            let parent_dir = parent.to_file(ntfs, fs).unwrap();
-                            let ntfs_file_name: Option<NtfsFileName> =
-                                match parent_dir.name(fs, Some(ns), None) {
-                                    Some(name) => Some(name.unwrap()),
-                                    None => {
-                                        parent_dir.name(fs, None, None).map(|name| name.unwrap())
-                                    }
-                                };
-                            dbg!(&ntfs_file_name);
-                            match ntfs_file_name {
-                                Some(name) => {
-                                    current_file_record_number = parent_dir.file_record_number();
-                                    path.push(name.name().to_string_lossy());
-                                    parent = name.parent_directory_reference();
-                                }
-                                None => {
-                                    path.push("[[no file name found]]".to_owned());
-                                    break;
-                                }
-                            };
-                        }
-                        path.reverse();
-                        let path = path.join(r"\");
-                        hard_links.push((data.namespace(), path, data));
-                    }
-                }
-                NtfsAttributeType::ObjectId => {
-                    let data: NtfsObjectId = attr.structured_value(fs).unwrap();
-                    object_ids.push(data);
-                }
-                _ => continue,
-                NtfsAttributeType::SecurityDescriptor => todo!(),
-                NtfsAttributeType::VolumeName => todo!(),
-                NtfsAttributeType::VolumeInformation => todo!(),
-                NtfsAttributeType::Data => todo!(),
-                NtfsAttributeType::IndexRoot => todo!(),
-                NtfsAttributeType::IndexAllocation => todo!(),
-                NtfsAttributeType::Bitmap => todo!(),
-                NtfsAttributeType::ReparsePoint => todo!(),
-                NtfsAttributeType::EAInformation => todo!(),
-                NtfsAttributeType::EA => todo!(),
-                NtfsAttributeType::PropertySet => todo!(),
-                NtfsAttributeType::LoggedUtilityStream => todo!(),
-                NtfsAttributeType::End => todo!(),
-            }
+                        format!("{}:{}", attribute.ty, attribute.name)
+                    },
+                    value: format!(
+                        "{}{}",
+                        ByteSize(attribute.size),
+                        if attribute.resident { ", resident" } else { "" }
+                    ),
+                })
+                .collect(),
+        });
+    }
+    let streams = volume.streams(entry.record_number).unwrap_or_default();
+    if !streams.is_empty() {
+        if let Ok(Some(zone)) = volume.zone_identifier(entry.record_number) {
+            sections.push(zone_identifier_section(&zone));
         }
     }
+    if let Some(file) = findings
+        .deleted
+        .as_ref()
+        .and_then(|deleted| deleted.get(&entry.record_number))
+    {
+        sections.push(deleted_file_section(file));
+    }
+    if let Some(file_hashes) = findings.hashes.get(&entry.record_number) {
+        sections.push(hash_section(file_hashes));
+    }
+    sections.extend(findings.usn.as_ref().and_then(|journal| {
+        usn_section(journal.history(entry.record_number, entry.sequence_number))
+    }));
+    sections.extend(entry.bad_sectors.map(bad_sector_section));
 
-    Ok(FileAttributes {
-        filenames,
-        hard_links,
-        standard_informations,
-        object_ids,
-    })
+    ui.upgrade_in_event_loop(move |ui| {
+        // The mouse may have moved on to another file in the meantime.
+        if ui.get_highlighted_file() != index as i32 {
+            return;
+        }
+        ui.set_file_property_sections(property_sections_model(sections));
+        ui.set_stream_items(stream_items_model(index, streams));
+    });
 }
 
-fn best_file_name<T>(
-    fs: &mut T,
-    file: &NtfsFile,
-    parent_record_number: u64,
-) -> anyhow::Result<NtfsFileName>
-where
-    T: Read + Seek,
-{
-    // Try to find a long filename (Win32) first.
-    // If we don't find one, the file may only have a single short name (Win32AndDos).
-    // If we don't find one either, go with any namespace. It may still be a Dos or Posix name then.
-    let priority = [
-        Some(NtfsFileNamespace::Win32),
-        Some(NtfsFileNamespace::Win32AndDos),
-        None,
-    ];
+/// The alternate data streams of the file with the given index in the file list.
+fn stream_items_model(file: usize, streams: Vec<(String, u64)>) -> slint::ModelRc<StreamItem> {
+    let items = streams
+        .into_iter()
+        .map(|(name, size)| StreamItem {
+            file: file as i32,
+            name: name.into(),
+            size: ByteSize(size).to_string().into(),
+        })
+        .collect_vec();
+    std::rc::Rc::new(slint::VecModel::from(items)).into()
+}
 
-    for match_namespace in priority {
-        if let Some(file_name) = file.name(fs, match_namespace, Some(parent_record_number)) {
-            let file_name = file_name?;
-            return Ok(file_name);
-        }
+fn property_sections_model(
+    sections: Vec<StringFilePropertySection>,
+) -> slint::ModelRc<FilePropertySection> {
+    let sections = sections
+        .into_iter()
+        .map(|v| FilePropertySection {
+            headline: v.headline.into(),
+            values: std::rc::Rc::new(slint::VecModel::from(
+                v.values
+                    .into_iter()
+                    .map(|u| FileProperty {
+                        name: u.name.into(),
+                        value: u.value.into(),
+                    })
+                    .collect_vec(),
+            ))
+            .into(),
+        })
+        .collect_vec();
+    std::rc::Rc::new(slint::VecModel::from(sections)).into()
+}
+
+/// Lists where a downloaded file came from.
+fn zone_identifier_section(zone: &ZoneIdentifier) -> StringFilePropertySection {
+    let mut values = vec![];
+    if let Some(zone_id) = zone.zone_id {
+        values.push(StringFileProperty {
+            name: "ZoneId".into(),
+            value: match zone.zone_name() {
+                Some(zone_name) => format!("{} ({})", zone_id, zone_name),
+                None => zone_id.to_string(),
+            },
+        });
+    }
+    if let Some(referrer_url) = &zone.referrer_url {
+        values.push(StringFileProperty {
+            name: "ReferrerUrl".into(),
+            value: referrer_url.clone(),
+        });
+    }
+    if let Some(host_url) = &zone.host_url {
+        values.push(StringFileProperty {
+            name: "HostUrl".into(),
+            value: host_url.clone(),
+        });
+    }
+    StringFilePropertySection {
+        headline: "Zone.Identifier".into(),
+        values,
     }
+}
 
-    panic!(
-        "Found no FileName attribute for File Record {:#x}",
-        file.file_record_number()
-    )
+/// Lists where a deleted file was and how much of its content is still there.
+fn deleted_file_section(file: &DeletedFile) -> StringFilePropertySection {
+    StringFilePropertySection {
+        headline: "Deleted file".into(),
+        values: vec![
+            StringFileProperty {
+                name: "Original path".into(),
+                value: file.path.clone(),
+            },
+            StringFileProperty {
+                name: "Parent record".into(),
+                value: file.parent_record_number.to_string(),
+            },
+            StringFileProperty {
+                name: "Recoverability".into(),
+                value: file.recoverability.description(),
+            },
+        ],
+    }
+}
+
+/// The timestamps of the `$FILE_NAME` attribute the file is listed under, which only Windows itself sets.
+fn file_name_times_section(times: &FileTimes) -> StringFilePropertySection {
+    StringFilePropertySection {
+        headline: "$FILE_NAME".into(),
+        values: vec![
+            StringFileProperty {
+                name: "Creation".into(),
+                value: format_time(times.creation),
+            },
+            StringFileProperty {
+                name: "Last Access".into(),
+                value: format_time(times.access),
+            },
+            StringFileProperty {
+                name: "Modification".into(),
+                value: format_time(times.modification),
+            },
+            StringFileProperty {
+                name: "MFT Record Modification".into(),
+                value: format_time(times.mft_record_modification),
+            },
+        ],
+    }
+}
+
+/// Lists the signs of timestomping of a file, or returns `None` if there are none.
+fn timestomping_section(
+    entry: &DirEntry,
+    lifetime: &VolumeLifetime,
+) -> Option<StringFilePropertySection> {
+    let anomalies = anomalies(&entry.times, entry.first_name_creation, lifetime);
+    if anomalies.is_empty() {
+        return None;
+    }
+    let mut values = anomalies
+        .iter()
+        .map(|anomaly| StringFileProperty {
+            name: anomaly.label().into(),
+            value: anomaly.description().into(),
+        })
+        .collect_vec();
+    // With their fractions of a second, which the other sections leave out.
+    values.extend([
+        StringFileProperty {
+            name: "Creation".into(),
+            value: format_precise_time(entry.times.creation),
+        },
+        StringFileProperty {
+            name: "Modification".into(),
+            value: format_precise_time(entry.times.modification),
+        },
+        StringFileProperty {
+            name: "First $FILE_NAME Creation".into(),
+            value: format_precise_time(entry.first_name_creation),
+        },
+        StringFileProperty {
+            name: "Volume Creation".into(),
+            value: format_precise_time(lifetime.created),
+        },
+    ]);
+    Some(StringFilePropertySection {
+        headline: "Timestomping".into(),
+        values,
+    })
 }
 
-fn format_time(time: NtfsTime) -> String {
-    DateTime::from(time).format("%Y-%m-%d %H:%M:%S").to_string()
+/// Lists the change journal records of a file, or returns `None` if there are none.
+fn usn_section<'j>(
+    records: impl Iterator<Item = &'j UsnRecord>,
+) -> Option<StringFilePropertySection> {
+    let values = records
+        .map(|record| StringFileProperty {
+            name: record
+                .timestamp
+                .map(format_time)
+                .unwrap_or_else(|| format!("USN {}", record.usn)),
+            value: format!("{} ({})", record.reasons().join(", "), record.name),
+        })
+        .collect_vec();
+    if values.is_empty() {
+        return None;
+    }
+    Some(StringFilePropertySection {
+        headline: "Change journal".into(),
+        values,
+    })
+}
+
+/// Lists the hashes of the content of a file.
+fn hash_section(hashes: &FileHashes) -> StringFilePropertySection {
+    StringFilePropertySection {
+        headline: "Hashes".into(),
+        values: vec![
+            StringFileProperty {
+                name: "MD5".into(),
+                value: hashes.md5.clone(),
+            },
+            StringFileProperty {
+                name: "SHA-1".into(),
+                value: hashes.sha1.clone(),
+            },
+            StringFileProperty {
+                name: "SHA-256".into(),
+                value: hashes.sha256.clone(),
+            },
+        ],
+    }
+}
+
+/// Lists how much of a file lies in bad sectors.
+fn bad_sector_section(overlap: BadSectorOverlap) -> StringFilePropertySection {
+    StringFilePropertySection {
+        headline: "Bad sectors".into(),
+        values: vec![
+            StringFileProperty {
+                name: "MFT record".into(),
+                value: if overlap.mft_record {
+                    "unreadable"
+                } else {
+                    "ok"
+                }
+                .into(),
+            },
+            StringFileProperty {
+                name: "Data".into(),
+                value: match overlap.data {
+                    Some(data) => format!("{} unreadable", ByteSize(data)),
+                    None => "unknown, spread over several file records".into(),
+                },
+            },
+        ],
+    }
 }
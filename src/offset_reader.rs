@@ -0,0 +1,101 @@
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+/// `OffsetReader` exposes a window of its inner reader as if it was a stream of its own.
+///
+/// Position 0 of this reader corresponds to position `offset` of the inner reader.
+/// This is used to open a single partition of a full disk image.
+/// If a length is given, reads are cut off at the end of the window and `SeekFrom::End` becomes available.
+pub struct OffsetReader<R>
+where
+    R: Read + Seek,
+{
+    /// The inner reader stream.
+    inner: R,
+    /// Position of the window in the inner reader.
+    offset: u64,
+    /// Length of the window, if known.
+    length: Option<u64>,
+    /// The current stream position relative to `offset`.
+    stream_position: u64,
+}
+
+impl<R> OffsetReader<R>
+where
+    R: Read + Seek,
+{
+    pub fn new(mut inner: R, offset: u64, length: Option<u64>) -> io::Result<Self> {
+        inner.seek(SeekFrom::Start(offset))?;
+
+        Ok(Self {
+            inner,
+            offset,
+            length,
+            stream_position: 0,
+        })
+    }
+}
+
+impl<R> Read for OffsetReader<R>
+where
+    R: Read + Seek,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max_len = match self.length {
+            Some(length) => {
+                let remaining = length.saturating_sub(self.stream_position);
+                buf.len().min(remaining.try_into().unwrap_or(usize::MAX))
+            }
+            None => buf.len(),
+        };
+
+        let bytes_read = self.inner.read(&mut buf[..max_len])?;
+        self.stream_position += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl<R> Seek for OffsetReader<R>
+where
+    R: Read + Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => match self.length {
+                Some(length) => {
+                    if n >= 0 {
+                        length.checked_add(n as u64)
+                    } else {
+                        length.checked_sub(n.wrapping_neg() as u64)
+                    }
+                }
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "SeekFrom::End is unsupported for an OffsetReader without length",
+                    ))
+                }
+            },
+            SeekFrom::Current(n) => {
+                if n >= 0 {
+                    self.stream_position.checked_add(n as u64)
+                } else {
+                    self.stream_position.checked_sub(n.wrapping_neg() as u64)
+                }
+            }
+        };
+
+        match new_pos.and_then(|n| n.checked_add(self.offset).map(|inner_n| (n, inner_n))) {
+            Some((n, inner_n)) => {
+                self.inner.seek(SeekFrom::Start(inner_n))?;
+                self.stream_position = n;
+                Ok(self.stream_position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
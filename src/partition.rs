@@ -0,0 +1,629 @@
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::boot_sector::{is_ntfs_boot_sector, BootSector};
+
+/// Partition types of the MBR that point to an extended partition containing further logical partitions.
+const MBR_EXTENDED_TYPES: [u8; 3] = [0x05, 0x0f, 0x85];
+/// MBR partition type of the single partition in a protective MBR, which announces a GPT.
+const MBR_GPT_PROTECTIVE: u8 = 0xee;
+/// Upper limit for the number of logical partitions we follow, to get out of cyclic EBR chains.
+const MAX_LOGICAL_PARTITIONS: u32 = 128;
+/// Upper limit for the number of GPT entries we read, to not allocate absurd amounts of memory for corrupted headers.
+const MAX_GPT_ENTRIES: u32 = 1024;
+/// Upper limit for the size of a GPT entry. The UEFI specification only requires a power of two of at least 128.
+const MAX_GPT_ENTRY_SIZE: usize = 4096;
+/// Sector sizes at which we look for a GPT header.
+const GPT_SECTOR_SIZES: [u64; 2] = [512, 4096];
+pub const DEFAULT_SECTOR_SIZE: u64 = 512;
+
+/// Where the sector size of a disk was taken from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectorSizeSource {
+    /// Chosen by the user.
+    Override,
+    /// Reported by the device or the container format.
+    Device,
+    /// Derived from the location of the GPT header in the second sector.
+    Gpt,
+    /// Taken from the NTFS boot sector of an image of a single volume.
+    BootSector,
+    /// Nothing to go by, so we assume 512 bytes.
+    Default,
+}
+
+impl SectorSizeSource {
+    pub fn description(&self) -> &'static str {
+        match self {
+            SectorSizeSource::Override => "chosen by user",
+            SectorSizeSource::Device => "reported by device",
+            SectorSizeSource::Gpt => "from GPT header",
+            SectorSizeSource::BootSector => "from NTFS boot sector",
+            SectorSizeSource::Default => "default",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionScheme {
+    Mbr,
+    Gpt,
+    /// Found by scanning the disk for NTFS boot sectors.
+    Scan,
+}
+
+#[derive(Debug, Clone)]
+pub struct Partition {
+    pub scheme: PartitionScheme,
+    /// Partition number as operating systems count it (logical MBR partitions start at 5).
+    pub number: u32,
+    /// Human-readable partition type.
+    pub type_description: String,
+    /// Partition name stored in the GPT. Always empty for MBR partitions.
+    pub name: String,
+    /// Byte offset of the partition from the start of the disk.
+    pub offset: u64,
+    /// Size of the partition in bytes.
+    pub size: u64,
+    /// Whether the first sector of the partition is an NTFS boot sector.
+    pub is_ntfs: bool,
+    /// Boot sector to use instead of the first sector of the partition, if that one is damaged.
+    pub replacement_boot_sector: Option<Vec<u8>>,
+}
+
+impl Partition {
+    pub fn description(&self) -> String {
+        let scheme = match self.scheme {
+            PartitionScheme::Mbr => "MBR",
+            PartitionScheme::Gpt => "GPT",
+            PartitionScheme::Scan => "Scan",
+        };
+        if self.name.is_empty() {
+            format!("#{} {}: {}", self.number, scheme, self.type_description)
+        } else {
+            format!(
+                "#{} {}: {} \"{}\"",
+                self.number, scheme, self.type_description, self.name
+            )
+        }
+    }
+}
+
+struct MbrEntry {
+    partition_type: u8,
+    start_lba: u64,
+    sector_count: u64,
+}
+
+/// Detects the sector size of a disk without a device that reports it.
+///
+/// The GPT header is always in the second sector, and an image of a single volume starts with an NTFS boot sector
+/// that records its sector size.
+/// Returns `None` if neither is found, which leaves MBR disks with 4096 byte sectors undetected.
+///
+/// `disk` has to allow reads at multiples of 4096 bytes, which works for devices with either sector size.
+pub fn detect_sector_size<R>(disk: &mut R) -> io::Result<Option<(u64, SectorSizeSource)>>
+where
+    R: Read + Seek,
+{
+    let mut start = Vec::new();
+    disk.seek(SeekFrom::Start(0))?;
+    disk.by_ref().take(2 * 4096).read_to_end(&mut start)?;
+
+    // Partition tables take up 512 bytes, so smaller sectors would not hold them.
+    if let Some(boot_sector) = BootSector::parse(&start) {
+        if boot_sector.bytes_per_sector >= 512 {
+            return Ok(Some((
+                boot_sector.bytes_per_sector as u64,
+                SectorSizeSource::BootSector,
+            )));
+        }
+    }
+
+    for sector_size in GPT_SECTOR_SIZES {
+        let offset = sector_size as usize;
+        if start.get(offset..offset + 8) == Some(&b"EFI PART"[..]) {
+            return Ok(Some((sector_size, SectorSizeSource::Gpt)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Reads the MBR or GPT partition table of a full disk image.
+///
+/// Returns an empty list if the disk has no partition table, which is the case if it is an image of a single volume.
+pub fn read_partitions<R>(disk: &mut R, sector_size: u64) -> io::Result<Vec<Partition>>
+where
+    R: Read + Seek,
+{
+    let mut sector = vec![0u8; sector_size as usize];
+    disk.seek(SeekFrom::Start(0))?;
+    disk.read_exact(&mut sector)?;
+
+    // An NTFS boot sector carries the same signature as an MBR, so rule that out first.
+    if is_ntfs_boot_sector(&sector) || sector[510..512] != [0x55, 0xaa] {
+        return Ok(Vec::new());
+    }
+
+    let entries = mbr_entries(&sector);
+    let mut partitions = if entries
+        .iter()
+        .any(|entry| entry.partition_type == MBR_GPT_PROTECTIVE)
+    {
+        read_gpt(disk, sector_size)?
+    } else {
+        read_mbr(disk, sector_size, &entries)?
+    };
+
+    for partition in &mut partitions {
+        partition.is_ntfs = probe_ntfs(disk, partition.offset, sector_size)?;
+    }
+
+    Ok(partitions)
+}
+
+fn probe_ntfs<R>(disk: &mut R, offset: u64, sector_size: u64) -> io::Result<bool>
+where
+    R: Read + Seek,
+{
+    let mut sector = vec![0u8; sector_size as usize];
+    disk.seek(SeekFrom::Start(offset))?;
+    match disk.read_exact(&mut sector) {
+        Ok(()) => Ok(is_ntfs_boot_sector(&sector)),
+        // The partition table may point beyond the end of a truncated image.
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+fn mbr_entries(sector: &[u8]) -> Vec<MbrEntry> {
+    (0..4)
+        .map(|i| &sector[446 + i * 16..446 + (i + 1) * 16])
+        .map(|entry| MbrEntry {
+            partition_type: entry[4],
+            start_lba: u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64,
+            sector_count: u32::from_le_bytes(entry[12..16].try_into().unwrap()) as u64,
+        })
+        .filter(|entry| entry.partition_type != 0 && entry.sector_count != 0)
+        .collect()
+}
+
+fn read_mbr<R>(disk: &mut R, sector_size: u64, entries: &[MbrEntry]) -> io::Result<Vec<Partition>>
+where
+    R: Read + Seek,
+{
+    let mut partitions = Vec::new();
+    let mut logical_number = 5;
+
+    for (i, entry) in entries.iter().enumerate() {
+        if !MBR_EXTENDED_TYPES.contains(&entry.partition_type) {
+            partitions.push(mbr_partition(i as u32 + 1, entry, 0, sector_size));
+            continue;
+        }
+
+        // Walk the chain of Extended Boot Records.
+        // The first entry of each EBR describes a logical partition relative to that EBR,
+        // the second entry points to the next EBR relative to the start of the extended partition.
+        let extended_start = entry.start_lba;
+        let mut ebr_lba = extended_start;
+        let mut sector = vec![0u8; sector_size as usize];
+        for _ in 0..MAX_LOGICAL_PARTITIONS {
+            disk.seek(SeekFrom::Start(ebr_lba * sector_size))?;
+            // The chain may point beyond the end of a truncated image, keep the partitions found so far then.
+            if disk.read_exact(&mut sector).is_err() || sector[510..512] != [0x55, 0xaa] {
+                break;
+            }
+
+            let ebr_entries = mbr_entries(&sector);
+            let mut next_ebr = None;
+            for ebr_entry in &ebr_entries {
+                if MBR_EXTENDED_TYPES.contains(&ebr_entry.partition_type) {
+                    next_ebr = Some(extended_start + ebr_entry.start_lba);
+                } else {
+                    partitions.push(mbr_partition(
+                        logical_number,
+                        ebr_entry,
+                        ebr_lba,
+                        sector_size,
+                    ));
+                    logical_number += 1;
+                }
+            }
+
+            match next_ebr {
+                Some(lba) if lba > ebr_lba => ebr_lba = lba,
+                _ => break,
+            }
+        }
+    }
+
+    Ok(partitions)
+}
+
+fn mbr_partition(number: u32, entry: &MbrEntry, base_lba: u64, sector_size: u64) -> Partition {
+    Partition {
+        scheme: PartitionScheme::Mbr,
+        number,
+        type_description: mbr_type_description(entry.partition_type),
+        name: String::new(),
+        offset: (base_lba + entry.start_lba) * sector_size,
+        size: entry.sector_count * sector_size,
+        is_ntfs: false,
+        replacement_boot_sector: None,
+    }
+}
+
+fn mbr_type_description(partition_type: u8) -> String {
+    let description = match partition_type {
+        0x01 => "FAT12",
+        0x04 | 0x06 | 0x0e => "FAT16",
+        0x07 => "NTFS/exFAT",
+        0x0b | 0x0c => "FAT32",
+        0x11 | 0x14 | 0x16 | 0x1b | 0x1c | 0x1e => "Hidden FAT",
+        0x17 => "Hidden NTFS",
+        0x27 => "Windows Recovery",
+        0x42 => "Windows Dynamic Disk",
+        0x82 => "Linux swap",
+        0x83 => "Linux",
+        0x8e => "Linux LVM",
+        0xa5 => "FreeBSD",
+        0xaf => "HFS+",
+        0xfd => "Linux RAID",
+        _ => return format!("Type {:#04x}", partition_type),
+    };
+    format!("{} ({:#04x})", description, partition_type)
+}
+
+fn read_gpt<R>(disk: &mut R, sector_size: u64) -> io::Result<Vec<Partition>>
+where
+    R: Read + Seek,
+{
+    let mut header = vec![0u8; sector_size as usize];
+    disk.seek(SeekFrom::Start(sector_size))?;
+    disk.read_exact(&mut header)?;
+    if &header[0..8] != b"EFI PART" {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "protective MBR found, but no valid GPT header",
+        ));
+    }
+
+    let entries_lba = u64::from_le_bytes(header[72..80].try_into().unwrap());
+    let entry_count = u32::from_le_bytes(header[80..84].try_into().unwrap()).min(MAX_GPT_ENTRIES);
+    let entry_size = u32::from_le_bytes(header[84..88].try_into().unwrap()) as usize;
+    if !(128..=MAX_GPT_ENTRY_SIZE).contains(&entry_size) || !entry_size.is_power_of_two() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid GPT partition entry size",
+        ));
+    }
+
+    // The entries have to be on the disk, if we know how large it is.
+    let entries_size = entry_count as usize * entry_size;
+    let entries_position = entries_lba.checked_mul(sector_size);
+    let entries_end =
+        entries_position.and_then(|position| position.checked_add(entries_size as u64));
+    let disk_size = disk.seek(SeekFrom::End(0)).unwrap_or(u64::MAX);
+    let entries_position = match (entries_position, entries_end) {
+        (Some(position), Some(end)) if end <= disk_size => position,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "GPT partition entries lie beyond the end of the disk",
+            ))
+        }
+    };
+
+    let mut entries = vec![0u8; entries_size];
+    disk.seek(SeekFrom::Start(entries_position))?;
+    disk.read_exact(&mut entries)?;
+
+    let mut partitions = Vec::new();
+    for (i, entry) in entries.chunks_exact(entry_size).enumerate() {
+        let type_guid = &entry[0..16];
+        if type_guid.iter().all(|&b| b == 0) {
+            continue;
+        }
+
+        let first_lba = u64::from_le_bytes(entry[32..40].try_into().unwrap());
+        let last_lba = u64::from_le_bytes(entry[40..48].try_into().unwrap());
+        let name_utf16 = entry[56..128]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0)
+            .collect::<Vec<u16>>();
+
+        partitions.push(Partition {
+            scheme: PartitionScheme::Gpt,
+            number: i as u32 + 1,
+            type_description: gpt_type_description(type_guid),
+            name: String::from_utf16_lossy(&name_utf16),
+            offset: first_lba * sector_size,
+            size: (last_lba.saturating_sub(first_lba) + 1) * sector_size,
+            is_ntfs: false,
+            replacement_boot_sector: None,
+        });
+    }
+
+    Ok(partitions)
+}
+
+fn gpt_type_description(type_guid: &[u8]) -> String {
+    let guid = format_guid(type_guid);
+    let description = match guid.as_str() {
+        "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7" => "Basic data",
+        "C12A7328-F81F-11D2-BA4B-00A0C93EC93B" => "EFI System",
+        "E3C9E316-0B5C-4DB8-817D-F92DF00215AE" => "Microsoft Reserved",
+        "DE94BBA4-06D1-4D40-A16A-BFD50179D6AC" => "Windows Recovery",
+        "5808C8AA-7E8F-42E0-85D2-E1E90434CFB3" => "LDM metadata",
+        "AF9B60A0-1431-4F62-BC68-3311714A69AD" => "LDM data",
+        "E75CAF8F-F680-4CEE-AFA3-B001E56EFC2D" => "Storage Spaces",
+        "0FC63DAF-8483-4772-8E79-3D69D8477DE4" => "Linux filesystem",
+        "0657FD6D-A4AB-43C4-84E5-0933C84B4F4F" => "Linux swap",
+        "E6D6D379-F507-44C2-A23C-238F2A3DF928" => "Linux LVM",
+        "48465300-0000-11AA-AA11-00306543ECAC" => "HFS+",
+        "7C3457EF-0000-11AA-AA11-00306543ECAC" => "APFS",
+        "21686148-6449-6E6F-744E-656564454649" => "BIOS boot",
+        _ => return guid,
+    };
+    description.to_owned()
+}
+
+/// Formats a GUID stored in the mixed-endian on-disk layout.
+pub fn format_guid(guid: &[u8]) -> String {
+    format!(
+        "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+        u32::from_le_bytes(guid[0..4].try_into().unwrap()),
+        u16::from_le_bytes(guid[4..6].try_into().unwrap()),
+        u16::from_le_bytes(guid[6..8].try_into().unwrap()),
+        guid[8],
+        guid[9],
+        guid[10],
+        guid[11],
+        guid[12],
+        guid[13],
+        guid[14],
+        guid[15],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const SECTOR: usize = 512;
+
+    fn ntfs_boot_sector(bytes_per_sector: u16) -> Vec<u8> {
+        let mut sector = vec![0u8; bytes_per_sector as usize];
+        sector[3..11].copy_from_slice(b"NTFS    ");
+        sector[0x0b..0x0d].copy_from_slice(&bytes_per_sector.to_le_bytes());
+        sector[0x0d] = 8;
+        sector[0x28..0x30].copy_from_slice(&80_000u64.to_le_bytes());
+        sector[0x30..0x38].copy_from_slice(&4u64.to_le_bytes());
+        sector[0x38..0x40].copy_from_slice(&2u64.to_le_bytes());
+        sector[0x40] = 0xf6;
+        sector[510..512].copy_from_slice(&[0x55, 0xaa]);
+        sector
+    }
+
+    fn set_mbr_entry(sector: &mut [u8], index: usize, partition_type: u8, start: u32, count: u32) {
+        let entry = &mut sector[446 + index * 16..446 + (index + 1) * 16];
+        entry[4] = partition_type;
+        entry[8..12].copy_from_slice(&start.to_le_bytes());
+        entry[12..16].copy_from_slice(&count.to_le_bytes());
+    }
+
+    fn write_sector(disk: &mut [u8], lba: usize, sector: &[u8]) {
+        disk[lba * SECTOR..lba * SECTOR + sector.len()].copy_from_slice(sector);
+    }
+
+    /// A disk with a GPT header in LBA 1 and its entries from LBA 2 on.
+    fn gpt_disk(entry_count: u32, entry_size: u32, entries_lba: u64) -> Vec<u8> {
+        let mut disk = vec![0u8; 8192 * SECTOR];
+        let mut mbr = vec![0u8; SECTOR];
+        set_mbr_entry(&mut mbr, 0, MBR_GPT_PROTECTIVE, 1, 8191);
+        mbr[510..512].copy_from_slice(&[0x55, 0xaa]);
+        write_sector(&mut disk, 0, &mbr);
+
+        let mut header = vec![0u8; SECTOR];
+        header[0..8].copy_from_slice(b"EFI PART");
+        header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        header[80..84].copy_from_slice(&entry_count.to_le_bytes());
+        header[84..88].copy_from_slice(&entry_size.to_le_bytes());
+        write_sector(&mut disk, 1, &header);
+        disk
+    }
+
+    fn set_gpt_entry(
+        disk: &mut [u8],
+        index: usize,
+        type_guid: [u8; 16],
+        first: u64,
+        last: u64,
+        name: &str,
+    ) {
+        let start = 2 * SECTOR + index * 128;
+        let entry = &mut disk[start..start + 128];
+        entry[0..16].copy_from_slice(&type_guid);
+        entry[32..40].copy_from_slice(&first.to_le_bytes());
+        entry[40..48].copy_from_slice(&last.to_le_bytes());
+        for (i, c) in name.encode_utf16().enumerate() {
+            entry[56 + i * 2..58 + i * 2].copy_from_slice(&c.to_le_bytes());
+        }
+    }
+
+    /// `EBD0A0A2-B9E5-4433-87C0-68B6B72699C7` in its on-disk layout.
+    const BASIC_DATA: [u8; 16] = [
+        0xa2, 0xa0, 0xd0, 0xeb, 0xe5, 0xb9, 0x33, 0x44, 0x87, 0xc0, 0x68, 0xb6, 0xb7, 0x26, 0x99,
+        0xc7,
+    ];
+
+    #[test]
+    fn mbr_with_logical_partitions() {
+        let mut disk = vec![0u8; 5000 * SECTOR];
+        let mut mbr = vec![0u8; SECTOR];
+        set_mbr_entry(&mut mbr, 0, 0x07, 2048, 100);
+        set_mbr_entry(&mut mbr, 1, 0x0f, 4096, 900);
+        mbr[510..512].copy_from_slice(&[0x55, 0xaa]);
+        write_sector(&mut disk, 0, &mbr);
+        write_sector(&mut disk, 2048, &ntfs_boot_sector(512));
+
+        // The first logical partition is relative to its EBR, the link to the next EBR to the extended partition.
+        let mut ebr = vec![0u8; SECTOR];
+        set_mbr_entry(&mut ebr, 0, 0x83, 63, 10);
+        set_mbr_entry(&mut ebr, 1, 0x05, 200, 100);
+        ebr[510..512].copy_from_slice(&[0x55, 0xaa]);
+        write_sector(&mut disk, 4096, &ebr);
+        let mut ebr = vec![0u8; SECTOR];
+        set_mbr_entry(&mut ebr, 0, 0x07, 63, 20);
+        ebr[510..512].copy_from_slice(&[0x55, 0xaa]);
+        write_sector(&mut disk, 4296, &ebr);
+
+        let partitions = read_partitions(&mut Cursor::new(disk), 512).unwrap();
+        let summary = partitions
+            .iter()
+            .map(|p| (p.number, p.offset / 512, p.size / 512, p.is_ntfs))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (1, 2048, 100, true),
+                (5, 4096 + 63, 10, false),
+                (6, 4296 + 63, 20, false)
+            ]
+        );
+        assert!(partitions.iter().all(|p| p.scheme == PartitionScheme::Mbr));
+        assert_eq!(partitions[0].type_description, "NTFS/exFAT (0x07)");
+    }
+
+    #[test]
+    fn cyclic_ebr_chain_ends() {
+        let mut disk = vec![0u8; 5000 * SECTOR];
+        let mut mbr = vec![0u8; SECTOR];
+        set_mbr_entry(&mut mbr, 0, 0x05, 4096, 900);
+        mbr[510..512].copy_from_slice(&[0x55, 0xaa]);
+        write_sector(&mut disk, 0, &mbr);
+        // The EBR links back to itself.
+        let mut ebr = vec![0u8; SECTOR];
+        set_mbr_entry(&mut ebr, 0, 0x07, 63, 20);
+        set_mbr_entry(&mut ebr, 1, 0x05, 0, 900);
+        ebr[510..512].copy_from_slice(&[0x55, 0xaa]);
+        write_sector(&mut disk, 4096, &ebr);
+
+        let partitions = read_partitions(&mut Cursor::new(disk), 512).unwrap();
+        assert_eq!(partitions.len(), 1);
+        assert_eq!(partitions[0].number, 5);
+    }
+
+    #[test]
+    fn ebr_chain_beyond_the_end() {
+        let mut disk = vec![0u8; 4200 * SECTOR];
+        let mut mbr = vec![0u8; SECTOR];
+        set_mbr_entry(&mut mbr, 0, 0x07, 2048, 100);
+        set_mbr_entry(&mut mbr, 1, 0x0f, 4096, 900);
+        mbr[510..512].copy_from_slice(&[0x55, 0xaa]);
+        write_sector(&mut disk, 0, &mbr);
+        // The second EBR is beyond the end of the truncated image.
+        let mut ebr = vec![0u8; SECTOR];
+        set_mbr_entry(&mut ebr, 0, 0x83, 63, 10);
+        set_mbr_entry(&mut ebr, 1, 0x05, 200, 100);
+        ebr[510..512].copy_from_slice(&[0x55, 0xaa]);
+        write_sector(&mut disk, 4096, &ebr);
+
+        let partitions = read_partitions(&mut Cursor::new(disk.clone()), 512).unwrap();
+        let numbers = partitions.iter().map(|p| p.number).collect::<Vec<_>>();
+        assert_eq!(numbers, [1, 5]);
+
+        // Even the first EBR is missing.
+        disk.truncate(4000 * SECTOR);
+        let partitions = read_partitions(&mut Cursor::new(disk), 512).unwrap();
+        let numbers = partitions.iter().map(|p| p.number).collect::<Vec<_>>();
+        assert_eq!(numbers, [1]);
+    }
+
+    #[test]
+    fn gpt_partitions() {
+        let mut disk = gpt_disk(128, 128, 2);
+        set_gpt_entry(&mut disk, 0, BASIC_DATA, 2048, 4095, "Data");
+        set_gpt_entry(&mut disk, 2, [1; 16], 4096, 8000, "");
+        write_sector(&mut disk, 2048, &ntfs_boot_sector(512));
+
+        let partitions = read_partitions(&mut Cursor::new(disk), 512).unwrap();
+        assert_eq!(partitions.len(), 2);
+        assert_eq!(partitions[0].number, 1);
+        assert_eq!(partitions[0].type_description, "Basic data");
+        assert_eq!(partitions[0].name, "Data");
+        assert_eq!(partitions[0].offset, 2048 * 512);
+        assert_eq!(partitions[0].size, 2048 * 512);
+        assert!(partitions[0].is_ntfs);
+        assert_eq!(partitions[1].number, 3);
+        assert_eq!(
+            partitions[1].type_description,
+            "01010101-0101-0101-0101-010101010101"
+        );
+        assert!(!partitions[1].is_ntfs);
+    }
+
+    #[test]
+    fn gpt_with_invalid_entry_size() {
+        for entry_size in [0, 64, 192, 8192, u32::MAX] {
+            let disk = gpt_disk(128, entry_size, 2);
+            let err = read_partitions(&mut Cursor::new(disk), 512).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", entry_size);
+        }
+    }
+
+    #[test]
+    fn gpt_entries_beyond_the_end() {
+        for entries_lba in [8190, u64::MAX / 256] {
+            let disk = gpt_disk(128, 128, entries_lba);
+            let err = read_partitions(&mut Cursor::new(disk), 512).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", entries_lba);
+        }
+    }
+
+    #[test]
+    fn volume_image_has_no_partitions() {
+        let mut disk = ntfs_boot_sector(512);
+        disk.resize(8 * SECTOR, 0);
+        assert!(read_partitions(&mut Cursor::new(disk), 512)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn sector_size_detection() {
+        let mut disk = ntfs_boot_sector(4096);
+        disk.resize(4 * 4096, 0);
+        assert_eq!(
+            detect_sector_size(&mut Cursor::new(disk)).unwrap(),
+            Some((4096, SectorSizeSource::BootSector))
+        );
+
+        let mut disk = vec![0u8; 4 * 4096];
+        disk[4096..4104].copy_from_slice(b"EFI PART");
+        assert_eq!(
+            detect_sector_size(&mut Cursor::new(disk)).unwrap(),
+            Some((4096, SectorSizeSource::Gpt))
+        );
+
+        let disk = gpt_disk(128, 128, 2);
+        assert_eq!(
+            detect_sector_size(&mut Cursor::new(disk)).unwrap(),
+            Some((512, SectorSizeSource::Gpt))
+        );
+
+        assert_eq!(
+            detect_sector_size(&mut Cursor::new(vec![0u8; 4096])).unwrap(),
+            None
+        );
+
+        let mut disk = ntfs_boot_sector(512);
+        disk[0x0b..0x0d].copy_from_slice(&256u16.to_le_bytes());
+        disk.resize(4 * 4096, 0);
+        assert_eq!(detect_sector_size(&mut Cursor::new(disk)).unwrap(), None);
+    }
+}
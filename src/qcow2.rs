@@ -0,0 +1,340 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use flate2::read::DeflateDecoder;
+
+use crate::virtual_disk::{
+    invalid_data, parent_path_candidates, read_exact_at, BlockRead, VirtualDisk,
+};
+
+pub const QCOW2_MAGIC: &[u8; 4] = b"QFI\xfb";
+
+const OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+const COMPRESSED_FLAG: u64 = 1 << 62;
+const ZERO_FLAG: u64 = 1;
+/// Incompatible feature bit for an external data file, which we don't support.
+const EXTERNAL_DATA_FILE: u64 = 1 << 2;
+/// Incompatible feature bit for a compression type other than deflate.
+const COMPRESSION_TYPE: u64 = 1 << 3;
+/// Upper limit for the size of the L1 table, to not allocate absurd amounts of memory for corrupted headers.
+const MAX_L1_SIZE: u64 = 32 << 20;
+
+/// A QEMU qcow2 disk, optionally backed by another disk.
+///
+/// All fields of the on-disk structures are big-endian. Snapshots are ignored, only the active state is read.
+pub struct Qcow2 {
+    file: File,
+    version: u32,
+    size: u64,
+    cluster_bits: u32,
+    l1_table: Vec<u64>,
+    /// L1 index and entries of the most recently read L2 table.
+    cached_l2: Option<(usize, Vec<u64>)>,
+    /// Host offset and data of the most recently decompressed cluster.
+    cached_cluster: Option<(u64, Vec<u8>)>,
+    parent_paths: Vec<PathBuf>,
+}
+
+impl Qcow2 {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut header = [0u8; 104];
+        read_exact_at(&mut file, 0, &mut header[..72])?;
+        if &header[0..4] != QCOW2_MAGIC {
+            return Err(invalid_data("not a qcow2 file"));
+        }
+
+        let version = u32::from_be_bytes(header[4..8].try_into().unwrap());
+        let backing_file_offset = u64::from_be_bytes(header[8..16].try_into().unwrap());
+        let backing_file_size = u32::from_be_bytes(header[16..20].try_into().unwrap());
+        let cluster_bits = u32::from_be_bytes(header[20..24].try_into().unwrap());
+        let size = u64::from_be_bytes(header[24..32].try_into().unwrap());
+        let crypt_method = u32::from_be_bytes(header[32..36].try_into().unwrap());
+        let l1_size = u32::from_be_bytes(header[36..40].try_into().unwrap()) as u64;
+        let l1_table_offset = u64::from_be_bytes(header[40..48].try_into().unwrap());
+
+        match version {
+            2 => (),
+            3 => {
+                read_exact_at(&mut file, 72, &mut header[72..104])?;
+                let incompatible_features = u64::from_be_bytes(header[72..80].try_into().unwrap());
+                if incompatible_features & EXTERNAL_DATA_FILE != 0 {
+                    return Err(invalid_data(
+                        "qcow2 images with an external data file are not supported",
+                    ));
+                }
+                if incompatible_features & COMPRESSION_TYPE != 0 {
+                    return Err(invalid_data(
+                        "qcow2 images with zstd compression are not supported",
+                    ));
+                }
+            }
+            _ => {
+                return Err(invalid_data(&format!(
+                    "qcow version {} is not supported",
+                    version
+                )))
+            }
+        }
+        if crypt_method != 0 {
+            return Err(invalid_data("encrypted qcow2 images are not supported"));
+        }
+        if !(9..=21).contains(&cluster_bits) {
+            return Err(invalid_data("invalid qcow2 cluster size"));
+        }
+        if l1_size * 8 > MAX_L1_SIZE {
+            return Err(invalid_data("qcow2 L1 table is too large"));
+        }
+
+        let mut l1_table = vec![0u8; l1_size as usize * 8];
+        read_exact_at(&mut file, l1_table_offset, &mut l1_table)?;
+        let l1_table = l1_table
+            .chunks_exact(8)
+            .map(|entry| u64::from_be_bytes(entry.try_into().unwrap()))
+            .collect();
+
+        let parent_paths = if backing_file_offset != 0 && backing_file_size > 0 {
+            let mut backing_file = vec![0u8; backing_file_size.min(1023) as usize];
+            read_exact_at(&mut file, backing_file_offset, &mut backing_file)?;
+            parent_path_candidates(path, &String::from_utf8_lossy(&backing_file))
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            file,
+            version,
+            size,
+            cluster_bits,
+            l1_table,
+            cached_l2: None,
+            cached_cluster: None,
+            parent_paths,
+        })
+    }
+
+    fn cluster_size(&self) -> u64 {
+        1 << self.cluster_bits
+    }
+
+    /// Returns the L2 entry of the cluster at `position`, or 0 if there is no L2 table for it.
+    fn l2_entry(&mut self, position: u64) -> io::Result<u64> {
+        let l2_entries = self.cluster_size() / 8;
+        let cluster = position >> self.cluster_bits;
+        let l1_index = (cluster / l2_entries) as usize;
+        let l2_offset = match self.l1_table.get(l1_index) {
+            Some(&entry) if entry & OFFSET_MASK != 0 => entry & OFFSET_MASK,
+            _ => return Ok(0),
+        };
+
+        let is_cached =
+            matches!(&self.cached_l2, Some((cached_index, _)) if *cached_index == l1_index);
+        if !is_cached {
+            let mut table = vec![0u8; self.cluster_size() as usize];
+            read_exact_at(&mut self.file, l2_offset, &mut table)?;
+            let table = table
+                .chunks_exact(8)
+                .map(|entry| u64::from_be_bytes(entry.try_into().unwrap()))
+                .collect();
+            self.cached_l2 = Some((l1_index, table));
+        }
+
+        let table = &self.cached_l2.as_ref().unwrap().1;
+        Ok(table[(cluster % l2_entries) as usize])
+    }
+
+    /// Reads and decompresses a compressed cluster, described by its L2 entry.
+    fn load_compressed_cluster(&mut self, entry: u64) -> io::Result<&[u8]> {
+        // The descriptor holds the host offset in the low bits and the number of additional 512 byte sectors above.
+        let offset_bits = 62 - (self.cluster_bits - 8);
+        let descriptor = entry & ((1 << 62) - 1);
+        let host_offset = descriptor & ((1 << offset_bits) - 1);
+        let sectors = (descriptor >> offset_bits) + 1;
+        let compressed_size = sectors * 512 - (host_offset & 511);
+
+        let is_cached = matches!(&self.cached_cluster, Some((cached_offset, _)) if *cached_offset == host_offset);
+        if !is_cached {
+            // The last sector may extend beyond the end of the file.
+            let mut compressed = Vec::with_capacity(compressed_size as usize);
+            self.file.seek(SeekFrom::Start(host_offset))?;
+            (&mut self.file)
+                .take(compressed_size)
+                .read_to_end(&mut compressed)?;
+
+            let cluster_size = self.cluster_size() as usize;
+            let mut cluster = Vec::with_capacity(cluster_size);
+            DeflateDecoder::new(&compressed[..])
+                .take(cluster_size as u64)
+                .read_to_end(&mut cluster)?;
+            cluster.resize(cluster_size, 0);
+            self.cached_cluster = Some((host_offset, cluster));
+        }
+
+        Ok(&self.cached_cluster.as_ref().unwrap().1)
+    }
+}
+
+impl VirtualDisk for Qcow2 {
+    fn format_name(&self) -> String {
+        format!("qcow2 (version {})", self.version)
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn parent_paths(&self) -> Vec<PathBuf> {
+        self.parent_paths.clone()
+    }
+
+    fn read_at(&mut self, position: u64, buf: &mut [u8]) -> io::Result<BlockRead> {
+        let cluster_size = self.cluster_size();
+        let offset_in_cluster = position % cluster_size;
+        let len = buf.len().min((cluster_size - offset_in_cluster) as usize);
+
+        let entry = self.l2_entry(position)?;
+        if entry & COMPRESSED_FLAG != 0 {
+            let cluster = self.load_compressed_cluster(entry)?;
+            let start = offset_in_cluster as usize;
+            buf[..len].copy_from_slice(&cluster[start..start + len]);
+            return Ok(BlockRead::Data(len));
+        }
+
+        // The zero flag only exists since version 3.
+        if self.version >= 3 && entry & ZERO_FLAG != 0 {
+            buf[..len].fill(0);
+            return Ok(BlockRead::Data(len));
+        }
+
+        match entry & OFFSET_MASK {
+            0 => Ok(BlockRead::Parent(len)),
+            cluster_offset => {
+                read_exact_at(
+                    &mut self.file,
+                    cluster_offset + offset_in_cluster,
+                    &mut buf[..len],
+                )?;
+                Ok(BlockRead::Data(len))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+    use crate::virtual_disk::tests::{pattern, read_disk};
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    const CLUSTER_BITS: u32 = 9;
+    const CLUSTER_SIZE: usize = 1 << CLUSTER_BITS;
+    /// Each L2 table of 512 byte clusters maps 64 clusters.
+    const L2_ENTRIES: usize = CLUSTER_SIZE / 8;
+
+    /// Builds a version 3 image with one L2 table, whose clusters are given by `l2`.
+    ///
+    /// Data clusters are appended after the L2 table at cluster 3, `Some(data)` entries get their offset filled in.
+    fn image(size: u64, l2: &[(u64, Option<Vec<u8>>)], backing_file: &str) -> Vec<u8> {
+        let mut file = vec![0u8; 3 * CLUSTER_SIZE];
+        file[0..4].copy_from_slice(QCOW2_MAGIC);
+        file[4..8].copy_from_slice(&3u32.to_be_bytes());
+        if !backing_file.is_empty() {
+            file[8..16].copy_from_slice(&1024u64.to_be_bytes());
+            file[16..20].copy_from_slice(&(backing_file.len() as u32).to_be_bytes());
+            file[1024..1024 + backing_file.len()].copy_from_slice(backing_file.as_bytes());
+        }
+        file[20..24].copy_from_slice(&CLUSTER_BITS.to_be_bytes());
+        file[24..32].copy_from_slice(&size.to_be_bytes());
+        file[36..40].copy_from_slice(&1u32.to_be_bytes());
+        // The L1 table is at cluster 1 and points to the L2 table at cluster 3.
+        file[40..48].copy_from_slice(&(CLUSTER_SIZE as u64).to_be_bytes());
+        file[CLUSTER_SIZE..CLUSTER_SIZE + 8]
+            .copy_from_slice(&(3 * CLUSTER_SIZE as u64).to_be_bytes());
+
+        let l2_offset = file.len();
+        file.resize(l2_offset + CLUSTER_SIZE, 0);
+        for (i, (entry, data)) in l2.iter().enumerate() {
+            let entry = match data {
+                Some(data) => {
+                    let offset = file.len() as u64;
+                    file.extend_from_slice(data);
+                    entry | offset
+                }
+                None => *entry,
+            };
+            file[l2_offset + 8 * i..l2_offset + 8 * i + 8].copy_from_slice(&entry.to_be_bytes());
+        }
+        file
+    }
+
+    #[test]
+    fn clusters() {
+        let dir = test_dir("qcow2");
+        let plain = pattern(1, CLUSTER_SIZE);
+        let compressed_data = pattern(2, CLUSTER_SIZE);
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&compressed_data).unwrap();
+        let mut compressed = encoder.finish().unwrap();
+        assert!(compressed.len() < 512);
+        compressed.resize(512, 0);
+
+        // The compressed descriptor has no additional sectors, so it only holds the host offset,
+        // which is filled in when the data is appended.
+        let file = image(
+            5 * CLUSTER_SIZE as u64,
+            &[
+                (0, Some(plain.clone())),
+                (0, None),
+                (COMPRESSED_FLAG, Some(compressed)),
+                (ZERO_FLAG, None),
+            ],
+            "base.qcow2",
+        );
+        std::fs::write(dir.join("disk.qcow2"), &file).unwrap();
+
+        let qcow2 = Qcow2::open(&dir.join("disk.qcow2")).unwrap();
+        assert_eq!(qcow2.format_name(), "qcow2 (version 3)");
+        assert_eq!(qcow2.parent_paths(), vec![dir.join("base.qcow2")]);
+
+        let parent = pattern(3, 5 * CLUSTER_SIZE);
+        let zeros = vec![0u8; CLUSTER_SIZE];
+        let expected = [
+            &plain[..],
+            &parent[CLUSTER_SIZE..2 * CLUSTER_SIZE],
+            &compressed_data[..],
+            &zeros[..],
+            &parent[4 * CLUSTER_SIZE..],
+        ]
+        .concat();
+        assert_eq!(read_disk(qcow2, Some(parent)), expected);
+    }
+
+    #[test]
+    fn clusters_beyond_the_l1_table() {
+        let dir = test_dir("qcow2-l1");
+        // Only the first L2 table exists, the rest of the disk reads as the (missing) backing file.
+        let size = (L2_ENTRIES * CLUSTER_SIZE + CLUSTER_SIZE) as u64;
+        let file = image(size, &[(0, Some(pattern(4, CLUSTER_SIZE)))], "");
+        std::fs::write(dir.join("disk.qcow2"), &file).unwrap();
+
+        let data = read_disk(Qcow2::open(&dir.join("disk.qcow2")).unwrap(), None);
+        assert_eq!(data.len() as u64, size);
+        assert_eq!(data[..CLUSTER_SIZE], pattern(4, CLUSTER_SIZE)[..]);
+        assert!(data[CLUSTER_SIZE..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn encrypted_image() {
+        let dir = test_dir("qcow2-encrypted");
+        let mut file = image(CLUSTER_SIZE as u64, &[], "");
+        file[32..36].copy_from_slice(&1u32.to_be_bytes());
+        std::fs::write(dir.join("disk.qcow2"), &file).unwrap();
+
+        assert!(Qcow2::open(&dir.join("disk.qcow2")).is_err());
+    }
+}
@@ -4,6 +4,9 @@
 use std::io;
 use std::io::{Read, Seek, SeekFrom};
 
+use crate::bad_sectors::SharedBadSectors;
+use crate::boot_sector::BootSector;
+
 /// `SectorReader` encapsulates any reader and only performs read and seek operations on it
 /// on boundaries of the given sector size.
 ///
@@ -11,6 +14,12 @@ use std::io::{Read, Seek, SeekFrom};
 /// from a raw partition on Windows).
 /// The sector size must be a power of two.
 ///
+/// If the length of the inner reader is known, `SectorReader` supports `SeekFrom::End` and clamps reads at the
+/// end, even if the last sector is only partially present.
+///
+/// By default, any read error of the inner reader is passed on.
+/// For images of failing disks, `tolerate_bad_sectors` makes `SectorReader` substitute unreadable sectors instead.
+///
 /// This reader does not keep any buffer.
 /// You are advised to encapsulate `SectorReader` in a buffered reader, as unbuffered reads of
 /// just a few bytes here and there are highly inefficient.
@@ -22,9 +31,13 @@ where
     inner: R,
     /// The sector size set at creation.
     sector_size: usize,
+    /// Length of the inner stream in bytes, if known.
+    length: Option<u64>,
     /// The current stream position as requested by the caller through `read` or `seek`.
     /// The implementation will internally make sure to only read/seek on sector boundaries.
     stream_position: u64,
+    /// How to handle unreadable sectors, `None` to fail on them.
+    tolerance: Option<BadSectorTolerance>,
     /// This buffer is only part of the struct as a small performance optimization (keeping it allocated between reads).
     temp_buf: Vec<u8>,
 }
@@ -33,7 +46,20 @@ impl<R> SectorReader<R>
 where
     R: Read + Seek,
 {
+    /// Creates a `SectorReader` and detects the length of `inner`.
+    ///
+    /// The length is taken from `SeekFrom::End` of the inner reader if that is supported.
+    /// Inner readers of devices should only support it if they know the device size, like the raw images of
+    /// `image::open_image`, which query it from the device first. Raw partitions on Windows report a length of 0,
+    /// so then the NTFS boot sector at the start of `inner` is consulted. If both fail, the length remains unknown.
     pub fn new(inner: R, sector_size: usize) -> io::Result<Self> {
+        let mut reader = Self::with_length(inner, sector_size, None)?;
+        reader.length = reader.detect_length()?;
+        Ok(reader)
+    }
+
+    /// Creates a `SectorReader` for an inner reader of the given length, or of unknown length.
+    pub fn with_length(inner: R, sector_size: usize, length: Option<u64>) -> io::Result<Self> {
         if !sector_size.is_power_of_two() {
             return Err(io::Error::new(
                 io::ErrorKind::Other,
@@ -44,17 +70,110 @@ where
         Ok(Self {
             inner,
             sector_size,
+            length,
             stream_position: 0,
+            tolerance: None,
             temp_buf: Vec::new(),
         })
     }
 
+    /// Substitutes sectors that cannot be read with the fill pattern of `tolerance` instead of failing.
+    ///
+    /// Sectors are read one by one after a failed read, to pinpoint the bad ones.
+    /// Sectors already listed as bad are not read at all, which spares failing disks from retrying them.
+    pub fn tolerate_bad_sectors(&mut self, tolerance: BadSectorTolerance) {
+        self.tolerance = Some(tolerance);
+    }
+
+    /// Gets a reference to the inner reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns the length of the inner stream in bytes, if known.
+    pub fn length(&self) -> Option<u64> {
+        self.length
+    }
+
+    fn detect_length(&mut self) -> io::Result<Option<u64>> {
+        match self.inner.seek(SeekFrom::End(0)) {
+            Ok(0) | Err(_) => (),
+            Ok(length) => {
+                self.inner.seek(SeekFrom::Start(0))?;
+                return Ok(Some(length));
+            }
+        }
+
+        // The boot sector is at least 512 bytes, which may be more than a sector.
+        self.temp_buf
+            .resize(self.align_up_to_sector_size(512) as usize, 0);
+        self.inner.seek(SeekFrom::Start(0))?;
+        let boot_sector = match self.inner.read_exact(&mut self.temp_buf) {
+            Ok(()) => BootSector::parse(&self.temp_buf),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(e) => return Err(e),
+        };
+        self.inner.seek(SeekFrom::Start(0))?;
+
+        // The backup boot sector in the last sector of the partition is not counted in the total sectors.
+        Ok(boot_sector.map(|b| b.volume_size() + b.bytes_per_sector as u64))
+    }
+
+    /// Fills `self.temp_buf` from `aligned_position` on, filling in bad sectors, and returns the number of bytes read.
+    fn read_tolerantly(&mut self, aligned_position: u64) -> io::Result<usize> {
+        let tolerance = self.tolerance.as_ref().unwrap();
+        let end_position = aligned_position + self.temp_buf.len() as u64;
+
+        // Try to read everything at once first, which should succeed for most reads.
+        let known_bad = tolerance
+            .bad_sectors
+            .lock()
+            .unwrap()
+            .overlaps(aligned_position, end_position);
+        if !known_bad {
+            self.inner.seek(SeekFrom::Start(aligned_position))?;
+            if let Ok(bytes_read) = read_up_to(&mut self.inner, &mut self.temp_buf) {
+                return Ok(bytes_read);
+            }
+        }
+
+        let mut bytes_read = 0;
+        for (i, sector) in self.temp_buf.chunks_mut(self.sector_size).enumerate() {
+            let position = aligned_position + (i * self.sector_size) as u64;
+            let sector_end = position + sector.len() as u64;
+            let sector_end = self
+                .length
+                .map_or(sector_end, |length| length.min(sector_end));
+
+            let mut bad_sectors = tolerance.bad_sectors.lock().unwrap();
+            if !bad_sectors.overlaps(position, sector_end) {
+                let result = self
+                    .inner
+                    .seek(SeekFrom::Start(position))
+                    .and_then(|_| read_up_to(&mut self.inner, sector));
+                match result {
+                    Ok(len) if len < sector.len() => return Ok(bytes_read + len),
+                    Ok(len) => {
+                        bytes_read += len;
+                        continue;
+                    }
+                    Err(_) => bad_sectors.insert(position, sector_end),
+                }
+            }
+
+            tolerance.fill(sector, position);
+            bytes_read += sector.len();
+        }
+
+        Ok(bytes_read)
+    }
+
     fn align_down_to_sector_size(&self, n: u64) -> u64 {
         n / self.sector_size as u64 * self.sector_size as u64
     }
 
     fn align_up_to_sector_size(&self, n: u64) -> u64 {
-        self.align_down_to_sector_size(n) + self.sector_size as u64
+        self.align_down_to_sector_size(n + self.sector_size as u64 - 1)
     }
 }
 
@@ -63,6 +182,19 @@ where
     R: Read + Seek,
 {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Don't read past the end if we know where it is.
+        let buf = match self.length {
+            Some(length) => {
+                let remaining = length.saturating_sub(self.stream_position);
+                let len = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+                &mut buf[..len]
+            }
+            None => buf,
+        };
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
         // We can only read from a sector boundary, and `self.stream_position` specifies the position where the
         // caller thinks we are.
         // Align down to a sector boundary to determine the position where we really are (see our `seek` implementation).
@@ -75,8 +207,23 @@ where
         let aligned_bytes_to_read = self.align_up_to_sector_size(end as u64) as usize;
 
         // Perform the sector-sized read and copy the actually requested bytes into the given buffer.
+        // The previous read may have left the inner reader at the end of a partially consumed sector,
+        // so go back to the aligned position first.
         self.temp_buf.resize(aligned_bytes_to_read, 0);
-        self.inner.read_exact(&mut self.temp_buf)?;
+        let bytes_read = if self.tolerance.is_some() {
+            self.read_tolerantly(aligned_position)?
+        } else {
+            self.inner.seek(SeekFrom::Start(aligned_position))?;
+            read_up_to(&mut self.inner, &mut self.temp_buf)?
+        };
+
+        // The last sector may only be partially present, but not the requested bytes.
+        if bytes_read < end {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "failed to fill whole buffer",
+            ));
+        }
         buf.copy_from_slice(&self.temp_buf[start..end]);
 
         // We are done.
@@ -92,14 +239,20 @@ where
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
         let new_pos = match pos {
             SeekFrom::Start(n) => Some(n),
-            SeekFrom::End(_n) => {
-                // This is unsupported, because it's not safely possible under Windows.
-                // We cannot seek to the end to determine the raw partition size.
-                // Which makes it impossible to set `self.stream_position`.
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "SeekFrom::End is unsupported for SectorReader",
-                ));
+            SeekFrom::End(n) => {
+                // We cannot just seek the inner reader to its end, because that's not possible for raw partitions
+                // under Windows.
+                let length = self.length.ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::Other,
+                        "SeekFrom::End is unsupported for SectorReader of unknown length",
+                    )
+                })?;
+                if n >= 0 {
+                    length.checked_add(n as u64)
+                } else {
+                    length.checked_sub(n.wrapping_neg() as u64)
+                }
             }
             SeekFrom::Current(n) => {
                 if n >= 0 {
@@ -128,3 +281,209 @@ where
         }
     }
 }
+
+/// Settings for reading images of failing disks, see `SectorReader::tolerate_bad_sectors`.
+#[derive(Clone, Debug)]
+pub struct BadSectorTolerance {
+    /// Pattern repeated over unreadable sectors, aligned to the start of the stream.
+    pub fill_pattern: Vec<u8>,
+    /// Receives the unreadable ranges, and may already contain ranges known to be bad.
+    pub bad_sectors: SharedBadSectors,
+}
+
+impl BadSectorTolerance {
+    fn fill(&self, sector: &mut [u8], position: u64) {
+        if self.fill_pattern.is_empty() {
+            sector.fill(0);
+            return;
+        }
+
+        let pattern_len = self.fill_pattern.len() as u64;
+        for (i, byte) in sector.iter_mut().enumerate() {
+            *byte = self.fill_pattern[((position + i as u64) % pattern_len) as usize];
+        }
+    }
+}
+
+/// Reads until `buf` is full or the reader ends and returns the number of bytes read.
+fn read_up_to<R>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize>
+where
+    R: Read,
+{
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Behaves like a raw partition on Windows, which claims to end at 0.
+    struct Device(Cursor<Vec<u8>>);
+
+    impl Read for Device {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Seek for Device {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            match pos {
+                SeekFrom::End(_) => Ok(0),
+                pos => self.0.seek(pos),
+            }
+        }
+    }
+
+    /// A volume of 16 sectors of 512 bytes, whose boot sector counts all but the backup boot sector.
+    fn volume() -> Vec<u8> {
+        let mut volume = vec![0u8; 16 * 512];
+        volume[3..11].copy_from_slice(b"NTFS    ");
+        volume[0x0b..0x0d].copy_from_slice(&512u16.to_le_bytes());
+        volume[0x0d] = 1;
+        volume[0x28..0x30].copy_from_slice(&15u64.to_le_bytes());
+        volume[0x30..0x38].copy_from_slice(&4u64.to_le_bytes());
+        volume[0x38..0x40].copy_from_slice(&8u64.to_le_bytes());
+        volume[0x40] = 0xf6;
+        volume[510..512].copy_from_slice(&[0x55, 0xaa]);
+        volume
+    }
+
+    #[test]
+    fn length_from_seek() {
+        let mut data = volume();
+        data.truncate(1000);
+        let mut reader = SectorReader::new(Cursor::new(data), 512).unwrap();
+        assert_eq!(reader.length(), Some(1000));
+        assert_eq!(reader.seek(SeekFrom::End(-10)).unwrap(), 990);
+
+        // The last sector is only partially present, which must not fail the read.
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf.len(), 10);
+    }
+
+    #[test]
+    fn length_from_boot_sector() {
+        let reader = SectorReader::new(Device(Cursor::new(volume())), 512).unwrap();
+        assert_eq!(reader.length(), Some(16 * 512));
+
+        let reader = SectorReader::new(Device(Cursor::new(vec![0; 4096])), 512).unwrap();
+        assert_eq!(reader.length(), None);
+    }
+
+    /// A disk whose listed sectors of 512 bytes fail every read touching them, and which counts those attempts.
+    struct FailingDisk {
+        data: Cursor<Vec<u8>>,
+        bad: Vec<u64>,
+        failed_reads: usize,
+    }
+
+    impl Read for FailingDisk {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let first = self.data.position() / 512;
+            let last = (self.data.position() + buf.len() as u64).saturating_sub(1) / 512;
+            if self
+                .bad
+                .iter()
+                .any(|sector| (first..=last).contains(sector))
+            {
+                self.failed_reads += 1;
+                return Err(io::Error::new(io::ErrorKind::Other, "bad sector"));
+            }
+            self.data.read(buf)
+        }
+    }
+
+    impl Seek for FailingDisk {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.data.seek(pos)
+        }
+    }
+
+    fn tolerant_reader(len: usize, bad: &[u64], fill_pattern: &[u8]) -> SectorReader<FailingDisk> {
+        let disk = FailingDisk {
+            data: Cursor::new((0..len).map(|i| (i % 251) as u8).collect()),
+            bad: bad.to_vec(),
+            failed_reads: 0,
+        };
+        let mut reader = SectorReader::new(disk, 512).unwrap();
+        reader.tolerate_bad_sectors(BadSectorTolerance {
+            fill_pattern: fill_pattern.to_vec(),
+            bad_sectors: Default::default(),
+        });
+        reader
+    }
+
+    fn bad_ranges(reader: &SectorReader<FailingDisk>) -> (usize, u64) {
+        let bad_sectors = reader
+            .tolerance
+            .as_ref()
+            .unwrap()
+            .bad_sectors
+            .lock()
+            .unwrap();
+        (bad_sectors.range_count(), bad_sectors.total_size())
+    }
+
+    #[test]
+    fn fill_bad_sectors() {
+        let mut reader = tolerant_reader(8 * 512, &[2, 3], &[0xde, 0xad, 0xbe]);
+        let mut buf = vec![0u8; 1500];
+        reader.seek(SeekFrom::Start(700)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+
+        // The pattern is aligned to the start of the stream, so it continues across sector boundaries.
+        let expected = (700..2200u64)
+            .map(|position| match position / 512 {
+                2 | 3 => [0xde, 0xad, 0xbe][(position % 3) as usize],
+                _ => (position % 251) as u8,
+            })
+            .collect::<Vec<u8>>();
+        assert_eq!(buf, expected);
+        // Adjacent bad sectors make up a single range.
+        assert_eq!(bad_ranges(&reader), (1, 1024));
+
+        // Sectors known to be bad are not read again.
+        let failed_reads = reader.get_ref().failed_reads;
+        reader.seek(SeekFrom::Start(1000)).unwrap();
+        reader.read_exact(&mut buf[..100]).unwrap();
+        assert_eq!(buf[..100], expected[300..400]);
+        assert_eq!(reader.get_ref().failed_reads, failed_reads);
+    }
+
+    #[test]
+    fn fill_partial_last_sector() {
+        // The last sector is only partially present and bad, the bad range ends with the disk.
+        let mut reader = tolerant_reader(3 * 512 + 100, &[3], &[]);
+        let mut buf = Vec::new();
+        reader.seek(SeekFrom::Start(1000)).unwrap();
+        reader.read_to_end(&mut buf).unwrap();
+
+        assert_eq!(buf.len(), 636);
+        assert!(buf[536..].iter().all(|&b| b == 0));
+        assert_eq!(bad_ranges(&reader), (1, 100));
+    }
+
+    #[test]
+    fn bad_sectors_fail_without_tolerance() {
+        let disk = FailingDisk {
+            data: Cursor::new(vec![0; 4 * 512]),
+            bad: vec![1],
+            failed_reads: 0,
+        };
+        let mut reader = SectorReader::new(disk, 512).unwrap();
+        let mut buf = [0u8; 1024];
+        assert!(reader.read_exact(&mut buf).is_err());
+    }
+}
@@ -0,0 +1,257 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// A raw image split into several segment files, like `image.001`, `image.002`, … or `xaa`, `xab`, …
+pub struct SplitImage {
+    /// Segment files with their position in the image.
+    segments: Vec<(File, u64)>,
+    size: u64,
+    /// The current stream position in the image.
+    stream_position: u64,
+}
+
+impl SplitImage {
+    pub fn open(paths: &[PathBuf]) -> io::Result<Self> {
+        let mut segments = Vec::with_capacity(paths.len());
+        let mut size = 0;
+        for path in paths {
+            let file = File::open(path)?;
+            let len = file.metadata()?.len();
+            segments.push((file, size));
+            size += len;
+        }
+
+        Ok(Self {
+            segments,
+            size,
+            stream_position: 0,
+        })
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+}
+
+/// Returns the paths of all segments if `path` is a segment of a split image.
+///
+/// Numeric suffixes (`.000`/`.001`, `x00`) and alphabetic suffixes as created by `split` (`aa`) are recognized.
+/// A file only counts as a segment if the first two segments of its series exist, so that files like `image.dd`
+/// or `disk2022` are not mistaken for segments.
+pub fn segment_paths(path: &Path) -> Option<Vec<PathBuf>> {
+    let file_name = path.file_name()?.to_str()?;
+
+    let digits = file_name
+        .chars()
+        .rev()
+        .take_while(|c| c.is_ascii_digit())
+        .count();
+    let letters = file_name
+        .chars()
+        .rev()
+        .take_while(|c| c.is_ascii_lowercase())
+        .count();
+
+    let paths = if digits >= 2 {
+        let prefix = &file_name[..file_name.len() - digits];
+        // Numbering starts at either 0 or 1, depending on the tool that split the image.
+        let first = if sibling(path, prefix, &format!("{:01$}", 0, digits)).is_file() {
+            0
+        } else {
+            1
+        };
+        (first..10u64.pow(digits as u32))
+            .map(|n| sibling(path, prefix, &format!("{:01$}", n, digits)))
+            .take_while(|path| path.is_file())
+            .collect::<Vec<_>>()
+    } else if letters >= 2 {
+        let prefix = &file_name[..file_name.len() - 2];
+        alphabetic_suffixes()
+            .map(|suffix| sibling(path, prefix, &suffix))
+            .take_while(|path| path.is_file())
+            .collect::<Vec<_>>()
+    } else {
+        return None;
+    };
+
+    if paths.len() >= 2
+        && paths
+            .iter()
+            .any(|segment| segment.file_name() == path.file_name())
+    {
+        Some(paths)
+    } else {
+        None
+    }
+}
+
+fn sibling(path: &Path, prefix: &str, suffix: &str) -> PathBuf {
+    path.with_file_name(format!("{}{}", prefix, suffix))
+}
+
+/// `aa`, `ab`, …, `zz`.
+fn alphabetic_suffixes() -> impl Iterator<Item = String> {
+    (b'a'..=b'z').flat_map(|first| {
+        (b'a'..=b'z').map(move |second| String::from_utf8(vec![first, second]).unwrap())
+    })
+}
+
+impl Read for SplitImage {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.stream_position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        // Find the last segment starting at or before the current position, skipping empty segments.
+        let index = self
+            .segments
+            .partition_point(|(_, start)| *start <= self.stream_position)
+            - 1;
+        let segment_end = self
+            .segments
+            .get(index + 1)
+            .map_or(self.size, |(_, start)| *start);
+        let (file, start) = &mut self.segments[index];
+
+        let max_len = buf.len().min(
+            (segment_end - self.stream_position)
+                .try_into()
+                .unwrap_or(usize::MAX),
+        );
+        file.seek(SeekFrom::Start(self.stream_position - *start))?;
+        let bytes_read = file.read(&mut buf[..max_len])?;
+        if bytes_read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "segment of split image is shorter than when it was opened",
+            ));
+        }
+
+        self.stream_position += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl Seek for SplitImage {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => {
+                if n >= 0 {
+                    self.size.checked_add(n as u64)
+                } else {
+                    self.size.checked_sub(n.wrapping_neg() as u64)
+                }
+            }
+            SeekFrom::Current(n) => {
+                if n >= 0 {
+                    self.stream_position.checked_add(n as u64)
+                } else {
+                    self.stream_position.checked_sub(n.wrapping_neg() as u64)
+                }
+            }
+        };
+
+        match new_pos {
+            Some(n) => {
+                self.stream_position = n;
+                Ok(self.stream_position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+    use std::fs;
+
+    fn create(dir: &Path, names: &[&str]) {
+        for name in names {
+            fs::write(dir.join(name), name.as_bytes()).unwrap();
+        }
+    }
+
+    fn names(paths: &[PathBuf]) -> Vec<String> {
+        paths
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn numeric_suffixes() {
+        let dir = test_dir("split-numeric");
+        create(&dir, &["image.003", "image.001", "image.002", "image.005"]);
+
+        // The series ends at the first gap, and any segment of it finds the whole series.
+        let paths = segment_paths(&dir.join("image.002")).unwrap();
+        assert_eq!(names(&paths), ["image.001", "image.002", "image.003"]);
+        assert_eq!(segment_paths(&dir.join("image.001")), Some(paths));
+        assert_eq!(segment_paths(&dir.join("image.005")), None);
+    }
+
+    #[test]
+    fn numbering_from_zero() {
+        let dir = test_dir("split-zero");
+        create(&dir, &["disk00", "disk01", "disk02"]);
+
+        let paths = segment_paths(&dir.join("disk01")).unwrap();
+        assert_eq!(names(&paths), ["disk00", "disk01", "disk02"]);
+    }
+
+    #[test]
+    fn alphabetic_series() {
+        let dir = test_dir("split-alphabetic");
+        let mut segments = alphabetic_suffixes()
+            .take(28)
+            .map(|suffix| format!("x{}", suffix))
+            .collect::<Vec<_>>();
+        create(
+            &dir,
+            &segments.iter().map(String::as_str).collect::<Vec<_>>(),
+        );
+
+        let paths = segment_paths(&dir.join("xba")).unwrap();
+        segments.sort();
+        assert_eq!(names(&paths), segments);
+        assert_eq!(names(&paths)[25..], ["xaz", "xba", "xbb"]);
+    }
+
+    #[test]
+    fn single_files_are_no_segments() {
+        let dir = test_dir("split-single");
+        create(&dir, &["image.001", "disk2022", "image.dd", "image.E01"]);
+
+        for name in ["image.001", "disk2022", "image.dd", "image.E01"] {
+            assert_eq!(segment_paths(&dir.join(name)), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn reads_across_segments() {
+        let dir = test_dir("split-read");
+        fs::write(dir.join("image.001"), b"abc").unwrap();
+        fs::write(dir.join("image.002"), b"").unwrap();
+        fs::write(dir.join("image.003"), b"defg").unwrap();
+
+        let mut image = SplitImage::open(&segment_paths(&dir.join("image.001")).unwrap()).unwrap();
+        assert_eq!(image.segment_count(), 3);
+        let mut data = Vec::new();
+        image.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"abcdefg");
+
+        let mut buf = [0u8; 4];
+        image.seek(SeekFrom::Start(1)).unwrap();
+        image.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"bcde");
+        assert_eq!(image.seek(SeekFrom::End(-1)).unwrap(), 6);
+    }
+}
@@ -0,0 +1,712 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Seek, Write};
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use itertools::Itertools;
+use ntfs::structured_values::{NtfsFileName, NtfsFileNamespace, NtfsStandardInformation};
+use ntfs::{NtfsAttributeType, NtfsFileFlags};
+
+use crate::hashes::csv_field;
+use crate::volume::{
+    file_name_times, format_time, standard_information_times, FileTimes, Volume, MAX_PATH_DEPTH,
+    ROOT_DIRECTORY,
+};
+
+/// How many file records are read between progress reports.
+const PROGRESS_INTERVAL: u64 = 1024;
+
+/// Which file records and timestamps go into a timeline.
+#[derive(Clone, Debug, Default)]
+pub struct TimelineOptions {
+    /// Timestamps before this are left out.
+    pub start: Option<DateTime<Utc>>,
+    /// Timestamps after this are left out.
+    pub end: Option<DateTime<Utc>>,
+    /// Whether to include file records that are no longer in use.
+    pub include_deleted: bool,
+}
+
+impl TimelineOptions {
+    pub fn contains(&self, time: DateTime<Utc>) -> bool {
+        self.start.map_or(true, |start| time >= start) && self.end.map_or(true, |end| time <= end)
+    }
+}
+
+/// The formats a timeline can be written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimelineFormat {
+    /// The body file of The Sleuth Kit, for `mactime` and other tools that read it.
+    Bodyfile,
+    /// One line for each timestamp attribute, with all four timestamps.
+    Csv,
+    /// One line for each point in time, sorted, like `mactime -d` prints it.
+    Mactime,
+}
+
+impl TimelineFormat {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "bodyfile" => Ok(TimelineFormat::Bodyfile),
+            "csv" => Ok(TimelineFormat::Csv),
+            "mactime" => Ok(TimelineFormat::Mactime),
+            _ => Err("expected bodyfile, csv or mactime".to_owned()),
+        }
+    }
+}
+
+/// Where the timestamps of a timeline record come from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeSource {
+    StandardInformation,
+    /// A `$FILE_NAME` attribute, in the namespace of its name.
+    FileName(NtfsFileNamespace),
+}
+
+impl TimeSource {
+    pub fn attribute_name(self) -> &'static str {
+        match self {
+            TimeSource::StandardInformation => "$STANDARD_INFORMATION",
+            TimeSource::FileName(_) => "$FILE_NAME",
+        }
+    }
+}
+
+/// The timestamps of one `$STANDARD_INFORMATION` or `$FILE_NAME` attribute.
+#[derive(Clone, Debug)]
+pub struct TimelineRecord {
+    pub record_number: u64,
+    pub sequence_number: u16,
+    /// Path of the file, under the name of the `$FILE_NAME` attribute or the preferred name for
+    /// `$STANDARD_INFORMATION`. It starts with `?` if the parent directory no longer exists.
+    pub path: String,
+    pub source: TimeSource,
+    pub is_directory: bool,
+    /// Whether the file record is no longer in use.
+    pub deleted: bool,
+    pub size: u64,
+    pub times: FileTimes,
+}
+
+impl TimelineRecord {
+    /// The file name as The Sleuth Kit writes it into body files, with markers for `$FILE_NAME` attributes and
+    /// deleted files.
+    fn display_name(&self) -> String {
+        let mut name = self.path.clone();
+        if let TimeSource::FileName(_) = self.source {
+            name += " ($FILE_NAME)";
+        }
+        if self.deleted {
+            name += " (deleted)";
+        }
+        name
+    }
+
+    fn mode(&self) -> &'static str {
+        if self.is_directory {
+            "d/drwxrwxrwx"
+        } else {
+            "r/rrwxrwxrwx"
+        }
+    }
+}
+
+/// A point in time at which one or more timestamps of a timeline record were set.
+#[derive(Clone, Debug)]
+pub struct TimelineEvent<'t> {
+    pub time: DateTime<Utc>,
+    /// Which timestamps have this time, as `m` for modification, `a` for access, `c` for MFT record modification
+    /// and `b` for creation, with dots for the others.
+    pub macb: String,
+    pub record: &'t TimelineRecord,
+}
+
+/// The timestamps of the file records of a volume.
+#[derive(Clone, Debug, Default)]
+pub struct Timeline {
+    pub records: Vec<TimelineRecord>,
+    pub options: TimelineOptions,
+    /// Whether reading `$MFT` was stopped before the end.
+    pub cancelled: bool,
+}
+
+impl Timeline {
+    /// Returns the timestamps within the date range, sorted by time and path.
+    /// Timestamps of a record with the same time are combined, like `mactime` does.
+    pub fn events(&self) -> Vec<TimelineEvent<'_>> {
+        let mut events = vec![];
+        for record in &self.records {
+            let times = [
+                record.times.modification,
+                record.times.access,
+                record.times.mft_record_modification,
+                record.times.creation,
+            ];
+            // `mactime` works in whole seconds.
+            for (_, group) in &times
+                .iter()
+                .enumerate()
+                .sorted_by_key(|(_, time)| time.timestamp())
+                .group_by(|(_, time)| time.timestamp())
+            {
+                let group = group.collect_vec();
+                let time = *group[0].1;
+                if !self.options.contains(time) {
+                    continue;
+                }
+                let macb = "macb"
+                    .chars()
+                    .enumerate()
+                    .map(|(i, flag)| {
+                        if group.iter().any(|&(j, _)| i == j) {
+                            flag
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect();
+                events.push(TimelineEvent { time, macb, record });
+            }
+        }
+        events.sort_by(|a, b| {
+            (a.time.timestamp(), &a.record.path).cmp(&(b.time.timestamp(), &b.record.path))
+        });
+        events
+    }
+
+    pub fn write(&self, format: TimelineFormat, writer: &mut dyn Write) -> io::Result<()> {
+        match format {
+            TimelineFormat::Bodyfile => self.write_bodyfile(writer),
+            TimelineFormat::Csv => self.write_csv(writer),
+            TimelineFormat::Mactime => self.write_mactime(writer),
+        }
+    }
+
+    /// Writes the timeline as a body file in the format of The Sleuth Kit 3:
+    /// `MD5|name|inode|mode_as_string|UID|GID|size|atime|mtime|ctime|crtime`, with times in seconds since 1970.
+    pub fn write_bodyfile(&self, writer: &mut dyn Write) -> io::Result<()> {
+        for record in &self.records {
+            let times = &record.times;
+            writeln!(
+                writer,
+                "0|{}|{}|{}|0|0|{}|{}|{}|{}|{}",
+                record.display_name(),
+                record.record_number,
+                record.mode(),
+                record.size,
+                unix_time(times.access),
+                unix_time(times.modification),
+                unix_time(times.mft_record_modification),
+                unix_time(times.creation)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes the timeline as CSV with a header line, one line for each timestamp attribute.
+    pub fn write_csv(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "record,sequence,attribute,namespace,deleted,directory,size,path,created,modified,mft_modified,accessed"
+        )?;
+        for record in &self.records {
+            let namespace = match record.source {
+                TimeSource::StandardInformation => String::new(),
+                TimeSource::FileName(namespace) => format!("{:?}", namespace),
+            };
+            let times = &record.times;
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{},{},{},{}",
+                record.record_number,
+                record.sequence_number,
+                record.source.attribute_name(),
+                namespace,
+                record.deleted,
+                record.is_directory,
+                record.size,
+                csv_field(&record.path),
+                format_time(times.creation),
+                format_time(times.modification),
+                format_time(times.mft_record_modification),
+                format_time(times.access)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes the sorted timeline in the comma separated format of `mactime -d`.
+    pub fn write_mactime(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(writer, "Date,Size,Type,Mode,UID,GID,Meta,File Name")?;
+        for event in self.events() {
+            let record = event.record;
+            writeln!(
+                writer,
+                "{},{},{},{},0,0,{},{}",
+                format_time(event.time),
+                record.size,
+                event.macb,
+                record.mode(),
+                record.record_number,
+                csv_field(&record.display_name())
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the start of a date range, as `YYYY-MM-DD` or `YYYY-MM-DD HH:MM:SS` in UTC.
+pub fn parse_range_start(s: &str) -> Result<DateTime<Utc>, String> {
+    parse_time(s).map(|(time, _)| time)
+}
+
+/// Parses the end of a date range like `parse_range_start`, but as the last moment of the given day or second, so
+/// that the range includes it.
+pub fn parse_range_end(s: &str) -> Result<DateTime<Utc>, String> {
+    parse_time(s).map(|(time, length)| time + length - Duration::nanoseconds(1))
+}
+
+/// Returns the time and the length of the period it stands for.
+fn parse_time(s: &str) -> Result<(DateTime<Utc>, Duration), String> {
+    let s = s.trim();
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        let time = date.and_hms_opt(0, 0, 0).unwrap();
+        return Ok((Utc.from_utc_datetime(&time), Duration::days(1)));
+    }
+    match NaiveDateTime::parse_from_str(&s.replacen('T', " ", 1), "%Y-%m-%d %H:%M:%S") {
+        Ok(time) => Ok((Utc.from_utc_datetime(&time), Duration::seconds(1))),
+        Err(_) => Err(format!(
+            "expected a date like 2021-06-30 or 2021-06-30 14:00:00, got {}",
+            s
+        )),
+    }
+}
+
+/// The timestamps and names of a file record, before the paths are known.
+struct MftFile {
+    record_number: u64,
+    sequence_number: u16,
+    in_use: bool,
+    is_directory: bool,
+    size: u64,
+    standard_information: Option<FileTimes>,
+    names: Vec<MftFileName>,
+}
+
+struct MftFileName {
+    namespace: NtfsFileNamespace,
+    name: String,
+    parent_record_number: u64,
+    parent_sequence_number: u16,
+    times: FileTimes,
+}
+
+impl MftFile {
+    /// Picks the name to show the file under, preferring long names like `best_file_name`.
+    fn preferred_name(&self) -> Option<&MftFileName> {
+        let priority = [NtfsFileNamespace::Win32, NtfsFileNamespace::Win32AndDos];
+        priority
+            .iter()
+            .find_map(|&namespace| self.names.iter().find(|name| name.namespace == namespace))
+            .or_else(|| self.names.first())
+    }
+}
+
+impl<R> Volume<R>
+where
+    R: Read + Seek,
+{
+    /// Reads the `$STANDARD_INFORMATION` and `$FILE_NAME` timestamps of every file record of `$MFT`.
+    ///
+    /// Records are included if any of their timestamps falls into the date range of `options`.
+    /// `progress` is called with the number of file records read and their total every now and then, and stops
+    /// reading by returning `false`.
+    pub fn timeline<F>(
+        &mut self,
+        options: &TimelineOptions,
+        mut progress: F,
+    ) -> anyhow::Result<Timeline>
+    where
+        F: FnMut(u64, u64) -> bool,
+    {
+        let (record_count, mft_bitmap) = self.read_mft_bitmap()?;
+        let fs = &mut self.fs;
+        let mut timeline = Timeline {
+            options: options.clone(),
+            ..Timeline::default()
+        };
+
+        // Deleted files are read even when they are left out, their paths may lead through deleted directories.
+        let mut files = vec![];
+        for record_number in 0..record_count {
+            if record_number % PROGRESS_INTERVAL == 0 && !progress(record_number, record_count) {
+                timeline.cancelled = true;
+                break;
+            }
+            // Records that were never used have no valid header.
+            let file = match self.ntfs.file(fs, record_number) {
+                Ok(file) => file,
+                Err(_) => continue,
+            };
+            let mut mft_file = MftFile {
+                record_number,
+                sequence_number: file.sequence_number(),
+                in_use: file.flags().contains(NtfsFileFlags::IN_USE)
+                    && mft_bitmap.is_set(record_number),
+                is_directory: file.is_directory(),
+                size: file
+                    .data(fs, "")
+                    .and_then(Result::ok)
+                    .map(|data| data.to_attribute().value_length())
+                    .unwrap_or_default(),
+                standard_information: None,
+                names: vec![],
+            };
+            // A damaged record shouldn't keep the rest of the volume out of the timeline.
+            let mut attributes = file.attributes();
+            while let Some(Ok(attribute)) = attributes.next(fs) {
+                let attribute = attribute.to_attribute();
+                match attribute.ty() {
+                    Ok(NtfsAttributeType::StandardInformation) => {
+                        let info: NtfsStandardInformation = match attribute.structured_value(fs) {
+                            Ok(info) => info,
+                            Err(_) => continue,
+                        };
+                        mft_file.standard_information = Some(standard_information_times(&info));
+                    }
+                    Ok(NtfsAttributeType::FileName) => {
+                        let file_name: NtfsFileName = match attribute.structured_value(fs) {
+                            Ok(file_name) => file_name,
+                            Err(_) => continue,
+                        };
+                        let parent = file_name.parent_directory_reference();
+                        mft_file.names.push(MftFileName {
+                            namespace: file_name.namespace(),
+                            name: file_name.name().to_string_lossy(),
+                            parent_record_number: parent.file_record_number(),
+                            parent_sequence_number: parent.sequence_number(),
+                            times: file_name_times(&file_name),
+                        });
+                    }
+                    _ => continue,
+                }
+            }
+            // Extension records of files with many attributes have neither.
+            if mft_file.standard_information.is_some() || !mft_file.names.is_empty() {
+                files.push(mft_file);
+            }
+        }
+
+        let mut paths = Paths {
+            by_record: files
+                .iter()
+                .enumerate()
+                .map(|(i, file)| (file.record_number, i))
+                .collect(),
+            files: &files,
+            directories: HashMap::new(),
+        };
+        for file in &files {
+            if !file.in_use && !options.include_deleted {
+                continue;
+            }
+            let mut records = vec![];
+            if let (Some(times), Some(name)) = (&file.standard_information, file.preferred_name()) {
+                records.push((
+                    paths.path(file, name),
+                    TimeSource::StandardInformation,
+                    times,
+                ));
+            }
+            for name in &file.names {
+                records.push((
+                    paths.path(file, name),
+                    TimeSource::FileName(name.namespace),
+                    &name.times,
+                ));
+            }
+            for (path, source, times) in records {
+                let in_range = [
+                    times.creation,
+                    times.modification,
+                    times.mft_record_modification,
+                    times.access,
+                ]
+                .into_iter()
+                .any(|time| options.contains(time));
+                if !in_range {
+                    continue;
+                }
+                timeline.records.push(TimelineRecord {
+                    record_number: file.record_number,
+                    sequence_number: file.sequence_number,
+                    path,
+                    source,
+                    is_directory: file.is_directory,
+                    deleted: !file.in_use,
+                    size: file.size,
+                    times: times.clone(),
+                });
+            }
+        }
+        Ok(timeline)
+    }
+}
+
+/// Builds paths from the names read from `$MFT`, rather than reading the file records of the parents again.
+struct Paths<'f> {
+    files: &'f [MftFile],
+    by_record: HashMap<u64, usize>,
+    /// Paths of the directories seen so far, by file record number and sequence number.
+    directories: HashMap<(u64, u16), String>,
+}
+
+impl<'f> Paths<'f> {
+    fn path(&mut self, file: &MftFile, name: &MftFileName) -> String {
+        if file.record_number == ROOT_DIRECTORY {
+            return r"\".to_owned();
+        }
+        let directory = self.directory(name.parent_record_number, name.parent_sequence_number);
+        format!(r"{}\{}", directory.trim_end_matches('\\'), name.name)
+    }
+
+    /// Follows the parent references of a directory up to the root directory.
+    /// The path starts with `?` where a parent reference leads to a file record that has been reused.
+    fn directory(&mut self, record_number: u64, sequence_number: u16) -> String {
+        if let Some(path) = self.directories.get(&(record_number, sequence_number)) {
+            return path.clone();
+        }
+        let files = self.files;
+        let mut components = vec![];
+        let mut parent = (record_number, sequence_number);
+        let prefix = loop {
+            if parent.0 == ROOT_DIRECTORY {
+                break String::new();
+            }
+            if let Some(path) = self.directories.get(&parent) {
+                break path.trim_end_matches('\\').to_owned();
+            }
+            if components.len() >= MAX_PATH_DEPTH {
+                break "?".to_owned();
+            }
+            let directory = match self.by_record.get(&parent.0) {
+                Some(&i) => &files[i],
+                None => break "?".to_owned(),
+            };
+            // Deleting a file increments the sequence number of its record.
+            let current = directory.sequence_number;
+            if current != parent.1 && (directory.in_use || current != parent.1.wrapping_add(1)) {
+                break "?".to_owned();
+            }
+            let name = match directory.preferred_name() {
+                Some(name) => name,
+                None => break "?".to_owned(),
+            };
+            components.push(name.name.as_str());
+            parent = (name.parent_record_number, name.parent_sequence_number);
+        };
+        let path = format!(r"{}\{}", prefix, components.iter().rev().join(r"\"));
+        self.directories
+            .insert((record_number, sequence_number), path.clone());
+        path
+    }
+}
+
+fn unix_time(time: DateTime<Utc>) -> i64 {
+    // Body files have no way to express times before 1970, like the zero timestamps of some system files.
+    time.timestamp().max(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(hour: u32, min: u32, sec: u32, nanos: u32) -> DateTime<Utc> {
+        Utc.ymd(2021, 6, 30).and_hms_nano(hour, min, sec, nanos)
+    }
+
+    fn same_times(time: DateTime<Utc>) -> FileTimes {
+        FileTimes {
+            creation: time,
+            modification: time,
+            mft_record_modification: time,
+            access: time,
+        }
+    }
+
+    fn record(path: &str, times: FileTimes) -> TimelineRecord {
+        TimelineRecord {
+            record_number: 64,
+            sequence_number: 1,
+            path: path.to_owned(),
+            source: TimeSource::StandardInformation,
+            is_directory: false,
+            deleted: false,
+            size: 1024,
+            times,
+        }
+    }
+
+    #[test]
+    fn events() {
+        let b = record(
+            r"\b.txt",
+            FileTimes {
+                creation: Utc.ymd(2021, 1, 1).and_hms(0, 0, 0),
+                modification: time(12, 0, 0, 0),
+                mft_record_modification: time(12, 0, 5, 0),
+                // The same second as the modification.
+                access: time(12, 0, 0, 500_000_000),
+            },
+        );
+        let a = record(r"\a.txt", same_times(time(12, 0, 0, 0)));
+        let mut timeline = Timeline {
+            records: vec![b, a],
+            ..Timeline::default()
+        };
+        let events = |timeline: &Timeline| {
+            timeline
+                .events()
+                .into_iter()
+                .map(|event| (event.time, event.macb, event.record.path.clone()))
+                .collect_vec()
+        };
+        assert_eq!(
+            events(&timeline),
+            [
+                (
+                    Utc.ymd(2021, 1, 1).and_hms(0, 0, 0),
+                    "...b".to_owned(),
+                    r"\b.txt".to_owned()
+                ),
+                (time(12, 0, 0, 0), "macb".to_owned(), r"\a.txt".to_owned()),
+                (time(12, 0, 0, 0), "ma..".to_owned(), r"\b.txt".to_owned()),
+                (time(12, 0, 5, 0), "..c.".to_owned(), r"\b.txt".to_owned()),
+            ]
+        );
+
+        timeline.options.start = Some(parse_range_start("2021-06-30").unwrap());
+        timeline.options.end = Some(parse_range_end("2021-06-30 12:00:04").unwrap());
+        assert_eq!(
+            events(&timeline),
+            [
+                (time(12, 0, 0, 0), "macb".to_owned(), r"\a.txt".to_owned()),
+                (time(12, 0, 0, 0), "ma..".to_owned(), r"\b.txt".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn date_ranges() {
+        assert_eq!(parse_range_start("2021-06-30"), Ok(time(0, 0, 0, 0)));
+        assert_eq!(
+            parse_range_end("2021-06-30"),
+            Ok(time(23, 59, 59, 999_999_999))
+        );
+        assert_eq!(
+            parse_range_start(" 2021-06-30 14:00:00 "),
+            Ok(time(14, 0, 0, 0))
+        );
+        assert_eq!(
+            parse_range_end("2021-06-30T14:00:00"),
+            Ok(time(14, 0, 0, 999_999_999))
+        );
+        assert!(parse_range_start("30.06.2021").is_err());
+        assert!(parse_range_end("2021-06-31").is_err());
+    }
+
+    #[test]
+    fn bodyfile() {
+        let mut directory = record(
+            r"\Old",
+            FileTimes {
+                // The zero timestamp of NTFS.
+                creation: Utc.ymd(1601, 1, 1).and_hms(0, 0, 0),
+                modification: Utc.ymd(1969, 12, 31).and_hms(23, 59, 59),
+                mft_record_modification: time(12, 30, 15, 0),
+                access: Utc.ymd(1970, 1, 1).and_hms(0, 0, 0),
+            },
+        );
+        directory.source = TimeSource::FileName(NtfsFileNamespace::Win32);
+        directory.is_directory = true;
+        directory.deleted = true;
+        let timeline = Timeline {
+            records: vec![directory],
+            ..Timeline::default()
+        };
+        let mut output = vec![];
+        timeline.write_bodyfile(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "0|\\Old ($FILE_NAME) (deleted)|64|d/drwxrwxrwx|0|0|1024|0|0|1625056215|0\n"
+        );
+    }
+
+    fn mft_file(
+        record_number: u64,
+        sequence_number: u16,
+        in_use: bool,
+        name: &str,
+        parent: (u64, u16),
+    ) -> MftFile {
+        MftFile {
+            record_number,
+            sequence_number,
+            in_use,
+            is_directory: false,
+            size: 0,
+            standard_information: None,
+            names: vec![MftFileName {
+                namespace: NtfsFileNamespace::Win32,
+                name: name.to_owned(),
+                parent_record_number: parent.0,
+                parent_sequence_number: parent.1,
+                times: same_times(time(0, 0, 0, 0)),
+            }],
+        }
+    }
+
+    #[test]
+    fn paths() {
+        let files = [
+            mft_file(ROOT_DIRECTORY, 5, true, ".", (ROOT_DIRECTORY, 5)),
+            mft_file(40, 3, true, "Docs", (ROOT_DIRECTORY, 5)),
+            // Deleting a directory increments its sequence number.
+            mft_file(41, 8, false, "Old", (ROOT_DIRECTORY, 5)),
+            mft_file(42, 9, false, "Older", (ROOT_DIRECTORY, 5)),
+            mft_file(64, 1, true, "a.txt", (40, 3)),
+            // Record 40 was reused after this file's directory was deleted.
+            mft_file(65, 1, false, "b.txt", (40, 2)),
+            mft_file(66, 1, false, "c.txt", (41, 7)),
+            mft_file(67, 1, false, "d.txt", (42, 7)),
+            mft_file(68, 1, false, "e.txt", (99, 1)),
+        ];
+        let mut paths = Paths {
+            by_record: files
+                .iter()
+                .enumerate()
+                .map(|(i, file)| (file.record_number, i))
+                .collect(),
+            files: &files,
+            directories: HashMap::new(),
+        };
+        let all = files
+            .iter()
+            .map(|file| paths.path(file, &file.names[0]))
+            .collect_vec();
+        assert_eq!(
+            all,
+            [
+                r"\",
+                r"\Docs",
+                r"\Old",
+                r"\Older",
+                r"\Docs\a.txt",
+                r"?\b.txt",
+                r"\Old\c.txt",
+                r"?\d.txt",
+                r"?\e.txt",
+            ]
+        );
+        assert_eq!(paths.directory(40, 2), r"?\");
+    }
+}
@@ -0,0 +1,333 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Seek, Write};
+
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+
+use crate::hashes::csv_field;
+use crate::timeline::{TimeSource, TimelineOptions};
+use crate::volume::{FileTimes, Volume, FIRST_USER_FILE_RECORD};
+
+/// File record number of `$Volume`, which is created when the volume is formatted.
+const VOLUME_RECORD: u64 = 3;
+
+/// A sign that the timestamps of a file were changed after the fact.
+///
+/// Tools that do this usually only change `$STANDARD_INFORMATION`, which is what `SetFileTime` writes, while
+/// Windows keeps the timestamps of `$FILE_NAME` to itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Anomaly {
+    /// The `$STANDARD_INFORMATION` creation time is earlier than the `$FILE_NAME` one, which is set when the file is
+    /// created.
+    CreatedBeforeFileName,
+    /// The `$STANDARD_INFORMATION` creation or modification time has no fraction of a second, which is rare for times
+    /// set by Windows but common for times set by hand. Files extracted from some archives show it as well.
+    WholeSeconds,
+    /// The file was created before the volume was formatted.
+    BeforeVolumeCreation,
+    /// A `$STANDARD_INFORMATION` time is later than the time of the check.
+    InTheFuture,
+}
+
+impl Anomaly {
+    /// A short label for the file list.
+    pub fn label(self) -> &'static str {
+        match self {
+            Anomaly::CreatedBeforeFileName => "SI < FN",
+            Anomaly::WholeSeconds => "whole seconds",
+            Anomaly::BeforeVolumeCreation => "before volume",
+            Anomaly::InTheFuture => "future",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Anomaly::CreatedBeforeFileName => {
+                "$STANDARD_INFORMATION creation time is earlier than $FILE_NAME creation time"
+            }
+            Anomaly::WholeSeconds => "$STANDARD_INFORMATION times have no fraction of a second",
+            Anomaly::BeforeVolumeCreation => "created before the volume was formatted",
+            Anomaly::InTheFuture => "$STANDARD_INFORMATION times are in the future",
+        }
+    }
+}
+
+/// The time span in which the timestamps of a volume can be genuine.
+#[derive(Clone, Copy, Debug)]
+pub struct VolumeLifetime {
+    /// When the volume was formatted, the creation time of `$Volume`.
+    pub created: DateTime<Utc>,
+    /// When the check was made. The system files don't tell reliably when a volume was last written to.
+    pub checked: DateTime<Utc>,
+}
+
+/// Checks the timestamps of a file for signs of having been changed, comparing the `$STANDARD_INFORMATION` times
+/// with `first_name_creation`, the earliest creation time of all `$FILE_NAME` attributes of the file.
+///
+/// Only the earliest name tells when the file was created, names added later, like hard links, are younger than the
+/// file and would make every file with one look suspicious.
+pub fn anomalies(
+    times: &FileTimes,
+    first_name_creation: DateTime<Utc>,
+    lifetime: &VolumeLifetime,
+) -> Vec<Anomaly> {
+    let mut anomalies = vec![];
+    if times.creation < first_name_creation {
+        anomalies.push(Anomaly::CreatedBeforeFileName);
+    }
+    if [times.creation, times.modification]
+        .iter()
+        .any(|time| time.timestamp_subsec_nanos() == 0)
+    {
+        anomalies.push(Anomaly::WholeSeconds);
+    }
+    // Copied files keep their modification time, so only creation times say something about the volume.
+    if times.creation.min(first_name_creation) < lifetime.created {
+        anomalies.push(Anomaly::BeforeVolumeCreation);
+    }
+    let latest = [
+        times.creation,
+        times.modification,
+        times.mft_record_modification,
+        times.access,
+    ]
+    .into_iter()
+    .max()
+    .unwrap();
+    if latest > lifetime.checked {
+        anomalies.push(Anomaly::InTheFuture);
+    }
+    anomalies
+}
+
+/// A file whose timestamps look like they were changed.
+#[derive(Clone, Debug)]
+pub struct SuspiciousFile {
+    pub record_number: u64,
+    pub path: String,
+    pub times: FileTimes,
+    /// The timestamps of the `$FILE_NAME` attribute of the preferred name.
+    pub file_name_times: FileTimes,
+    pub anomalies: Vec<Anomaly>,
+}
+
+/// The files of a volume whose timestamps look like they were changed.
+#[derive(Clone, Debug)]
+pub struct TimestompingReport {
+    pub lifetime: VolumeLifetime,
+    pub files: Vec<SuspiciousFile>,
+    /// Whether reading `$MFT` was stopped before the end.
+    pub cancelled: bool,
+}
+
+impl TimestompingReport {
+    /// Writes the report as CSV with a header line, with both sets of timestamps of each file.
+    pub fn write_csv(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "record,path,created,modified,mft_modified,accessed,\
+             fn_created,fn_modified,fn_mft_modified,fn_accessed,anomalies"
+        )?;
+        for file in &self.files {
+            let times = [&file.times, &file.file_name_times]
+                .iter()
+                .flat_map(|times| {
+                    [
+                        times.creation,
+                        times.modification,
+                        times.mft_record_modification,
+                        times.access,
+                    ]
+                })
+                .map(format_precise_time)
+                .join(",");
+            writeln!(
+                writer,
+                "{},{},{},{}",
+                file.record_number,
+                csv_field(&file.path),
+                times,
+                csv_field(
+                    &file
+                        .anomalies
+                        .iter()
+                        .map(|anomaly| anomaly.description())
+                        .join("; ")
+                )
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<R> Volume<R>
+where
+    R: Read + Seek,
+{
+    /// Returns when the volume was formatted, along with the current time.
+    pub fn lifetime(&mut self) -> anyhow::Result<VolumeLifetime> {
+        let times = self
+            .file_info(VOLUME_RECORD)?
+            .times
+            .ok_or_else(|| anyhow!("$Volume has no $STANDARD_INFORMATION attribute"))?;
+        Ok(VolumeLifetime {
+            created: times.creation,
+            checked: Utc::now(),
+        })
+    }
+
+    /// Checks the timestamps of every file in use for signs of having been changed, see `anomalies`.
+    /// System files are left out, their timestamps are set when the volume is formatted.
+    ///
+    /// `progress` is called with the number of file records read and their total every now and then, and stops
+    /// reading by returning `false`.
+    pub fn scan_timestomping<F>(&mut self, progress: F) -> anyhow::Result<TimestompingReport>
+    where
+        F: FnMut(u64, u64) -> bool,
+    {
+        let lifetime = self.lifetime()?;
+        let timeline = self.timeline(&TimelineOptions::default(), progress)?;
+
+        let mut by_record = BTreeMap::<u64, Vec<_>>::new();
+        for record in &timeline.records {
+            if record.record_number >= FIRST_USER_FILE_RECORD {
+                by_record
+                    .entry(record.record_number)
+                    .or_default()
+                    .push(record);
+            }
+        }
+        let mut files = vec![];
+        for (record_number, records) in by_record {
+            let standard_information = match records
+                .iter()
+                .find(|record| record.source == TimeSource::StandardInformation)
+            {
+                Some(record) => record,
+                None => continue,
+            };
+            // The timestamps of the preferred name, which is the one the path was built from.
+            let file_name = match records.iter().find(|record| {
+                record.source != TimeSource::StandardInformation
+                    && record.path == standard_information.path
+            }) {
+                Some(record) => record,
+                None => continue,
+            };
+            let first_name_creation = records
+                .iter()
+                .filter(|record| record.source != TimeSource::StandardInformation)
+                .map(|record| record.times.creation)
+                .min()
+                .unwrap();
+            let anomalies = anomalies(&standard_information.times, first_name_creation, &lifetime);
+            if !anomalies.is_empty() {
+                files.push(SuspiciousFile {
+                    record_number,
+                    path: standard_information.path.clone(),
+                    times: standard_information.times.clone(),
+                    file_name_times: file_name.times.clone(),
+                    anomalies,
+                });
+            }
+        }
+
+        Ok(TimestompingReport {
+            lifetime,
+            files,
+            cancelled: timeline.cancelled,
+        })
+    }
+}
+
+/// Formats a time with the full precision of NTFS, 100 nanoseconds, so that whole seconds stand out.
+pub fn format_precise_time(time: DateTime<Utc>) -> String {
+    format!(
+        "{}.{:07}",
+        time.format("%Y-%m-%d %H:%M:%S"),
+        time.timestamp_subsec_nanos() / 100
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn time(day: u32, nanos: u32) -> DateTime<Utc> {
+        Utc.ymd(2021, 6, day).and_hms_nano(12, 30, 15, nanos)
+    }
+
+    fn file_times(creation: DateTime<Utc>, modification: DateTime<Utc>) -> FileTimes {
+        FileTimes {
+            creation,
+            modification,
+            mft_record_modification: modification,
+            access: modification,
+        }
+    }
+
+    fn lifetime() -> VolumeLifetime {
+        VolumeLifetime {
+            created: time(1, 1_234_500),
+            checked: time(30, 0),
+        }
+    }
+
+    #[test]
+    fn genuine_times() {
+        let times = file_times(time(10, 123_456_700), time(12, 765_432_100));
+        assert_eq!(anomalies(&times, time(10, 123_456_700), &lifetime()), []);
+    }
+
+    #[test]
+    fn created_before_first_name() {
+        let times = file_times(time(8, 123_456_700), time(12, 765_432_100));
+        assert_eq!(
+            anomalies(&times, time(10, 123_456_700), &lifetime()),
+            [Anomaly::CreatedBeforeFileName]
+        );
+    }
+
+    #[test]
+    fn whole_seconds() {
+        let times = file_times(time(10, 123_456_700), time(12, 0));
+        assert_eq!(
+            anomalies(&times, time(10, 123_456_700), &lifetime()),
+            [Anomaly::WholeSeconds]
+        );
+    }
+
+    #[test]
+    fn before_volume_creation() {
+        let times = file_times(
+            Utc.ymd(2019, 3, 4).and_hms_nano(8, 0, 0, 500_000_000),
+            time(12, 765_432_100),
+        );
+        assert_eq!(
+            anomalies(&times, times.creation, &lifetime()),
+            [Anomaly::BeforeVolumeCreation]
+        );
+        // Stomping only `$STANDARD_INFORMATION` shows in both ways.
+        assert_eq!(
+            anomalies(&times, time(10, 123_456_700), &lifetime()),
+            [
+                Anomaly::CreatedBeforeFileName,
+                Anomaly::BeforeVolumeCreation
+            ]
+        );
+    }
+
+    #[test]
+    fn in_the_future() {
+        let times = file_times(
+            time(10, 123_456_700),
+            Utc.ymd(2031, 6, 1).and_hms_nano(0, 0, 0, 100),
+        );
+        assert_eq!(
+            anomalies(&times, time(10, 123_456_700), &lifetime()),
+            [Anomaly::InTheFuture]
+        );
+    }
+}
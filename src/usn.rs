@@ -0,0 +1,673 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use anyhow::bail;
+use chrono::{DateTime, TimeZone, Utc};
+use itertools::Itertools;
+use ntfs::attribute_value::NtfsAttributeValue;
+use ntfs::{NtfsFileFlags, NtfsReadSeek};
+use serde_json::{json, Value};
+
+use crate::hashes::csv_field;
+use crate::timestomping::format_precise_time;
+use crate::volume::{Volume, MAX_PATH_DEPTH, ROOT_DIRECTORY};
+
+/// Path of the change journal, whose records are in the stream `$J`.
+pub const USN_JOURNAL_PATH: &str = r"\$Extend\$UsnJrnl";
+pub const USN_JOURNAL_STREAM: &str = "$J";
+/// How much of the journal is read at once.
+const CHUNK_SIZE: usize = 1 << 20;
+/// Records are much smaller than this, anything larger is garbage.
+const MAX_RECORD_SIZE: usize = 64 * 1024;
+/// Difference between the start of `FILETIME`s in 1601 and that of Unix time in 1970, in seconds.
+const FILETIME_UNIX_OFFSET: i64 = 11_644_473_600;
+
+/// The reasons of a record, by their names in the Windows API without the `USN_REASON_` prefix.
+const REASONS: [(u32, &str); 24] = [
+    (0x0000_0001, "DATA_OVERWRITE"),
+    (0x0000_0002, "DATA_EXTEND"),
+    (0x0000_0004, "DATA_TRUNCATION"),
+    (0x0000_0010, "NAMED_DATA_OVERWRITE"),
+    (0x0000_0020, "NAMED_DATA_EXTEND"),
+    (0x0000_0040, "NAMED_DATA_TRUNCATION"),
+    (0x0000_0100, "FILE_CREATE"),
+    (0x0000_0200, "FILE_DELETE"),
+    (0x0000_0400, "EA_CHANGE"),
+    (0x0000_0800, "SECURITY_CHANGE"),
+    (0x0000_1000, "RENAME_OLD_NAME"),
+    (0x0000_2000, "RENAME_NEW_NAME"),
+    (0x0000_4000, "INDEXABLE_CHANGE"),
+    (0x0000_8000, "BASIC_INFO_CHANGE"),
+    (0x0001_0000, "HARD_LINK_CHANGE"),
+    (0x0002_0000, "COMPRESSION_CHANGE"),
+    (0x0004_0000, "ENCRYPTION_CHANGE"),
+    (0x0008_0000, "OBJECT_ID_CHANGE"),
+    (0x0010_0000, "REPARSE_POINT_CHANGE"),
+    (0x0020_0000, "STREAM_CHANGE"),
+    (0x0040_0000, "TRANSACTED_CHANGE"),
+    (0x0080_0000, "INTEGRITY_CHANGE"),
+    (0x0100_0000, "DESIRED_STORAGE_CLASS_CHANGE"),
+    (0x8000_0000, "CLOSE"),
+];
+
+/// A record of the change journal, telling what happened to a file.
+#[derive(Clone, Debug)]
+pub struct UsnRecord {
+    /// Update sequence number, the position of the record in the journal.
+    pub usn: i64,
+    /// Version of the record format, 2 to 4.
+    pub version: u16,
+    /// `None` for version 4 records, which only list the ranges of a file that changed.
+    pub timestamp: Option<DateTime<Utc>>,
+    pub record_number: u64,
+    pub sequence_number: u16,
+    pub parent_record_number: u64,
+    pub parent_sequence_number: u16,
+    /// The `USN_REASON_*` flags of the changes.
+    pub reason: u32,
+    pub source_info: u32,
+    pub file_attributes: u32,
+    /// Name of the file at the time of the record. Version 4 records have none and get the name of the file from
+    /// other records.
+    pub name: String,
+    /// Path of the file at the time of the record, as far as it can be told. It starts with `?` where the parent
+    /// directory is neither on the volume nor in the journal anymore.
+    pub path: String,
+}
+
+impl UsnRecord {
+    /// Returns the names of the reasons of the record, like `FILE_CREATE`.
+    pub fn reasons(&self) -> Vec<&'static str> {
+        REASONS
+            .iter()
+            .filter(|(flag, _)| self.reason & flag != 0)
+            .map(|(_, name)| *name)
+            .collect()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "usn": self.usn,
+            "version": self.version,
+            "timestamp": self.timestamp.map(|timestamp| timestamp.to_rfc3339()),
+            "record_number": self.record_number,
+            "sequence_number": self.sequence_number,
+            "parent_record_number": self.parent_record_number,
+            "parent_sequence_number": self.parent_sequence_number,
+            "reasons": self.reasons(),
+            "source_info": self.source_info,
+            "file_attributes": self.file_attributes,
+            "name": self.name,
+            "path": self.path,
+        })
+    }
+}
+
+/// A filter for change journal records: `#1234` for the records of a file record number, or text to look for in
+/// paths and reasons, ignoring case.
+#[derive(Clone, Debug, Default)]
+pub struct UsnFilter {
+    record_number: Option<u64>,
+    text: String,
+}
+
+impl UsnFilter {
+    pub fn parse(s: &str) -> Self {
+        let s = s.trim();
+        if let Some(record_number) = s.strip_prefix('#').and_then(|n| n.parse().ok()) {
+            return Self {
+                record_number: Some(record_number),
+                text: String::new(),
+            };
+        }
+        Self {
+            record_number: None,
+            text: s.to_lowercase(),
+        }
+    }
+
+    pub fn matches(&self, record: &UsnRecord) -> bool {
+        if let Some(record_number) = self.record_number {
+            return record.record_number == record_number;
+        }
+        self.text.is_empty()
+            || record.path.to_lowercase().contains(&self.text)
+            || record
+                .reasons()
+                .iter()
+                .any(|reason| reason.to_lowercase().contains(&self.text))
+    }
+}
+
+/// The records of the change journal of a volume, in the order of their update sequence numbers.
+#[derive(Clone, Debug, Default)]
+pub struct UsnJournal {
+    pub records: Vec<UsnRecord>,
+    /// Whether reading the journal was stopped before the end.
+    pub cancelled: bool,
+    /// Indexes of the records of each file, by file record number and sequence number.
+    by_file: HashMap<(u64, u16), Vec<usize>>,
+}
+
+impl UsnJournal {
+    fn new(records: Vec<UsnRecord>, cancelled: bool) -> Self {
+        let mut by_file = HashMap::<_, Vec<_>>::new();
+        for (i, record) in records.iter().enumerate() {
+            by_file
+                .entry((record.record_number, record.sequence_number))
+                .or_default()
+                .push(i);
+        }
+        Self {
+            records,
+            cancelled,
+            by_file,
+        }
+    }
+
+    /// Returns the records of a file, oldest first.
+    pub fn history(
+        &self,
+        record_number: u64,
+        sequence_number: u16,
+    ) -> impl Iterator<Item = &UsnRecord> {
+        self.by_file
+            .get(&(record_number, sequence_number))
+            .into_iter()
+            .flatten()
+            .map(move |&i| &self.records[i])
+    }
+
+    pub fn filter<'j>(&'j self, filter: &'j UsnFilter) -> impl Iterator<Item = &'j UsnRecord> {
+        self.records
+            .iter()
+            .filter(move |record| filter.matches(record))
+    }
+
+    /// Writes the records that match `filter` as CSV with a header line.
+    pub fn write_csv(&self, filter: &UsnFilter, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "usn,timestamp,record,sequence,parent_record,parent_sequence,reasons,attributes,source_info,version,path"
+        )?;
+        for record in self.filter(filter) {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{:#x},{:#x},{},{}",
+                record.usn,
+                record
+                    .timestamp
+                    .map(format_precise_time)
+                    .unwrap_or_default(),
+                record.record_number,
+                record.sequence_number,
+                record.parent_record_number,
+                record.parent_sequence_number,
+                record.reasons().join("|"),
+                record.file_attributes,
+                record.source_info,
+                record.version,
+                csv_field(&record.path)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes the records that match `filter` as a JSON array.
+    pub fn write_json(&self, filter: &UsnFilter, writer: &mut dyn Write) -> io::Result<()> {
+        let records = self.filter(filter).map(UsnRecord::to_json).collect_vec();
+        serde_json::to_writer_pretty(&mut *writer, &records)?;
+        writeln!(writer)
+    }
+}
+
+impl<R> Volume<R>
+where
+    R: Read + Seek,
+{
+    /// Reads the change journal in `$Extend\$UsnJrnl:$J` and resolves the paths of the files in it.
+    ///
+    /// The journal is a sparse stream whose beginning has been freed as it wrapped around, only the allocated parts
+    /// are read where the data runs tell them apart.
+    /// `progress` is called with the position in the stream and its size every now and then, and stops reading by
+    /// returning `false`.
+    pub fn usn_journal<F>(&mut self, mut progress: F) -> anyhow::Result<UsnJournal>
+    where
+        F: FnMut(u64, u64) -> bool,
+    {
+        let record_number = match self.lookup(USN_JOURNAL_PATH)? {
+            Some(record_number) => record_number,
+            None => bail!("the volume has no change journal"),
+        };
+        let (mut records, cancelled) = self.read_usn_records(record_number, &mut progress)?;
+        self.resolve_usn_paths(&mut records);
+        Ok(UsnJournal::new(records, cancelled))
+    }
+
+    /// Reads the records of the journal through a single value of its `$J` attribute, and returns them along with
+    /// whether `progress` stopped reading.
+    fn read_usn_records(
+        &mut self,
+        record_number: u64,
+        progress: &mut dyn FnMut(u64, u64) -> bool,
+    ) -> anyhow::Result<(Vec<UsnRecord>, bool)> {
+        let fs = &mut self.fs;
+        let file = self.ntfs.file(fs, record_number)?;
+        let data = match file.data(fs, USN_JOURNAL_STREAM) {
+            Some(data) => data?,
+            None => bail!(
+                "file record {:#x} has no stream {:?}",
+                record_number,
+                USN_JOURNAL_STREAM
+            ),
+        };
+        let data = data.to_attribute();
+        let size = data.value_length();
+        let mut value = data.value(fs)?;
+        let ranges = allocated_ranges(&value, size)?;
+
+        let mut records = vec![];
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        for (start, end) in ranges {
+            // The end of a chunk that may hold the beginning of a record.
+            let mut pending = vec![];
+            value.seek(fs, SeekFrom::Start(start))?;
+            let mut offset = start;
+            while offset < end {
+                if !progress(offset, size) {
+                    return Ok((records, true));
+                }
+                let len = CHUNK_SIZE.min((end - offset) as usize);
+                let bytes_read = value.read(fs, &mut buf[..len])?;
+                if bytes_read == 0 {
+                    break;
+                }
+                offset += bytes_read as u64;
+                let chunk = &buf[..bytes_read];
+                // Sparse ranges the data runs didn't tell apart read as zeros, which hold no records.
+                if pending.is_empty() && chunk.iter().all(|&byte| byte == 0) {
+                    continue;
+                }
+                pending.extend_from_slice(chunk);
+                let consumed = parse_records(&pending, &mut records);
+                pending.drain(..consumed);
+            }
+        }
+        Ok((records, false))
+    }
+
+    /// Builds the paths of the files in the journal, from the volume where their parents still exist and from the
+    /// journal itself where they don't.
+    fn resolve_usn_paths(&mut self, records: &mut [UsnRecord]) {
+        // The last name and parent the journal knows of each file.
+        let mut names = HashMap::new();
+        for record in records.iter().filter(|record| !record.name.is_empty()) {
+            names.insert(
+                (record.record_number, record.sequence_number),
+                (
+                    record.name.clone(),
+                    (record.parent_record_number, record.parent_sequence_number),
+                ),
+            );
+        }
+
+        let mut directories = HashMap::new();
+        for record in records.iter_mut() {
+            if record.name.is_empty() {
+                if let Some((name, _)) = names.get(&(record.record_number, record.sequence_number))
+                {
+                    record.name = name.clone();
+                }
+            }
+            let parent = (record.parent_record_number, record.parent_sequence_number);
+            let directory = self.usn_directory(parent, &names, &mut directories);
+            record.path = format!(r"{}\{}", directory.trim_end_matches('\\'), record.name);
+        }
+    }
+
+    fn usn_directory(
+        &mut self,
+        reference: (u64, u16),
+        names: &HashMap<(u64, u16), (String, (u64, u16))>,
+        directories: &mut HashMap<(u64, u16), String>,
+    ) -> String {
+        if let Some(path) = directories.get(&reference) {
+            return path.clone();
+        }
+        let mut components = vec![];
+        let mut current = reference;
+        let prefix = loop {
+            let (record_number, sequence_number) = current;
+            if record_number == ROOT_DIRECTORY {
+                break String::new();
+            }
+            if let Some(path) = directories.get(&current) {
+                break path.trim_end_matches('\\').to_owned();
+            }
+            if components.len() >= MAX_PATH_DEPTH {
+                break "?".to_owned();
+            }
+            // The directory is still there, unless its record has been reused for another file.
+            let fs = &mut self.fs;
+            let exists = match self.ntfs.file(fs, record_number) {
+                Ok(file) => {
+                    file.flags().contains(NtfsFileFlags::IN_USE)
+                        && file.sequence_number() == sequence_number
+                }
+                Err(_) => false,
+            };
+            if exists {
+                if let Ok(path) = self.path(record_number) {
+                    break path.trim_end_matches('\\').to_owned();
+                }
+            }
+            match names.get(&current) {
+                Some((name, parent)) => {
+                    components.push(name.as_str());
+                    current = *parent;
+                }
+                None => break "?".to_owned(),
+            }
+        };
+        let path = format!(r"{}\{}", prefix, components.iter().rev().join(r"\"));
+        directories.insert(reference, path.clone());
+        path
+    }
+}
+
+/// Returns the ranges of a stream that are stored on disk, as start and end offsets.
+///
+/// Values that aren't a single non-resident attribute count as allocated throughout. That includes streams split over
+/// several file records by an `$ATTRIBUTE_LIST`, as the ntfs crate doesn't give access to their data runs.
+fn allocated_ranges(value: &NtfsAttributeValue, size: u64) -> anyhow::Result<Vec<(u64, u64)>> {
+    let value = match value {
+        NtfsAttributeValue::NonResident(value) => value,
+        NtfsAttributeValue::AttributeListNonResident(_) | NtfsAttributeValue::Resident(_) => {
+            return Ok(vec![(0, size)])
+        }
+    };
+
+    let mut ranges: Vec<(u64, u64)> = vec![];
+    let mut offset = 0;
+    for data_run in value.data_runs() {
+        let data_run = data_run?;
+        let end = (offset + data_run.allocated_size()).min(size);
+        if data_run.data_position().is_some() && offset < end {
+            match ranges.last_mut() {
+                Some(last) if last.1 == offset => last.1 = end,
+                _ => ranges.push((offset, end)),
+            }
+        }
+        offset += data_run.allocated_size();
+    }
+    Ok(ranges)
+}
+
+/// Parses the records at the start of `data`, which starts at a record boundary, and returns how many bytes were
+/// parsed. The rest holds the beginning of a record that continues in the following data.
+fn parse_records(data: &[u8], records: &mut Vec<UsnRecord>) -> usize {
+    let mut position = 0;
+    // Records are aligned to 8 bytes, with zeros up to the end of each page of the journal.
+    while position + 8 <= data.len() {
+        let length = u32_at(data, position) as usize;
+        let version = u16_at(data, position + 4);
+        let min_length = match version {
+            2 => 0x3c,
+            3 => 0x4c,
+            4 => 0x40,
+            _ => usize::MAX,
+        };
+        if length < min_length || length % 8 != 0 || length > MAX_RECORD_SIZE {
+            position += 8;
+            continue;
+        }
+        if position + length > data.len() {
+            break;
+        }
+        records.extend(parse_record(&data[position..position + length]));
+        position += length;
+    }
+    position
+}
+
+/// Parses a record of version 2, 3 or 4, whose length has been checked against the version.
+fn parse_record(record: &[u8]) -> Option<UsnRecord> {
+    let version = u16_at(record, 4);
+    // Version 3 and 4 have 128-bit file references, of which NTFS only uses the lower 64 bits.
+    let (file_reference, parent_reference, rest) = match version {
+        2 => (u64_at(record, 8), u64_at(record, 16), 24),
+        _ => (u64_at(record, 8), u64_at(record, 24), 40),
+    };
+    let usn = u64_at(record, rest) as i64;
+    let (timestamp, reason, source_info, file_attributes, name) = if version == 4 {
+        (
+            None,
+            u32_at(record, rest + 8),
+            u32_at(record, rest + 12),
+            0,
+            String::new(),
+        )
+    } else {
+        let name_length = u16_at(record, rest + 32) as usize;
+        let name_offset = u16_at(record, rest + 34) as usize;
+        let name = record.get(name_offset..name_offset + name_length)?;
+        let name = name
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect_vec();
+        (
+            filetime(u64_at(record, rest + 8) as i64),
+            u32_at(record, rest + 16),
+            u32_at(record, rest + 20),
+            u32_at(record, rest + 28),
+            String::from_utf16_lossy(&name),
+        )
+    };
+    Some(UsnRecord {
+        usn,
+        version,
+        timestamp,
+        record_number: file_reference & 0xffff_ffff_ffff,
+        sequence_number: (file_reference >> 48) as u16,
+        parent_record_number: parent_reference & 0xffff_ffff_ffff,
+        parent_sequence_number: (parent_reference >> 48) as u16,
+        reason,
+        source_info,
+        file_attributes,
+        name,
+        path: String::new(),
+    })
+}
+
+fn filetime(filetime: i64) -> Option<DateTime<Utc>> {
+    let seconds = filetime.div_euclid(10_000_000) - FILETIME_UNIX_OFFSET;
+    let nanoseconds = filetime.rem_euclid(10_000_000) as u32 * 100;
+    Utc.timestamp_opt(seconds, nanoseconds).single()
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2021-06-10 12:00:00.5 UTC.
+    const TIMESTAMP: u64 = 132_678_000_005_000_000;
+
+    fn file_reference(record_number: u64, sequence_number: u16) -> u64 {
+        record_number | (sequence_number as u64) << 48
+    }
+
+    fn utf16(name: &str) -> Vec<u8> {
+        name.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    /// Pads a record to a multiple of 8 bytes and writes its length.
+    fn finish(mut record: Vec<u8>) -> Vec<u8> {
+        record.resize((record.len() + 7) / 8 * 8, 0);
+        let length = record.len() as u32;
+        record[0..4].copy_from_slice(&length.to_le_bytes());
+        record
+    }
+
+    fn record_v2(file: u64, parent: u64, usn: u64, reason: u32, name: &str) -> Vec<u8> {
+        let name = utf16(name);
+        let mut record = vec![0u8; 0x3c];
+        record[4..6].copy_from_slice(&2u16.to_le_bytes());
+        record[8..16].copy_from_slice(&file.to_le_bytes());
+        record[16..24].copy_from_slice(&parent.to_le_bytes());
+        record[24..32].copy_from_slice(&usn.to_le_bytes());
+        record[32..40].copy_from_slice(&TIMESTAMP.to_le_bytes());
+        record[40..44].copy_from_slice(&reason.to_le_bytes());
+        record[52..56].copy_from_slice(&0x20u32.to_le_bytes());
+        record[56..58].copy_from_slice(&(name.len() as u16).to_le_bytes());
+        record[58..60].copy_from_slice(&0x3cu16.to_le_bytes());
+        record.extend(name);
+        finish(record)
+    }
+
+    fn record_v3(file: u64, parent: u64, usn: u64, reason: u32, name: &str) -> Vec<u8> {
+        let name = utf16(name);
+        let mut record = vec![0u8; 0x4c];
+        record[4..6].copy_from_slice(&3u16.to_le_bytes());
+        record[8..16].copy_from_slice(&file.to_le_bytes());
+        record[24..32].copy_from_slice(&parent.to_le_bytes());
+        record[40..48].copy_from_slice(&usn.to_le_bytes());
+        record[48..56].copy_from_slice(&TIMESTAMP.to_le_bytes());
+        record[56..60].copy_from_slice(&reason.to_le_bytes());
+        record[68..72].copy_from_slice(&0x20u32.to_le_bytes());
+        record[72..74].copy_from_slice(&(name.len() as u16).to_le_bytes());
+        record[74..76].copy_from_slice(&0x4cu16.to_le_bytes());
+        record.extend(name);
+        finish(record)
+    }
+
+    /// A record with a single extent.
+    fn record_v4(file: u64, parent: u64, usn: u64, reason: u32) -> Vec<u8> {
+        let mut record = vec![0u8; 0x50];
+        record[4..6].copy_from_slice(&4u16.to_le_bytes());
+        record[8..16].copy_from_slice(&file.to_le_bytes());
+        record[24..32].copy_from_slice(&parent.to_le_bytes());
+        record[40..48].copy_from_slice(&usn.to_le_bytes());
+        record[48..52].copy_from_slice(&reason.to_le_bytes());
+        record[60..62].copy_from_slice(&1u16.to_le_bytes());
+        record[62..64].copy_from_slice(&16u16.to_le_bytes());
+        finish(record)
+    }
+
+    #[test]
+    fn all_versions() {
+        let mut data = record_v2(
+            file_reference(64, 3),
+            file_reference(5, 5),
+            0x1000,
+            0x100,
+            "report.docx",
+        );
+        data.extend(record_v3(
+            file_reference(65, 1),
+            file_reference(64, 3),
+            0x1060,
+            0x8000_0200,
+            "Ünïcode.txt",
+        ));
+        data.extend(record_v4(
+            file_reference(65, 1),
+            file_reference(64, 3),
+            0x10c0,
+            0x2,
+        ));
+
+        let mut records = vec![];
+        assert_eq!(parse_records(&data, &mut records), data.len());
+        assert_eq!(records.len(), 3);
+
+        let record = &records[0];
+        assert_eq!(record.version, 2);
+        assert_eq!(record.usn, 0x1000);
+        assert_eq!(
+            record.timestamp,
+            Some(Utc.ymd(2021, 6, 10).and_hms_milli(12, 0, 0, 500))
+        );
+        assert_eq!((record.record_number, record.sequence_number), (64, 3));
+        assert_eq!(
+            (record.parent_record_number, record.parent_sequence_number),
+            (5, 5)
+        );
+        assert_eq!(record.reasons(), ["FILE_CREATE"]);
+        assert_eq!(record.file_attributes, 0x20);
+        assert_eq!(record.name, "report.docx");
+
+        let record = &records[1];
+        assert_eq!(record.version, 3);
+        assert_eq!(record.usn, 0x1060);
+        assert!(record.timestamp.is_some());
+        assert_eq!((record.record_number, record.sequence_number), (65, 1));
+        assert_eq!(record.parent_record_number, 64);
+        assert_eq!(record.reasons(), ["FILE_DELETE", "CLOSE"]);
+        assert_eq!(record.name, "Ünïcode.txt");
+
+        let record = &records[2];
+        assert_eq!(record.version, 4);
+        assert_eq!(record.usn, 0x10c0);
+        assert_eq!(record.timestamp, None);
+        assert_eq!(record.record_number, 65);
+        assert_eq!(record.reasons(), ["DATA_EXTEND"]);
+        assert_eq!(record.name, "");
+    }
+
+    #[test]
+    fn padding_and_garbage() {
+        let first = record_v2(file_reference(64, 1), 5, 0x1000, 0x100, "a");
+        let second = record_v2(file_reference(65, 1), 5, 0x2000, 0x100, "b");
+        // The rest of a page is zeros, and a record from a reused page may be cut off.
+        let mut data = first.clone();
+        data.extend([0u8; 64]);
+        data.extend(&second[8..]);
+        data.extend(&second);
+
+        let mut records = vec![];
+        assert_eq!(parse_records(&data, &mut records), data.len());
+        assert_eq!(
+            records.iter().map(|record| record.usn).collect_vec(),
+            [0x1000, 0x2000]
+        );
+    }
+
+    #[test]
+    fn record_across_chunks() {
+        let first = record_v3(file_reference(64, 1), 5, 0x1000, 0x100, "first");
+        let second = record_v3(file_reference(65, 1), 5, 0x2000, 0x100, "second");
+        let mut data = first.clone();
+        data.extend(&second[..40]);
+
+        let mut records = vec![];
+        assert_eq!(parse_records(&data, &mut records), first.len());
+        assert_eq!(records.len(), 1);
+
+        let mut pending = second[..40].to_vec();
+        pending.extend(&second[40..]);
+        assert_eq!(parse_records(&pending, &mut records), second.len());
+        assert_eq!(records[1].name, "second");
+    }
+
+    #[test]
+    fn name_beyond_the_record() {
+        let mut record = record_v2(file_reference(64, 1), 5, 0x1000, 0x100, "name");
+        record[56..58].copy_from_slice(&0x100u16.to_le_bytes());
+
+        let mut records = vec![];
+        assert_eq!(parse_records(&record, &mut records), record.len());
+        assert!(records.is_empty());
+    }
+}
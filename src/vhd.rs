@@ -0,0 +1,344 @@
+use std::fs::File;
+use std::io;
+use std::io::{Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::virtual_disk::{
+    bitmap_run, invalid_data, parent_path_candidates, read_exact_at, utf16le_string, BlockRead,
+    VirtualDisk,
+};
+
+pub const VHD_FOOTER_COOKIE: &[u8; 8] = b"conectix";
+const VHD_DYNAMIC_COOKIE: &[u8; 8] = b"cxsparse";
+const FOOTER_SIZE: u64 = 512;
+const SECTOR_SIZE: u64 = 512;
+const UNALLOCATED_BLOCK: u32 = 0xffff_ffff;
+/// Upper limit for the number of BAT entries, to not allocate absurd amounts of memory for corrupted headers.
+const MAX_BAT_ENTRIES: u32 = 1 << 24;
+
+const DISK_TYPE_FIXED: u32 = 2;
+const DISK_TYPE_DYNAMIC: u32 = 3;
+const DISK_TYPE_DIFFERENCING: u32 = 4;
+
+/// A Virtual PC / Hyper-V VHD disk (fixed, dynamic or differencing).
+///
+/// All fields of the on-disk structures are big-endian.
+pub struct Vhd {
+    file: File,
+    disk_type: u32,
+    size: u64,
+    block_size: u64,
+    /// Sector offset of each block in the file.
+    bat: Vec<u32>,
+    /// Size of the sector bitmap in front of each block.
+    bitmap_size: u64,
+    /// Index and sector bitmap of the most recently read block of a differencing disk.
+    cached_bitmap: Option<(usize, Vec<u8>)>,
+    parent_paths: Vec<PathBuf>,
+}
+
+impl Vhd {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+
+        // Dynamic disks have a copy of the footer at the start, fixed disks only have the one at the end.
+        let mut footer = [0u8; FOOTER_SIZE as usize];
+        read_exact_at(&mut file, 0, &mut footer)?;
+        if &footer[0..8] != VHD_FOOTER_COOKIE {
+            let len = file.seek(SeekFrom::End(0))?;
+            read_exact_at(&mut file, len.saturating_sub(FOOTER_SIZE), &mut footer)?;
+            if &footer[0..8] != VHD_FOOTER_COOKIE {
+                return Err(invalid_data("no VHD footer found"));
+            }
+        }
+
+        let data_offset = u64::from_be_bytes(footer[16..24].try_into().unwrap());
+        let size = u64::from_be_bytes(footer[48..56].try_into().unwrap());
+        let disk_type = u32::from_be_bytes(footer[60..64].try_into().unwrap());
+
+        let mut vhd = Self {
+            file,
+            disk_type,
+            size,
+            block_size: 0,
+            bat: Vec::new(),
+            bitmap_size: 0,
+            cached_bitmap: None,
+            parent_paths: Vec::new(),
+        };
+
+        match disk_type {
+            DISK_TYPE_FIXED => (),
+            DISK_TYPE_DYNAMIC | DISK_TYPE_DIFFERENCING => {
+                vhd.read_dynamic_header(path, data_offset)?
+            }
+            _ => return Err(invalid_data("unknown VHD disk type")),
+        }
+
+        Ok(vhd)
+    }
+
+    fn read_dynamic_header(&mut self, path: &Path, offset: u64) -> io::Result<()> {
+        let mut header = [0u8; 1024];
+        read_exact_at(&mut self.file, offset, &mut header)?;
+        if &header[0..8] != VHD_DYNAMIC_COOKIE {
+            return Err(invalid_data("invalid VHD dynamic disk header"));
+        }
+
+        let table_offset = u64::from_be_bytes(header[16..24].try_into().unwrap());
+        let max_table_entries = u32::from_be_bytes(header[28..32].try_into().unwrap());
+        self.block_size = u32::from_be_bytes(header[32..36].try_into().unwrap()) as u64;
+        if self.block_size == 0 || self.block_size % SECTOR_SIZE != 0 {
+            return Err(invalid_data("invalid VHD block size"));
+        }
+        if max_table_entries > MAX_BAT_ENTRIES {
+            return Err(invalid_data("VHD block allocation table is too large"));
+        }
+
+        // One bit per sector, padded to a full sector.
+        let bitmap_bytes = self.block_size / SECTOR_SIZE / 8;
+        self.bitmap_size = (bitmap_bytes + SECTOR_SIZE - 1) / SECTOR_SIZE * SECTOR_SIZE;
+
+        let mut bat = vec![0u8; max_table_entries as usize * 4];
+        read_exact_at(&mut self.file, table_offset, &mut bat)?;
+        self.bat = bat
+            .chunks_exact(4)
+            .map(|entry| u32::from_be_bytes(entry.try_into().unwrap()))
+            .collect();
+
+        if self.disk_type == DISK_TYPE_DIFFERENCING {
+            self.parent_paths = self.read_parent_locators(path, &header)?;
+        }
+
+        Ok(())
+    }
+
+    /// Collects the parent paths of the parent locator entries, followed by the parent's bare file name.
+    fn read_parent_locators(&mut self, path: &Path, header: &[u8]) -> io::Result<Vec<PathBuf>> {
+        let mut relative = Vec::new();
+        let mut absolute = Vec::new();
+
+        for entry in header[576..576 + 8 * 24].chunks_exact(24) {
+            let platform_code = &entry[0..4];
+            let data_length = u32::from_be_bytes(entry[8..12].try_into().unwrap());
+            let data_offset = u64::from_be_bytes(entry[16..24].try_into().unwrap());
+            if data_length == 0 || data_length > 64 * 1024 {
+                continue;
+            }
+
+            let mut data = vec![0u8; data_length as usize];
+            read_exact_at(&mut self.file, data_offset, &mut data)?;
+            match platform_code {
+                b"W2ru" => relative.extend(parent_path_candidates(path, &utf16le_string(&data))),
+                b"W2ku" => absolute.extend(parent_path_candidates(path, &utf16le_string(&data))),
+                _ => (),
+            }
+        }
+
+        // The parent's name is stored as UTF-16BE.
+        let name_utf16 = header[64..576]
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0)
+            .collect::<Vec<u16>>();
+        let name = String::from_utf16_lossy(&name_utf16);
+
+        let mut candidates = relative;
+        candidates.extend(absolute);
+        if !name.is_empty() {
+            candidates.extend(parent_path_candidates(path, &name));
+        }
+        candidates.dedup();
+        Ok(candidates)
+    }
+
+    fn load_bitmap(&mut self, block: usize, block_offset: u64) -> io::Result<&[u8]> {
+        let is_cached =
+            matches!(&self.cached_bitmap, Some((cached_block, _)) if *cached_block == block);
+        if !is_cached {
+            let mut bitmap = vec![0u8; self.bitmap_size as usize];
+            read_exact_at(&mut self.file, block_offset, &mut bitmap)?;
+            self.cached_bitmap = Some((block, bitmap));
+        }
+
+        Ok(&self.cached_bitmap.as_ref().unwrap().1)
+    }
+}
+
+impl VirtualDisk for Vhd {
+    fn format_name(&self) -> String {
+        let disk_type = match self.disk_type {
+            DISK_TYPE_FIXED => "fixed",
+            DISK_TYPE_DYNAMIC => "dynamic",
+            _ => "differencing",
+        };
+        format!("VHD ({})", disk_type)
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn parent_paths(&self) -> Vec<PathBuf> {
+        self.parent_paths.clone()
+    }
+
+    fn read_at(&mut self, position: u64, buf: &mut [u8]) -> io::Result<BlockRead> {
+        if self.disk_type == DISK_TYPE_FIXED {
+            read_exact_at(&mut self.file, position, buf)?;
+            return Ok(BlockRead::Data(buf.len()));
+        }
+
+        let block = (position / self.block_size) as usize;
+        let offset_in_block = position % self.block_size;
+        let mut len = buf.len().min((self.block_size - offset_in_block) as usize);
+
+        let block_sector = match self.bat.get(block) {
+            Some(&sector) if sector != UNALLOCATED_BLOCK => sector,
+            _ => return Ok(BlockRead::Parent(len)),
+        };
+        let block_offset = block_sector as u64 * SECTOR_SIZE;
+
+        if self.disk_type == DISK_TYPE_DIFFERENCING {
+            // Each bit of the sector bitmap tells whether the sector is stored here or in the parent.
+            let sector = (offset_in_block / SECTOR_SIZE) as usize;
+            let sectors = ((offset_in_block % SECTOR_SIZE + len as u64 + SECTOR_SIZE - 1)
+                / SECTOR_SIZE) as usize;
+            let bitmap = self.load_bitmap(block, block_offset)?;
+            let (present, run) = bitmap_run(bitmap, sector, sectors, true);
+            let run_end = (sector + run) as u64 * SECTOR_SIZE;
+            len = len.min((run_end - offset_in_block) as usize);
+            if !present {
+                return Ok(BlockRead::Parent(len));
+            }
+        }
+
+        read_exact_at(
+            &mut self.file,
+            block_offset + self.bitmap_size + offset_in_block,
+            &mut buf[..len],
+        )?;
+        Ok(BlockRead::Data(len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+    use crate::virtual_disk::tests::{pattern, read_disk};
+
+    const BLOCK_SIZE: usize = 4096;
+    const BLOCK_COUNT: usize = 4;
+    const BAT_OFFSET: usize = 1536;
+    /// The sector bitmap of 4096 byte blocks takes one byte, padded to a sector.
+    const BITMAP_SIZE: usize = 512;
+
+    fn footer(disk_type: u32, size: u64, data_offset: u64) -> Vec<u8> {
+        let mut footer = vec![0u8; FOOTER_SIZE as usize];
+        footer[0..8].copy_from_slice(VHD_FOOTER_COOKIE);
+        footer[16..24].copy_from_slice(&data_offset.to_be_bytes());
+        footer[48..56].copy_from_slice(&size.to_be_bytes());
+        footer[60..64].copy_from_slice(&disk_type.to_be_bytes());
+        footer
+    }
+
+    /// Builds a dynamic or differencing disk with the given blocks, each with its sector bitmap.
+    fn dynamic_disk(
+        disk_type: u32,
+        blocks: &[Option<(u8, Vec<u8>)>],
+        parent_name: &str,
+    ) -> Vec<u8> {
+        let size = (BLOCK_SIZE * BLOCK_COUNT) as u64;
+        let mut file = footer(disk_type, size, FOOTER_SIZE);
+
+        let mut header = vec![0u8; 1024];
+        header[0..8].copy_from_slice(VHD_DYNAMIC_COOKIE);
+        header[16..24].copy_from_slice(&(BAT_OFFSET as u64).to_be_bytes());
+        header[28..32].copy_from_slice(&(BLOCK_COUNT as u32).to_be_bytes());
+        header[32..36].copy_from_slice(&(BLOCK_SIZE as u32).to_be_bytes());
+        for (i, c) in parent_name.encode_utf16().enumerate() {
+            header[64 + 2 * i..66 + 2 * i].copy_from_slice(&c.to_be_bytes());
+        }
+        file.extend_from_slice(&header);
+
+        file.resize(BAT_OFFSET + 512, 0);
+        for (i, block) in blocks.iter().enumerate() {
+            let sector = match block {
+                Some((bitmap, data)) => {
+                    let sector = file.len() / SECTOR_SIZE as usize;
+                    let mut sector_bitmap = vec![0u8; BITMAP_SIZE];
+                    sector_bitmap[0] = *bitmap;
+                    file.extend_from_slice(&sector_bitmap);
+                    file.extend_from_slice(data);
+                    sector as u32
+                }
+                None => UNALLOCATED_BLOCK,
+            };
+            file[BAT_OFFSET + 4 * i..BAT_OFFSET + 4 * i + 4].copy_from_slice(&sector.to_be_bytes());
+        }
+
+        file.extend_from_slice(&footer(disk_type, size, FOOTER_SIZE));
+        file
+    }
+
+    #[test]
+    fn fixed_disk() {
+        let dir = test_dir("vhd-fixed");
+        let data = pattern(1, 8192);
+        let mut file = data.clone();
+        file.extend_from_slice(&footer(DISK_TYPE_FIXED, data.len() as u64, u64::MAX));
+        std::fs::write(dir.join("fixed.vhd"), &file).unwrap();
+
+        let vhd = Vhd::open(&dir.join("fixed.vhd")).unwrap();
+        assert_eq!(vhd.format_name(), "VHD (fixed)");
+        assert_eq!(read_disk(vhd, None), data);
+    }
+
+    #[test]
+    fn dynamic_disk_blocks() {
+        let dir = test_dir("vhd-dynamic");
+        let (first, third) = (pattern(2, BLOCK_SIZE), pattern(3, BLOCK_SIZE));
+        let file = dynamic_disk(
+            DISK_TYPE_DYNAMIC,
+            &[
+                Some((0xff, first.clone())),
+                None,
+                Some((0xff, third.clone())),
+                None,
+            ],
+            "",
+        );
+        std::fs::write(dir.join("dynamic.vhd"), &file).unwrap();
+
+        let vhd = Vhd::open(&dir.join("dynamic.vhd")).unwrap();
+        assert_eq!(vhd.size(), (BLOCK_SIZE * BLOCK_COUNT) as u64);
+        let zeros = vec![0u8; BLOCK_SIZE];
+        assert_eq!(
+            read_disk(vhd, None),
+            [first, zeros.clone(), third, zeros].concat()
+        );
+    }
+
+    #[test]
+    fn differencing_disk_sector_bitmap() {
+        let dir = test_dir("vhd-differencing");
+        let child = pattern(4, BLOCK_SIZE);
+        let parent = pattern(5, BLOCK_SIZE * BLOCK_COUNT);
+        // Sectors 0 and 2 of the first block are stored in the child, the bitmap is most significant bit first.
+        let file = dynamic_disk(
+            DISK_TYPE_DIFFERENCING,
+            &[Some((0b1010_0000, child.clone())), None, None, None],
+            "parent.vhd",
+        );
+        std::fs::write(dir.join("child.vhd"), &file).unwrap();
+
+        let vhd = Vhd::open(&dir.join("child.vhd")).unwrap();
+        assert_eq!(vhd.parent_paths(), vec![dir.join("parent.vhd")]);
+
+        let mut expected = parent.clone();
+        expected[0..512].copy_from_slice(&child[0..512]);
+        expected[1024..1536].copy_from_slice(&child[1024..1536]);
+        assert_eq!(read_disk(vhd, Some(parent)), expected);
+    }
+}
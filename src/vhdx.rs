@@ -0,0 +1,487 @@
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::partition::format_guid;
+use crate::virtual_disk::{
+    bitmap_run, invalid_data, parent_path_candidates, read_exact_at, utf16le_string, BlockRead,
+    VirtualDisk,
+};
+
+pub const VHDX_SIGNATURE: &[u8; 8] = b"vhdxfile";
+
+const HEADER_OFFSETS: [u64; 2] = [64 * 1024, 128 * 1024];
+const REGION_TABLE_OFFSET: u64 = 192 * 1024;
+const BAT_REGION: &str = "2DC27766-F623-4200-9D64-115E9BFD4A08";
+const METADATA_REGION: &str = "8B7CA206-4790-4B9A-B8FE-575F050F886E";
+const FILE_PARAMETERS_ITEM: &str = "CAA16737-FA36-4D43-B3B6-33F0AA44E76B";
+const VIRTUAL_DISK_SIZE_ITEM: &str = "2FA54224-CD1B-4876-B211-5DBED83BF4B8";
+const LOGICAL_SECTOR_SIZE_ITEM: &str = "8141BF1D-A96F-4709-BA47-F233A8FAAB5F";
+const PARENT_LOCATOR_ITEM: &str = "A8D35F2D-B30B-454D-ABF7-D3D84834AB0C";
+
+/// Sector bitmap blocks are always 1 MiB and cover 2^23 sectors.
+const SECTOR_BITMAP_BLOCK_SIZE: u64 = 1 << 20;
+/// Upper limit for the size of the BAT, to not allocate absurd amounts of memory for corrupted region tables.
+const MAX_BAT_SIZE: u32 = 256 << 20;
+/// Upper limit for the size of the metadata region, as given by the specification.
+const MAX_METADATA_SIZE: u32 = 1 << 20;
+/// Size of the metadata table header, which is followed by the 32 byte table entries.
+const METADATA_HEADER_SIZE: usize = 32;
+
+const PAYLOAD_BLOCK_NOT_PRESENT: u64 = 0;
+const PAYLOAD_BLOCK_FULLY_PRESENT: u64 = 6;
+const PAYLOAD_BLOCK_PARTIALLY_PRESENT: u64 = 7;
+const SB_BLOCK_PRESENT: u64 = 6;
+
+/// A Hyper-V VHDX disk (fixed, dynamic or differencing).
+///
+/// The log is not replayed, so changes that were still pending when the disk was last closed uncleanly are not visible.
+pub struct Vhdx {
+    file: File,
+    size: u64,
+    block_size: u64,
+    logical_sector_size: u64,
+    /// Number of payload blocks per sector bitmap block.
+    chunk_ratio: u64,
+    /// Payload and sector bitmap block entries, interleaved as on disk.
+    bat: Vec<u64>,
+    has_parent: bool,
+    /// Index and contents of the most recently read sector bitmap block.
+    cached_bitmap: Option<(usize, Vec<u8>)>,
+    parent_paths: Vec<PathBuf>,
+}
+
+impl Vhdx {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+
+        let mut signature = [0u8; 8];
+        read_exact_at(&mut file, 0, &mut signature)?;
+        if &signature != VHDX_SIGNATURE {
+            return Err(invalid_data("not a VHDX file"));
+        }
+
+        // There are two headers for crash safety. They only matter for log replay, which we don't do,
+        // but at least one of them has to be intact.
+        let mut has_header = false;
+        for offset in HEADER_OFFSETS {
+            let mut signature = [0u8; 4];
+            read_exact_at(&mut file, offset, &mut signature)?;
+            has_header |= &signature == b"head";
+        }
+        if !has_header {
+            return Err(invalid_data("no valid VHDX header found"));
+        }
+
+        let mut region_table = vec![0u8; 64 * 1024];
+        read_exact_at(&mut file, REGION_TABLE_OFFSET, &mut region_table)?;
+        if &region_table[0..4] != b"regi" {
+            return Err(invalid_data("invalid VHDX region table"));
+        }
+        let entry_count = u32::from_le_bytes(region_table[8..12].try_into().unwrap()) as usize;
+        let mut bat_region = None;
+        let mut metadata_region = None;
+        for entry in region_table[16..].chunks_exact(32).take(entry_count) {
+            let offset = u64::from_le_bytes(entry[16..24].try_into().unwrap());
+            let length = u32::from_le_bytes(entry[24..28].try_into().unwrap());
+            match format_guid(&entry[0..16]).as_str() {
+                BAT_REGION => bat_region = Some((offset, length)),
+                METADATA_REGION => metadata_region = Some((offset, length)),
+                _ => (),
+            }
+        }
+        let (bat_offset, bat_length) =
+            bat_region.ok_or_else(|| invalid_data("VHDX has no BAT region"))?;
+        let (metadata_offset, metadata_length) =
+            metadata_region.ok_or_else(|| invalid_data("VHDX has no metadata region"))?;
+
+        if !(METADATA_HEADER_SIZE as u32..=MAX_METADATA_SIZE).contains(&metadata_length) {
+            return Err(invalid_data("invalid VHDX metadata region size"));
+        }
+        let mut metadata = vec![0u8; metadata_length as usize];
+        read_exact_at(&mut file, metadata_offset, &mut metadata)?;
+        if &metadata[0..8] != b"metadata" {
+            return Err(invalid_data("invalid VHDX metadata region"));
+        }
+
+        let mut block_size = 0;
+        let mut has_parent = false;
+        let mut size = 0;
+        let mut logical_sector_size = 512;
+        let mut parent_locator = None;
+        let item_count = u16::from_le_bytes(metadata[10..12].try_into().unwrap()) as usize;
+        for entry in metadata[METADATA_HEADER_SIZE..]
+            .chunks_exact(32)
+            .take(item_count)
+        {
+            let item_offset = u32::from_le_bytes(entry[16..20].try_into().unwrap()) as usize;
+            let item_length = u32::from_le_bytes(entry[20..24].try_into().unwrap()) as usize;
+            let item = item_offset
+                .checked_add(item_length)
+                .and_then(|item_end| metadata.get(item_offset..item_end))
+                .ok_or_else(|| {
+                    invalid_data("VHDX metadata item lies outside the metadata region")
+                })?;
+
+            match format_guid(&entry[0..16]).as_str() {
+                FILE_PARAMETERS_ITEM if item.len() >= 8 => {
+                    block_size = u32::from_le_bytes(item[0..4].try_into().unwrap()) as u64;
+                    has_parent = item[4] & 0x02 != 0;
+                }
+                VIRTUAL_DISK_SIZE_ITEM if item.len() >= 8 => {
+                    size = u64::from_le_bytes(item[0..8].try_into().unwrap());
+                }
+                LOGICAL_SECTOR_SIZE_ITEM if item.len() >= 4 => {
+                    logical_sector_size = u32::from_le_bytes(item[0..4].try_into().unwrap()) as u64;
+                }
+                PARENT_LOCATOR_ITEM => parent_locator = Some(item.to_vec()),
+                _ => (),
+            }
+        }
+        // The specification allows 1 MiB to 256 MiB blocks and 512 or 4096 byte sectors.
+        if !(1 << 20..=256 << 20).contains(&block_size)
+            || !block_size.is_power_of_two()
+            || !matches!(logical_sector_size, 512 | 4096)
+        {
+            return Err(invalid_data("invalid VHDX block or sector size"));
+        }
+
+        if bat_length > MAX_BAT_SIZE {
+            return Err(invalid_data("VHDX BAT is too large"));
+        }
+        let mut bat = vec![0u8; bat_length as usize];
+        read_exact_at(&mut file, bat_offset, &mut bat)?;
+        let bat = bat
+            .chunks_exact(8)
+            .map(|entry| u64::from_le_bytes(entry.try_into().unwrap()))
+            .collect();
+
+        let parent_paths = match (has_parent, parent_locator) {
+            (true, Some(locator)) => parse_parent_locator(path, &locator),
+            (true, None) => return Err(invalid_data("differencing VHDX has no parent locator")),
+            (false, _) => Vec::new(),
+        };
+
+        Ok(Self {
+            file,
+            size,
+            block_size,
+            logical_sector_size,
+            chunk_ratio: (1 << 23) * logical_sector_size / block_size,
+            bat,
+            has_parent,
+            cached_bitmap: None,
+            parent_paths,
+        })
+    }
+
+    fn load_bitmap(&mut self, chunk: usize, offset: u64) -> io::Result<&[u8]> {
+        let is_cached =
+            matches!(&self.cached_bitmap, Some((cached_chunk, _)) if *cached_chunk == chunk);
+        if !is_cached {
+            let mut bitmap = vec![0u8; SECTOR_BITMAP_BLOCK_SIZE as usize];
+            read_exact_at(&mut self.file, offset, &mut bitmap)?;
+            self.cached_bitmap = Some((chunk, bitmap));
+        }
+
+        Ok(&self.cached_bitmap.as_ref().unwrap().1)
+    }
+}
+
+/// Collects the parent paths of the key/value pairs of a parent locator, in order of preference.
+fn parse_parent_locator(path: &Path, locator: &[u8]) -> Vec<PathBuf> {
+    if locator.len() < 20 {
+        return Vec::new();
+    }
+
+    let key_value_count = u16::from_le_bytes(locator[18..20].try_into().unwrap()) as usize;
+    let mut relative = Vec::new();
+    let mut absolute = Vec::new();
+    for entry in locator[20..].chunks_exact(12).take(key_value_count) {
+        let key_offset = u32::from_le_bytes(entry[0..4].try_into().unwrap()) as usize;
+        let value_offset = u32::from_le_bytes(entry[4..8].try_into().unwrap()) as usize;
+        let key_length = u16::from_le_bytes(entry[8..10].try_into().unwrap()) as usize;
+        let value_length = u16::from_le_bytes(entry[10..12].try_into().unwrap()) as usize;
+
+        let key = locator
+            .get(key_offset..key_offset + key_length)
+            .map(utf16le_string);
+        let value = locator
+            .get(value_offset..value_offset + value_length)
+            .map(utf16le_string);
+        match (key.as_deref(), value) {
+            (Some("relative_path"), Some(value)) => {
+                relative.extend(parent_path_candidates(path, &value))
+            }
+            (Some("absolute_win32_path"), Some(value)) => {
+                absolute.extend(parent_path_candidates(path, &value))
+            }
+            _ => (),
+        }
+    }
+
+    let mut candidates = relative;
+    candidates.extend(absolute);
+    candidates.dedup();
+    candidates
+}
+
+impl VirtualDisk for Vhdx {
+    fn format_name(&self) -> String {
+        if self.has_parent {
+            "VHDX (differencing)".to_owned()
+        } else {
+            "VHDX".to_owned()
+        }
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn parent_paths(&self) -> Vec<PathBuf> {
+        self.parent_paths.clone()
+    }
+
+    fn logical_sector_size(&self) -> Option<u64> {
+        Some(self.logical_sector_size)
+    }
+
+    fn read_at(&mut self, position: u64, buf: &mut [u8]) -> io::Result<BlockRead> {
+        let block = position / self.block_size;
+        let offset_in_block = position % self.block_size;
+        let mut len = buf.len().min((self.block_size - offset_in_block) as usize);
+
+        // After every `chunk_ratio` payload block entries, the BAT contains a sector bitmap block entry.
+        let chunk = block / self.chunk_ratio;
+        let entry = self
+            .bat
+            .get((block + chunk) as usize)
+            .copied()
+            .unwrap_or(PAYLOAD_BLOCK_NOT_PRESENT);
+        let state = entry & 0x7;
+        let block_offset = entry >> 20 << 20;
+
+        match state {
+            PAYLOAD_BLOCK_FULLY_PRESENT => (),
+            PAYLOAD_BLOCK_PARTIALLY_PRESENT if self.has_parent => {
+                let bitmap_entry_index =
+                    (chunk * (self.chunk_ratio + 1) + self.chunk_ratio) as usize;
+                let bitmap_entry = self.bat.get(bitmap_entry_index).copied().unwrap_or(0);
+                if bitmap_entry & 0x7 != SB_BLOCK_PRESENT {
+                    return Err(invalid_data("VHDX sector bitmap block is missing"));
+                }
+
+                // Each bit of the sector bitmap tells whether the sector is stored here or in the parent.
+                let sectors_per_block = self.block_size / self.logical_sector_size;
+                let sector = ((block % self.chunk_ratio) * sectors_per_block
+                    + offset_in_block / self.logical_sector_size)
+                    as usize;
+                let sectors = ((offset_in_block % self.logical_sector_size
+                    + len as u64
+                    + self.logical_sector_size
+                    - 1)
+                    / self.logical_sector_size) as usize;
+                let bitmap = self.load_bitmap(chunk as usize, bitmap_entry >> 20 << 20)?;
+                let (present, run) = bitmap_run(bitmap, sector, sectors, false);
+
+                let run_end = (sector + run) as u64 * self.logical_sector_size
+                    - (block % self.chunk_ratio) * self.block_size;
+                len = len.min((run_end - offset_in_block) as usize);
+                if !present {
+                    return Ok(BlockRead::Parent(len));
+                }
+            }
+            PAYLOAD_BLOCK_NOT_PRESENT if self.has_parent => return Ok(BlockRead::Parent(len)),
+            _ => {
+                // Zero, unmapped and undefined blocks as well as missing blocks of non-differencing disks.
+                buf[..len].fill(0);
+                return Ok(BlockRead::Data(len));
+            }
+        }
+
+        read_exact_at(
+            &mut self.file,
+            block_offset + offset_in_block,
+            &mut buf[..len],
+        )?;
+        Ok(BlockRead::Data(len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+    use crate::virtual_disk::tests::{pattern, read_disk};
+
+    const BLOCK_SIZE: usize = 1 << 20;
+    const METADATA_OFFSET: usize = 256 * 1024;
+    const BAT_OFFSET: usize = 320 * 1024;
+    /// With 1 MiB blocks and 512 byte sectors, a sector bitmap block covers 4096 payload blocks.
+    const CHUNK_RATIO: usize = 4096;
+
+    /// Inverse of `format_guid`.
+    fn guid_bytes(guid: &str) -> [u8; 16] {
+        let hex = guid.replace('-', "");
+        let byte = |i: usize| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+        let mut bytes = [0u8; 16];
+        for (i, byte_index) in [3, 2, 1, 0, 5, 4, 7, 6].into_iter().enumerate() {
+            bytes[i] = byte(byte_index);
+        }
+        for (i, b) in bytes.iter_mut().enumerate().skip(8) {
+            *b = byte(i);
+        }
+        bytes
+    }
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(|c| c.to_le_bytes()).collect()
+    }
+
+    /// Builds a disk with 1 MiB blocks whose BAT entries are given by `bat`, as `(index, entry)` pairs.
+    fn disk(size: u64, bat: &[(usize, u64)], has_parent: bool) -> Vec<u8> {
+        let mut file = vec![0u8; BLOCK_SIZE];
+        file[0..8].copy_from_slice(VHDX_SIGNATURE);
+        file[HEADER_OFFSETS[1] as usize..][..4].copy_from_slice(b"head");
+
+        let regions = &mut file[REGION_TABLE_OFFSET as usize..];
+        regions[0..4].copy_from_slice(b"regi");
+        regions[8..12].copy_from_slice(&2u32.to_le_bytes());
+        for (i, (guid, offset, length)) in [
+            (BAT_REGION, BAT_OFFSET, 64 * 1024),
+            (METADATA_REGION, METADATA_OFFSET, 64 * 1024),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let entry = &mut regions[16 + 32 * i..48 + 32 * i];
+            entry[0..16].copy_from_slice(&guid_bytes(guid));
+            entry[16..24].copy_from_slice(&(offset as u64).to_le_bytes());
+            entry[24..28].copy_from_slice(&(length as u32).to_le_bytes());
+        }
+
+        let mut file_parameters = (BLOCK_SIZE as u32).to_le_bytes().to_vec();
+        file_parameters.extend_from_slice(&[if has_parent { 0x02 } else { 0 }, 0, 0, 0]);
+        let mut locator = vec![0u8; 20];
+        locator[18..20].copy_from_slice(&1u16.to_le_bytes());
+        let (key, value) = (utf16le("relative_path"), utf16le(".\\parent.vhdx"));
+        locator.extend_from_slice(&32u32.to_le_bytes());
+        locator.extend_from_slice(&(32 + key.len() as u32).to_le_bytes());
+        locator.extend_from_slice(&(key.len() as u16).to_le_bytes());
+        locator.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        locator.extend_from_slice(&key);
+        locator.extend_from_slice(&value);
+        let mut items = vec![
+            (FILE_PARAMETERS_ITEM, file_parameters),
+            (VIRTUAL_DISK_SIZE_ITEM, size.to_le_bytes().to_vec()),
+            (LOGICAL_SECTOR_SIZE_ITEM, 512u32.to_le_bytes().to_vec()),
+        ];
+        if has_parent {
+            items.push((PARENT_LOCATOR_ITEM, locator));
+        }
+
+        let metadata = &mut file[METADATA_OFFSET..];
+        metadata[0..8].copy_from_slice(b"metadata");
+        metadata[10..12].copy_from_slice(&(items.len() as u16).to_le_bytes());
+        let mut item_offset = 1024;
+        for (i, (guid, item)) in items.iter().enumerate() {
+            let entry = &mut metadata[32 + 32 * i..64 + 32 * i];
+            entry[0..16].copy_from_slice(&guid_bytes(guid));
+            entry[16..20].copy_from_slice(&(item_offset as u32).to_le_bytes());
+            entry[20..24].copy_from_slice(&(item.len() as u32).to_le_bytes());
+            metadata[item_offset..item_offset + item.len()].copy_from_slice(item);
+            item_offset += item.len();
+        }
+
+        for &(index, entry) in bat {
+            file[BAT_OFFSET + 8 * index..][..8].copy_from_slice(&entry.to_le_bytes());
+        }
+        file
+    }
+
+    #[test]
+    fn dynamic_disk() {
+        let dir = test_dir("vhdx-dynamic");
+        let size = 3 * BLOCK_SIZE as u64;
+        let (first, third) = (pattern(1, BLOCK_SIZE), pattern(2, BLOCK_SIZE));
+        let mut file = disk(
+            size,
+            &[
+                (0, (1 << 20) | PAYLOAD_BLOCK_FULLY_PRESENT),
+                (2, (2 << 20) | PAYLOAD_BLOCK_FULLY_PRESENT),
+            ],
+            false,
+        );
+        file.extend_from_slice(&first);
+        file.extend_from_slice(&third);
+        std::fs::write(dir.join("disk.vhdx"), &file).unwrap();
+
+        let vhdx = Vhdx::open(&dir.join("disk.vhdx")).unwrap();
+        assert_eq!(vhdx.format_name(), "VHDX");
+        assert_eq!(vhdx.logical_sector_size(), Some(512));
+        let expected = [first, vec![0u8; BLOCK_SIZE], third].concat();
+        assert_eq!(read_disk(vhdx, None), expected);
+    }
+
+    #[test]
+    fn differencing_disk_sector_bitmap() {
+        let dir = test_dir("vhdx-differencing");
+        let size = 3 * BLOCK_SIZE as u64;
+        let child = pattern(3, BLOCK_SIZE);
+        let parent = pattern(4, 3 * BLOCK_SIZE);
+        // The first block is partially present, the second not at all, the third is fully present.
+        let mut file = disk(
+            size,
+            &[
+                (0, (1 << 20) | PAYLOAD_BLOCK_PARTIALLY_PRESENT),
+                (2, (2 << 20) | PAYLOAD_BLOCK_FULLY_PRESENT),
+                (CHUNK_RATIO, (3 << 20) | SB_BLOCK_PRESENT),
+            ],
+            true,
+        );
+        file.extend_from_slice(&child);
+        file.extend_from_slice(&child);
+        // Sectors 0 and 2 of the first block are stored in the child, the bitmap is least significant bit first.
+        let mut bitmap = vec![0u8; SECTOR_BITMAP_BLOCK_SIZE as usize];
+        bitmap[0] = 0b0000_0101;
+        file.extend_from_slice(&bitmap);
+        std::fs::write(dir.join("child.vhdx"), &file).unwrap();
+
+        let vhdx = Vhdx::open(&dir.join("child.vhdx")).unwrap();
+        assert_eq!(vhdx.format_name(), "VHDX (differencing)");
+        assert_eq!(vhdx.parent_paths(), vec![dir.join("parent.vhdx")]);
+
+        let mut expected = parent.clone();
+        expected[0..512].copy_from_slice(&child[0..512]);
+        expected[1024..1536].copy_from_slice(&child[1024..1536]);
+        expected[2 * BLOCK_SIZE..].copy_from_slice(&child);
+        assert_eq!(read_disk(vhdx, Some(parent)), expected);
+    }
+
+    #[test]
+    fn invalid_metadata_region() {
+        let dir = test_dir("vhdx-metadata");
+        let mut file = disk(BLOCK_SIZE as u64, &[], false);
+        // A metadata region too small for its own header.
+        let length_offset = REGION_TABLE_OFFSET as usize + 16 + 32 + 24;
+        file[length_offset..length_offset + 4].copy_from_slice(&16u32.to_le_bytes());
+        std::fs::write(dir.join("small.vhdx"), &file).unwrap();
+        let error = Vhdx::open(&dir.join("small.vhdx")).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // A metadata region beyond the limit of the specification.
+        file[length_offset..length_offset + 4].copy_from_slice(&(64u32 << 20).to_le_bytes());
+        std::fs::write(dir.join("large.vhdx"), &file).unwrap();
+        let error = Vhdx::open(&dir.join("large.vhdx")).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // A metadata item pointing beyond the region.
+        let mut file = disk(BLOCK_SIZE as u64, &[], false);
+        let item_length_offset = METADATA_OFFSET + 32 + 20;
+        file[item_length_offset..item_length_offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(dir.join("item.vhdx"), &file).unwrap();
+        let error = Vhdx::open(&dir.join("item.vhdx")).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
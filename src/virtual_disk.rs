@@ -0,0 +1,281 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::image::ReadSeek;
+
+/// Result of reading a part of a virtual disk through `VirtualDisk::read_at`.
+pub enum BlockRead {
+    /// The given number of bytes have been read into the buffer.
+    Data(usize),
+    /// The given number of bytes are not stored in this disk and have to be read from its parent.
+    /// Disks without parent read as zeros there.
+    Parent(usize),
+}
+
+/// A virtual disk format that maps positions of the virtual disk to data in one or more files.
+pub trait VirtualDisk: Send {
+    /// Human-readable name of the format, including the disk type if the format has several.
+    fn format_name(&self) -> String;
+
+    /// Size of the virtual disk in bytes.
+    fn size(&self) -> u64;
+
+    /// Possible locations of the parent disk (or backing file), in order of preference.
+    ///
+    /// Empty for disks without parent.
+    fn parent_paths(&self) -> Vec<PathBuf>;
+
+    /// Logical sector size presented to the guest, if the format records one.
+    fn logical_sector_size(&self) -> Option<u64> {
+        None
+    }
+
+    /// Reads from `position` into `buf`.
+    ///
+    /// Implementations may handle fewer bytes than requested, usually up to the end of the current block.
+    fn read_at(&mut self, position: u64, buf: &mut [u8]) -> io::Result<BlockRead>;
+}
+
+/// `VirtualDiskReader` provides the contents of a virtual disk as a seekable stream.
+///
+/// Unallocated parts of a differencing disk are read from the parent stream.
+pub struct VirtualDiskReader {
+    disk: Box<dyn VirtualDisk>,
+    parent: Option<Box<dyn ReadSeek>>,
+    /// The current stream position in the virtual disk.
+    stream_position: u64,
+}
+
+impl VirtualDiskReader {
+    pub fn new(disk: Box<dyn VirtualDisk>, parent: Option<Box<dyn ReadSeek>>) -> Self {
+        Self {
+            disk,
+            parent,
+            stream_position: 0,
+        }
+    }
+}
+
+impl Read for VirtualDiskReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.disk.size();
+        if self.stream_position >= size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let max_len = buf.len().min(
+            (size - self.stream_position)
+                .try_into()
+                .unwrap_or(usize::MAX),
+        );
+        let buf = &mut buf[..max_len];
+
+        let bytes_read = match self.disk.read_at(self.stream_position, buf)? {
+            BlockRead::Data(n) => n,
+            BlockRead::Parent(n) => {
+                match &mut self.parent {
+                    Some(parent) => {
+                        parent.seek(SeekFrom::Start(self.stream_position))?;
+                        read_or_zero(parent, &mut buf[..n])?;
+                    }
+                    None => buf[..n].fill(0),
+                }
+                n
+            }
+        };
+
+        self.stream_position += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl Seek for VirtualDiskReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => {
+                let size = self.disk.size();
+                if n >= 0 {
+                    size.checked_add(n as u64)
+                } else {
+                    size.checked_sub(n.wrapping_neg() as u64)
+                }
+            }
+            SeekFrom::Current(n) => {
+                if n >= 0 {
+                    self.stream_position.checked_add(n as u64)
+                } else {
+                    self.stream_position.checked_sub(n.wrapping_neg() as u64)
+                }
+            }
+        };
+
+        match new_pos {
+            Some(n) => {
+                self.stream_position = n;
+                Ok(self.stream_position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+/// Fills `buf` from `reader` and zeroes whatever lies beyond its end.
+///
+/// A parent disk may be smaller than its child if the child has been resized.
+fn read_or_zero<R>(reader: &mut R, buf: &mut [u8]) -> io::Result<()>
+where
+    R: Read + ?Sized,
+{
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+
+    buf[len..].fill(0);
+    Ok(())
+}
+
+/// Reads exactly `buf.len()` bytes at `offset` of `file`.
+pub fn read_exact_at(file: &mut File, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+/// Returns the bit at `index` of `bitmap` and how many bits from there on (up to `max_bits`) have the same value.
+///
+/// `msb_first` selects whether bit 0 is the most significant (VHD) or least significant (VHDX) bit of each byte.
+pub fn bitmap_run(bitmap: &[u8], index: usize, max_bits: usize, msb_first: bool) -> (bool, usize) {
+    let bit = |i: usize| {
+        let byte = bitmap.get(i / 8).copied().unwrap_or(0);
+        let shift = if msb_first { 7 - i % 8 } else { i % 8 };
+        byte >> shift & 1 == 1
+    };
+
+    let value = bit(index);
+    let run = (index..index + max_bits)
+        .take_while(|&i| bit(i) == value)
+        .count();
+    (value, run)
+}
+
+/// Resolves a path to a parent disk stored in a child disk.
+///
+/// Relative paths are relative to the directory of the child.
+/// Paths with Windows separators are converted, and the bare file name next to the child is offered as a fallback,
+/// because absolute paths usually refer to the machine that created the disk.
+pub fn parent_path_candidates(child: &Path, stored_path: &str) -> Vec<PathBuf> {
+    let child_dir = child.parent().unwrap_or_else(|| Path::new("."));
+    let separator = std::path::MAIN_SEPARATOR.to_string();
+    let normalized = stored_path.replace('\\', &separator);
+    let normalized = normalized
+        .strip_prefix(&format!(".{}", separator))
+        .unwrap_or(&normalized);
+
+    let mut candidates = vec![child_dir.join(normalized)];
+    if let Some(file_name) = Path::new(normalized).file_name() {
+        let fallback = child_dir.join(file_name);
+        if !candidates.contains(&fallback) {
+            candidates.push(fallback);
+        }
+    }
+    candidates
+}
+
+/// Decodes a UTF-16LE string, stopping at the first NUL character.
+pub fn utf16le_string(bytes: &[u8]) -> String {
+    let utf16 = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&c| c != 0)
+        .collect::<Vec<u16>>();
+    String::from_utf16_lossy(&utf16)
+}
+
+pub fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Reads a whole virtual disk, with `parent` as the contents of its parent disk.
+    pub fn read_disk<D>(disk: D, parent: Option<Vec<u8>>) -> Vec<u8>
+    where
+        D: VirtualDisk + 'static,
+    {
+        let parent = parent.map(|parent| Box::new(Cursor::new(parent)) as Box<dyn ReadSeek>);
+        let mut reader = VirtualDiskReader::new(Box::new(disk), parent);
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        data
+    }
+
+    /// Returns `len` bytes of a pattern that differs for each `seed`.
+    pub fn pattern(seed: u8, len: usize) -> Vec<u8> {
+        (0..len).map(|i| seed ^ (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn bitmap_runs() {
+        let bitmap = [0b1100_0001, 0b0000_0011];
+        assert_eq!(bitmap_run(&bitmap, 0, 16, true), (true, 2));
+        assert_eq!(bitmap_run(&bitmap, 2, 16, true), (false, 5));
+        assert_eq!(bitmap_run(&bitmap, 0, 16, false), (true, 1));
+        assert_eq!(bitmap_run(&bitmap, 7, 16, false), (true, 3));
+        assert_eq!(bitmap_run(&bitmap, 7, 2, false), (true, 2));
+        // Bits beyond the bitmap read as not present.
+        assert_eq!(bitmap_run(&bitmap, 10, 100, false), (false, 100));
+    }
+
+    #[test]
+    fn parent_paths() {
+        let child = Path::new("/images/child.vhd");
+        assert_eq!(
+            parent_path_candidates(child, ".\\base\\parent.vhd"),
+            vec![
+                PathBuf::from("/images/base/parent.vhd"),
+                PathBuf::from("/images/parent.vhd")
+            ]
+        );
+        assert_eq!(
+            parent_path_candidates(child, "parent.vhd"),
+            vec![PathBuf::from("/images/parent.vhd")]
+        );
+    }
+
+    #[test]
+    fn parent_shorter_than_child() {
+        struct Empty;
+        impl VirtualDisk for Empty {
+            fn format_name(&self) -> String {
+                String::new()
+            }
+            fn size(&self) -> u64 {
+                8
+            }
+            fn parent_paths(&self) -> Vec<PathBuf> {
+                Vec::new()
+            }
+            fn read_at(&mut self, _position: u64, buf: &mut [u8]) -> io::Result<BlockRead> {
+                Ok(BlockRead::Parent(buf.len()))
+            }
+        }
+
+        assert_eq!(
+            read_disk(Empty, Some(vec![1, 2, 3])),
+            [1, 2, 3, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(read_disk(Empty, None), [0; 8]);
+    }
+}
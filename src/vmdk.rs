@@ -0,0 +1,584 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use flate2::read::ZlibDecoder;
+
+use crate::virtual_disk::{
+    invalid_data, parent_path_candidates, read_exact_at, BlockRead, VirtualDisk,
+};
+
+/// Magic number of a hosted sparse extent ("KDMV").
+pub const VMDK_SPARSE_MAGIC: &[u8; 4] = b"KDMV";
+/// Start of a text descriptor file.
+pub const VMDK_DESCRIPTOR_START: &[u8] = b"# Disk DescriptorFile";
+
+const SECTOR_SIZE: u64 = 512;
+/// Grain directory offset of stream-optimized extents, whose real header is in a footer at the end of the file.
+const GD_AT_END: u64 = u64::MAX;
+const FLAG_ZEROED_GRAIN_GTE: u32 = 1 << 2;
+const FLAG_COMPRESSED_GRAINS: u32 = 1 << 16;
+/// Upper limit for the size of descriptors and grain directories, to not allocate absurd amounts of memory.
+const MAX_METADATA_SIZE: u64 = 64 << 20;
+/// Number of entries of every grain table, as required by the specification.
+const GRAIN_TABLE_ENTRIES: u64 = 512;
+/// Smallest grain size in sectors allowed by the specification.
+const MIN_GRAIN_SECTORS: u64 = 16;
+/// Upper limit for the grain size in sectors, to not allocate absurd amounts of memory for a compressed grain.
+const MAX_GRAIN_SECTORS: u64 = 1 << 15;
+
+enum ExtentKind {
+    /// Data stored 1:1 in a file, starting at the given offset.
+    Flat {
+        file: File,
+        offset: u64,
+    },
+    Sparse(SparseExtent),
+    Zero,
+}
+
+struct Extent {
+    /// Position of the extent in the virtual disk.
+    start: u64,
+    size: u64,
+    kind: ExtentKind,
+}
+
+/// A hosted sparse extent, which stores its data in grains listed by grain tables.
+struct SparseExtent {
+    file: File,
+    grain_size: u64,
+    grain_table_entries: u64,
+    /// Sector offset of each grain table.
+    grain_directory: Vec<u32>,
+    zeroed_grain_entries: bool,
+    compressed: bool,
+    /// Index and entries of the most recently read grain table.
+    cached_table: Option<(usize, Vec<u32>)>,
+    /// Sector offset and data of the most recently decompressed grain.
+    cached_grain: Option<(u32, Vec<u8>)>,
+}
+
+/// A VMware VMDK disk, consisting of flat, sparse and zero extents.
+///
+/// Monolithic and split sparse disks as well as stream-optimized disks with compressed grains are supported.
+/// Extents of the descriptor are read relative to the descriptor's directory.
+pub struct Vmdk {
+    create_type: String,
+    extents: Vec<Extent>,
+    size: u64,
+    parent_paths: Vec<PathBuf>,
+}
+
+impl Vmdk {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut magic = [0u8; 4];
+        read_exact_at(&mut file, 0, &mut magic)?;
+
+        let descriptor = if &magic == VMDK_SPARSE_MAGIC {
+            // A monolithic sparse disk embeds its descriptor.
+            let header = read_sparse_header(&mut file)?;
+            let descriptor_offset = u64::from_le_bytes(header[28..36].try_into().unwrap());
+            let descriptor_size = u64::from_le_bytes(header[36..44].try_into().unwrap());
+            if descriptor_offset == 0 {
+                None
+            } else {
+                let size = descriptor_size
+                    .saturating_mul(SECTOR_SIZE)
+                    .min(MAX_METADATA_SIZE);
+                let mut descriptor = vec![0u8; size as usize];
+                read_exact_at(
+                    &mut file,
+                    sectors_to_bytes(descriptor_offset)?,
+                    &mut descriptor,
+                )?;
+                Some(descriptor)
+            }
+        } else {
+            let len = file.seek(SeekFrom::End(0))?.min(MAX_METADATA_SIZE);
+            let mut descriptor = vec![0u8; len as usize];
+            read_exact_at(&mut file, 0, &mut descriptor)?;
+            if !descriptor.starts_with(VMDK_DESCRIPTOR_START) {
+                return Err(invalid_data("not a VMDK descriptor or sparse extent"));
+            }
+            Some(descriptor)
+        };
+
+        let mut vmdk = Self {
+            create_type: "monolithicSparse".to_owned(),
+            extents: Vec::new(),
+            size: 0,
+            parent_paths: Vec::new(),
+        };
+
+        match descriptor {
+            Some(descriptor) => {
+                let descriptor = String::from_utf8_lossy(&descriptor);
+                let descriptor = descriptor.trim_end_matches('\0');
+                vmdk.parse_descriptor(path, descriptor, &magic)?;
+            }
+            None => {
+                // A sparse extent without descriptor is a disk of its own.
+                let extent = SparseExtent::open(path)?;
+                let capacity = extent.capacity;
+                vmdk.push_extent(capacity, ExtentKind::Sparse(extent.extent))?;
+            }
+        }
+
+        Ok(vmdk)
+    }
+
+    fn push_extent(&mut self, size: u64, kind: ExtentKind) -> io::Result<()> {
+        self.extents.push(Extent {
+            start: self.size,
+            size,
+            kind,
+        });
+        self.size = self
+            .size
+            .checked_add(size)
+            .ok_or_else(|| invalid_data("VMDK extents are too large"))?;
+        Ok(())
+    }
+
+    fn parse_descriptor(&mut self, path: &Path, descriptor: &str, magic: &[u8]) -> io::Result<()> {
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+        for line in descriptor.lines().map(str::trim) {
+            if let Some(value) = descriptor_value(line, "createType") {
+                self.create_type = value.to_owned();
+            } else if let Some(value) = descriptor_value(line, "parentFileNameHint") {
+                self.parent_paths = parent_path_candidates(path, value);
+            } else if line.starts_with("RW ") || line.starts_with("RDONLY ") {
+                // Extent lines look like `RW 4192256 SPARSE "disk-s001.vmdk"` or `RW 2048 FLAT "disk-flat.vmdk" 0`.
+                let mut fields = line.splitn(3, ' ');
+                let _access = fields.next();
+                let sectors = fields
+                    .next()
+                    .and_then(|sectors| sectors.parse::<u64>().ok())
+                    .ok_or_else(|| invalid_data("invalid VMDK extent size"))?;
+                let rest = fields.next().unwrap_or_default();
+                let (extent_type, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+                let (file_name, offset) = match rest.trim().strip_prefix('"') {
+                    Some(quoted) => {
+                        let (file_name, offset) = quoted.split_once('"').unwrap_or((quoted, ""));
+                        (file_name, offset.trim().parse::<u64>().unwrap_or(0))
+                    }
+                    None => ("", 0),
+                };
+
+                let size = sectors_to_bytes(sectors)?;
+                let kind = match extent_type {
+                    "FLAT" | "VMFS" => ExtentKind::Flat {
+                        file: File::open(dir.join(file_name))?,
+                        offset: sectors_to_bytes(offset)?,
+                    },
+                    "SPARSE" | "VMFSSPARSE" if magic == VMDK_SPARSE_MAGIC => {
+                        // The extent of a monolithic sparse disk is the file with the embedded descriptor.
+                        ExtentKind::Sparse(SparseExtent::open(path)?.extent)
+                    }
+                    "SPARSE" | "VMFSSPARSE" => {
+                        ExtentKind::Sparse(SparseExtent::open(&dir.join(file_name))?.extent)
+                    }
+                    "ZERO" => ExtentKind::Zero,
+                    _ => {
+                        return Err(invalid_data(&format!(
+                            "unsupported VMDK extent type {}",
+                            extent_type
+                        )))
+                    }
+                };
+                self.push_extent(size, kind)?;
+            }
+        }
+
+        if self.extents.is_empty() {
+            return Err(invalid_data("VMDK descriptor lists no extents"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Converts a size or offset in sectors, as found in headers and descriptors, to bytes.
+fn sectors_to_bytes(sectors: u64) -> io::Result<u64> {
+    sectors
+        .checked_mul(SECTOR_SIZE)
+        .ok_or_else(|| invalid_data("VMDK size or offset is too large"))
+}
+
+/// Returns the value of a `key="value"` line of a descriptor.
+fn descriptor_value<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let value = line
+        .strip_prefix(key)?
+        .trim_start()
+        .strip_prefix('=')?
+        .trim();
+    Some(value.trim_matches('"'))
+}
+
+/// Reads the header of a sparse extent.
+///
+/// Stream-optimized extents only have a placeholder at the start, their real header is in the footer.
+fn read_sparse_header(file: &mut File) -> io::Result<[u8; 512]> {
+    let mut header = [0u8; 512];
+    read_exact_at(file, 0, &mut header)?;
+    if &header[0..4] != VMDK_SPARSE_MAGIC {
+        return Err(invalid_data("invalid VMDK sparse extent header"));
+    }
+
+    let gd_offset = u64::from_le_bytes(header[56..64].try_into().unwrap());
+    if gd_offset == GD_AT_END {
+        // The footer is followed by an end-of-stream marker.
+        let len = file.seek(SeekFrom::End(0))?;
+        read_exact_at(file, len.saturating_sub(2 * SECTOR_SIZE), &mut header)?;
+        if &header[0..4] != VMDK_SPARSE_MAGIC {
+            return Err(invalid_data("invalid VMDK stream-optimized footer"));
+        }
+    }
+
+    Ok(header)
+}
+
+struct OpenedSparseExtent {
+    extent: SparseExtent,
+    /// Size of the extent in bytes.
+    capacity: u64,
+}
+
+impl SparseExtent {
+    fn open(path: &Path) -> io::Result<OpenedSparseExtent> {
+        let mut file = File::open(path)?;
+        let header = read_sparse_header(&mut file)?;
+
+        let flags = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        let capacity = u64::from_le_bytes(header[12..20].try_into().unwrap());
+        let grain_sectors = u64::from_le_bytes(header[20..28].try_into().unwrap());
+        let grain_table_entries = u32::from_le_bytes(header[44..48].try_into().unwrap()) as u64;
+        let gd_offset = u64::from_le_bytes(header[56..64].try_into().unwrap());
+        if !(MIN_GRAIN_SECTORS..=MAX_GRAIN_SECTORS).contains(&grain_sectors)
+            || !grain_sectors.is_power_of_two()
+        {
+            return Err(invalid_data("invalid VMDK grain size"));
+        }
+        if grain_table_entries != GRAIN_TABLE_ENTRIES {
+            return Err(invalid_data("invalid VMDK grain table size"));
+        }
+
+        // Bounding the capacity in bytes also keeps the rounding below from overflowing.
+        let size = sectors_to_bytes(capacity)?;
+        let grains = (capacity + grain_sectors - 1) / grain_sectors;
+        let tables = (grains + grain_table_entries - 1) / grain_table_entries;
+        if tables * 4 > MAX_METADATA_SIZE {
+            return Err(invalid_data("VMDK grain directory is too large"));
+        }
+        let mut grain_directory = vec![0u8; tables as usize * 4];
+        read_exact_at(
+            &mut file,
+            sectors_to_bytes(gd_offset)?,
+            &mut grain_directory,
+        )?;
+
+        Ok(OpenedSparseExtent {
+            extent: SparseExtent {
+                file,
+                grain_size: grain_sectors * SECTOR_SIZE,
+                grain_table_entries,
+                grain_directory: grain_directory
+                    .chunks_exact(4)
+                    .map(|entry| u32::from_le_bytes(entry.try_into().unwrap()))
+                    .collect(),
+                zeroed_grain_entries: flags & FLAG_ZEROED_GRAIN_GTE != 0,
+                compressed: flags & FLAG_COMPRESSED_GRAINS != 0,
+                cached_table: None,
+                cached_grain: None,
+            },
+            capacity: size,
+        })
+    }
+
+    fn grain_sector(&mut self, grain: u64) -> io::Result<u32> {
+        let table = (grain / self.grain_table_entries) as usize;
+        let table_sector = match self.grain_directory.get(table) {
+            Some(&sector) if sector != 0 => sector,
+            _ => return Ok(0),
+        };
+
+        let is_cached =
+            matches!(&self.cached_table, Some((cached_table, _)) if *cached_table == table);
+        if !is_cached {
+            let mut entries = vec![0u8; self.grain_table_entries as usize * 4];
+            read_exact_at(
+                &mut self.file,
+                table_sector as u64 * SECTOR_SIZE,
+                &mut entries,
+            )?;
+            let entries = entries
+                .chunks_exact(4)
+                .map(|entry| u32::from_le_bytes(entry.try_into().unwrap()))
+                .collect();
+            self.cached_table = Some((table, entries));
+        }
+
+        let entries = &self.cached_table.as_ref().unwrap().1;
+        Ok(entries[(grain % self.grain_table_entries) as usize])
+    }
+
+    /// Reads and decompresses a grain of a stream-optimized extent.
+    fn load_compressed_grain(&mut self, sector: u32) -> io::Result<&[u8]> {
+        let is_cached =
+            matches!(&self.cached_grain, Some((cached_sector, _)) if *cached_sector == sector);
+        if !is_cached {
+            // Compressed grains are prefixed with a marker of their LBA (8 bytes) and compressed size (4 bytes).
+            let mut marker = [0u8; 12];
+            read_exact_at(&mut self.file, sector as u64 * SECTOR_SIZE, &mut marker)?;
+            let compressed_size = u32::from_le_bytes(marker[8..12].try_into().unwrap()) as u64;
+            if compressed_size > MAX_METADATA_SIZE {
+                return Err(invalid_data("invalid VMDK compressed grain size"));
+            }
+
+            let mut compressed = vec![0u8; compressed_size as usize];
+            self.file.read_exact(&mut compressed)?;
+            let mut grain = Vec::with_capacity(self.grain_size as usize);
+            ZlibDecoder::new(&compressed[..]).read_to_end(&mut grain)?;
+            grain.resize(self.grain_size as usize, 0);
+            self.cached_grain = Some((sector, grain));
+        }
+
+        Ok(&self.cached_grain.as_ref().unwrap().1)
+    }
+
+    fn read_at(&mut self, position: u64, buf: &mut [u8]) -> io::Result<BlockRead> {
+        let grain = position / self.grain_size;
+        let offset_in_grain = position % self.grain_size;
+        let len = buf.len().min((self.grain_size - offset_in_grain) as usize);
+
+        match self.grain_sector(grain)? {
+            0 => Ok(BlockRead::Parent(len)),
+            1 if self.zeroed_grain_entries => {
+                buf[..len].fill(0);
+                Ok(BlockRead::Data(len))
+            }
+            sector if self.compressed => {
+                let data = self.load_compressed_grain(sector)?;
+                let start = offset_in_grain as usize;
+                buf[..len].copy_from_slice(&data[start..start + len]);
+                Ok(BlockRead::Data(len))
+            }
+            sector => {
+                read_exact_at(
+                    &mut self.file,
+                    sector as u64 * SECTOR_SIZE + offset_in_grain,
+                    &mut buf[..len],
+                )?;
+                Ok(BlockRead::Data(len))
+            }
+        }
+    }
+}
+
+impl VirtualDisk for Vmdk {
+    fn format_name(&self) -> String {
+        format!("VMDK ({})", self.create_type)
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn parent_paths(&self) -> Vec<PathBuf> {
+        self.parent_paths.clone()
+    }
+
+    fn read_at(&mut self, position: u64, buf: &mut [u8]) -> io::Result<BlockRead> {
+        let extent = self
+            .extents
+            .iter_mut()
+            .find(|extent| position < extent.start + extent.size)
+            .ok_or_else(|| invalid_data("read beyond the last VMDK extent"))?;
+        let offset_in_extent = position - extent.start;
+        let len = buf.len().min(
+            (extent.size - offset_in_extent)
+                .try_into()
+                .unwrap_or(usize::MAX),
+        );
+
+        match &mut extent.kind {
+            ExtentKind::Flat { file, offset } => {
+                read_exact_at(file, *offset + offset_in_extent, &mut buf[..len])?;
+                Ok(BlockRead::Data(len))
+            }
+            ExtentKind::Sparse(sparse) => sparse.read_at(offset_in_extent, &mut buf[..len]),
+            ExtentKind::Zero => {
+                buf[..len].fill(0);
+                Ok(BlockRead::Data(len))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+    use crate::virtual_disk::tests::{pattern, read_disk};
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    const GRAIN_SECTORS: u64 = 16;
+    const GRAIN_SIZE: usize = (GRAIN_SECTORS * SECTOR_SIZE) as usize;
+    /// The grains of a full grain table and eight more in a second one.
+    const CAPACITY: u64 = (GRAIN_TABLE_ENTRIES + 8) * GRAIN_SECTORS;
+
+    enum Grain {
+        Missing,
+        Zeroed,
+        Data(Vec<u8>),
+    }
+
+    /// Builds a sparse extent whose first grain table lists `grains`, the second grain table is missing.
+    fn sparse_extent(flags: u32, descriptor: &str, grains: &[Grain]) -> Vec<u8> {
+        let mut file = vec![0u8; 7 * SECTOR_SIZE as usize];
+        file[0..4].copy_from_slice(VMDK_SPARSE_MAGIC);
+        file[4..8].copy_from_slice(&1u32.to_le_bytes());
+        file[8..12].copy_from_slice(&flags.to_le_bytes());
+        file[12..20].copy_from_slice(&CAPACITY.to_le_bytes());
+        file[20..28].copy_from_slice(&GRAIN_SECTORS.to_le_bytes());
+        if !descriptor.is_empty() {
+            file[28..36].copy_from_slice(&1u64.to_le_bytes());
+            file[36..44].copy_from_slice(&1u64.to_le_bytes());
+            file[512..512 + descriptor.len()].copy_from_slice(descriptor.as_bytes());
+        }
+        file[44..48].copy_from_slice(&(GRAIN_TABLE_ENTRIES as u32).to_le_bytes());
+        // The grain directory is in sector 2 and points to the grain table in sectors 3 to 6.
+        file[56..64].copy_from_slice(&2u64.to_le_bytes());
+        file[1024..1028].copy_from_slice(&3u32.to_le_bytes());
+
+        for (i, grain) in grains.iter().enumerate() {
+            let sector = match grain {
+                Grain::Missing => 0,
+                Grain::Zeroed => 1,
+                Grain::Data(data) => {
+                    let sector = file.len() as u64 / SECTOR_SIZE;
+                    file.extend_from_slice(data);
+                    file.resize((file.len() + 511) / 512 * 512, 0);
+                    sector as u32
+                }
+            };
+            file[1536 + 4 * i..1540 + 4 * i].copy_from_slice(&sector.to_le_bytes());
+        }
+        file
+    }
+
+    #[test]
+    fn monolithic_sparse() {
+        let dir = test_dir("vmdk-sparse");
+        let (first, fourth) = (pattern(1, GRAIN_SIZE), pattern(2, GRAIN_SIZE));
+        let descriptor = format!(
+            "# Disk DescriptorFile\ncreateType=\"monolithicSparse\"\n\
+             parentFileNameHint=\"base.vmdk\"\nRW {} SPARSE \"disk.vmdk\"\n",
+            CAPACITY
+        );
+        let file = sparse_extent(
+            FLAG_ZEROED_GRAIN_GTE,
+            &descriptor,
+            &[
+                Grain::Data(first.clone()),
+                Grain::Missing,
+                Grain::Zeroed,
+                Grain::Data(fourth.clone()),
+            ],
+        );
+        std::fs::write(dir.join("disk.vmdk"), &file).unwrap();
+
+        let vmdk = Vmdk::open(&dir.join("disk.vmdk")).unwrap();
+        assert_eq!(vmdk.format_name(), "VMDK (monolithicSparse)");
+        assert_eq!(vmdk.parent_paths(), vec![dir.join("base.vmdk")]);
+
+        let parent = pattern(3, CAPACITY as usize * 512);
+        let expected = [
+            &first[..],
+            &parent[GRAIN_SIZE..2 * GRAIN_SIZE],
+            &vec![0u8; GRAIN_SIZE][..],
+            &fourth[..],
+            &parent[4 * GRAIN_SIZE..],
+        ]
+        .concat();
+        assert_eq!(read_disk(vmdk, Some(parent)), expected);
+    }
+
+    #[test]
+    fn compressed_grains() {
+        let dir = test_dir("vmdk-compressed");
+        let data = pattern(4, GRAIN_SIZE);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let mut grain = 0u64.to_le_bytes().to_vec();
+        grain.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        grain.extend_from_slice(&compressed);
+
+        let file = sparse_extent(FLAG_COMPRESSED_GRAINS, "", &[Grain::Data(grain)]);
+        std::fs::write(dir.join("disk.vmdk"), &file).unwrap();
+
+        let disk = read_disk(Vmdk::open(&dir.join("disk.vmdk")).unwrap(), None);
+        assert_eq!(disk.len(), CAPACITY as usize * 512);
+        assert_eq!(disk[..GRAIN_SIZE], data[..]);
+        assert!(disk[GRAIN_SIZE..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn flat_and_zero_extents() {
+        let dir = test_dir("vmdk-flat");
+        let flat = pattern(5, 4096);
+        std::fs::write(dir.join("disk-flat.vmdk"), &flat).unwrap();
+        let descriptor = "# Disk DescriptorFile\n\
+                          createType=\"twoGbMaxExtentFlat\"\n\
+                          RW 4 FLAT \"disk-flat.vmdk\" 2\n\
+                          RW 2 ZERO\n\
+                          RDONLY 1 FLAT \"disk-flat.vmdk\" 0\n";
+        std::fs::write(dir.join("disk.vmdk"), descriptor).unwrap();
+
+        let vmdk = Vmdk::open(&dir.join("disk.vmdk")).unwrap();
+        assert_eq!(vmdk.size(), 7 * 512);
+        let expected = [&flat[1024..3072], &[0u8; 1024][..], &flat[..512]].concat();
+        assert_eq!(read_disk(vmdk, None), expected);
+    }
+
+    #[test]
+    fn descriptor_without_extents() {
+        let dir = test_dir("vmdk-empty");
+        std::fs::write(dir.join("disk.vmdk"), "# Disk DescriptorFile\nversion=1\n").unwrap();
+
+        assert!(Vmdk::open(&dir.join("disk.vmdk")).is_err());
+    }
+
+    #[test]
+    fn invalid_sparse_header() {
+        let dir = test_dir("vmdk-invalid-header");
+        let valid = sparse_extent(0, "", &[]);
+        let corruptions: [(usize, &[u8]); 6] = [
+            // Capacity that overflows in bytes.
+            (12, &u64::MAX.to_le_bytes()),
+            // Grain sizes below the minimum, above the maximum and not a power of two.
+            (20, &8u64.to_le_bytes()),
+            (20, &(1u64 << 40).to_le_bytes()),
+            (20, &24u64.to_le_bytes()),
+            // Grain tables of other sizes than the specification allows.
+            (44, &u32::MAX.to_le_bytes()),
+            // Grain directory offset that overflows in bytes.
+            (56, &(u64::MAX - 1).to_le_bytes()),
+        ];
+        for (offset, value) in corruptions {
+            let mut file = valid.clone();
+            file[offset..offset + value.len()].copy_from_slice(value);
+            std::fs::write(dir.join("disk.vmdk"), &file).unwrap();
+
+            let error = Vmdk::open(&dir.join("disk.vmdk")).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,824 @@
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+
+use anyhow::bail;
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use ntfs::attribute_value::NtfsAttributeValue;
+use ntfs::indexes::NtfsFileNameIndex;
+use ntfs::structured_values::{
+    NtfsFileAttributeFlags, NtfsFileName, NtfsFileNamespace, NtfsObjectId, NtfsStandardInformation,
+};
+use ntfs::{Ntfs, NtfsAttributeType, NtfsFile, NtfsReadSeek};
+
+use crate::bad_sectors::SharedBadSectors;
+use crate::block_cache::{BlockCache, CacheConfig, CacheStatistics};
+use crate::boot_sector::{BootSector, BootSectorOverlay};
+use crate::deleted::Bitmap;
+use crate::offset_reader::OffsetReader;
+use crate::partition::{Partition, SectorSizeSource};
+use crate::sector_reader::SectorReader;
+use crate::zone_identifier::{ZoneIdentifier, ZONE_IDENTIFIER_STREAM};
+
+/// File record number of the root directory.
+pub const ROOT_DIRECTORY: u64 = 5;
+/// The file records before this one are reserved for system files, from `$MFT` to `$Extend` and four unused ones.
+pub(crate) const FIRST_USER_FILE_RECORD: u64 = 16;
+/// Upper limit for the depth of a path, to get out of directories that are their own ancestors.
+pub(crate) const MAX_PATH_DEPTH: usize = 1024;
+/// `Zone.Identifier` streams are a few lines of text, anything beyond this is not what Windows wrote.
+const MAX_ZONE_IDENTIFIER_SIZE: usize = 64 * 1024;
+
+type VolumeReader<R> = BufReader<SectorReader<BlockCache<BootSectorOverlay<OffsetReader<R>>>>>;
+
+/// An opened NTFS volume.
+///
+/// Files are identified by their file record number, so that callers don't have to deal with the lifetimes of the
+/// `ntfs` crate.
+pub struct Volume<R>
+where
+    R: Read + Seek,
+{
+    pub(crate) fs: VolumeReader<R>,
+    pub(crate) ntfs: Ntfs,
+    /// Byte offset of the volume from the start of the disk.
+    pub(crate) offset: u64,
+    /// Bad sectors of the whole disk.
+    pub(crate) bad_sectors: SharedBadSectors,
+    /// The content of `$Bitmap`, read the first time deleted files are looked at.
+    pub(crate) cluster_bitmap: Option<Bitmap>,
+}
+
+/// A file or directory as listed in its parent directory.
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    pub record_number: u64,
+    /// Incremented each time the file record is reused.
+    pub sequence_number: u16,
+    pub name: String,
+    /// Namespace of `name`, files with a long name also have a short one in the Dos namespace.
+    pub namespace: NtfsFileNamespace,
+    pub is_directory: bool,
+    /// Size of the unnamed data stream.
+    pub size: u64,
+    /// Bytes of the unnamed data stream stored outside the file record, without sparse ranges.
+    /// For data runs spread over several file records, this is the size rounded up to whole clusters.
+    pub allocated_size: u64,
+    pub attributes: NtfsFileAttributeFlags,
+    /// The timestamps of the `$STANDARD_INFORMATION` attribute.
+    pub times: FileTimes,
+    /// The timestamps of the `$FILE_NAME` attribute the file is listed under.
+    /// Windows only updates them when the file is renamed or moved, so they are harder to tamper with.
+    pub file_name_times: FileTimes,
+    /// The earliest creation time of all `$FILE_NAME` attributes of the file, which is when it got its first name.
+    /// Names added later, like hard links, are younger than the file.
+    pub first_name_creation: DateTime<Utc>,
+    /// How the file is affected by known bad sectors, `None` if it isn't.
+    pub bad_sectors: Option<BadSectorOverlap>,
+}
+
+/// The timestamps NTFS keeps for a file, both in `$STANDARD_INFORMATION` and in each `$FILE_NAME` attribute.
+#[derive(Clone, Debug)]
+pub struct FileTimes {
+    pub creation: DateTime<Utc>,
+    pub modification: DateTime<Utc>,
+    pub mft_record_modification: DateTime<Utc>,
+    pub access: DateTime<Utc>,
+}
+
+/// A `$FILE_NAME` attribute. Files have one per hard link, plus a short name for long names in the Win32 namespace.
+#[derive(Clone, Debug)]
+pub struct FileName {
+    pub namespace: NtfsFileNamespace,
+    pub name: String,
+    pub parent_record_number: u64,
+}
+
+/// Metadata of a file.
+#[derive(Clone, Debug)]
+pub struct FileInfo {
+    pub record_number: u64,
+    /// Byte position of the file record in the volume.
+    pub record_position: u64,
+    pub is_directory: bool,
+    pub attributes: Option<NtfsFileAttributeFlags>,
+    /// Missing if the `$STANDARD_INFORMATION` attribute is.
+    pub times: Option<FileTimes>,
+    pub file_names: Vec<FileName>,
+    pub object_id: Option<String>,
+}
+
+/// An NTFS attribute of a file.
+#[derive(Clone, Debug)]
+pub struct AttributeInfo {
+    /// Name of the attribute type, like `$DATA`.
+    pub ty: &'static str,
+    /// Empty for unnamed attributes, like the `$DATA` attribute holding the content of a file.
+    pub name: String,
+    pub resident: bool,
+    /// Size of the attribute value.
+    pub size: u64,
+}
+
+/// How a file is affected by bad sectors.
+#[derive(Clone, Copy, Debug)]
+pub struct BadSectorOverlap {
+    /// Whether the file record itself is unreadable.
+    pub mft_record: bool,
+    /// Number of unreadable bytes of the unnamed data stream, `None` if its data runs are spread over several file
+    /// records and could not be checked.
+    pub data: Option<u64>,
+}
+
+impl<R> Volume<R>
+where
+    R: Read + Seek,
+{
+    /// Opens the NTFS volume in `partition` of the disk read by `reader`, or the entire disk if no `partition` is given.
+    ///
+    /// The sector size of the disk is checked against the one in the boot sector, see `SectorSizeSource`.
+    /// `bad_sectors` is only consulted to report affected files, it has to be filled by the reader.
+    pub fn open(
+        reader: R,
+        partition: Option<&Partition>,
+        sector_size: u64,
+        sector_size_source: SectorSizeSource,
+        bad_sectors: SharedBadSectors,
+        cache_config: CacheConfig,
+    ) -> anyhow::Result<Self> {
+        let offset = partition.map(|p| p.offset).unwrap_or_default();
+        let length = partition.map(|p| p.size);
+        let replacement_boot_sector = partition.and_then(|p| p.replacement_boot_sector.clone());
+        let volume = OffsetReader::new(reader, offset, length)?;
+        let volume = BootSectorOverlay::new(volume, replacement_boot_sector)?;
+        let mut cache = BlockCache::new(volume, cache_config);
+        let sector_size = volume_sector_size(&mut cache, sector_size, sector_size_source)?;
+        let mut fs = BufReader::new(SectorReader::new(cache, sector_size as usize)?);
+
+        let mut ntfs = Ntfs::new(&mut fs)?;
+        ntfs.read_upcase_table(&mut fs)?;
+
+        Ok(Self {
+            fs,
+            ntfs,
+            offset,
+            bad_sectors,
+            cluster_bitmap: None,
+        })
+    }
+
+    /// Byte offset of the volume from the start of the disk.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn cache_statistics(&self) -> CacheStatistics {
+        self.fs.get_ref().get_ref().statistics()
+    }
+
+    pub fn bad_sectors(&self) -> &SharedBadSectors {
+        &self.bad_sectors
+    }
+
+    /// Lists the files of a directory, each under its preferred name.
+    pub fn list_directory(&mut self, record_number: u64) -> anyhow::Result<Vec<DirEntry>> {
+        let fs = &mut self.fs;
+        let dir = self.ntfs.file(fs, record_number)?;
+        let index = dir.directory_index(fs)?;
+        let mut iter = index.entries();
+
+        // Files with a long name are listed a second time under their short name.
+        let mut files = vec![];
+        while let Some(entry) = iter.next(fs) {
+            let file = entry?.to_file(&self.ntfs, fs)?;
+            files.push(file);
+        }
+        let files = files
+            .into_iter()
+            .unique_by(|file| file.file_record_number())
+            .collect_vec();
+
+        let mut entries = vec![];
+        for file in files {
+            // Skip files whose name cannot be read, rather than failing the entire listing.
+            let file_name = match best_file_name(fs, &file, Some(record_number)) {
+                Ok(file_name) => file_name,
+                Err(_) => continue,
+            };
+            entries.push(dir_entry(
+                fs,
+                &self.ntfs,
+                &file,
+                &file_name,
+                self.offset,
+                &self.bad_sectors,
+            )?);
+        }
+
+        Ok(entries)
+    }
+
+    /// Lists every entry of a directory index, so files show up once for each of their names, short names included.
+    ///
+    /// The names and their timestamps are the copies in the index, which may differ from those in the file record.
+    pub fn list_all_names(&mut self, record_number: u64) -> anyhow::Result<Vec<DirEntry>> {
+        let fs = &mut self.fs;
+        let dir = self.ntfs.file(fs, record_number)?;
+        let index = dir.directory_index(fs)?;
+        let mut iter = index.entries();
+
+        let mut entries = vec![];
+        while let Some(entry) = iter.next(fs) {
+            let entry = entry?;
+            let file_name = match entry.key() {
+                Some(file_name) => file_name?,
+                None => continue,
+            };
+            let file = entry.to_file(&self.ntfs, fs)?;
+            entries.push(dir_entry(
+                fs,
+                &self.ntfs,
+                &file,
+                &file_name,
+                self.offset,
+                &self.bad_sectors,
+            )?);
+        }
+
+        Ok(entries)
+    }
+
+    /// Lists the system files, which describe the volume itself: `$MFT`, `$Bitmap`, `$LogFile`, `$Secure`, `$Extend`
+    /// and the like.
+    pub fn system_files(&mut self) -> anyhow::Result<Vec<DirEntry>> {
+        let fs = &mut self.fs;
+        let mut entries = vec![];
+        for record_number in 0..FIRST_USER_FILE_RECORD {
+            // The reserved file records at the end are usually unused.
+            let file = match self.ntfs.file(fs, record_number) {
+                Ok(file) => file,
+                Err(_) => continue,
+            };
+            let file_name = match best_file_name(fs, &file, Some(ROOT_DIRECTORY)) {
+                Ok(file_name) => file_name,
+                Err(_) => continue,
+            };
+            entries.push(dir_entry(
+                fs,
+                &self.ntfs,
+                &file,
+                &file_name,
+                self.offset,
+                &self.bad_sectors,
+            )?);
+        }
+
+        Ok(entries)
+    }
+
+    /// Looks up `name` in a directory and returns the file record number of the file.
+    pub fn find(&mut self, directory: u64, name: &str) -> anyhow::Result<Option<u64>> {
+        let fs = &mut self.fs;
+        let dir = self.ntfs.file(fs, directory)?;
+        let index = dir.directory_index(fs)?;
+        let mut finder = index.finder();
+        match NtfsFileNameIndex::find(&mut finder, &self.ntfs, fs, name) {
+            Some(entry) => Ok(Some(entry?.to_file(&self.ntfs, fs)?.file_record_number())),
+            None => Ok(None),
+        }
+    }
+
+    /// Looks up a path like `\Windows\System32` or `/Windows/System32` from the root directory and returns the file
+    /// record number of the file.
+    pub fn lookup(&mut self, path: &str) -> anyhow::Result<Option<u64>> {
+        let mut current = ROOT_DIRECTORY;
+        for component in path.split(['\\', '/']).filter(|c| !c.is_empty()) {
+            current = match self.find(current, component)? {
+                Some(record_number) => record_number,
+                None => return Ok(None),
+            };
+        }
+        Ok(Some(current))
+    }
+
+    /// Reads the metadata of a file.
+    pub fn file_info(&mut self, record_number: u64) -> anyhow::Result<FileInfo> {
+        let fs = &mut self.fs;
+        let file = self.ntfs.file(fs, record_number)?;
+
+        let mut standard_information: Option<NtfsStandardInformation> = None;
+        let mut file_names = vec![];
+        let mut object_id = None;
+        let mut attributes = file.attributes();
+        while let Some(attribute) = attributes.next(fs) {
+            let attribute = attribute?.to_attribute();
+            // Attribute types we don't know about are no reason to fail.
+            match attribute.ty() {
+                Ok(NtfsAttributeType::StandardInformation) => {
+                    standard_information = Some(attribute.structured_value(fs)?);
+                }
+                Ok(NtfsAttributeType::FileName) => {
+                    let file_name: NtfsFileName = attribute.structured_value(fs)?;
+                    file_names.push(FileName {
+                        namespace: file_name.namespace(),
+                        name: file_name.name().to_string_lossy(),
+                        parent_record_number: file_name
+                            .parent_directory_reference()
+                            .file_record_number(),
+                    });
+                }
+                Ok(NtfsAttributeType::ObjectId) => {
+                    let data: NtfsObjectId = attribute.structured_value(fs)?;
+                    object_id = Some(data.object_id().to_string());
+                }
+                _ => continue,
+            }
+        }
+
+        Ok(FileInfo {
+            record_number,
+            record_position: file.position(),
+            is_directory: file.is_directory(),
+            attributes: standard_information
+                .as_ref()
+                .map(|info| info.file_attributes()),
+            times: standard_information
+                .as_ref()
+                .map(standard_information_times),
+            file_names,
+            object_id,
+        })
+    }
+
+    /// Lists the NTFS attributes of a file, in the order of the file record.
+    pub fn attributes(&mut self, record_number: u64) -> anyhow::Result<Vec<AttributeInfo>> {
+        let fs = &mut self.fs;
+        let file = self.ntfs.file(fs, record_number)?;
+
+        let mut attributes = vec![];
+        let mut iter = file.attributes();
+        while let Some(attribute) = iter.next(fs) {
+            let attribute = attribute?.to_attribute();
+            attributes.push(AttributeInfo {
+                ty: attribute
+                    .ty()
+                    .map(attribute_type_name)
+                    .unwrap_or("(unknown)"),
+                name: attribute.name()?.to_string_lossy(),
+                resident: attribute.is_resident(),
+                size: attribute.value_length(),
+            });
+        }
+        Ok(attributes)
+    }
+
+    /// Returns the preferred name of a file in the directory `parent`.
+    pub fn file_name(&mut self, record_number: u64, parent: u64) -> anyhow::Result<String> {
+        let fs = &mut self.fs;
+        let file = self.ntfs.file(fs, record_number)?;
+        Ok(best_file_name(fs, &file, Some(parent))?
+            .name()
+            .to_string_lossy())
+    }
+
+    /// Returns the directory holding the preferred name of a file.
+    pub fn parent_directory(&mut self, record_number: u64) -> anyhow::Result<u64> {
+        let fs = &mut self.fs;
+        let file = self.ntfs.file(fs, record_number)?;
+        let file_name = best_file_name(fs, &file, None)?;
+        Ok(file_name.parent_directory_reference().file_record_number())
+    }
+
+    /// Builds the path of a file from the root directory, following the parent of its preferred name.
+    pub fn path(&mut self, record_number: u64) -> anyhow::Result<String> {
+        let components = self.path_components(record_number)?;
+        Ok(format!(
+            r"\{}",
+            components.iter().map(|(_, name)| name).join(r"\")
+        ))
+    }
+
+    /// Returns the file record numbers and names of the ancestors of a file below the root directory, followed by the
+    /// file itself, by following the parent references of the preferred names.
+    pub fn path_components(&mut self, record_number: u64) -> anyhow::Result<Vec<(u64, String)>> {
+        let fs = &mut self.fs;
+        let mut components = vec![];
+        let mut current = record_number;
+        while current != ROOT_DIRECTORY {
+            if components.len() >= MAX_PATH_DEPTH {
+                bail!("path of file record {:#x} is too deep", record_number);
+            }
+
+            let file = self.ntfs.file(fs, current)?;
+            let file_name = best_file_name(fs, &file, None)?;
+            components.push((current, file_name.name().to_string_lossy()));
+            current = file_name.parent_directory_reference().file_record_number();
+        }
+
+        components.reverse();
+        Ok(components)
+    }
+
+    /// Lists the alternate data streams of a file, along with their sizes.
+    pub fn streams(&mut self, record_number: u64) -> anyhow::Result<Vec<(String, u64)>> {
+        let fs = &mut self.fs;
+        let file = self.ntfs.file(fs, record_number)?;
+
+        let mut streams = vec![];
+        let mut attributes = file.attributes();
+        while let Some(attribute) = attributes.next(fs) {
+            let attribute = attribute?.to_attribute();
+            if !matches!(attribute.ty(), Ok(NtfsAttributeType::Data)) {
+                continue;
+            }
+            let name = attribute.name()?.to_string_lossy();
+            // The unnamed stream is the content of the file itself.
+            // A stream split over several file records only needs to be listed once.
+            if !name.is_empty() && !streams.iter().any(|(existing, _)| *existing == name) {
+                streams.push((name, attribute.value_length()));
+            }
+        }
+        Ok(streams)
+    }
+
+    /// Reads and parses the `Zone.Identifier` stream of a file, `None` if it has none.
+    pub fn zone_identifier(
+        &mut self,
+        record_number: u64,
+    ) -> anyhow::Result<Option<ZoneIdentifier>> {
+        let has_zone_identifier = self
+            .streams(record_number)?
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case(ZONE_IDENTIFIER_STREAM));
+        if !has_zone_identifier {
+            return Ok(None);
+        }
+        let data = self.read_stream_range(
+            record_number,
+            ZONE_IDENTIFIER_STREAM,
+            0,
+            MAX_ZONE_IDENTIFIER_SIZE,
+        )?;
+        Ok(ZoneIdentifier::parse(&data))
+    }
+
+    /// Returns the size of a data stream, `""` being the unnamed one.
+    pub fn stream_size(&mut self, record_number: u64, stream: &str) -> anyhow::Result<u64> {
+        let fs = &mut self.fs;
+        let file = self.ntfs.file(fs, record_number)?;
+        let data = match file.data(fs, stream) {
+            Some(data) => data?,
+            None => bail!(
+                "file record {:#x} has no stream {:?}",
+                record_number,
+                stream
+            ),
+        };
+        Ok(data.to_attribute().value_length())
+    }
+
+    /// Reads from a data stream at `offset` and returns the number of bytes read, which is 0 at the end.
+    pub fn read_stream(
+        &mut self,
+        record_number: u64,
+        stream: &str,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> anyhow::Result<usize> {
+        let fs = &mut self.fs;
+        let file = self.ntfs.file(fs, record_number)?;
+        let data = match file.data(fs, stream) {
+            Some(data) => data?,
+            None => bail!(
+                "file record {:#x} has no stream {:?}",
+                record_number,
+                stream
+            ),
+        };
+        let data = data.to_attribute();
+        let mut value = data.value(fs)?;
+        value.seek(fs, SeekFrom::Start(offset))?;
+        Ok(value.read(fs, buf)?)
+    }
+
+    /// Reads up to `size` bytes from a data stream at `offset`, less only at the end of the stream.
+    pub fn read_stream_range(
+        &mut self,
+        record_number: u64,
+        stream: &str,
+        offset: u64,
+        size: usize,
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut buf = vec![0u8; size];
+        let mut len = 0;
+        while len < buf.len() {
+            let bytes_read =
+                self.read_stream(record_number, stream, offset + len as u64, &mut buf[len..])?;
+            if bytes_read == 0 {
+                break;
+            }
+            len += bytes_read;
+        }
+        buf.truncate(len);
+        Ok(buf)
+    }
+
+    /// Copies an entire data stream to `writer` and returns the number of bytes copied.
+    pub fn copy_stream(
+        &mut self,
+        record_number: u64,
+        stream: &str,
+        writer: &mut dyn Write,
+    ) -> anyhow::Result<u64> {
+        let copied =
+            self.copy_stream_with_progress(record_number, stream, writer, &mut |_| true)?;
+        Ok(copied.unwrap_or_default())
+    }
+
+    /// Like [`Volume::copy_stream`], but calls `progress` with the number of bytes copied so far before each read.
+    /// Returns `None` if `progress` stopped the copy by returning `false`, `writer` keeps what was copied until then.
+    pub fn copy_stream_with_progress(
+        &mut self,
+        record_number: u64,
+        stream: &str,
+        writer: &mut dyn Write,
+        progress: &mut dyn FnMut(u64) -> bool,
+    ) -> anyhow::Result<Option<u64>> {
+        let fs = &mut self.fs;
+        let file = self.ntfs.file(fs, record_number)?;
+        let data = match file.data(fs, stream) {
+            Some(data) => data?,
+            None => bail!(
+                "file record {:#x} has no stream {:?}",
+                record_number,
+                stream
+            ),
+        };
+        let data = data.to_attribute();
+        let mut value = data.value(fs)?;
+
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut copied = 0;
+        loop {
+            if !progress(copied) {
+                return Ok(None);
+            }
+            let bytes_read = value.read(fs, &mut buf)?;
+            if bytes_read == 0 {
+                break;
+            }
+            writer.write_all(&buf[..bytes_read])?;
+            copied += bytes_read as u64;
+        }
+        Ok(Some(copied))
+    }
+}
+
+/// Checks the sector size of the disk against the NTFS boot sector of a volume and returns the one to read it with.
+///
+/// A guessed sector size gives way to the boot sector.
+/// Any other disagreement means that either the sector size or the partition table is wrong.
+fn volume_sector_size<R>(
+    volume: &mut R,
+    sector_size: u64,
+    sector_size_source: SectorSizeSource,
+) -> anyhow::Result<u64>
+where
+    R: Read + Seek,
+{
+    let mut sector = [0u8; 512];
+    volume.seek(SeekFrom::Start(0))?;
+    volume.read_exact(&mut sector)?;
+    volume.seek(SeekFrom::Start(0))?;
+
+    let bytes_per_sector = match BootSector::parse(&sector) {
+        Some(boot_sector) => boot_sector.bytes_per_sector as u64,
+        // Not our business, `Ntfs::new` reports that much better.
+        None => return Ok(sector_size),
+    };
+    if bytes_per_sector == sector_size || sector_size_source == SectorSizeSource::Default {
+        return Ok(bytes_per_sector);
+    }
+
+    bail!(
+        "the NTFS boot sector specifies {} bytes per sector, but the disk has {} byte sectors ({}), \
+         choose the sector size manually if that is wrong",
+        bytes_per_sector,
+        sector_size,
+        sector_size_source.description()
+    )
+}
+
+pub(crate) fn standard_information_times(info: &NtfsStandardInformation) -> FileTimes {
+    FileTimes {
+        creation: DateTime::from(info.creation_time()),
+        modification: DateTime::from(info.modification_time()),
+        mft_record_modification: DateTime::from(info.mft_record_modification_time()),
+        access: DateTime::from(info.access_time()),
+    }
+}
+
+pub(crate) fn file_name_times(file_name: &NtfsFileName) -> FileTimes {
+    FileTimes {
+        creation: DateTime::from(file_name.creation_time()),
+        modification: DateTime::from(file_name.modification_time()),
+        mft_record_modification: DateTime::from(file_name.mft_record_modification_time()),
+        access: DateTime::from(file_name.access_time()),
+    }
+}
+
+/// Formats a time to the second, the way timestamps are shown in listings.
+pub fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Describes a file as listed under `file_name`.
+pub(crate) fn dir_entry<T>(
+    fs: &mut T,
+    ntfs: &Ntfs,
+    file: &NtfsFile,
+    file_name: &NtfsFileName,
+    volume_offset: u64,
+    bad_sectors: &SharedBadSectors,
+) -> anyhow::Result<DirEntry>
+where
+    T: Read + Seek,
+{
+    let size = file
+        .data(fs, "")
+        .transpose()?
+        .map(|d| d.to_attribute().value_length())
+        .unwrap_or_default();
+    // Damaged data runs shouldn't keep the rest of the directory from being listed.
+    let allocated_size = match data_runs(fs, file) {
+        Ok(Some(data_runs)) => data_runs.iter().map(|&(_, size)| size).sum(),
+        Ok(None) => {
+            let cluster_size = ntfs.cluster_size() as u64;
+            (size + cluster_size - 1) / cluster_size * cluster_size
+        }
+        Err(_) => 0,
+    };
+    let info = file.info()?;
+    let file_name_times = file_name_times(file_name);
+    // A damaged name shouldn't keep the rest of the directory from being listed either.
+    let first_name_creation = match first_name_creation(fs, file) {
+        Ok(Some(creation)) => creation.min(file_name_times.creation),
+        Ok(None) | Err(_) => file_name_times.creation,
+    };
+
+    // Without any bad sectors, we don't need to look at the data runs of every file.
+    let bad_sectors = if bad_sectors.lock().unwrap().is_empty() {
+        None
+    } else {
+        bad_sector_overlap(fs, ntfs, file, volume_offset, bad_sectors)
+    };
+
+    Ok(DirEntry {
+        record_number: file.file_record_number(),
+        sequence_number: file.sequence_number(),
+        name: file_name.name().to_string_lossy(),
+        namespace: file_name.namespace(),
+        is_directory: file_name.is_directory(),
+        size,
+        allocated_size,
+        attributes: info.file_attributes(),
+        times: standard_information_times(&info),
+        file_name_times,
+        first_name_creation,
+        bad_sectors,
+    })
+}
+
+/// Returns the earliest creation time of the `$FILE_NAME` attributes of a file, `None` if it has none.
+fn first_name_creation<T>(fs: &mut T, file: &NtfsFile) -> anyhow::Result<Option<DateTime<Utc>>>
+where
+    T: Read + Seek,
+{
+    let mut first = None;
+    let mut attributes = file.attributes();
+    while let Some(attribute) = attributes.next(fs) {
+        let attribute = attribute?.to_attribute();
+        if let Ok(NtfsAttributeType::FileName) = attribute.ty() {
+            let file_name: NtfsFileName = attribute.structured_value(fs)?;
+            let creation = DateTime::from(file_name.creation_time());
+            first = Some(first.map_or(creation, |first: DateTime<Utc>| first.min(creation)));
+        }
+    }
+    Ok(first)
+}
+
+fn attribute_type_name(ty: NtfsAttributeType) -> &'static str {
+    match ty {
+        NtfsAttributeType::StandardInformation => "$STANDARD_INFORMATION",
+        NtfsAttributeType::AttributeList => "$ATTRIBUTE_LIST",
+        NtfsAttributeType::FileName => "$FILE_NAME",
+        NtfsAttributeType::ObjectId => "$OBJECT_ID",
+        NtfsAttributeType::SecurityDescriptor => "$SECURITY_DESCRIPTOR",
+        NtfsAttributeType::VolumeName => "$VOLUME_NAME",
+        NtfsAttributeType::VolumeInformation => "$VOLUME_INFORMATION",
+        NtfsAttributeType::Data => "$DATA",
+        NtfsAttributeType::IndexRoot => "$INDEX_ROOT",
+        NtfsAttributeType::IndexAllocation => "$INDEX_ALLOCATION",
+        NtfsAttributeType::Bitmap => "$BITMAP",
+        NtfsAttributeType::ReparsePoint => "$REPARSE_POINT",
+        NtfsAttributeType::EAInformation => "$EA_INFORMATION",
+        NtfsAttributeType::EA => "$EA",
+        NtfsAttributeType::PropertySet => "$PROPERTY_SET",
+        NtfsAttributeType::LoggedUtilityStream => "$LOGGED_UTILITY_STREAM",
+        NtfsAttributeType::End => "(end)",
+    }
+}
+
+/// Picks the name of a file to show in the directory `parent_record_number`, or in any directory if none is given.
+pub(crate) fn best_file_name<T>(
+    fs: &mut T,
+    file: &NtfsFile,
+    parent_record_number: Option<u64>,
+) -> anyhow::Result<NtfsFileName>
+where
+    T: Read + Seek,
+{
+    // Try to find a long filename (Win32) first.
+    // If we don't find one, the file may only have a single short name (Win32AndDos).
+    // If we don't find one either, go with any namespace. It may still be a Dos or Posix name then.
+    let priority = [
+        Some(NtfsFileNamespace::Win32),
+        Some(NtfsFileNamespace::Win32AndDos),
+        None,
+    ];
+
+    for match_namespace in priority {
+        if let Some(file_name) = file.name(fs, match_namespace, parent_record_number) {
+            let file_name = file_name?;
+            return Ok(file_name);
+        }
+    }
+
+    bail!(
+        "Found no FileName attribute for File Record {:#x}",
+        file.file_record_number()
+    )
+}
+
+/// Finds out how much of a file lies in bad sectors, or returns `None` if the file is not affected.
+///
+/// Bad sectors are tracked for the whole disk, so file positions have to be moved by `volume_offset`.
+/// Only unreadable sectors that have been read before or were known up front can be found.
+fn bad_sector_overlap<T>(
+    fs: &mut T,
+    ntfs: &Ntfs,
+    file: &NtfsFile,
+    volume_offset: u64,
+    bad_sectors: &SharedBadSectors,
+) -> Option<BadSectorOverlap>
+where
+    T: Read + Seek,
+{
+    let record_start = volume_offset + file.position();
+    let record_end = record_start + ntfs.file_record_size() as u64;
+    // A file record in bad sectors holds the fill pattern, so its data runs are garbage and we go without them.
+    let data_runs = data_runs(fs, file).unwrap_or(Some(vec![]));
+
+    // Lock only now, because reading the data runs may find more bad sectors.
+    let bad_sectors = bad_sectors.lock().unwrap();
+    let mft_record = bad_sectors.overlaps(record_start, record_end);
+    let data = data_runs.map(|data_runs| {
+        data_runs
+            .iter()
+            .map(|&(position, size)| {
+                bad_sectors.overlap(volume_offset + position, volume_offset + position + size)
+            })
+            .sum::<u64>()
+    });
+    if !mft_record && data == Some(0) {
+        return None;
+    }
+
+    Some(BadSectorOverlap { mft_record, data })
+}
+
+/// Returns the position and allocated size of each non-sparse data run of the unnamed `$DATA` attribute.
+///
+/// Returns `None` if the attribute is split over several file records by an `$ATTRIBUTE_LIST`, as the ntfs crate
+/// doesn't give access to the data runs of those.
+pub(crate) fn data_runs<T>(fs: &mut T, file: &NtfsFile) -> anyhow::Result<Option<Vec<(u64, u64)>>>
+where
+    T: Read + Seek,
+{
+    let mut data_runs = vec![];
+    if let Some(data) = file.data(fs, "").transpose()? {
+        match data.to_attribute().value(fs)? {
+            NtfsAttributeValue::NonResident(value) => {
+                for data_run in value.data_runs() {
+                    let data_run = data_run?;
+                    // Sparse data runs are not stored anywhere.
+                    if let Some(position) = data_run.data_position() {
+                        data_runs.push((position, data_run.allocated_size()));
+                    }
+                }
+            }
+            NtfsAttributeValue::AttributeListNonResident(_) => return Ok(None),
+            NtfsAttributeValue::Resident(_) => (),
+        }
+    }
+    Ok(Some(data_runs))
+}
@@ -0,0 +1,124 @@
+use std::collections::HashSet;
+use std::io::{Read, Seek};
+
+use itertools::Itertools;
+
+use crate::volume::{DirEntry, Volume};
+
+/// What to do with the files and folders found by [`walk`].
+pub(crate) trait Visitor<R>
+where
+    R: Read + Seek,
+{
+    /// What the children of a folder are visited with, like the folder they are written to.
+    type Context;
+
+    /// Visits a file with the path `path` on the volume, returns `false` to stop the walk.
+    fn file(
+        &mut self,
+        volume: &mut Volume<R>,
+        entry: &DirEntry,
+        path: &str,
+        parent: &Self::Context,
+    ) -> bool;
+
+    /// Visits a folder whose children have been listed, before them.
+    /// Returns the context to visit the children with, or `None` to skip them.
+    fn enter_directory(
+        &mut self,
+        entry: &DirEntry,
+        path: &str,
+        parent: &Self::Context,
+    ) -> Option<Self::Context>;
+
+    /// Called once all children of a folder have been visited.
+    fn leave_directory(&mut self, _entry: &DirEntry, _context: &Self::Context) {}
+
+    /// Records that the children of a folder couldn't be listed.
+    fn directory_failed(
+        &mut self,
+        entry: &DirEntry,
+        path: &str,
+        parent: &Self::Context,
+        err: anyhow::Error,
+    );
+}
+
+/// Visits `entries`, which are listed in `directory` with the path `source_directory` on the volume, and everything
+/// below the folders among them.
+///
+/// Files listed under several names are visited once, and folders that contain their ancestors are not entered again.
+/// Returns `false` if the visitor stopped the walk.
+pub(crate) fn walk<R, V>(
+    volume: &mut Volume<R>,
+    directory: u64,
+    source_directory: &str,
+    entries: &[DirEntry],
+    context: &V::Context,
+    visitor: &mut V,
+) -> bool
+where
+    R: Read + Seek,
+    V: Visitor<R>,
+{
+    let mut walker = Walker {
+        volume,
+        visitor,
+        visited: HashSet::from([directory]),
+    };
+    // Files are listed once for each name in the all-names view.
+    entries
+        .iter()
+        .unique_by(|entry| entry.record_number)
+        .all(|entry| walker.visit(entry, source_directory, context))
+}
+
+struct Walker<'v, R, V>
+where
+    R: Read + Seek,
+{
+    volume: &'v mut Volume<R>,
+    visitor: &'v mut V,
+    /// Folders visited so far, to get out of folders that contain their ancestors.
+    visited: HashSet<u64>,
+}
+
+impl<'v, R, V> Walker<'v, R, V>
+where
+    R: Read + Seek,
+    V: Visitor<R>,
+{
+    fn visit(&mut self, entry: &DirEntry, source_directory: &str, parent: &V::Context) -> bool {
+        let path = format!(
+            r"{}\{}",
+            source_directory.trim_end_matches('\\'),
+            entry.name
+        );
+        if !entry.is_directory {
+            return self.visitor.file(self.volume, entry, &path, parent);
+        }
+        if !self.visited.insert(entry.record_number) {
+            return true;
+        }
+
+        let children = match self.volume.list_directory(entry.record_number) {
+            Ok(children) => children,
+            Err(err) => {
+                self.visitor.directory_failed(entry, &path, parent, err);
+                return true;
+            }
+        };
+        let context = match self.visitor.enter_directory(entry, &path, parent) {
+            Some(context) => context,
+            None => return true,
+        };
+        if !children
+            .iter()
+            .all(|child| self.visit(child, &path, &context))
+        {
+            return false;
+        }
+        self.visitor.leave_directory(entry, &context);
+        true
+    }
+}
@@ -0,0 +1,264 @@
+use std::fs::File;
+use std::io;
+use std::io::{Cursor, Read, Seek, SeekFrom, Take};
+use std::path::Path;
+
+use xz2::read::XzDecoder;
+use xz2::stream::Stream;
+
+use crate::compressed::{Block, BlockFormat, BlockReader};
+
+pub const XZ_MAGIC: &[u8; 6] = b"\xfd7zXZ\x00";
+const XZ_FOOTER_MAGIC: &[u8; 2] = b"YZ";
+const HEADER_SIZE: u64 = 12;
+const FOOTER_SIZE: u64 = 12;
+/// Upper limit for the size of an index, to not allocate absurd amounts of memory for corrupted footers.
+const MAX_INDEX_SIZE: u64 = 64 << 20;
+
+/// Decompresses the blocks of an xz file.
+///
+/// A block can't be decompressed on its own, so it is prefixed with the header of its stream.
+/// The decoder then fails at the end of the block where it expects the index, but by then we have all the data.
+struct XzFormat {
+    /// Stream header of each block.
+    stream_headers: Vec<[u8; HEADER_SIZE as usize]>,
+}
+
+impl BlockFormat for XzFormat {
+    fn decoder(&self, index: usize, data: Take<File>) -> io::Result<Box<dyn Read + Send>> {
+        let stream = Stream::new_stream_decoder(u64::MAX, 0)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let header = Cursor::new(self.stream_headers[index]);
+        Ok(Box::new(XzDecoder::new_stream(header.chain(data), stream)))
+    }
+}
+
+/// Opens an xz compressed image, using the index of the xz file to find its blocks.
+///
+/// xz only compresses in multiple blocks when run with several threads (`xz -T0`) or an explicit `--block-size`.
+/// Files with a single block have to be decompressed from the start for every backward seek.
+pub fn open_xz(path: &Path) -> io::Result<BlockReader> {
+    let mut file = File::open(path)?;
+    let mut streams = Vec::new();
+
+    // A file can consist of several concatenated streams, which we read from the end.
+    let mut end = file.seek(SeekFrom::End(0))?;
+    while end > 0 {
+        // Streams may be followed by padding in multiples of 4 bytes.
+        let mut padding = [0u8; 4];
+        read_exact_at(&mut file, end.saturating_sub(4), &mut padding)?;
+        if padding == [0; 4] {
+            end -= 4;
+            continue;
+        }
+
+        let mut footer = [0u8; FOOTER_SIZE as usize];
+        read_exact_at(
+            &mut file,
+            end.checked_sub(FOOTER_SIZE).ok_or_else(invalid_xz)?,
+            &mut footer,
+        )?;
+        if &footer[10..12] != XZ_FOOTER_MAGIC {
+            return Err(invalid_xz());
+        }
+
+        let index_size = (u32::from_le_bytes(footer[4..8].try_into().unwrap()) as u64 + 1) * 4;
+        if index_size > MAX_INDEX_SIZE {
+            return Err(invalid_xz());
+        }
+        let index_start = (end - FOOTER_SIZE)
+            .checked_sub(index_size)
+            .ok_or_else(invalid_xz)?;
+        let mut index = vec![0u8; index_size as usize];
+        read_exact_at(&mut file, index_start, &mut index)?;
+        let records = parse_index(&index)?;
+
+        let blocks_size = records
+            .iter()
+            .try_fold(0u64, |sum, (unpadded_size, _)| {
+                sum.checked_add(padded(*unpadded_size))
+            })
+            .ok_or_else(invalid_xz)?;
+        let stream_start = blocks_size
+            .checked_add(HEADER_SIZE)
+            .and_then(|size| index_start.checked_sub(size))
+            .ok_or_else(invalid_xz)?;
+        let mut header = [0u8; HEADER_SIZE as usize];
+        read_exact_at(&mut file, stream_start, &mut header)?;
+        if &header[0..6] != XZ_MAGIC {
+            return Err(invalid_xz());
+        }
+
+        streams.push((stream_start, header, records));
+        end = stream_start;
+    }
+
+    let mut blocks = Vec::new();
+    let mut stream_headers = Vec::new();
+    let mut offset = 0;
+    for (stream_start, header, records) in streams.into_iter().rev() {
+        let mut compressed_offset = stream_start + HEADER_SIZE;
+        for (unpadded_size, size) in records {
+            blocks.push(Block {
+                compressed_offset,
+                compressed_size: padded(unpadded_size),
+                offset,
+                size: Some(size),
+            });
+            stream_headers.push(header);
+            compressed_offset += padded(unpadded_size);
+            offset = offset.checked_add(size).ok_or_else(invalid_xz)?;
+        }
+    }
+    if blocks.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "xz file contains no data",
+        ));
+    }
+
+    Ok(BlockReader::new(
+        file,
+        Box::new(XzFormat { stream_headers }),
+        blocks,
+    ))
+}
+
+/// Parses an index into pairs of unpadded block size and uncompressed size.
+fn parse_index(index: &[u8]) -> io::Result<Vec<(u64, u64)>> {
+    if index.first() != Some(&0) {
+        return Err(invalid_xz());
+    }
+
+    let mut position = 1;
+    let count = read_varint(index, &mut position)?;
+    let mut records = Vec::new();
+    for _ in 0..count {
+        let unpadded_size = read_varint(index, &mut position)?;
+        let size = read_varint(index, &mut position)?;
+        records.push((unpadded_size, size));
+    }
+    Ok(records)
+}
+
+/// Reads a variable-length integer, which stores 7 bits per byte and sets the high bit on all but the last byte.
+fn read_varint(data: &[u8], position: &mut usize) -> io::Result<u64> {
+    let mut value = 0u64;
+    for i in 0..9 {
+        let byte = *data.get(*position).ok_or_else(invalid_xz)?;
+        *position += 1;
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid_xz())
+}
+
+/// Blocks are padded to a multiple of 4 bytes.
+fn padded(size: u64) -> u64 {
+    (size + 3) & !3
+}
+
+fn read_exact_at(file: &mut File, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+fn invalid_xz() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid or truncated xz file")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+    use std::io::Write;
+    use xz2::write::XzEncoder;
+
+    fn data(len: usize, seed: u8) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8 ^ seed).collect()
+    }
+
+    fn xz(data: &[u8]) -> Vec<u8> {
+        let mut encoder = XzEncoder::new(Vec::new(), 1);
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn read_at(reader: &mut BlockReader, position: u64, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        reader.seek(SeekFrom::Start(position)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn padded_streams() {
+        let (first, second) = (data(100_000, 1), data(50_000, 2));
+        let mut compressed = xz(&first);
+        // Stream padding in multiples of 4 bytes is allowed between and after streams.
+        compressed.extend_from_slice(&[0; 4]);
+        compressed.extend_from_slice(&xz(&second));
+        compressed.extend_from_slice(&[0; 8]);
+        let path = test_dir("xz-streams").join("image.xz");
+        std::fs::write(&path, &compressed).unwrap();
+
+        let mut reader = open_xz(&path).unwrap();
+        assert_eq!(reader.block_count(), 2);
+        let expected = [&first[..], &second[..]].concat();
+        assert_eq!(reader.known_size(), Some(expected.len() as u64));
+        assert_eq!(
+            read_at(&mut reader, first.len() as u64 - 500, 1000),
+            expected[first.len() - 500..first.len() + 500]
+        );
+        assert_eq!(read_at(&mut reader, 10, 1000), expected[10..1010]);
+    }
+
+    #[test]
+    fn invalid_files() {
+        let dir = test_dir("xz-invalid");
+        let compressed = xz(&data(1000, 3));
+        // Truncated, so the footer is missing.
+        let truncated = &compressed[..compressed.len() - 1];
+        // Padding that isn't a multiple of 4 bytes.
+        let misaligned = [&compressed[..], &[0; 2]].concat();
+        for (name, file) in [("truncated", truncated), ("misaligned", &misaligned[..])] {
+            let path = dir.join(name);
+            std::fs::write(&path, file).unwrap();
+            let error = open_xz(&path).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}", name);
+        }
+    }
+
+    #[test]
+    fn index_records() {
+        // Two records, padded to a multiple of 4 bytes and followed by the CRC32 of the index.
+        let index = [
+            0x00, 0x02, 0x80, 0x01, 0xe8, 0x07, 0x10, 0x20, 0, 0, 0, 0, 0xaa, 0xbb, 0xcc, 0xdd,
+        ];
+        assert_eq!(parse_index(&index).unwrap(), vec![(128, 1000), (16, 32)]);
+
+        // Not an index, or fewer records than it claims.
+        assert!(parse_index(&[0x01, 0x00, 0, 0]).is_err());
+        assert!(parse_index(&[0x00, 0x03, 0x10, 0x20]).is_err());
+    }
+
+    #[test]
+    fn varint_limits() {
+        let mut position = 0;
+        assert_eq!(read_varint(&[0x7f], &mut position).unwrap(), 0x7f);
+        assert_eq!(position, 1);
+
+        // Nine bytes hold 63 bits, which is the most a varint may have.
+        let longest = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f];
+        let mut position = 0;
+        assert_eq!(read_varint(&longest, &mut position).unwrap(), u64::MAX >> 1);
+        assert_eq!(position, 9);
+
+        let too_long = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert!(read_varint(&too_long, &mut 0).is_err());
+        assert!(read_varint(&[0x80, 0x80], &mut 0).is_err());
+        assert!(read_varint(&[], &mut 0).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+/// Name of the alternate data stream in which Windows records where a downloaded file came from.
+pub const ZONE_IDENTIFIER_STREAM: &str = "Zone.Identifier";
+
+/// The "Mark of the Web" of a downloaded file, as found in its `Zone.Identifier` stream.
+///
+/// The stream is a small INI file with a `[ZoneTransfer]` section. Browsers only write `ReferrerUrl` and `HostUrl`
+/// since Windows 10, so those are often missing.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ZoneIdentifier {
+    pub zone_id: Option<u32>,
+    /// The page the file was downloaded from.
+    pub referrer_url: Option<String>,
+    /// The URL of the file itself.
+    pub host_url: Option<String>,
+}
+
+impl ZoneIdentifier {
+    /// Parses the content of a `Zone.Identifier` stream, or returns `None` if it has no `[ZoneTransfer]` section.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let text = decode(data);
+        let mut zone_identifier = None;
+        let mut in_zone_transfer = false;
+
+        for line in text.lines().map(str::trim) {
+            if line.starts_with('[') {
+                in_zone_transfer = line.eq_ignore_ascii_case("[ZoneTransfer]");
+                if in_zone_transfer {
+                    zone_identifier.get_or_insert_with(Self::default);
+                }
+                continue;
+            }
+            let zone_identifier = match &mut zone_identifier {
+                Some(zone_identifier) if in_zone_transfer => zone_identifier,
+                _ => continue,
+            };
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => continue,
+            };
+            if key.eq_ignore_ascii_case("ZoneId") {
+                zone_identifier.zone_id = value.parse().ok();
+            } else if key.eq_ignore_ascii_case("ReferrerUrl") {
+                zone_identifier.referrer_url = Some(value.to_owned());
+            } else if key.eq_ignore_ascii_case("HostUrl") {
+                zone_identifier.host_url = Some(value.to_owned());
+            }
+        }
+
+        zone_identifier
+    }
+
+    /// Returns the name of the security zone, as in the Internet Options of Windows.
+    pub fn zone_name(&self) -> Option<&'static str> {
+        match self.zone_id? {
+            0 => Some("Local machine"),
+            1 => Some("Local intranet"),
+            2 => Some("Trusted sites"),
+            3 => Some("Internet"),
+            4 => Some("Restricted sites"),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes the stream, which is usually ANSI text but may also be UTF-16 with a byte order mark.
+fn decode(data: &[u8]) -> String {
+    match data {
+        [0xff, 0xfe, rest @ ..] => {
+            let units = rest
+                .chunks_exact(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                .collect::<Vec<_>>();
+            String::from_utf16_lossy(&units)
+        }
+        [0xef, 0xbb, 0xbf, rest @ ..] => String::from_utf8_lossy(rest).into_owned(),
+        _ => String::from_utf8_lossy(data).into_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn browser_download() {
+        let data = b"[ZoneTransfer]\r\nZoneId=3\r\nReferrerUrl=https://example.com/downloads\r\n\
+                     HostUrl=https://example.com/setup.exe\r\n";
+        let zone = ZoneIdentifier::parse(data).unwrap();
+        assert_eq!(
+            zone,
+            ZoneIdentifier {
+                zone_id: Some(3),
+                referrer_url: Some("https://example.com/downloads".to_owned()),
+                host_url: Some("https://example.com/setup.exe".to_owned()),
+            }
+        );
+        assert_eq!(zone.zone_name(), Some("Internet"));
+    }
+
+    #[test]
+    fn utf16_with_other_sections() {
+        let text = "[Other]\r\nZoneId=1\r\n[zonetransfer]\r\n zoneid = 4 \r\n[Other]\r\nHostUrl=ignored\r\n";
+        let mut data = vec![0xff, 0xfe];
+        data.extend(text.encode_utf16().flat_map(|c| c.to_le_bytes()));
+
+        let zone = ZoneIdentifier::parse(&data).unwrap();
+        assert_eq!(zone.zone_id, Some(4));
+        assert_eq!(zone.host_url, None);
+        assert_eq!(zone.zone_name(), Some("Restricted sites"));
+    }
+
+    #[test]
+    fn utf8_bom_and_unknown_zone() {
+        let zone = ZoneIdentifier::parse(b"\xef\xbb\xbf[ZoneTransfer]\nZoneId=7\n").unwrap();
+        assert_eq!(zone.zone_id, Some(7));
+        assert_eq!(zone.zone_name(), None);
+
+        let zone = ZoneIdentifier::parse(b"[ZoneTransfer]\nZoneId=internet\n").unwrap();
+        assert_eq!(zone, ZoneIdentifier::default());
+    }
+
+    #[test]
+    fn no_zone_transfer_section() {
+        assert_eq!(ZoneIdentifier::parse(b""), None);
+        assert_eq!(ZoneIdentifier::parse(b"ZoneId=3\r\n"), None);
+        assert_eq!(ZoneIdentifier::parse(b"[Other]\r\nZoneId=3\r\n"), None);
+    }
+}
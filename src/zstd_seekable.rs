@@ -0,0 +1,210 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Take};
+use std::path::Path;
+
+use zstd::stream::read::Decoder;
+
+use crate::compressed::{Block, BlockFormat, BlockReader};
+
+pub const ZSTD_MAGIC: &[u8; 4] = b"\x28\xb5\x2f\xfd";
+/// Magic number of the skippable frame that holds the seek table.
+const SEEK_TABLE_FRAME_MAGIC: u32 = 0x184d_2a5e;
+const SEEKABLE_MAGIC: u32 = 0x8f92_eab1;
+const SEEK_TABLE_FOOTER_SIZE: u64 = 9;
+/// Upper limit for the number of frames, to not allocate absurd amounts of memory for corrupted seek tables.
+const MAX_FRAMES: u32 = 1 << 24;
+
+struct ZstdFormat;
+
+impl BlockFormat for ZstdFormat {
+    fn decoder(&self, _index: usize, data: Take<File>) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(Decoder::new(data)?))
+    }
+}
+
+/// Opens a zstd compressed image and returns whether it is in the seekable format.
+///
+/// Images in the seekable format (from zstd's `contrib/seekable_format`) are split into independent frames listed
+/// in a seek table at the end of the file, which allows fast random access. Other zstd files are decompressed
+/// from the start for every backward seek.
+pub fn open_zstd(path: &Path) -> io::Result<(BlockReader, bool)> {
+    let mut file = File::open(path)?;
+    let len = file.seek(SeekFrom::End(0))?;
+
+    let (blocks, seekable) = match read_seek_table(&mut file, len)? {
+        Some(blocks) => (blocks, true),
+        None => (
+            vec![Block {
+                compressed_offset: 0,
+                compressed_size: len,
+                offset: 0,
+                size: None,
+            }],
+            false,
+        ),
+    };
+
+    Ok((
+        BlockReader::new(file, Box::new(ZstdFormat), blocks),
+        seekable,
+    ))
+}
+
+/// Reads the seek table, if the file has one.
+fn read_seek_table(file: &mut File, len: u64) -> io::Result<Option<Vec<Block>>> {
+    if len < SEEK_TABLE_FOOTER_SIZE {
+        return Ok(None);
+    }
+
+    let mut footer = [0u8; SEEK_TABLE_FOOTER_SIZE as usize];
+    file.seek(SeekFrom::Start(len - SEEK_TABLE_FOOTER_SIZE))?;
+    file.read_exact(&mut footer)?;
+    if u32::from_le_bytes(footer[5..9].try_into().unwrap()) != SEEKABLE_MAGIC {
+        return Ok(None);
+    }
+
+    let frame_count = u32::from_le_bytes(footer[0..4].try_into().unwrap());
+    let has_checksums = footer[4] & 0x80 != 0;
+    if frame_count > MAX_FRAMES {
+        return Err(invalid_seek_table());
+    }
+    let entry_size = if has_checksums { 12 } else { 8 };
+
+    // The seek table is a skippable frame with an 8 byte header.
+    let table_size = frame_count as u64 * entry_size + SEEK_TABLE_FOOTER_SIZE;
+    let frame_start = len
+        .checked_sub(table_size + 8)
+        .ok_or_else(invalid_seek_table)?;
+    let mut table = vec![0u8; (table_size + 8) as usize];
+    file.seek(SeekFrom::Start(frame_start))?;
+    file.read_exact(&mut table)?;
+    if u32::from_le_bytes(table[0..4].try_into().unwrap()) != SEEK_TABLE_FRAME_MAGIC
+        || u32::from_le_bytes(table[4..8].try_into().unwrap()) as u64 != table_size
+    {
+        return Err(invalid_seek_table());
+    }
+
+    let mut blocks = Vec::with_capacity(frame_count as usize);
+    let mut compressed_offset = 0;
+    let mut offset = 0;
+    for entry in table[8..]
+        .chunks_exact(entry_size as usize)
+        .take(frame_count as usize)
+    {
+        let compressed_size = u32::from_le_bytes(entry[0..4].try_into().unwrap()) as u64;
+        let size = u32::from_le_bytes(entry[4..8].try_into().unwrap()) as u64;
+        blocks.push(Block {
+            compressed_offset,
+            compressed_size,
+            offset,
+            size: Some(size),
+        });
+        compressed_offset += compressed_size;
+        offset += size;
+    }
+    if blocks.is_empty() || compressed_offset > frame_start {
+        return Err(invalid_seek_table());
+    }
+
+    Ok(Some(blocks))
+}
+
+fn invalid_seek_table() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid zstd seek table")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    fn data(len: usize, seed: u8) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8 ^ seed).collect()
+    }
+
+    /// Compresses each of `frames` on its own and appends a seek table listing them.
+    fn seekable(frames: &[&[u8]]) -> Vec<u8> {
+        let mut file = Vec::new();
+        let mut table = Vec::new();
+        for frame in frames {
+            let compressed = zstd::encode_all(*frame, 1).unwrap();
+            table.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            table.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            file.extend_from_slice(&compressed);
+        }
+        table.extend_from_slice(&(frames.len() as u32).to_le_bytes());
+        table.push(0);
+        table.extend_from_slice(&SEEKABLE_MAGIC.to_le_bytes());
+
+        file.extend_from_slice(&SEEK_TABLE_FRAME_MAGIC.to_le_bytes());
+        file.extend_from_slice(&(table.len() as u32).to_le_bytes());
+        file.extend_from_slice(&table);
+        file
+    }
+
+    fn read_all(reader: &mut BlockReader, position: u64) -> Vec<u8> {
+        let mut data = Vec::new();
+        reader.seek(SeekFrom::Start(position)).unwrap();
+        reader.read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn seek_table() {
+        let (first, second) = (data(10_000, 1), data(5_000, 2));
+        let path = test_dir("zstd-seek-table").join("image.zst");
+        std::fs::write(&path, seekable(&[&first, &second])).unwrap();
+
+        let (mut reader, seekable) = open_zstd(&path).unwrap();
+        assert!(seekable);
+        assert_eq!(reader.block_count(), 2);
+        assert_eq!(reader.known_size(), Some(15_000));
+        assert_eq!(read_all(&mut reader, 12_000), second[2_000..]);
+        assert_eq!(read_all(&mut reader, 0), [&first[..], &second[..]].concat());
+    }
+
+    #[test]
+    fn without_seek_table() {
+        let data = data(10_000, 3);
+        let path = test_dir("zstd-plain").join("image.zst");
+        std::fs::write(&path, zstd::encode_all(&data[..], 1).unwrap()).unwrap();
+
+        let (mut reader, seekable) = open_zstd(&path).unwrap();
+        assert!(!seekable);
+        assert_eq!(reader.block_count(), 1);
+        assert_eq!(reader.known_size(), None);
+        assert_eq!(reader.seek(SeekFrom::End(0)).unwrap(), 10_000);
+        assert_eq!(read_all(&mut reader, 5_000), data[5_000..]);
+    }
+
+    #[test]
+    fn invalid_seek_tables() {
+        let dir = test_dir("zstd-invalid");
+        let valid = seekable(&[&data(1000, 4)]);
+        let footer = valid.len() - SEEK_TABLE_FOOTER_SIZE as usize;
+
+        // More frames than fit in the file.
+        let mut too_many_frames = valid.clone();
+        too_many_frames[footer..footer + 4].copy_from_slice(&1000u32.to_le_bytes());
+        // A frame that is larger than the data in front of the seek table.
+        let mut oversized_frame = valid.clone();
+        let entry = footer - 8;
+        oversized_frame[entry..entry + 4].copy_from_slice(&1_000_000u32.to_le_bytes());
+        // A skippable frame whose size doesn't match the seek table.
+        let mut wrong_frame_size = valid;
+        let frame_size = footer - 8 - 4;
+        wrong_frame_size[frame_size..frame_size + 4].copy_from_slice(&0u32.to_le_bytes());
+
+        for (name, file) in [
+            ("too-many-frames", too_many_frames),
+            ("oversized-frame", oversized_frame),
+            ("wrong-frame-size", wrong_frame_size),
+        ] {
+            let path = dir.join(name);
+            std::fs::write(&path, file).unwrap();
+            let error = open_zstd(&path).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}", name);
+        }
+    }
+}